@@ -0,0 +1,820 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use num_traits::Zero;
+
+use crate::{Cache, Expr, Idx, Options, Rat, Shape, Target, MAX_NUMBERS};
+use crate::partition::{sub_multisets, unique_indices};
+use crate::search::{allowed_partitions, cache_key, cached, exact_solutions, expressions,
+    get_nearest_expression_cached};
+use crate::strategy::complements;
+
+/// Find the nearest expressions when leaving out a single number.
+///
+/// For every index `i` in `nrs`, find the expression nearest to `target`
+/// that uses all numbers in `nrs` except `nrs[i]`, and return these in a
+/// vector in the same order as the input numbers. The returned expressions
+/// index into the full `nrs` array. A single cache is shared between the
+/// searches, so that sub-expressions are computed only once. If a number
+/// can be removed while the target remains reachable, it is dead weight.
+pub fn leave_one_out(nrs: &[u64], target: &Target, opts: &Options) -> Vec<Expr>
+{
+    let mut cache = Cache::default();
+    let idxs = unique_indices(nrs);
+
+    let mut res = vec![];
+    for idx in 0..nrs.len()
+    {
+        let uniq_idx = nrs.iter().position(|&x| x == nrs[idx]).unwrap();
+        let mut sub_idxs = idxs.clone();
+        let pos = sub_idxs.iter().position(|&i| i as usize == uniq_idx).unwrap();
+        sub_idxs.remove(pos);
+
+        res.push(get_nearest_expression_cached(nrs, &sub_idxs, target, opts, &mut cache));
+    }
+
+    res
+}
+
+/// The contribution of an input number to the exact solutions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Contribution
+{
+    /// The number is used in every exact solution
+    Required,
+    /// The number is used in some, but not all, exact solutions
+    Optional,
+    /// The number is not used in any exact solution
+    Never
+}
+
+/// Determine the contribution of each input number.
+///
+/// Enumerate the exact solutions for `target` over all subsets of `nrs`, and
+/// for each input number determine whether it is required in every solution,
+/// used in some, or never useful. When a number occurs more than once in
+/// `nrs`, a solution using `m` copies of it is counted as using the first `m`
+/// occurrences. Returns `None` if no exact solution exists.
+pub fn contributions(nrs: &[u64], target: &Target, opts: &Options) -> Option<Vec<Contribution>>
+{
+    let solutions = exact_solutions(nrs, target, false, opts);
+    if solutions.is_empty()
+    {
+        return None;
+    }
+
+    let mut use_counts = vec![0; nrs.len()];
+    for expr in solutions.iter()
+    {
+        let mut used = vec![false; nrs.len()];
+        for idx in expr.indices()
+        {
+            let pos = (idx as usize..nrs.len())
+                .find(|&i| nrs[i] == nrs[idx as usize] && !used[i])
+                .unwrap();
+            used[pos] = true;
+        }
+        for (count, &u) in use_counts.iter_mut().zip(used.iter())
+        {
+            if u
+            {
+                *count += 1;
+            }
+        }
+    }
+
+    let res = use_counts.iter().map(|&count| {
+            if count == solutions.len()
+            {
+                Contribution::Required
+            }
+            else if count > 0
+            {
+                Contribution::Optional
+            }
+            else
+            {
+                Contribution::Never
+            }
+        }).collect();
+    Some(res)
+}
+
+/// The maximum count of numbers for which the powers of two dividing the
+/// values of the expressions are enumerated.
+const MAX_VALUATION_NUMBERS: usize = 8;
+
+/// The powers of two dividing the values of integer expressions.
+///
+/// Bit `k` of `mask` is set if an expression may evaluate to a nonzero integer
+/// that is divisible by `2^k`, but not by `2^(k+1)`, and `zero` is set if an
+/// expression may evaluate to zero. Both may be set when no such expression
+/// exists, but never the other way around.
+#[derive(Clone, Copy, Default)]
+struct Valuations
+{
+    mask: u64,
+    zero: bool
+}
+
+impl Valuations
+{
+    /// The power of two dividing number `nr`.
+    fn of(nr: u64) -> Self
+    {
+        if nr == 0
+        {
+            Valuations { mask: 0, zero: true }
+        }
+        else
+        {
+            Valuations { mask: 1 << nr.trailing_zeros(), zero: false }
+        }
+    }
+
+    /// The powers of two dividing any integer combination of an expression
+    /// from `self` with one from `other`, in either order.
+    fn combine(self, other: Self) -> Self
+    {
+        let bits = |mask: u64| (0..64).filter(move |k| mask >> k & 1 == 1);
+        let mut res = Valuations::default();
+        for k0 in bits(self.mask)
+        {
+            for k1 in bits(other.mask)
+            {
+                if k0 == k1
+                {
+                    // The sum or difference of two odd multiples of 2^k is an
+                    // even multiple of it, or zero
+                    res.mask |= (!0u64).checked_shl(k0 + 1).unwrap_or(0);
+                    res.zero = true;
+                }
+                else
+                {
+                    res.mask |= 1 << k0.min(k1);
+                }
+                if k0 + k1 < 64
+                {
+                    res.mask |= 1 << (k0 + k1);
+                }
+                // An exact division by the number with the lower power
+                res.mask |= 1 << (k0.max(k1) - k0.min(k1));
+            }
+        }
+        if self.zero
+        {
+            res.mask |= other.mask;
+            res.zero = true;
+        }
+        if other.zero
+        {
+            res.mask |= self.mask;
+            res.zero = true;
+        }
+        res
+    }
+
+    /// Add the powers of two in `other` to those in `self`.
+    fn merge(&mut self, other: Self)
+    {
+        self.mask |= other.mask;
+        self.zero |= other.zero;
+    }
+}
+
+/// Find the powers of two dividing the values of integer expressions.
+///
+/// Find the powers of two that may divide the value of an expression over
+/// all numbers in `nrs`, or over any subset of them if `subset` is true, when
+/// all intermediate results are integers. Returns `None` if there are too many
+/// numbers to enumerate the sets of them cheaply.
+fn valuations(nrs: &[u64], subset: bool) -> Option<Valuations>
+{
+    let count = nrs.len();
+    if count > MAX_VALUATION_NUMBERS
+    {
+        return None;
+    }
+
+    let full = (1usize << count) - 1;
+    let mut table = vec![Valuations::default(); full + 1];
+    for set in 1..=full
+    {
+        if set.is_power_of_two()
+        {
+            table[set] = Valuations::of(nrs[set.trailing_zeros() as usize]);
+            continue;
+        }
+
+        // Every split once, with the lowest number in the first part
+        let low = set & set.wrapping_neg();
+        let mut part = (set - 1) & set;
+        while part > 0
+        {
+            if part & low != 0
+            {
+                let vals = table[part].combine(table[set ^ part]);
+                table[set].merge(vals);
+            }
+            part = (part - 1) & set;
+        }
+    }
+
+    if subset
+    {
+        let mut res = Valuations::default();
+        for &vals in table[1..].iter()
+        {
+            res.merge(vals);
+        }
+        Some(res)
+    }
+    else
+    {
+        Some(table[full])
+    }
+}
+
+/// Explain why a target cannot be reached.
+///
+/// Given that an exhaustive search for `target` over the numbers in `nrs`
+/// found no exact solution, try to derive a human-readable reason for this
+/// that is cheap to establish. If no such reason is found, the explanation
+/// simply states that the exhaustive search found none. Since nothing is known
+/// about custom operations, no reason is derived when these are used.
+pub fn explain_infeasible(nrs: &[u64], target: &Target, opts: &Options) -> String
+{
+    if !opts.binary_ops.is_empty() || !opts.unary_ops.is_empty()
+    {
+        return String::from("exhaustive search found none");
+    }
+
+    if nrs.len() == 1
+    {
+        return format!("the only number {} does not hit the target {}", nrs[0], target);
+    }
+
+    if nrs.iter().all(|&nr| nr == 0)
+    {
+        return String::from("all numbers are zero, and so is every expression over them");
+    }
+
+    if opts.integers_only
+    {
+        // With integer intermediate results, subtraction and division never
+        // increase a value, and for non-negative integers a and b both
+        // a+b+1 and a*b+1 are at most (a+1)*(b+1). Hence every expression
+        // evaluates to a value v with v+1 at most the product of all nr+1.
+        let bound = nrs.iter().try_fold(1u64, |acc, &nr| acc.checked_mul(nr.saturating_add(1)));
+        if let Some(bound) = bound
+        {
+            if target.min() >= Rat::from_integer(bound)
+            {
+                return format!("with integer intermediate results no expression can \
+                    exceed {}", bound - 1);
+            }
+        }
+
+        // The parity argument: an odd number cannot be made from even numbers
+        // when every division is exact, unless the operands of a division are
+        // divisible by the same power of two.
+        let odd_targets = target.values().is_some_and(|values| {
+                values.iter().all(|val| val.is_integer() && val.numer() % 2 == 1)
+            });
+        if odd_targets && valuations(nrs, opts.subset).is_some_and(|vals| vals.mask & 1 == 0)
+        {
+            return String::from("with integer intermediate results every expression \
+                evaluates to an even number, but the target is odd");
+        }
+    }
+
+    String::from("exhaustive search found none")
+}
+
+/// The maximum count of numbers in the range of `suggest_extra()` for which a
+/// full search is done.
+pub const MAX_SEARCHED_EXTRAS: u64 = 64;
+
+/// Suggest an extra number that makes the target reachable.
+///
+/// Find the numbers `x` with `min <= x <= max`, for which `target` can be hit
+/// exactly by an expression over the numbers in `nrs` and `x`, or over `x` and
+/// a subset of the numbers if `opts.subset` is set. First, the numbers `x` are
+/// found that can be combined in a single, final, operation with an expression
+/// over the numbers, which is cheap, since only the values that are enumerated
+/// in a normal search are needed. Then, if the range holds at most
+/// `MAX_SEARCHED_EXTRAS` numbers, every other number in it is tried in a full
+/// search, so that solutions in which `x` is used deeper inside the expression
+/// are found as well. The expressions over the numbers are taken from, and
+/// stored in, `cache`, which may have been filled by a search over `nrs` with
+/// the same `opts`. Returns the numbers found in increasing order, each with a
+/// witness expression over the numbers array `nrs` followed by `x`. Only
+/// targets consisting of single values, rather than ranges, are supported.
+pub fn suggest_extra(nrs: &[u64], target: &Target, min: u64, max: u64, opts: &Options,
+    cache: &mut Cache) -> Vec<(u64, Expr)>
+{
+    let values = match target.values()
+        {
+            Some(values) if nrs.len() < MAX_NUMBERS => values,
+            _ => return vec![]
+        };
+
+    let idxs = unique_indices(nrs);
+    let subsets = if opts.subset { sub_multisets(&idxs) } else { vec![idxs.clone()] };
+    let mut extended = [nrs, &[0]].concat();
+    let mut found = BTreeMap::new();
+    // In a balanced tree, the extra number can only be combined with a
+    // single other number at the root.
+    let root_subsets = if opts.shape == Shape::Balanced && nrs.len() > 1
+        {
+            vec![]
+        }
+        else
+        {
+            subsets
+        };
+    for sub_idxs in root_subsets
+    {
+        let key = expressions(nrs, &sub_idxs, opts, cache);
+        for expr in cached(cache, &key, opts).iter()
+        {
+            if opts.max_ops.is_some_and(|max_ops| expr.op_count() + 1 > max_ops)
+            {
+                continue;
+            }
+
+            for &val in values.iter()
+            {
+                for (b, op, swap) in complements(expr.val, val, opts)
+                {
+                    if !b.is_integer() || b < Rat::from_integer(min) || b > Rat::from_integer(max)
+                        || found.contains_key(b.numer())
+                    {
+                        continue;
+                    }
+
+                    let x = *b.numer();
+                    extended[nrs.len()] = x;
+                    let leaf = [nrs.len() as Idx];
+                    let ops = if swap
+                        {
+                            [&leaf[..], &expr.ops[..], &[op]].concat()
+                        }
+                        else
+                        {
+                            [&expr.ops[..], &leaf[..], &[op]].concat()
+                        };
+                    if let Some(expr) = Expr::from_ops(ops, &extended, opts)
+                    {
+                        if expr.val == val
+                        {
+                            found.insert(x, expr);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if max >= min && max - min < MAX_SEARCHED_EXTRAS
+    {
+        // Only the expressions over the numbers themselves are kept, those
+        // with an extra number are of no use for the next one
+        let keys = sub_multisets(&idxs).iter().map(|sub_idxs| cache_key(nrs, sub_idxs))
+            .collect::<::std::collections::HashSet<_>>();
+        for x in min..=max
+        {
+            if found.contains_key(&x)
+            {
+                continue;
+            }
+
+            extended[nrs.len()] = x;
+            let expr = get_nearest_expression_cached(&extended, &unique_indices(&extended),
+                target, opts, cache);
+            cache.retain(|key, _| keys.contains(key));
+            // With a subset, the expression should use the extra number, i.e.
+            // more copies of x than the numbers have
+            let used = expr.ops.iter()
+                .filter(|&&op| (op as usize) < MAX_NUMBERS && extended[op as usize] == x)
+                .count();
+            if !expr.is_empty() && target.contains(expr.val)
+                && used > nrs.iter().filter(|&&nr| nr == x).count()
+            {
+                found.insert(x, expr);
+            }
+        }
+    }
+
+    found.into_iter().collect()
+}
+
+/// How a single target can be reached
+#[derive(Clone, Debug)]
+pub struct TargetReach
+{
+    /// The target number
+    pub target: u64,
+    /// The fewest input numbers used in an exact solution, or `None` if the
+    /// target cannot be hit exactly
+    pub min_numbers: Option<usize>,
+    /// The number of exact solutions, counted as in `exact_solutions()`
+    pub solutions: usize,
+    /// An exact solution using the fewest numbers
+    pub example: Option<Expr>,
+    /// The distance between the target and the nearest value that can be
+    /// made, which is zero if the target can be hit exactly
+    pub error: Rat
+}
+
+/// Determine how every target in a range can be reached.
+///
+/// For every target from `min` to `max` (inclusive), count the exact
+/// solutions over the numbers in `nrs`, and find the fewest numbers needed to
+/// hit it, or the error of the nearest value. If `opts.subset` is set,
+/// solutions over any subset of the numbers are considered, otherwise only
+/// those using all numbers. All targets are handled in a single enumeration of
+/// the expressions, so this is much faster than a search for every target.
+pub fn reachability(nrs: &[u64], min: u64, max: u64, opts: &Options) -> Vec<TargetReach>
+{
+    let mut res = (min..=max).map(|target| {
+            TargetReach { target, min_numbers: None, solutions: 0, example: None,
+                error: Rat::zero() }
+        }).collect::<Vec<_>>();
+    let (lo, hi) = (Rat::from_integer(min), Rat::from_integer(max));
+    // The largest value below the range, the smallest value above it, and
+    // for every target the largest value rounding down to it, and the
+    // smallest value rounding up to it
+    let (mut under, mut over) = (None, None);
+    let mut below: Vec<Option<Rat>> = vec![None; res.len()];
+    let mut above: Vec<Option<Rat>> = vec![None; res.len()];
+    let mut record = |val: Rat, count: usize, expr: &dyn Fn() -> Expr| {
+            if val < lo
+            {
+                under = under.max(Some(val));
+                return;
+            }
+            if val > hi
+            {
+                over = Some(over.map_or(val, |o: Rat| o.min(val)));
+                return;
+            }
+
+            let (floor, ceil) = ((val.floor().to_integer() - min) as usize,
+                (val.ceil().to_integer() - min) as usize);
+            below[floor] = below[floor].max(Some(val));
+            above[ceil] = Some(above[ceil].map_or(val, |a| a.min(val)));
+            if val.is_integer()
+            {
+                let reach = &mut res[floor];
+                reach.solutions += 1;
+                if reach.min_numbers.is_none_or(|n| count < n)
+                {
+                    reach.min_numbers = Some(count);
+                    reach.example = Some(expr());
+                }
+            }
+        };
+
+    let mut cache = Cache::default();
+    let idxs = unique_indices(nrs);
+    let subsets = if opts.subset { sub_multisets(&idxs) } else { vec![idxs] };
+    for sub_idxs in subsets
+    {
+        if sub_idxs.len() == 1
+        {
+            let expr = Expr::new(nrs, sub_idxs[0]);
+            record(expr.val, 1, &|| expr.clone());
+            continue;
+        }
+
+        for (idxs0, idxs1) in allowed_partitions(&sub_idxs, opts)
+        {
+            let key0 = expressions(nrs, &idxs0, opts, &mut cache);
+            let key1 = expressions(nrs, &idxs1, opts, &mut cache);
+            let exprs1 = cached(&cache, &key1, opts);
+            for expr0 in cached(&cache, &key0, opts).iter()
+            {
+                for expr1 in exprs1.iter()
+                {
+                    for (op, val) in expr0.possible_combinations(expr1, opts)
+                    {
+                        record(val, sub_idxs.len(), &|| expr0.combine(expr1, op, val));
+                    }
+                }
+            }
+        }
+    }
+
+    // The values rounding down to a target lie above it, so they are only
+    // below the next targets, and the values rounding up to a target are only
+    // above the previous targets
+    let mut nearest = under;
+    let mut errors_below = vec![];
+    for (reach, &val) in res.iter().zip(below.iter())
+    {
+        errors_below.push(nearest.map(|v| Rat::from_integer(reach.target) - v));
+        nearest = nearest.max(val);
+    }
+    let mut nearest = over;
+    for ((reach, &val), error_below) in res.iter_mut().zip(above.iter()).zip(errors_below).rev()
+    {
+        let error_above = nearest.map(|v| v - Rat::from_integer(reach.target));
+        reach.error = match (error_below, error_above)
+            {
+                _ if reach.min_numbers.is_some() => Rat::zero(),
+                (Some(b), Some(a)) => b.min(a),
+                (b, a) => b.or(a).unwrap_or_else(Rat::zero)
+            };
+        nearest = match (nearest, val)
+            {
+                (Some(a), Some(v)) => Some(a.min(v)),
+                (a, v) => a.or(v)
+            };
+    }
+
+    res
+}
+
+/// Find the hardest targets in a range.
+///
+/// Determine the reachability of every target from `min` to `max` as in
+/// `reachability()`, and return the targets that can be hit exactly, hardest
+/// first: the targets needing the most numbers come first, and of those, the
+/// ones with the fewest exact solutions.
+pub fn hardest_targets(nrs: &[u64], min: u64, max: u64, opts: &Options) -> Vec<TargetReach>
+{
+    let mut res = reachability(nrs, min, max, opts).into_iter()
+        .filter(|reach| reach.min_numbers.is_some())
+        .collect::<Vec<_>>();
+    res.sort_by_key(|reach| (::std::cmp::Reverse(reach.min_numbers), reach.solutions));
+    res
+}
+
+/// Summary of the values that can be made from a subset of the numbers
+#[cfg(feature = "xxhash")]
+#[derive(Clone, Debug)]
+pub struct SubsetSummary
+{
+    /// The numbers in the subset, separated by underscores
+    pub key: String,
+    /// The number of distinct values that can be made from the subset
+    pub count: usize,
+    /// The 64-bit xxHash of the sorted distinct values, each value being
+    /// encoded as its numerator and denominator in little endian byte order
+    pub hash: u64
+}
+
+/// Certificate of an exhaustive search
+///
+/// Struct `Certificate` summarizes the distinct values that can be made from
+/// every sub-multiset of the input numbers, so that an independent run or
+/// tool can confirm that the enumeration of values was complete. Requires the
+/// `xxhash` feature.
+#[cfg(feature = "xxhash")]
+#[derive(Clone, Debug)]
+pub struct Certificate
+{
+    /// Summaries for every sub-multiset of the numbers, sorted by size. The
+    /// last entry is for the full set of numbers
+    pub subsets: Vec<SubsetSummary>,
+    /// Whether the target is among the values that can be made from the full
+    /// set of numbers
+    pub target_found: bool
+}
+
+/// Create a certificate for an exhaustive search.
+///
+/// Enumerate the distinct values for every sub-multiset of the numbers in
+/// `nrs`, and summarize them in a `Certificate`. Note that this keeps the
+/// values for the full set of numbers in memory, which a normal search
+/// avoids. Requires the `xxhash` feature.
+#[cfg(feature = "xxhash")]
+pub fn certify(nrs: &[u64], target: &Target, opts: &Options) -> Certificate
+{
+    let mut cache = Cache::default();
+    let idxs = unique_indices(nrs);
+
+    let mut subsets = vec![];
+    let mut target_found = false;
+    for sub_idxs in sub_multisets(&idxs)
+    {
+        let key = expressions(nrs, &sub_idxs, opts, &mut cache);
+
+        let mut vals = cached(&cache, &key, opts).iter().map(|expr| expr.val).collect::<Vec<_>>();
+        vals.sort();
+        let mut bytes = Vec::with_capacity(16 * vals.len());
+        for val in vals.iter()
+        {
+            bytes.extend_from_slice(&val.numer().to_le_bytes());
+            bytes.extend_from_slice(&val.denom().to_le_bytes());
+        }
+
+        if sub_idxs.len() == idxs.len()
+        {
+            target_found = vals.iter().any(|&val| target.contains(val));
+        }
+        subsets.push(SubsetSummary { key, count: vals.len(), hash: fasthash::xx::hash64(&bytes) });
+    }
+
+    Certificate { subsets, target_found }
+}
+
+/// Find the trade-offs between closeness to the target and complexity.
+///
+/// For every number of operations, find the expression over a subset of the
+/// numbers in `nrs` with that many binary operations that is nearest to
+/// `target`, and return those that are not dominated by a simpler expression
+/// that is at least as near. The expressions are returned in order of
+/// increasing complexity, so that each is nearer to the target than the one
+/// before it, and the last one is the nearest expression overall. Subsets of
+/// the numbers are always considered, regardless of `opts.subset`.
+pub fn pareto_front(nrs: &[u64], target: &Target, opts: &Options) -> Vec<Expr>
+{
+    // The distance to the target and expression of the nearest expression,
+    // by the number of numbers used
+    let mut best: BTreeMap<usize, (Rat, Expr)> = BTreeMap::new();
+    let mut record = |val: Rat, count: usize, expr: &dyn Fn() -> Expr| {
+            let dist = target.distance(val);
+            if best.get(&count).is_none_or(|&(d, _)| dist < d)
+            {
+                best.insert(count, (dist, expr()));
+            }
+        };
+
+    let mut cache = Cache::default();
+    let idxs = unique_indices(nrs);
+    for sub_idxs in sub_multisets(&idxs)
+    {
+        if sub_idxs.len() == 1
+        {
+            let expr = Expr::new(nrs, sub_idxs[0]);
+            record(expr.val, 1, &|| expr.clone());
+            continue;
+        }
+
+        for (idxs0, idxs1) in allowed_partitions(&sub_idxs, opts)
+        {
+            let key0 = expressions(nrs, &idxs0, opts, &mut cache);
+            let key1 = expressions(nrs, &idxs1, opts, &mut cache);
+            let exprs1 = cached(&cache, &key1, opts);
+            for expr0 in cached(&cache, &key0, opts).iter()
+            {
+                for expr1 in exprs1.iter()
+                {
+                    for (op, val) in expr0.possible_combinations(expr1, opts)
+                    {
+                        record(val, sub_idxs.len(), &|| expr0.combine(expr1, op, val));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut front: Vec<(Rat, Expr)> = vec![];
+    for (_, (dist, expr)) in best.into_iter()
+    {
+        if front.last().is_none_or(|&(d, _)| dist < d)
+        {
+            front.push((dist, expr));
+        }
+    }
+    front.into_iter().map(|(_, expr)| expr).collect()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{builtin_binary_op, distinct_values, get_nearest_expression};
+
+    fn integer_opts() -> Options
+    {
+        Options { integers_only: true, ..Options::default() }
+    }
+
+    #[test]
+    fn leave_one_out_finds_dead_weight()
+    {
+        let nrs = [2, 3, 4, 100];
+        let target = Target::new(24);
+        let exprs = leave_one_out(&nrs, &target, &Options::default());
+        assert_eq!(exprs.len(), nrs.len());
+        for (i, expr) in exprs.iter().enumerate()
+        {
+            assert!(expr.indices().all(|idx| nrs[idx as usize] != nrs[i]));
+        }
+        // Only the 100 can be left out
+        assert_eq!(exprs[3].val, 24.into());
+        assert!(exprs[..3].iter().all(|expr| expr.val != 24.into()));
+    }
+
+    #[test]
+    fn hardest_targets_first()
+    {
+        let opts = Options { subset: true, ..Options::default() };
+        let hardest = hardest_targets(&[2, 3, 7], 1, 60, &opts);
+        assert!(hardest.iter().all(|reach| reach.min_numbers.is_some()));
+        assert!(hardest.windows(2).all(|pair| {
+                (::std::cmp::Reverse(pair[0].min_numbers), pair[0].solutions)
+                    <= (::std::cmp::Reverse(pair[1].min_numbers), pair[1].solutions)
+            }));
+        assert_eq!(hardest.last().map(|reach| reach.min_numbers), Some(Some(1)));
+    }
+
+    #[test]
+    fn reachability_matches_search()
+    {
+        for opts in [Options::default(), Options { subset: true, ..Options::default() }].iter()
+        {
+            let nrs = [2, 3, 7];
+            for reach in reachability(&nrs, 1, 60, opts)
+            {
+                let target = Target::new(reach.target);
+                let expr = get_nearest_expression(&nrs, &target, opts);
+                assert_eq!(reach.error, target.distance(expr.val), "{}", reach.target);
+                assert_eq!(reach.min_numbers.is_some(), target.contains(expr.val));
+                if let Some(example) = &reach.example
+                {
+                    assert_eq!(example.val, target.min());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parity_argument()
+    {
+        let explanation = explain_infeasible(&[2, 4], &Target::new(3), &integer_opts());
+        assert!(explanation.contains("even number"), "{}", explanation);
+        // 8/(4*2) is odd
+        let explanation = explain_infeasible(&[2, 4, 8], &Target::new(7), &integer_opts());
+        assert_eq!(explanation, "exhaustive search found none");
+    }
+
+    #[test]
+    fn parity_argument_is_sound()
+    {
+        let sets: [&[u64]; 6] = [&[2, 4], &[2, 6], &[2, 4, 8], &[4, 8, 12], &[2, 12, 40],
+            &[0, 6, 16, 20]];
+        let opts = integer_opts();
+        for &nrs in sets.iter()
+        {
+            let vals = valuations(nrs, false).unwrap();
+            let idxs = unique_indices(nrs);
+            for expr in distinct_values(nrs, &idxs, &opts, &mut Cache::default())
+            {
+                let val = *expr.val.numer();
+                if val == 0
+                {
+                    assert!(vals.zero);
+                }
+                else
+                {
+                    assert!(vals.mask >> val.trailing_zeros() & 1 == 1, "{:?}: {}", nrs, val);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn suggest_extra_deeper_in_expression()
+    {
+        let opts = Options::default();
+        let nrs = [3, 5];
+        let target = Target::new(17);
+        let found = suggest_extra(&nrs, &target, 1, 6, &opts, &mut Cache::default());
+        // 3*5+2 at the root, 3*4+5 deeper in the expression
+        assert_eq!(found.iter().map(|&(x, _)| x).collect::<Vec<_>>(), [2, 4]);
+        for (x, expr) in found.iter()
+        {
+            assert_eq!(expr.val, 17.into());
+            assert_eq!(expr.indices().count(), 3, "with {}", x);
+        }
+        // In a large range, only the root is considered
+        let found = suggest_extra(&nrs, &target, 1, 1000, &opts, &mut Cache::default());
+        assert!(found.iter().all(|&(x, _)| x != 4));
+    }
+
+    #[test]
+    fn no_bound_with_custom_ops()
+    {
+        let mut opts = integer_opts();
+        assert!(explain_infeasible(&[2, 3], &Target::new(100), &opts).contains("exceed 11"));
+        opts.binary_ops.push(builtin_binary_op("pow").unwrap());
+        let explanation = explain_infeasible(&[2, 3], &Target::new(100), &opts);
+        assert_eq!(explanation, "exhaustive search found none");
+    }
+}
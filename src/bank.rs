@@ -0,0 +1,180 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use serde::{Deserialize, Serialize};
+
+use crate::{elegance_score, essential_solutions, parse_with, Options, Target, SCHEMA_VERSION};
+
+/// A puzzle with its solutions, as stored in a puzzle bank
+///
+/// Struct `Puzzle` holds the numbers and target of a puzzle, all its
+/// essentially different exact solutions in infix notation, the most elegant
+/// first, and a difficulty rating. A puzzle bank is a JSON object with the
+/// schema version and an array of puzzles, e.g.
+/// ```text
+/// {"schema_version":1,"puzzles":[{"numbers":[1,3,4,6],"target":24,
+///     "solutions":["6/(1-3/4)"],"difficulty":5}]}
+/// ```
+/// See `BANK_SCHEMA` for its schema.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Puzzle
+{
+    /// The input numbers
+    pub numbers: Vec<u64>,
+    /// The target
+    pub target: u64,
+    /// The essentially different exact solutions, the most elegant first
+    pub solutions: Vec<String>,
+    /// The difficulty, from 1 for the easiest puzzles to 5 for the hardest,
+    /// as given by `puzzle_difficulty()`
+    pub difficulty: u32
+}
+
+/// Rate the difficulty of a puzzle with `solutions` essentially different
+/// exact solutions, from 1 for puzzles with many solutions to 5 for puzzles
+/// with a single solution.
+pub fn puzzle_difficulty(solutions: usize) -> u32
+{
+    match solutions
+    {
+        0..=1   => 5,
+        2..=4   => 4,
+        5..=19  => 3,
+        20..=49 => 2,
+        _       => 1
+    }
+}
+
+impl Puzzle
+{
+    /// Create the puzzle for target `target` over the numbers `nrs`, finding
+    /// its solutions subject to the options in `opts`. Returns `None` if the
+    /// target cannot be hit exactly.
+    pub fn new(nrs: &[u64], target: u64, opts: &Options) -> Option<Self>
+    {
+        let mut exprs = essential_solutions(nrs, &Target::new(target), opts);
+        if exprs.is_empty()
+        {
+            return None;
+        }
+
+        exprs.sort_by_key(|expr| elegance_score(expr, nrs));
+        Some(Puzzle {
+            numbers: nrs.to_vec(),
+            target,
+            solutions: exprs.iter().map(|expr| expr.to_string(nrs)).collect(),
+            difficulty: puzzle_difficulty(exprs.len())
+        })
+    }
+
+    /// Verify the solutions of this puzzle.
+    ///
+    /// Check that the puzzle has at least one solution, and that every
+    /// solution is a valid expression over the numbers of the puzzle, subject
+    /// to the options in `opts`, that evaluates to the target. Returns a
+    /// description of the first problem found, if any.
+    pub fn verify(&self, opts: &Options) -> Result<(), String>
+    {
+        if self.solutions.is_empty()
+        {
+            return Err(String::from("the puzzle has no solutions"));
+        }
+
+        for solution in self.solutions.iter()
+        {
+            let expr = parse_with(solution, &self.numbers, opts)
+                .map_err(|err| format!("invalid solution {}: {}", solution, err))?;
+            if !Target::new(self.target).contains(expr.val)
+            {
+                return Err(format!("solution {} evaluates to {}, not to the target", solution,
+                    expr.val));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A puzzle bank, as stored in JSON
+#[derive(Serialize, Deserialize)]
+struct Bank<P>
+{
+    /// The version of the format, see `SCHEMA_VERSION`
+    schema_version: u32,
+    /// The puzzles in the bank
+    puzzles: P
+}
+
+/// Serialize a puzzle bank to JSON.
+pub fn bank_to_json(puzzles: &[Puzzle]) -> String
+{
+    let bank = Bank { schema_version: SCHEMA_VERSION, puzzles };
+    serde_json::to_string(&bank).expect("failed to serialize puzzle bank")
+}
+
+/// Deserialize a puzzle bank from JSON.
+///
+/// Besides the current format, a bare array of puzzles, as written before the
+/// format was versioned, is accepted. Banks with a schema version newer than
+/// `SCHEMA_VERSION` are rejected.
+pub fn bank_from_json(s: &str) -> Result<Vec<Puzzle>, String>
+{
+    let value = serde_json::from_str::<serde_json::Value>(s).map_err(|err| err.to_string())?;
+    if value.is_array()
+    {
+        return serde_json::from_value(value).map_err(|err| err.to_string());
+    }
+
+    let bank = serde_json::from_value::<Bank<Vec<Puzzle>>>(value).map_err(|err| err.to_string())?;
+    if bank.schema_version > SCHEMA_VERSION
+    {
+        return Err(format!("unsupported schema version {}, expected at most {}",
+            bank.schema_version, SCHEMA_VERSION));
+    }
+    Ok(bank.puzzles)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn create_and_verify_puzzle()
+    {
+        let opts = Options::default();
+        let puzzle = Puzzle::new(&[1, 3, 4, 6], 24, &opts).unwrap();
+        assert_eq!(puzzle.solutions, ["6/(1-3/4)"]);
+        assert_eq!(puzzle.difficulty, 5);
+        assert_eq!(puzzle.verify(&opts), Ok(()));
+        assert!(Puzzle::new(&[1, 1], 24, &opts).is_none());
+
+        let wrong = Puzzle { solutions: vec![String::from("6*4+1-3")], ..puzzle.clone() };
+        assert!(wrong.verify(&opts).is_err());
+        let invalid = Puzzle { solutions: vec![String::from("6*4*4")], ..puzzle.clone() };
+        assert!(invalid.verify(&opts).is_err());
+
+        let puzzles = vec![puzzle];
+        assert_eq!(bank_from_json(&bank_to_json(&puzzles)), Ok(puzzles));
+    }
+
+    #[test]
+    fn bank_versions()
+    {
+        let puzzle = r#"{"numbers":[2,3],"target":6,"solutions":["2*3"],"difficulty":5}"#;
+        assert_eq!(bank_from_json(&format!("[{}]", puzzle)).unwrap().len(), 1);
+        let newer = format!(r#"{{"schema_version":{},"puzzles":[]}}"#, SCHEMA_VERSION + 1);
+        assert!(bank_from_json(&newer).is_err());
+    }
+}
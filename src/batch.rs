@@ -0,0 +1,597 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Solving a file of puzzles, for the `--input` option of the program.
+//!
+//! Every line of a puzzle file describes a puzzle, either in the form of the
+//! command line, e.g. `100 75 50 25 6 3 952 --integers-only`, or as a JSON
+//! object, e.g. `{"numbers":[1,3,4,6],"target":24}`. Empty lines and lines
+//! starting with `#` are skipped.
+//!
+//! Puzzles can also be read from the rows of a CSV file, with the numbers and
+//! the target in given columns. The rows are then written back with the
+//! results appended as extra columns.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+
+use makeexpr::{format_expr_with, format_markdown, Expr, Format, OpSet, Options, Solver,
+    SolverBuilder, Style, Target};
+use serde::Deserialize;
+
+use crate::{parse_number, parse_target};
+
+/// A puzzle in a puzzle file, as a JSON object
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonPuzzle
+{
+    /// The input numbers
+    numbers: Vec<u64>,
+    /// The target, a number or a range like `"900..=999"`
+    target: ::serde_json::Value,
+    /// The extra operations allowed, as for `--extra-ops`
+    #[serde(default)]
+    ops: Option<String>,
+    /// Whether all intermediate results must be integers, if not as on the
+    /// command line
+    #[serde(default)]
+    integers_only: Option<bool>,
+    /// Whether a subset of the numbers may be used, if not as on the command
+    /// line
+    #[serde(default)]
+    subset: Option<bool>,
+    /// The maximum number of operations
+    #[serde(default)]
+    max_ops: Option<usize>
+}
+
+/// Parse the JSON puzzle in line `line` into a solver, with the options in
+/// `builder` as defaults.
+fn parse_json(line: &str, builder: SolverBuilder) -> Result<Solver, String>
+{
+    let puzzle = serde_json::from_str::<JsonPuzzle>(line).map_err(|err| err.to_string())?;
+    let target = match &puzzle.target
+        {
+            ::serde_json::Value::Number(nr) => nr.as_u64().map(Target::new),
+            ::serde_json::Value::String(s) => parse_target(s),
+            _ => None
+        };
+    let target = target.ok_or_else(|| format!("invalid target {}", puzzle.target))?;
+
+    let mut builder = builder.numbers(&puzzle.numbers).target(target);
+    if let Some(integers_only) = puzzle.integers_only
+    {
+        builder = builder.integers_only(integers_only);
+    }
+    if let Some(subset) = puzzle.subset
+    {
+        builder = builder.subset(subset);
+    }
+    if let Some(names) = puzzle.ops.as_deref()
+    {
+        builder = builder.ops(OpSet::from_names(names)?);
+    }
+    if let Some(max_ops) = puzzle.max_ops
+    {
+        builder = builder.max_ops(max_ops);
+    }
+    builder.build().map_err(|err| err.to_string())
+}
+
+/// Parse the puzzle in line `line`, in the form of the command line, into a
+/// solver, with the options in `builder` as defaults.
+///
+/// The options `--integers-only`, `--subset`, `--extra-ops names`,
+/// `--max-ops k` and `--target t` (a number or a range) are supported. Without
+/// `--target`, the last number is the target.
+fn parse_words(line: &str, mut builder: SolverBuilder) -> Result<Solver, String>
+{
+    let mut nrs = vec![];
+    let mut target = None;
+    let mut words = line.split_whitespace();
+    while let Some(word) = words.next()
+    {
+        let mut value = || words.next().ok_or_else(|| format!("missing value for {}", word));
+        match word
+        {
+            "--integers-only" => {
+                builder = builder.integers_only(true);
+            },
+            "--subset" => {
+                builder = builder.subset(true);
+            },
+            "--extra-ops" => {
+                builder = builder.ops(OpSet::from_names(value()?)?);
+            },
+            "--max-ops" => {
+                let s = value()?;
+                let max_ops = s.parse::<usize>().map_err(|_| format!("invalid count {}", s))?;
+                builder = builder.max_ops(max_ops);
+            },
+            "--target" => {
+                let s = value()?;
+                target = Some(parse_target(s).ok_or_else(|| format!("invalid target {}", s))?);
+            },
+            _ => {
+                nrs.push(parse_number(word).ok_or_else(|| format!("invalid number {}", word))?);
+            }
+        }
+    }
+
+    let target = match target
+        {
+            Some(target) => target,
+            None => Target::new(nrs.pop().ok_or_else(|| String::from("missing target"))?)
+        };
+    builder.numbers(&nrs).target(target).build().map_err(|err| err.to_string())
+}
+
+/// Read the puzzles in the puzzle file at `path`, with the search options in
+/// `opts`, including the number of threads, as defaults.
+///
+/// Returns a message with the line number of the first invalid puzzle, if
+/// any.
+pub fn read_puzzles(path: &str, opts: &Options) -> Result<Vec<Solver>, String>
+{
+    let contents = ::std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let builder = Solver::builder().options(opts.clone()).threads(opts.threads);
+    let mut solvers = vec![];
+    for (idx, line) in contents.lines().enumerate()
+    {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#')
+        {
+            continue;
+        }
+
+        let solver = if line.starts_with('{')
+            {
+                parse_json(line, builder.clone())
+            }
+            else
+            {
+                parse_words(line, builder.clone())
+            };
+        solvers.push(solver.map_err(|msg| format!("line {}: {}", idx + 1, msg))?);
+    }
+    Ok(solvers)
+}
+
+/// The columns of the puzzles in a CSV file, counting from 0
+#[derive(Clone, Debug, Default)]
+pub struct CsvColumns
+{
+    /// The columns with the input numbers, all but the last by default
+    pub numbers: Option<Vec<usize>>,
+    /// The column with the target, the last by default
+    pub target: Option<usize>
+}
+
+/// Parse a list of columns, counting from 1, like `1-6` or `1,3,5-7`, into
+/// the column indices counting from 0.
+pub fn parse_columns(s: &str) -> Option<Vec<usize>>
+{
+    let mut cols = vec![];
+    for part in s.split(',')
+    {
+        let (first, last) = part.split_once('-').unwrap_or((part, part));
+        let first = first.trim().parse::<usize>().ok().filter(|&col| col > 0)?;
+        let last = last.trim().parse::<usize>().ok().filter(|&col| col >= first)?;
+        cols.extend(first-1..last);
+    }
+    Some(cols)
+}
+
+/// Split a line of a CSV file into its fields.
+///
+/// Fields are separated by commas, and may be enclosed in double quotes, in
+/// which a double quote is written twice. Fields spanning multiple lines are
+/// not supported.
+fn split_csv(line: &str) -> Vec<String>
+{
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next()
+    {
+        match c
+        {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            },
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(::std::mem::take(&mut field)),
+            _ => field.push(c)
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Write `fields` as a line of a CSV file to `out`, quoting the fields that
+/// need it.
+fn write_csv_line(out: &mut dyn Write, fields: &[String]) -> ::std::io::Result<()>
+{
+    let fields = fields.iter().map(|field| {
+            if field.contains([',', '"'])
+            {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            }
+            else
+            {
+                field.clone()
+            }
+        }).collect::<Vec<_>>();
+    writeln!(out, "{}", fields.join(","))
+}
+
+/// The rows of a CSV file of puzzles
+#[derive(Debug)]
+pub struct CsvRows
+{
+    /// The header row, if the file has one
+    header: Option<Vec<String>>,
+    /// The rows with the puzzles
+    rows: Vec<Vec<String>>
+}
+
+/// Read the puzzles in the CSV file at `path`, in columns `cols`, with the
+/// search options in `opts`, including the number of threads. Returns the
+/// rows of the file, and the solvers for the puzzles in them.
+///
+/// The first row is taken to be a header if its target column is not a
+/// number. Empty cells in the number columns are skipped, so that puzzles
+/// with fewer numbers can be stored in the same file. Returns a message with
+/// the line number of the first invalid row, if any.
+pub fn read_csv(path: &str, cols: &CsvColumns, opts: &Options)
+    -> Result<(CsvRows, Vec<Solver>), String>
+{
+    let contents = ::std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let builder = Solver::builder().options(opts.clone()).threads(opts.threads);
+    let mut puzzles = CsvRows { header: None, rows: vec![] };
+    let mut solvers = vec![];
+    for (idx, line) in contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty())
+    {
+        let row = split_csv(line);
+        let target_col = cols.target.unwrap_or(row.len() - 1);
+        let target = row.get(target_col).map(|s| s.trim())
+            .ok_or_else(|| format!("line {}: no column {}", idx + 1, target_col + 1))?;
+        if puzzles.rows.is_empty() && puzzles.header.is_none() && parse_target(target).is_none()
+        {
+            puzzles.header = Some(row);
+            continue;
+        }
+
+        let target = parse_target(target)
+            .ok_or_else(|| format!("line {}: invalid target {}", idx + 1, target))?;
+        let default_cols = (0..target_col).collect::<Vec<_>>();
+        let mut nrs = vec![];
+        for &col in cols.numbers.as_ref().unwrap_or(&default_cols).iter()
+        {
+            let cell = row.get(col).map_or("", |s| s.trim());
+            if !cell.is_empty()
+            {
+                nrs.push(parse_number(cell)
+                    .ok_or_else(|| format!("line {}: invalid number {}", idx + 1, cell))?);
+            }
+        }
+        let solver = builder.clone().numbers(&nrs).target(target).build()
+            .map_err(|err| format!("line {}: {}", idx + 1, err))?;
+        puzzles.rows.push(row);
+        solvers.push(solver);
+    }
+    Ok((puzzles, solvers))
+}
+
+/// Write the header row of the CSV file `puzzles` to `out`, if it has one,
+/// with the names of the result columns `expression`, `value` and `error`
+/// appended.
+pub fn write_csv_header(out: &mut dyn Write, puzzles: &CsvRows) -> ::std::io::Result<()>
+{
+    match puzzles.header.as_ref()
+    {
+        Some(header) => {
+            let mut fields = header.clone();
+            fields.extend(["expression", "value", "error"].iter().map(|s| s.to_string()));
+            write_csv_line(out, &fields)
+        },
+        None => Ok(())
+    }
+}
+
+/// Write row `idx` of the CSV file `puzzles` to `out`, with the result `expr`
+/// of its puzzle `solver` appended in columns `expression`, `value` and
+/// `error`, the distance of the value to the target.
+pub fn write_csv_row(out: &mut dyn Write, puzzles: &CsvRows, idx: usize, solver: &Solver,
+    expr: &Expr) -> ::std::io::Result<()>
+{
+    let mut fields = puzzles.rows[idx].clone();
+    if expr.is_empty()
+    {
+        fields.extend([String::new(), String::new(), String::new()]);
+    }
+    else
+    {
+        fields.push(expr.to_string_with(solver.numbers(), solver.options()));
+        fields.push(expr.val.to_string());
+        fields.push(solver.target().distance(expr.val).to_string());
+    }
+    write_csv_line(out, &fields)
+}
+
+/// Solve the puzzles of `solvers`, and pass their results to `emit`.
+///
+/// If `jobs` is greater than one, as many puzzles are solved concurrently,
+/// otherwise they are solved one after the other. The results are passed to
+/// `emit` together with the index of their puzzle, in the order of the
+/// puzzles, every result as soon as the puzzles before it are solved as well.
+/// Thus the results of a large file can be written while the remaining
+/// puzzles are still being solved. No more puzzles are started after `emit`
+/// returns an error, which is then returned.
+pub fn solve_puzzles<F>(solvers: &[Solver], jobs: usize, mut emit: F) -> ::std::io::Result<()>
+where F: FnMut(usize, &Expr) -> ::std::io::Result<()>
+{
+    if jobs <= 1
+    {
+        return solvers.iter().enumerate().try_for_each(|(idx, solver)| emit(idx, &solver.solve()));
+    }
+
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    ::std::thread::scope(|scope| {
+            for _ in 0..jobs.min(solvers.len())
+            {
+                let sender = sender.clone();
+                let next = &next;
+                scope.spawn(move || {
+                        loop
+                        {
+                            let idx = next.fetch_add(1, Ordering::Relaxed);
+                            let expr = match solvers.get(idx)
+                                {
+                                    Some(solver) => solver.solve(),
+                                    None => break
+                                };
+                            if sender.send((idx, expr)).is_err()
+                            {
+                                break;
+                            }
+                        }
+                    });
+            }
+            drop(sender);
+
+            // Results that arrive before those of earlier puzzles wait here
+            // until it is their turn
+            let mut pending = BTreeMap::new();
+            let mut res = Ok(());
+            let mut done = 0;
+            for (idx, expr) in receiver
+            {
+                pending.insert(idx, expr);
+                while let Some(expr) = pending.remove(&done)
+                {
+                    if res.is_ok()
+                    {
+                        res = emit(done, &expr);
+                        if res.is_err()
+                        {
+                            next.store(solvers.len(), Ordering::Relaxed);
+                        }
+                    }
+                    done += 1;
+                }
+            }
+            res
+        })
+}
+
+/// Write the result `expr` of the puzzle of `solver` to `out`, on a line of
+/// its own, in format `format` with style `style`.
+///
+/// In the plain format, the line starts with the numbers and the target of
+/// the puzzle. In the Markdown format, the result takes several lines, below
+/// a heading with the numbers and the target, and is followed by an empty
+/// line. In the other formats, a puzzle without result gives an empty line.
+pub fn write_result(out: &mut dyn Write, solver: &Solver, expr: &Expr, format: Format,
+    style: Style) -> ::std::io::Result<()>
+{
+    let nrs = solver.numbers();
+    let puzzle = nrs.iter().map(|nr| nr.to_string()).collect::<Vec<_>>().join(" ");
+    if format == Format::Markdown
+    {
+        writeln!(out, "### {} -> {}\n", puzzle, solver.target())?;
+        if expr.is_empty()
+        {
+            writeln!(out, "No expression satisfies the constraints.\n")
+        }
+        else
+        {
+            writeln!(out, "{}\n", format_markdown(expr, nrs, solver.target(), solver.options()))
+        }
+    }
+    else if format == Format::Plain
+    {
+        if expr.is_empty()
+        {
+            writeln!(out, "{} -> {}: no expression", puzzle, solver.target())
+        }
+        else
+        {
+            writeln!(out, "{} -> {}: {}", puzzle, solver.target(),
+                format_expr_with(expr, nrs, solver.options(), format, style))
+        }
+    }
+    else if expr.is_empty()
+    {
+        writeln!(out)
+    }
+    else
+    {
+        writeln!(out, "{}", format_expr_with(expr, nrs, solver.options(), format, style))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use makeexpr::Rat;
+
+    use super::*;
+
+    #[test]
+    fn puzzle_lines()
+    {
+        let line = "100 75 50 25 6 3 --target 900..=999 --integers-only --max-ops 4";
+        let solver = parse_words(line, Solver::builder()).unwrap();
+        assert_eq!(solver.numbers(), &[100, 75, 50, 25, 6, 3]);
+        assert_eq!(solver.target(), &parse_target("900..=999").unwrap());
+        assert!(solver.options().integers_only);
+        assert_eq!(solver.options().max_ops, Some(4));
+
+        let line = r#"{"numbers":[1,3,4,6],"target":24,"subset":true}"#;
+        let solver = parse_json(line, Solver::builder()).unwrap();
+        assert_eq!(solver.numbers(), &[1, 3, 4, 6]);
+        assert_eq!(solver.target(), &Target::new(24));
+        assert!(solver.options().subset);
+        assert!(!solver.options().integers_only);
+
+        let solver = parse_words("1 3 4 6 24", Solver::builder().subset(true)).unwrap();
+        assert_eq!(solver.numbers(), &[1, 3, 4, 6]);
+        assert_eq!(solver.target(), &Target::new(24));
+        assert!(solver.options().subset);
+
+        // Options left out of a JSON puzzle are taken from the defaults
+        let line = r#"{"numbers":[1,3,4,6],"target":24}"#;
+        let solver = parse_json(line, Solver::builder().integers_only(true)).unwrap();
+        assert!(solver.options().integers_only);
+        assert_ne!(solver.solve().val, Rat::from(24));
+        let solver = parse_json(line, Solver::builder().subset(true)).unwrap();
+        assert!(solver.options().subset);
+        let line = r#"{"numbers":[1,3,4,6],"target":24,"integers_only":false}"#;
+        let solver = parse_json(line, Solver::builder().integers_only(true)).unwrap();
+        assert!(!solver.options().integers_only);
+
+        assert!(parse_words("", Solver::builder()).is_err());
+        assert!(parse_words("1 2 3 --max-ops", Solver::builder()).is_err());
+        assert!(parse_words("1 2 x", Solver::builder()).is_err());
+        assert!(parse_json(r#"{"numbers":[1,2],"target":3,"depth":2}"#, Solver::builder())
+            .is_err());
+    }
+
+    #[test]
+    fn read_puzzle_file()
+    {
+        let path = ::std::env::temp_dir().join(format!("makeexpr-puzzles-{}.txt",
+            ::std::process::id()));
+        let path = path.to_str().unwrap();
+        ::std::fs::write(path, "# Two puzzles\n\n1 3 4 6 24\n{\"numbers\":[2,5],\"target\":10}\n")
+            .unwrap();
+        let opts = Options { threads: 1, ..Options::default() };
+        let solvers = read_puzzles(path, &opts).unwrap();
+        assert_eq!(solvers.len(), 2);
+        assert_eq!(solvers[1].numbers(), &[2, 5]);
+
+        ::std::fs::write(path, "1 3 4 6 24\n\n--subset\n").unwrap();
+        assert_eq!(read_puzzles(path, &opts).err().as_deref(),
+            Some("line 3: missing target"));
+        ::std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn csv_columns_and_fields()
+    {
+        assert_eq!(parse_columns("1-3,5"), Some(vec![0, 1, 2, 4]));
+        assert_eq!(parse_columns("2"), Some(vec![1]));
+        for s in ["0", "3-2", "1,", "a-b"]
+        {
+            assert_eq!(parse_columns(s), None, "columns {}", s);
+        }
+
+        let fields = split_csv(r#"1,"a, b","say ""hi""",,x"#);
+        assert_eq!(fields, ["1", "a, b", r#"say "hi""#, "", "x"]);
+        let mut out = vec![];
+        write_csv_line(&mut out, &fields).unwrap();
+        let line = String::from_utf8(out).unwrap();
+        assert_eq!(split_csv(line.trim_end()), fields);
+    }
+
+    #[test]
+    fn read_and_write_csv()
+    {
+        let path = ::std::env::temp_dir().join(format!("makeexpr-puzzles-{}.csv",
+            ::std::process::id()));
+        let path = path.to_str().unwrap();
+        ::std::fs::write(path, "a,b,c,target\n2,5,,10\n1,3,4,8\n").unwrap();
+        let opts = Options { threads: 1, ..Options::default() };
+        let (puzzles, solvers) = read_csv(path, &CsvColumns::default(), &opts).unwrap();
+        assert_eq!(solvers.len(), 2);
+        assert_eq!(solvers[0].numbers(), &[2, 5]);
+        assert_eq!(solvers[1].target(), &Target::new(8));
+
+        let mut out = vec![];
+        write_csv_header(&mut out, &puzzles).unwrap();
+        for (idx, solver) in solvers.iter().enumerate()
+        {
+            write_csv_row(&mut out, &puzzles, idx, solver, &solver.solve()).unwrap();
+        }
+        let lines = String::from_utf8(out).unwrap().lines().map(split_csv).collect::<Vec<_>>();
+        assert_eq!(lines[0], ["a", "b", "c", "target", "expression", "value", "error"]);
+        assert_eq!(lines[1][4..], ["5*2", "10", "0"]);
+        assert_eq!(lines[2][5..], ["8", "0"]);
+
+        let cols = CsvColumns { numbers: Some(vec![0, 1]), target: Some(3) };
+        let (_, solvers) = read_csv(path, &cols, &opts).unwrap();
+        assert_eq!(solvers[1].numbers(), &[1, 3]);
+
+        ::std::fs::write(path, "2,5,x,10\n").unwrap();
+        assert_eq!(read_csv(path, &CsvColumns::default(), &opts).err().as_deref(),
+            Some("line 1: invalid number x"));
+        ::std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn results_in_order()
+    {
+        let puzzles = ["1 3 4 6 24", "25 50 75 100 3 6 952", "2 5 10", "1 2 3 4 5 6 7 999"];
+        let solvers = puzzles.iter()
+            .map(|line| parse_words(line, Solver::builder()).unwrap())
+            .collect::<Vec<_>>();
+        let solve = |jobs| {
+                let mut results = vec![];
+                solve_puzzles(&solvers, jobs, |idx, expr| {
+                        results.push((idx, expr.val));
+                        Ok(())
+                    }).unwrap();
+                results
+            };
+        let results = solve(1);
+        assert_eq!(results.iter().map(|&(idx, _)| idx).collect::<Vec<_>>(), [0, 1, 2, 3]);
+        assert_eq!(solve(3), results);
+
+        let mut count = 0;
+        let res = solve_puzzles(&solvers, 2, |_, _| {
+                count += 1;
+                Err(::std::io::Error::new(::std::io::ErrorKind::WriteZero, "full"))
+            });
+        assert!(res.is_err());
+        assert_eq!(count, 1);
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+use crate::TargetReach;
+
+/// Magic bytes at the start of a bitset file
+const MAGIC: &[u8; 8] = b"MXBITS01";
+/// The size of the header, in bytes
+const HEADER_SIZE: usize = 24;
+
+/// Set of exactly reachable targets
+///
+/// Struct `ReachableSet` is a compact bitset of the integer targets in a range
+/// that can be hit exactly, for external tools that only need to test whether
+/// a target can be reached. In a file, the set is stored as the magic bytes
+/// `MXBITS01`, followed by the lowest and highest target in the range as
+/// 64-bit little endian integers, and the bits, where bit `i % 8` (counting
+/// from the least significant bit) of byte `i / 8` is set if target `min + i`
+/// can be reached.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReachableSet
+{
+    /// The lowest target in the range
+    min: u64,
+    /// The highest target in the range
+    max: u64,
+    /// The bits for the targets in the range
+    bits: Vec<u8>
+}
+
+impl ReachableSet
+{
+    /// Create the set of the targets in `reach` that can be hit exactly. The
+    /// targets should form a consecutive range, as returned by
+    /// `reachability()`.
+    pub fn from_reach(reach: &[TargetReach]) -> Self
+    {
+        let min = reach.first().map_or(0, |r| r.target);
+        let max = reach.last().map_or(0, |r| r.target);
+        let mut bits = vec![0; reach.len().div_ceil(8)];
+        for (i, r) in reach.iter().enumerate()
+        {
+            if r.min_numbers.is_some()
+            {
+                bits[i / 8] |= 1 << (i % 8);
+            }
+        }
+        ReachableSet { min, max, bits }
+    }
+
+    /// The lowest and highest target in the range of this set.
+    pub fn range(&self) -> (u64, u64)
+    {
+        (self.min, self.max)
+    }
+
+    /// Check if `target` can be hit exactly. Targets outside the range of
+    /// this set are never reachable.
+    pub fn contains(&self, target: u64) -> bool
+    {
+        if target < self.min || target > self.max
+        {
+            return false;
+        }
+        let i = (target - self.min) as usize;
+        self.bits[i / 8] & (1 << (i % 8)) != 0
+    }
+
+    /// The number of targets in the set.
+    pub fn len(&self) -> usize
+    {
+        self.bits.iter().map(|b| b.count_ones() as usize).sum()
+    }
+
+    /// Check if no target in the range can be reached.
+    pub fn is_empty(&self) -> bool
+    {
+        self.bits.iter().all(|&b| b == 0)
+    }
+
+    /// Write this set to the file at `path`.
+    pub fn write(&self, path: &str) -> Result<()>
+    {
+        let mut buf = Vec::with_capacity(HEADER_SIZE + self.bits.len());
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&self.min.to_le_bytes());
+        buf.extend_from_slice(&self.max.to_le_bytes());
+        buf.extend_from_slice(&self.bits);
+        File::create(path)?.write_all(&buf)
+    }
+
+    /// Read a set from the file at `path`.
+    pub fn read(path: &str) -> Result<Self>
+    {
+        let mut buf = vec![];
+        File::open(path)?.read_to_end(&mut buf)?;
+        let invalid = || Error::new(ErrorKind::InvalidData, "not a valid bitset");
+        if buf.len() < HEADER_SIZE || &buf[..8] != MAGIC
+        {
+            return Err(invalid());
+        }
+
+        let min = u64::from_le_bytes(buf[8..16].try_into().unwrap());
+        let max = u64::from_le_bytes(buf[16..24].try_into().unwrap());
+        let len = max.checked_sub(min).and_then(|n| n.checked_add(1)).ok_or_else(invalid)?;
+        if (buf.len() - HEADER_SIZE) as u64 != len.div_ceil(8)
+        {
+            return Err(invalid());
+        }
+        Ok(ReachableSet { min, max, bits: buf.split_off(HEADER_SIZE) })
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{is_reachable, reachability, Options, Target};
+
+    #[test]
+    fn set_matches_reachability()
+    {
+        let nrs = [2, 3, 7];
+        let opts = Options { subset: true, ..Options::default() };
+        let set = ReachableSet::from_reach(&reachability(&nrs, 1, 50, &opts));
+        assert_eq!(set.range(), (1, 50));
+        for target in 0..=51
+        {
+            let reachable = (1..=50).contains(&target)
+                && is_reachable(&nrs, &Target::new(target), &opts);
+            assert_eq!(set.contains(target), reachable, "{}", target);
+        }
+        assert_eq!(set.len(), (1..=50).filter(|&t| set.contains(t)).count());
+        assert!(!set.is_empty());
+    }
+
+    #[test]
+    fn write_and_read()
+    {
+        let path = ::std::env::temp_dir().join(format!("makeexpr-bitset-{}.bin",
+            ::std::process::id()));
+        let path = path.to_str().unwrap();
+        let set = ReachableSet::from_reach(&reachability(&[3, 5], 1, 20, &Options::default()));
+        set.write(path).unwrap();
+        assert_eq!(ReachableSet::read(path).unwrap(), set);
+        ::std::fs::write(path, b"MXBITS01 too short").unwrap();
+        assert_eq!(ReachableSet::read(path).unwrap_err().kind(), ErrorKind::InvalidData);
+        ::std::fs::remove_file(path).unwrap();
+    }
+}
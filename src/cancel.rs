@@ -0,0 +1,90 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Handle for cancelling a search
+///
+/// Struct `CancellationToken` wraps the flag through which a search is
+/// cancelled, as `Options::cancel`. Clones of a token share the flag, so that
+/// a clone can be handed to e.g. a user interface or a request timer, which
+/// cancels the search by calling `cancel()` from any thread. The search then
+/// stops as soon as possible, and returns the best expression found thus far.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken
+{
+    /// The flag that is set when the search is cancelled
+    flag: Arc<AtomicBool>
+}
+
+impl CancellationToken
+{
+    /// Create a new token, that has not been cancelled.
+    pub fn new() -> Self
+    {
+        CancellationToken::default()
+    }
+
+    /// Cancel the searches using this token.
+    pub fn cancel(&self)
+    {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    /// Check if this token has been cancelled.
+    pub fn is_cancelled(&self) -> bool
+    {
+        self.flag.load(Ordering::Relaxed)
+    }
+
+    /// The flag of this token, for use as `Options::cancel`.
+    pub fn flag(&self) -> Arc<AtomicBool>
+    {
+        self.flag.clone()
+    }
+}
+
+impl From<Arc<AtomicBool>> for CancellationToken
+{
+    fn from(flag: Arc<AtomicBool>) -> Self
+    {
+        CancellationToken { flag }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::Solver;
+
+    #[test]
+    fn cancel_search()
+    {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        let solver = Solver::builder().numbers(&[25, 50, 75, 100, 3, 6]).target(952).build()
+            .unwrap();
+        let stats = solver.solve_cancellable(&token);
+        assert!(!stats.cancelled && stats.steps > 0);
+        assert_eq!(stats.best.unwrap().distance, 0.into());
+
+        clone.cancel();
+        assert!(token.is_cancelled());
+        let stats = solver.solve_cancellable(&token);
+        assert!(stats.cancelled);
+    }
+}
@@ -0,0 +1,313 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::{format, vec};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+
+use crate::{Expr, Op, Options, Shape, ADD, DIV, MAX_NUMBERS, MUL, SUB};
+use crate::operator::unary_op_index;
+
+/// Term in the canonical form of an expression
+///
+/// A sum is stored as the lists of terms that are added and subtracted, and a
+/// product as the lists of factors that are multiplied and divided by. Since
+/// the order within the lists is irrelevant, nested sums and products are
+/// flattened, and the lists are sorted, expressions that only differ by
+/// commutativity or associativity map onto the same term.
+enum Term
+{
+    /// A single number
+    Number(u64),
+    /// A sum of terms, with the positive and negative terms
+    Sum(Vec<String>, Vec<String>),
+    /// A product of terms, with the factors in the numerator and denominator
+    Product(Vec<String>, Vec<String>),
+    /// A custom operation, by operation code, on its operands
+    Call(Op, Vec<String>)
+}
+
+impl Term
+{
+    /// The canonical string for this term.
+    fn render(self) -> String
+    {
+        match self
+        {
+            Term::Number(nr) => nr.to_string(),
+            Term::Sum(mut pos, mut neg) => {
+                pos.sort();
+                neg.sort();
+                format!("+({};{})", pos.join(","), neg.join(","))
+            },
+            Term::Product(mut pos, mut neg) => {
+                pos.sort();
+                neg.sort();
+                format!("*({};{})", pos.join(","), neg.join(","))
+            },
+            Term::Call(op, args) => format!("op{}({})", Op::MAX - op, args.join(","))
+        }
+    }
+
+    /// Split this term into its parts for a sum, or product if `product` is
+    /// true, as `(positive, negative)`.
+    fn into_parts(self, product: bool) -> (Vec<String>, Vec<String>)
+    {
+        match self
+        {
+            Term::Sum(pos, neg) if !product => (pos, neg),
+            Term::Product(pos, neg) if product => (pos, neg),
+            term => (vec![term.render()], vec![])
+        }
+    }
+}
+
+/// Compute the canonical form of an expression.
+///
+/// Create a string describing expression `expr` over the numbers array `nrs`,
+/// that is the same for all expressions that differ only in the order of the
+/// operands of additions and multiplications, or in the grouping of
+/// successive additions and subtractions, or multiplications and divisions.
+/// Custom operations are kept as they are. Expressions with the same
+/// canonical form are considered structurally equal.
+pub fn canonical_form(expr: &Expr, nrs: &[u64]) -> String
+{
+    let mut stack: Vec<Term> = vec![];
+    for &op in expr.ops.iter()
+    {
+        let term = match op
+            {
+                ADD | SUB | MUL | DIV => {
+                    let product = op == MUL || op == DIV;
+                    let (pos1, neg1) = stack.pop().unwrap().into_parts(product);
+                    let (mut pos, mut neg) = stack.pop().unwrap().into_parts(product);
+                    if op == ADD || op == MUL
+                    {
+                        pos.extend(pos1);
+                        neg.extend(neg1);
+                    }
+                    else
+                    {
+                        pos.extend(neg1);
+                        neg.extend(pos1);
+                    }
+                    if product { Term::Product(pos, neg) } else { Term::Sum(pos, neg) }
+                },
+                op if unary_op_index(op).is_some() => {
+                    let a = stack.pop().unwrap().render();
+                    Term::Call(op, vec![a])
+                },
+                op if (op as usize) >= MAX_NUMBERS => {
+                    let b = stack.pop().unwrap().render();
+                    let a = stack.pop().unwrap().render();
+                    Term::Call(op, vec![a, b])
+                },
+                idx => Term::Number(nrs[idx as usize])
+            };
+        stack.push(term);
+    }
+
+    stack.pop().map(Term::render).unwrap_or_default()
+}
+
+/// A sub-expression in canonical order, with its canonical form
+struct Part
+{
+    /// The sub-expression
+    expr: Expr,
+    /// The canonical form of the sub-expression
+    key: String
+}
+
+impl Part
+{
+    /// Evaluate the sub-expression in reverse polish notation `ops` over the
+    /// numbers `nrs`, subject to the options in `opts`.
+    fn new(ops: Vec<Op>, nrs: &[u64], opts: &Options) -> Option<Self>
+    {
+        let expr = Expr::from_ops(ops, nrs, opts)?;
+        let key = canonical_form(&expr, nrs);
+        Some(Part { expr, key })
+    }
+}
+
+/// A flattened sum or product of sub-expressions, or a single sub-expression
+enum Group
+{
+    /// A single sub-expression
+    Single(Part),
+    /// A sum, with the terms that are added and subtracted
+    Sum(Vec<Part>, Vec<Part>),
+    /// A product, with the factors that are multiplied and divided by
+    Product(Vec<Part>, Vec<Part>)
+}
+
+impl Group
+{
+    /// Split this group into its parts for a sum, or product if `product` is
+    /// true, as `(positive, negative)`.
+    fn into_parts(self, product: bool, nrs: &[u64], opts: &Options)
+        -> Option<(Vec<Part>, Vec<Part>)>
+    {
+        match self
+        {
+            Group::Sum(pos, neg) if !product => Some((pos, neg)),
+            Group::Product(pos, neg) if product => Some((pos, neg)),
+            group => Some((vec![group.finish(nrs, opts)?], vec![]))
+        }
+    }
+
+    /// Build the sub-expression for this group.
+    ///
+    /// The positive and negative parts are each sorted by decreasing value,
+    /// and by canonical form for equal values. The positive parts are combined
+    /// first, and the negative parts are then subtracted or divided by one by
+    /// one, so that every intermediate result is valid if the group is.
+    fn finish(self, nrs: &[u64], opts: &Options) -> Option<Part>
+    {
+        let (mut pos, mut neg, op, inv) = match self
+            {
+                Group::Single(part) => return Some(part),
+                Group::Sum(pos, neg) => (pos, neg, ADD, SUB),
+                Group::Product(pos, neg) => (pos, neg, MUL, DIV)
+            };
+        pos.sort_by(|p0, p1| (Reverse(p0.expr.val), &p0.key).cmp(&(Reverse(p1.expr.val), &p1.key)));
+        neg.sort_by(|p0, p1| (Reverse(p0.expr.val), &p0.key).cmp(&(Reverse(p1.expr.val), &p1.key)));
+
+        let mut ops = vec![];
+        for (i, part) in pos.into_iter().enumerate()
+        {
+            ops.extend(part.expr.ops);
+            if i > 0
+            {
+                ops.push(op);
+            }
+        }
+        for part in neg.into_iter()
+        {
+            ops.extend(part.expr.ops);
+            ops.push(inv);
+        }
+        Part::new(ops, nrs, opts)
+    }
+}
+
+/// Rewrite an expression into canonical order.
+///
+/// Rewrite expression `expr` over the numbers array `nrs` into a fixed form,
+/// that is the same for all expressions with the same canonical form, as
+/// returned by `canonical_form()`. Successive additions and subtractions, and
+/// multiplications and divisions, are flattened, their operands are sorted by
+/// decreasing value, and the terms that are subtracted, or the factors that
+/// are divided by, come last, e.g. `3+(4*25-6)` becomes `25*4+3-6`. Operands
+/// of custom operations keep their order. Since the rewrite changes the shape
+/// of the expression tree, the expression is returned unchanged if
+/// `opts.shape` constrains the shape, or if the rewritten expression is not
+/// valid subject to `opts`, e.g. because it has a forbidden intermediate value.
+pub fn canonical_expr(expr: &Expr, nrs: &[u64], opts: &Options) -> Expr
+{
+    if expr.is_empty() || opts.shape != Shape::Any
+    {
+        return expr.clone();
+    }
+    canonical_parts(expr, nrs, opts)
+        .and_then(|part| Expr::from_ops(part.expr.ops, nrs, opts))
+        .unwrap_or_else(|| expr.clone())
+}
+
+/// Build the canonically ordered expression for `expr`, as in
+/// `canonical_expr()`, or `None` if one of its parts is invalid.
+fn canonical_parts(expr: &Expr, nrs: &[u64], opts: &Options) -> Option<Part>
+{
+    let mut stack: Vec<Group> = vec![];
+    for &op in expr.ops.iter()
+    {
+        let group = match op
+            {
+                ADD | SUB | MUL | DIV => {
+                    let product = op == MUL || op == DIV;
+                    let (pos1, neg1) = stack.pop()?.into_parts(product, nrs, opts)?;
+                    let (mut pos, mut neg) = stack.pop()?.into_parts(product, nrs, opts)?;
+                    if op == ADD || op == MUL
+                    {
+                        pos.extend(pos1);
+                        neg.extend(neg1);
+                    }
+                    else
+                    {
+                        pos.extend(neg1);
+                        neg.extend(pos1);
+                    }
+                    if product { Group::Product(pos, neg) } else { Group::Sum(pos, neg) }
+                },
+                op if (op as usize) >= MAX_NUMBERS => {
+                    let arity = if unary_op_index(op).is_some() { 1 } else { 2 };
+                    let mut ops = vec![];
+                    for group in stack.split_off(stack.len().checked_sub(arity)?)
+                    {
+                        ops.extend(group.finish(nrs, opts)?.expr.ops);
+                    }
+                    ops.push(op);
+                    Group::Single(Part::new(ops, nrs, opts)?)
+                },
+                idx => Group::Single(Part::new(vec![idx], nrs, opts)?)
+            };
+        stack.push(group);
+    }
+
+    stack.pop()?.finish(nrs, opts)
+}
+
+/// Hash the canonical form of an expression.
+///
+/// Compute a 64-bit hash of the canonical form of `expr` over `nrs`, as
+/// returned by `canonical_form()`, with the hasher of `FastHash`.
+#[cfg(feature = "std")]
+pub fn canonical_hash(expr: &Expr, nrs: &[u64]) -> u64
+{
+    use core::hash::{BuildHasher, Hasher};
+
+    let mut hasher = crate::FastHash.build_hasher();
+    hasher.write(canonical_form(expr, nrs).as_bytes());
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn canonical_form_ignores_order()
+    {
+        let nrs = [3, 4, 6, 25];
+        let form = |s| canonical_form(&parse(s, &nrs).unwrap(), &nrs);
+        assert_eq!(form("3+(4*25-6)"), form("25*4+3-6"));
+        assert_eq!(form("(3+6)*4"), form("4*(6+3)"));
+        assert_ne!(form("3+6*4"), form("(3+6)*4"));
+        assert_ne!(form("25-4"), form("4+25"));
+    }
+
+    #[test]
+    fn canonical_expr_sorts_operands()
+    {
+        let nrs = [3, 4, 6, 25];
+        let opts = Options::default();
+        let expr = canonical_expr(&parse("3+(4*25-6)", &nrs).unwrap(), &nrs, &opts);
+        assert_eq!(expr.to_string(&nrs), "25*4+3-6");
+        assert_eq!(expr.val, 97.into());
+    }
+}
@@ -0,0 +1,212 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::{Observer, Options, Progress, Rat, Solution};
+
+/// Update on the progress of a search
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProgressUpdate
+{
+    /// The value of the best expression found thus far, if any
+    pub best: Option<Rat>,
+    /// The distance of the best expression found thus far to the target, if
+    /// any
+    pub error: Option<Rat>,
+    /// The number of steps of the search completed
+    pub done: usize,
+    /// The total number of steps of the search
+    pub total: usize,
+    /// The estimated time until the search is completed, once a step has been
+    /// completed
+    pub eta: Option<Duration>,
+    /// Whether this is the last update, sent when the search finishes or is
+    /// cancelled
+    pub finished: bool
+}
+
+/// The state of a search, as tracked by a `ProgressChannel`
+struct ChannelState
+{
+    /// The update as it currently stands
+    update: ProgressUpdate,
+    /// The time the search started
+    start: Instant,
+    /// The time the last update was sent, if any
+    last_sent: Option<Instant>
+}
+
+/// Channel of throttled progress updates
+///
+/// Struct `ProgressChannel` observes a search, both as a `Progress` and as an
+/// `Observer`, and sends a `ProgressUpdate` over an mpsc channel when the
+/// search makes progress, but at most once per interval, so that e.g. a
+/// graphical or web frontend can follow the search without being flooded,
+/// and without the search printing anything. A final update is always sent
+/// when the search finishes. Sending never blocks the search, and once the
+/// receiver is dropped, the updates are simply discarded.
+pub struct ProgressChannel
+{
+    /// The sending end of the channel
+    sender: Sender<ProgressUpdate>,
+    /// The minimum time between updates
+    interval: Duration,
+    /// The state of the search
+    state: Mutex<ChannelState>
+}
+
+impl ::core::fmt::Debug for ProgressChannel
+{
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result
+    {
+        f.debug_struct("ProgressChannel").field("interval", &self.interval).finish()
+    }
+}
+
+impl ProgressChannel
+{
+    /// Create a channel sending updates at most once per `interval`, and
+    /// return it with the receiving end.
+    pub fn new(interval: Duration) -> (Arc<Self>, Receiver<ProgressUpdate>)
+    {
+        let (sender, receiver) = channel();
+        let update = ProgressUpdate {
+                best: None,
+                error: None,
+                done: 0,
+                total: 0,
+                eta: None,
+                finished: false
+            };
+        let state = ChannelState { update, start: Instant::now(), last_sent: None };
+        let progress = ProgressChannel {
+                sender,
+                interval,
+                state: Mutex::new(state)
+            };
+        (Arc::new(progress), receiver)
+    }
+
+    /// Register this channel in the search options `opts`, as both the
+    /// progress observer and the event observer, replacing those set before.
+    pub fn register(self: &Arc<Self>, opts: &mut Options)
+    {
+        opts.progress = Some(self.clone());
+        opts.observer = Some(self.clone());
+    }
+
+    /// Send the current update, if the interval since the last update has
+    /// passed, or if `force` is true.
+    fn send(&self, state: &mut ChannelState, force: bool)
+    {
+        let now = Instant::now();
+        if force || state.last_sent.is_none_or(|last| now.duration_since(last) >= self.interval)
+        {
+            state.last_sent = Some(now);
+            // The receiver may have been dropped, in which case the update
+            // is discarded
+            let _ = self.sender.send(state.update.clone());
+        }
+    }
+}
+
+impl Progress for ProgressChannel
+{
+    fn start(&self, total: usize)
+    {
+        let mut state = self.state.lock().unwrap();
+        state.start = Instant::now();
+        state.update.total = total;
+        state.update.done = 0;
+        self.send(&mut state, false);
+    }
+
+    fn step(&self, best_distance: Option<Rat>)
+    {
+        let mut state = self.state.lock().unwrap();
+        state.update.done += 1;
+        state.update.error = best_distance.or(state.update.error);
+        let (done, total) = (state.update.done, state.update.total);
+        let remaining = total.saturating_sub(done) as f64 / done as f64;
+        state.update.eta = Some(state.start.elapsed().mul_f64(remaining));
+        self.send(&mut state, false);
+    }
+
+    fn finish(&self)
+    {
+        let mut state = self.state.lock().unwrap();
+        state.update.eta = Some(Duration::ZERO);
+        state.update.finished = true;
+        self.send(&mut state, true);
+    }
+}
+
+impl Observer for ProgressChannel
+{
+    fn on_new_best(&self, solution: &Solution)
+    {
+        let mut state = self.state.lock().unwrap();
+        state.update.best = Some(solution.expr.val);
+        state.update.error = Some(solution.distance);
+        self.send(&mut state, false);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{get_nearest_expression, Target};
+
+    #[test]
+    fn updates_of_search()
+    {
+        // A target that cannot be reached, so that no partition is skipped
+        let target = Target::new(1000);
+        for interval in [Duration::ZERO, Duration::from_secs(3600)]
+        {
+            let (channel, receiver) = ProgressChannel::new(interval);
+            let mut opts = Options::default();
+            channel.register(&mut opts);
+            let expr = get_nearest_expression(&[1, 3, 4, 6], &target, &opts);
+            drop(opts);
+            drop(channel);
+
+            let updates = receiver.iter().collect::<Vec<_>>();
+            if interval > Duration::ZERO
+            {
+                // Only the first update and the final update are sent
+                assert_eq!(updates.len(), 2);
+            }
+            assert!(updates.windows(2).all(|pair| pair[0].done <= pair[1].done));
+            assert!(updates.iter().rev().skip(1).all(|update| !update.finished));
+            let last = updates.last().unwrap();
+            assert!(last.finished);
+            assert_eq!(last.done, last.total);
+            assert_eq!(last.best, Some(expr.val));
+            assert_eq!(last.error, Some(target.distance(expr.val)));
+        }
+
+        // Updates are discarded once the receiver is dropped
+        let (channel, receiver) = ProgressChannel::new(Duration::ZERO);
+        drop(receiver);
+        let mut opts = Options::default();
+        channel.register(&mut opts);
+        get_nearest_expression(&[1, 3, 4, 6], &target, &opts);
+    }
+}
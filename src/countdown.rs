@@ -0,0 +1,355 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+use num_traits::Zero;
+
+use crate::{get_nearest_expression, Cache, Expr, Options, Rat, Shape, Target, COUNTDOWN_LARGE,
+    COUNTDOWN_SMALL, COUNTDOWN_TILES};
+use crate::partition::{sub_multisets, unique_indices};
+use crate::search::{cached, expressions};
+use crate::small::exact_expression_small;
+
+/// The lowest target in a Countdown table
+pub const COUNTDOWN_MIN_TARGET: u64 = 101;
+/// The highest target in a Countdown table
+pub const COUNTDOWN_MAX_TARGET: u64 = 999;
+
+/// Magic bytes at the start of a Countdown table file
+const MAGIC: &[u8; 8] = b"MXCDOWN1";
+/// The size of the header, in bytes
+const HEADER_SIZE: usize = 16;
+/// The number of bytes in the set of reachable targets of a single selection
+const SET_SIZE: usize = ((COUNTDOWN_MAX_TARGET - COUNTDOWN_MIN_TARGET + 1) as usize).div_ceil(8);
+/// The largest number of tiles in a set whose values are kept in the cache
+/// while generating a table. Larger sets are shared by few selections.
+const MAX_CACHED_TILES: usize = 4;
+
+/// Extend the selection of tiles in `cur` with tiles from `tiles`, which should
+/// be sorted, from position `start` on, and add the complete selections to
+/// `res`. Equal tiles are skipped, so that every multiset is added once.
+fn extend_selection(tiles: &[u64], start: usize, cur: &mut Vec<u64>,
+    res: &mut Vec<[u64; COUNTDOWN_TILES]>)
+{
+    if cur.len() == COUNTDOWN_TILES
+    {
+        res.push(cur[..].try_into().unwrap());
+        return;
+    }
+    for i in start..tiles.len()
+    {
+        if i == start || tiles[i] != tiles[i-1]
+        {
+            cur.push(tiles[i]);
+            extend_selection(tiles, i + 1, cur, res);
+            cur.pop();
+        }
+    }
+}
+
+/// All distinct selections of Countdown tiles.
+///
+/// Return every distinct multiset of `COUNTDOWN_TILES` tiles that can be drawn
+/// from `COUNTDOWN_LARGE` and `COUNTDOWN_SMALL`, each sorted, in lexicographic
+/// order.
+pub fn countdown_selections() -> Vec<[u64; COUNTDOWN_TILES]>
+{
+    let mut tiles = COUNTDOWN_LARGE.iter().chain(COUNTDOWN_SMALL.iter()).cloned()
+        .collect::<Vec<_>>();
+    tiles.sort_unstable();
+
+    let mut res = vec![];
+    extend_selection(&tiles, 0, &mut vec![], &mut res);
+    res
+}
+
+/// The search options the Countdown table is generated with: the rules of the
+/// game only allow integer intermediate results, and not all tiles need to be
+/// used.
+fn table_options() -> Options
+{
+    Options { integers_only: true, subset: true, print_intermediate: false, ..Options::default() }
+}
+
+/// Precomputed table of the Countdown targets that can be reached
+///
+/// Struct `CountdownTable` holds, for every distinct selection of tiles in
+/// the Countdown numbers game, as returned by `countdown_selections()`, the
+/// set of targets from `COUNTDOWN_MIN_TARGET` to `COUNTDOWN_MAX_TARGET` that
+/// can be hit exactly under the rules of the game. A query for the numbers of
+/// a game then finds the nearest reachable target in the table, and only
+/// searches for an expression for that target, which is found much faster
+/// than the nearest expression when the target cannot be reached.
+///
+/// In a file, the table is stored as the magic bytes `MXCDOWN1`, followed by
+/// the number of selections as a 64-bit little endian integer, and the sets
+/// of reachable targets of the selections, in order, in `SET_SIZE` bytes
+/// each, where bit `i % 8` of byte `i / 8` is set if target
+/// `COUNTDOWN_MIN_TARGET + i` can be reached.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CountdownTable
+{
+    /// The selections of tiles
+    selections: Vec<[u64; COUNTDOWN_TILES]>,
+    /// The reachable targets of every selection
+    bits: Vec<u8>
+}
+
+impl CountdownTable
+{
+    /// Generate the table.
+    ///
+    /// Compute the reachable targets for every selection of tiles, dividing
+    /// the selections over `threads` threads. This takes a minute or two on a
+    /// single thread, so the table is best generated once, and stored with
+    /// `write()`.
+    pub fn generate(threads: usize) -> Self
+    {
+        let selections = countdown_selections();
+        let mut bits = vec![0; selections.len() * SET_SIZE];
+        let chunk = selections.len().div_ceil(threads.max(1));
+        ::std::thread::scope(|scope| {
+                for (sels, sets) in selections.chunks(chunk).zip(bits.chunks_mut(chunk * SET_SIZE))
+                {
+                    scope.spawn(move || {
+                            let mut cache = Cache::default();
+                            for (nrs, set) in sels.iter().zip(sets.chunks_mut(SET_SIZE))
+                            {
+                                reachable_targets(nrs, &mut cache, set);
+                            }
+                        });
+                }
+            });
+        CountdownTable { selections, bits }
+    }
+
+    /// Write this table to the file at `path`.
+    pub fn write(&self, path: &str) -> Result<()>
+    {
+        let mut buf = Vec::with_capacity(HEADER_SIZE + self.bits.len());
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&(self.selections.len() as u64).to_le_bytes());
+        buf.extend_from_slice(&self.bits);
+        File::create(path)?.write_all(&buf)
+    }
+
+    /// Read a table from the file at `path`.
+    pub fn read(path: &str) -> Result<Self>
+    {
+        let mut buf = vec![];
+        File::open(path)?.read_to_end(&mut buf)?;
+        let invalid = || Error::new(ErrorKind::InvalidData, "not a valid Countdown table");
+        if buf.len() < HEADER_SIZE || &buf[..8] != MAGIC
+        {
+            return Err(invalid());
+        }
+
+        let selections = countdown_selections();
+        let count = u64::from_le_bytes(buf[8..16].try_into().unwrap());
+        let size = HEADER_SIZE + selections.len() * SET_SIZE;
+        if count != selections.len() as u64 || buf.len() != size
+        {
+            return Err(invalid());
+        }
+        Ok(CountdownTable { selections, bits: buf.split_off(HEADER_SIZE) })
+    }
+
+    /// The number of selections of tiles in this table.
+    pub fn len(&self) -> usize
+    {
+        self.selections.len()
+    }
+
+    /// Check if this table contains no selections.
+    pub fn is_empty(&self) -> bool
+    {
+        self.selections.is_empty()
+    }
+
+    /// The set of reachable targets for the numbers `nrs`, in any order, or
+    /// `None` if they are not a selection of Countdown tiles.
+    fn set(&self, nrs: &[u64]) -> Option<&[u8]>
+    {
+        let mut sel = nrs.to_vec();
+        sel.sort_unstable();
+        let i = self.selections.binary_search_by(|s| s[..].cmp(&sel[..])).ok()?;
+        Some(&self.bits[i * SET_SIZE..(i + 1) * SET_SIZE])
+    }
+
+    /// Check if `target` can be hit exactly with the numbers `nrs`, in any
+    /// order. Returns `None` if `nrs` is not a selection of Countdown tiles,
+    /// or if `target` is outside the range of the table.
+    pub fn reachable(&self, nrs: &[u64], target: u64) -> Option<bool>
+    {
+        if !(COUNTDOWN_MIN_TARGET..=COUNTDOWN_MAX_TARGET).contains(&target)
+        {
+            return None;
+        }
+        let i = (target - COUNTDOWN_MIN_TARGET) as usize;
+        self.set(nrs).map(|set| set[i / 8] & (1 << (i % 8)) != 0)
+    }
+
+    /// Find the reachable value nearest to target.
+    ///
+    /// Look up the target in the table that can be reached with the numbers
+    /// `nrs` and is nearest to `target`. Returns `None` if `nrs` is not a
+    /// selection of Countdown tiles, or if a value outside the range of the
+    /// table might be nearer.
+    pub fn nearest_target(&self, nrs: &[u64], target: &Target) -> Option<u64>
+    {
+        let set = self.set(nrs)?;
+        let (lo, hi) = (COUNTDOWN_MIN_TARGET - 1, COUNTDOWN_MAX_TARGET + 1);
+        let mut best: Option<(Rat, u64)> = None;
+        for (i, t) in (COUNTDOWN_MIN_TARGET..=COUNTDOWN_MAX_TARGET).enumerate()
+        {
+            if set[i / 8] & (1 << (i % 8)) != 0
+            {
+                let diff = target.distance(Rat::from_integer(t));
+                if best.is_none_or(|(best_diff, _)| diff < best_diff)
+                {
+                    best = Some((diff, t));
+                }
+            }
+        }
+
+        // The values outside the range are at least as far from the target
+        // as the bounds of the range, unless the target extends beyond them
+        let max = target.intervals().iter().map(|&(_, max)| max).max().unwrap();
+        let below = if target.min() <= Rat::from_integer(lo) { Rat::zero() }
+            else { target.distance(Rat::from_integer(lo)) };
+        let above = if max >= Rat::from_integer(hi) { Rat::zero() }
+            else { target.distance(Rat::from_integer(hi)) };
+        best.filter(|&(diff, _)| diff <= below && diff <= above).map(|(_, t)| t)
+    }
+
+    /// Find the expression nearest to target.
+    ///
+    /// Find the expression over the numbers `nrs` nearest to `target`, using
+    /// the table to find the nearest reachable target, and a search for an
+    /// expression hitting it exactly, subject to the options in `opts`. The
+    /// search stops at the first such expression, which need not be the one
+    /// using the fewest numbers.
+    /// Returns `None` if the table cannot answer the query: if `nrs` is not a
+    /// selection of Countdown tiles, if the options are not the rules of the
+    /// game, i.e. `opts.integers_only` and `opts.subset` should be set, and no
+    /// other options constraining the expression, or if the nearest value may
+    /// lie outside the range of the table.
+    pub fn solve(&self, nrs: &[u64], target: &Target, opts: &Options) -> Option<Expr>
+    {
+        let supported = opts.integers_only && opts.subset && opts.max_ops.is_none()
+            && opts.shape == Shape::Any && opts.forbidden_values.is_empty()
+            && opts.binary_ops.is_empty() && opts.unary_ops.is_empty();
+        if !supported
+        {
+            return None;
+        }
+
+        let nearest = Target::new(self.nearest_target(nrs, target)?);
+        let opts = Options { print_intermediate: false, ..opts.clone() };
+        exact_expression_small(nrs, &unique_indices(nrs), &nearest, &opts)
+            .or_else(|| Some(get_nearest_expression(nrs, &nearest, &opts)))
+    }
+}
+
+/// Mark the targets that can be reached with the numbers `nrs` in `set`, using
+/// and filling the sub-expressions in `cache`.
+fn reachable_targets(nrs: &[u64], cache: &mut Cache, set: &mut [u8])
+{
+    let opts = table_options();
+    let lo = Rat::from_integer(COUNTDOWN_MIN_TARGET);
+    let hi = Rat::from_integer(COUNTDOWN_MAX_TARGET);
+    for sub in sub_multisets(&unique_indices(nrs))
+    {
+        let key = expressions(nrs, &sub, &opts, cache);
+        for expr in cached(cache, &key, &opts).iter()
+        {
+            if expr.val >= lo && expr.val <= hi
+            {
+                let i = (expr.val.to_integer() - COUNTDOWN_MIN_TARGET) as usize;
+                set[i / 8] |= 1 << (i % 8);
+            }
+        }
+    }
+
+    // The sets of many tiles are shared by few selections, so they are not
+    // worth keeping
+    cache.retain(|key, _| key.split('_').count() <= MAX_CACHED_TILES);
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::is_reachable;
+
+    /// A table for a few selections only
+    fn partial_table(selections: Vec<[u64; COUNTDOWN_TILES]>) -> CountdownTable
+    {
+        let mut bits = vec![0; selections.len() * SET_SIZE];
+        let mut cache = Cache::default();
+        for (nrs, set) in selections.iter().zip(bits.chunks_mut(SET_SIZE))
+        {
+            reachable_targets(nrs, &mut cache, set);
+        }
+        CountdownTable { selections, bits }
+    }
+
+    #[test]
+    fn selections_of_tiles()
+    {
+        let selections = countdown_selections();
+        assert_eq!(selections.len(), 13_243);
+        assert!(selections.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn table_matches_search()
+    {
+        let table = partial_table(vec![[1, 1, 2, 2, 3, 3], [1, 2, 3, 4, 5, 6],
+            [3, 6, 25, 50, 75, 100]]);
+        let opts = table_options();
+        for &(nrs, target) in [(&[6, 5, 4, 3, 2, 1], 587), (&[100, 75, 50, 25, 6, 3], 952)].iter()
+        {
+            let target = Target::new(target);
+            let reachable = table.reachable(nrs, target.min().to_integer()).unwrap();
+            assert_eq!(reachable, is_reachable(nrs, &target, &opts));
+            let expr = table.solve(nrs, &target, &opts).unwrap();
+            let nearest = get_nearest_expression(nrs, &target, &opts);
+            assert_eq!(target.distance(expr.val), target.distance(nearest.val));
+        }
+        assert_eq!(table.reachable(&[1, 1, 2, 2, 3, 4], 500), None);
+        assert_eq!(table.reachable(&[1, 1, 2, 2, 3, 3], 1000), None);
+        // None of the values is in the range of the table, so the nearest
+        // value is below it
+        assert_eq!(table.nearest_target(&[1, 1, 2, 2, 3, 3], &Target::new(101)), None);
+        assert!(table.solve(&[1, 1, 2, 2, 3, 3], &Target::new(200), &Options::default()).is_none());
+    }
+
+    #[test]
+    fn read_rejects_partial_table()
+    {
+        let path = ::std::env::temp_dir().join(format!("makeexpr-countdown-{}.bin",
+            ::std::process::id()));
+        let path = path.to_str().unwrap();
+        partial_table(vec![[1, 1, 2, 2, 3, 3]]).write(path).unwrap();
+        assert_eq!(CountdownTable::read(path).unwrap_err().kind(), ErrorKind::InvalidData);
+        ::std::fs::write(path, b"not a table").unwrap();
+        assert_eq!(CountdownTable::read(path).unwrap_err().kind(), ErrorKind::InvalidData);
+        ::std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use crate::{Cache, Expr, Idx, Options};
+use crate::partition::{sub_multisets, unique_indices};
+use crate::search::{allowed_partitions, cached, expressions};
+
+/// The largest subset size for which the values are computed in a dry run
+const SAMPLE_MAX_SIZE: usize = 5;
+
+/// Estimate of the size of a search
+#[derive(Clone, Debug)]
+pub struct SearchEstimate
+{
+    /// The number of partitions of the input numbers at the top level of
+    /// the search
+    pub partitions: usize,
+    /// The estimated number of distinct values for a subset of `k` numbers,
+    /// at index `k-1`, up to and including the full set of numbers
+    pub values: Vec<f64>,
+    /// The largest subset size for which the number of values was measured
+    /// rather than extrapolated
+    pub sampled_size: usize,
+    /// The projected memory used by the cache of sub-expressions, in bytes
+    pub memory: f64,
+    /// The projected time for the full search, in seconds
+    pub seconds: f64
+}
+
+/// The number of ways to choose `k` out of `n` distinct items.
+fn binomial(n: usize, k: usize) -> f64
+{
+    (0..k).fold(1.0, |acc, i| acc * (n - i) as f64 / (i + 1) as f64)
+}
+
+/// The number of pairs of sub-expressions combined when making all values
+/// for a set of `k` numbers, from the number of values `values[j-1]` for
+/// subsets of `j < k` numbers, treating the numbers as distinct.
+fn pair_count(k: usize, values: &[f64], opts: &Options) -> f64
+{
+    (1..=k/2).filter(|&b| opts.shape.allows_split(k - b, b)).map(|b| {
+            let splits = if 2 * b == k { binomial(k, b) / 2.0 } else { binomial(k, b) };
+            splits * values[k-b-1] * values[b-1]
+        }).sum()
+}
+
+/// Estimate the size of a search.
+///
+/// Without actually solving, estimate the number of distinct values for every
+/// subset size of the numbers in `nrs`, and the memory and time needed for a
+/// full search subject to the options in `opts`. The estimate is based on a
+/// short sampling pass, computing all values for the smallest numbers up to
+/// `SAMPLE_MAX_SIZE` at a time, and timing it. For larger subsets, the number
+/// of values is extrapolated from the number of pairs of sub-expressions that
+/// are combined, assuming the same fraction of distinct results as in the
+/// largest sampled subset. This is a rough estimate: the fraction of distinct
+/// results decreases as the sets of numbers grow, which makes it too high, but
+/// the smallest numbers, which are sampled, give fewer distinct values than
+/// large ones, which makes it too low.
+pub fn estimate_search(nrs: &[u64], opts: &Options) -> SearchEstimate
+{
+    let idxs = unique_indices(nrs);
+    let count = idxs.len();
+    let opts = Options { print_intermediate: false, progress: None, ..opts.clone() };
+    let partitions = if count < 2 { 0 } else { allowed_partitions(&idxs, &opts).len() };
+
+    // Sampling pass: compute all values for the first numbers
+    let sampled_size = count.saturating_sub(1).clamp(1, SAMPLE_MAX_SIZE).min(count);
+    let sample = &idxs[..sampled_size];
+    let mut cache = Cache::default();
+    let start = ::std::time::Instant::now();
+    expressions(nrs, sample, &opts, &mut cache);
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let mut totals = vec![(0.0, 0); sampled_size];
+    let mut pairs = 0.0;
+    for sub in sub_multisets(sample)
+    {
+        let key = expressions(nrs, &sub, &opts, &mut cache);
+        let entry = &mut totals[sub.len()-1];
+        entry.0 += cached(&cache, &key, &opts).len() as f64;
+        entry.1 += 1;
+        if sub.len() > 1
+        {
+            pairs += sampled_pairs(nrs, &sub, &opts, &mut cache);
+        }
+    }
+
+    let mut values: Vec<f64> = totals.iter().map(|&(sum, n)| sum / n as f64).collect();
+    let mut fraction = 1.0;
+    if sampled_size > 1
+    {
+        let raw = pair_count(sampled_size, &values, &opts);
+        if raw > 0.0
+        {
+            fraction = values[sampled_size-1] / raw;
+        }
+    }
+    for k in sampled_size+1..=count
+    {
+        let estimate = fraction * pair_count(k, &values, &opts);
+        values.push(estimate);
+    }
+
+    let mut memory = 0.0;
+    let mut total_pairs = 0.0;
+    let mut keys = vec![0usize; count];
+    for sub in sub_multisets(&idxs)
+    {
+        keys[sub.len()-1] += 1;
+    }
+    for k in 1..=count
+    {
+        if k < count
+        {
+            let bytes = ::std::mem::size_of::<Expr>() + 2 * k - 1;
+            memory += keys[k-1] as f64 * values[k-1] * bytes as f64;
+        }
+        if k > 1
+        {
+            total_pairs += keys[k-1] as f64 * pair_count(k, &values, &opts);
+        }
+    }
+    let seconds = if pairs > 0.0 { total_pairs * elapsed / pairs } else { elapsed };
+
+    SearchEstimate { partitions, values, sampled_size, memory, seconds }
+}
+
+/// The number of pairs of sub-expressions combined when making the values
+/// for the numbers indexed by `idxs`, whose subsets are all in `cache`.
+fn sampled_pairs(nrs: &[u64], idxs: &[Idx], opts: &Options, cache: &mut Cache) -> f64
+{
+    let mut pairs = 0.0;
+    for (idxs0, idxs1) in allowed_partitions(idxs, opts)
+    {
+        let key0 = expressions(nrs, &idxs0, opts, cache);
+        let key1 = expressions(nrs, &idxs1, opts, cache);
+        pairs += (cached(cache, &key0, opts).len() * cached(cache, &key1, opts).len()) as f64;
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::distinct_values;
+
+    #[test]
+    fn binomials()
+    {
+        assert_eq!(binomial(6, 0), 1.0);
+        assert_eq!(binomial(6, 2), 15.0);
+        assert_eq!(binomial(6, 3), 20.0);
+    }
+
+    #[test]
+    fn estimate_small_search()
+    {
+        let nrs = [1, 3, 4, 6, 25];
+        let opts = Options::default();
+        let estimate = estimate_search(&nrs, &opts);
+        assert_eq!(estimate.partitions, 15);
+        assert_eq!(estimate.sampled_size, 4);
+        assert_eq!(estimate.values.len(), nrs.len());
+        assert_eq!(estimate.values[0], 1.0);
+        // The sampled values are exact, the others a rough estimate
+        let idxs = unique_indices(&nrs);
+        let mut cache = Cache::default();
+        let sampled = distinct_values(&nrs, &idxs[..4], &opts, &mut cache).len();
+        assert_eq!(estimate.values[3], sampled as f64);
+        let all = distinct_values(&nrs, &idxs, &opts, &mut cache).len();
+        let ratio = estimate.values[4] / all as f64;
+        assert!(ratio > 0.5 && ratio < 2.0, "{} for {}", estimate.values[4], all);
+        assert!(estimate.memory > 0.0 && estimate.seconds >= 0.0);
+    }
+}
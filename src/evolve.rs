@@ -0,0 +1,228 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use alloc::vec::Vec;
+use num_traits::Zero;
+
+use crate::{Expr, Op, Options, Rat, Shape, Target, ADD, DIV, MAX_NUMBERS, MUL, SUB};
+use crate::operator::{binary_op_code, unary_op_index};
+use crate::partition::unique_indices;
+use crate::shuffle::SplitMix;
+use crate::strategy::random_expr;
+
+/// The number of expressions in the population of an evolutionary search
+const POPULATION_SIZE: usize = 100;
+
+/// The number of operands of operation `op` in an expression.
+fn arity(op: Op) -> usize
+{
+    if (op as usize) < MAX_NUMBERS
+    {
+        0
+    }
+    else if unary_op_index(op).is_some()
+    {
+        1
+    }
+    else
+    {
+        2
+    }
+}
+
+/// The position of the first operation of the sub-expression ending at
+/// position `end` in the reverse polish notation `ops`.
+fn subtree_start(ops: &[Op], end: usize) -> usize
+{
+    let mut need = 1;
+    let mut i = end + 1;
+    while need > 0
+    {
+        i -= 1;
+        need = need + arity(ops[i]) - 1;
+    }
+    i
+}
+
+/// Pick a random element of `items`.
+fn pick<T: Copy>(items: &[T], rng: &mut SplitMix) -> T
+{
+    items[(rng.next_u64() % items.len() as u64) as usize]
+}
+
+/// Mutate an expression.
+///
+/// Apply a random mutation to the reverse polish notation `ops`: replace a
+/// binary operation by a different one, swap two numbers, or, if the shape in
+/// `opts` does not constrain the expression tree, swap two disjoint
+/// sub-expressions. The result may not be a valid expression.
+fn mutate(ops: &mut Vec<Op>, opts: &Options, rng: &mut SplitMix)
+{
+    let mut bin_ops = Vec::from([ADD, SUB, MUL, DIV]);
+    bin_ops.extend((0..opts.binary_ops.len()).map(binary_op_code));
+    let positions = |pred: &dyn Fn(Op) -> bool| {
+            (0..ops.len()).filter(|&i| pred(ops[i])).collect::<Vec<_>>()
+        };
+    let ops_pos = positions(&|op| arity(op) == 2);
+    let leaf_pos = positions(&|op| arity(op) == 0);
+    if ops_pos.is_empty()
+    {
+        return;
+    }
+
+    match rng.next_u64() % 3
+    {
+        0 => {
+            let i = pick(&ops_pos, rng);
+            ops[i] = pick(&bin_ops, rng);
+        },
+        1 => {
+            let (i, j) = (pick(&leaf_pos, rng), pick(&leaf_pos, rng));
+            ops.swap(i, j);
+        },
+        _ if opts.shape == Shape::Any => {
+            let (p, q) = (rng.next_u64() as usize % ops.len(), rng.next_u64() as usize % ops.len());
+            let (p, q) = (p.min(q), p.max(q));
+            let (sp, sq) = (subtree_start(ops, p), subtree_start(ops, q));
+            if p < sq
+            {
+                *ops = [&ops[..sp], &ops[sq..=q], &ops[p+1..sq], &ops[sp..=p], &ops[q+1..]]
+                    .concat();
+            }
+        },
+        _ => {}
+    }
+}
+
+/// Cross two expressions.
+///
+/// Create a new expression with the operations and tree shape of `ops0`, and
+/// the numbers in the order in which they occur in `ops1`. Both expressions
+/// should use the same numbers.
+fn crossover(ops0: &[Op], ops1: &[Op]) -> Vec<Op>
+{
+    let mut leaves = ops1.iter().filter(|&&op| arity(op) == 0);
+    ops0.iter().map(|&op| if arity(op) == 0 { *leaves.next().unwrap() } else { op }).collect()
+}
+
+/// Find an expression by evolution.
+///
+/// See `Strategy::Evolve`. A population of random expressions, built as in a
+/// sampling search, is improved for `generations` generations. In every
+/// generation, as many new expressions are created as there are in the
+/// population, by crossing two expressions picked in a tournament, and
+/// mutating the result. A new expression replaces the worst expression in the
+/// population if it comes closer to the target, and its value does not yet
+/// occur in the population. The random choices are determined by
+/// `opts.shuffle_seed` (or 0 if it is not set), so the result is reproducible.
+pub(crate) fn evolve(nrs: &[u64], target: &Target, opts: &Options, generations: usize) -> Expr
+{
+    let idxs = unique_indices(nrs);
+    let mut rng = SplitMix::new(opts.shuffle_seed.unwrap_or(0), &idxs);
+    let mut best: Option<(Rat, Expr)> = None;
+    let update = |best: &mut Option<(Rat, Expr)>, expr: &Expr| {
+            let diff = target.distance(expr.val);
+            if best.as_ref().is_none_or(|(best_diff, _)| diff < *best_diff)
+            {
+                *best = Some((diff, expr.clone()));
+            }
+        };
+
+    let start = idxs.iter().map(|&idx| Expr::new(nrs, idx)).collect::<Vec<_>>();
+    if opts.subset || start.len() == 1
+    {
+        start.iter().for_each(|expr| update(&mut best, expr));
+    }
+
+    let mut population: Vec<(Rat, Expr)> = Vec::with_capacity(POPULATION_SIZE);
+    for _ in 0..10 * POPULATION_SIZE
+    {
+        if population.len() == POPULATION_SIZE
+        {
+            break;
+        }
+
+        let mut visit = |expr: &Expr| {
+                if opts.subset
+                {
+                    update(&mut best, expr);
+                }
+            };
+        if let Some(expr) = random_expr(&start, target, opts, &mut rng, &mut visit)
+        {
+            update(&mut best, &expr);
+            population.push((target.distance(expr.val), expr));
+        }
+    }
+    if population.is_empty() || start.len() == 1
+    {
+        return best.map_or_else(Expr::empty, |(_, expr)| expr);
+    }
+
+    let tournament = |population: &[(Rat, Expr)], rng: &mut SplitMix| {
+            let i = (rng.next_u64() % population.len() as u64) as usize;
+            let j = (rng.next_u64() % population.len() as u64) as usize;
+            if population[i].0 <= population[j].0 { i } else { j }
+        };
+    for _ in 0..generations
+    {
+        if opts.cancelled() || best.as_ref().is_some_and(|(diff, _)| diff.is_zero())
+        {
+            break;
+        }
+
+        for _ in 0..POPULATION_SIZE
+        {
+            let i = tournament(&population, &mut rng);
+            let j = tournament(&population, &mut rng);
+            let mut ops = if rng.next_u64().is_multiple_of(2)
+                {
+                    crossover(&population[i].1.ops, &population[j].1.ops)
+                }
+                else
+                {
+                    population[i].1.ops.clone()
+                };
+            mutate(&mut ops, opts, &mut rng);
+
+            let expr = match Expr::from_ops(ops, nrs, opts)
+                {
+                    Some(expr) => expr,
+                    None       => continue
+                };
+            if opts.subset
+            {
+                for end in 0..expr.ops.len()
+                {
+                    let sub = expr.ops[subtree_start(&expr.ops, end)..=end].to_vec();
+                    if let Some(sub) = Expr::from_ops(sub, nrs, opts)
+                    {
+                        update(&mut best, &sub);
+                    }
+                }
+            }
+            update(&mut best, &expr);
+
+            let diff = target.distance(expr.val);
+            let worst = (0..population.len()).max_by_key(|&k| population[k].0).unwrap();
+            if diff < population[worst].0 && population.iter().all(|(_, e)| e.val != expr.val)
+            {
+                population[worst] = (diff, expr);
+            }
+        }
+    }
+
+    best.map_or_else(Expr::empty, |(_, expr)| expr)
+}
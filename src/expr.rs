@@ -0,0 +1,586 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::{format, vec};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use arrayvec::ArrayVec;
+use num_traits::Zero;
+
+use crate::{Idx, Op, Options, Rat, Shape, ADD, DIV, MAX_BINARY_OPS, MAX_NUMBERS, MUL, SUB};
+use crate::operator::{binary_op_code, binary_op_index, unary_op_code, unary_op_index};
+
+/// The maximum number of ways to combine two expressions: the six basic
+/// combinations, and both orders for every custom binary operation.
+const MAX_COMBINATIONS: usize = 6 + 2 * MAX_BINARY_OPS;
+
+/// Structure describing an expression
+///
+/// Struct `Expr` stores an expression and the value it evaluates to. The
+/// expression is stored in reverse polish notation, and uses indices into
+/// a numbers array instead of the actual numbers themselves. The operators
+/// in the expression are encoded as the 4 greatest numbers that can be encoded
+/// in the index type. Thus, an expression like
+/// ```text
+/// [0, 3, ADD, 2, MUL]
+/// ```
+/// will for a numbers array `nrs` evaluate to
+/// ```text
+/// (nrs[0] + nrs[3]) * nrs[2]
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Expr
+{
+    /// The expression itself
+    pub ops: Vec<Op>,
+    /// The resulting value of the expression
+    pub val: Rat,
+    /// The largest value of any sub-expression, including the numbers and
+    /// the expression as a whole
+    pub max: Rat
+}
+
+impl Expr
+{
+    /// Create a new expression.
+    ///
+    /// Create a new expression for the single number `nrs[idx]`.
+    pub fn new(nrs: &[u64], idx: Idx) -> Self
+    {
+        let val = Rat::from_integer(nrs[idx as usize]);
+        Expr { ops: vec![idx], val, max: val }
+    }
+
+    /// Create an empty expression.
+    ///
+    /// Create an empty expression that evaluates to zero.
+    pub fn empty() -> Self
+    {
+        Expr { ops: vec![], val: Rat::zero(), max: Rat::zero() }
+    }
+
+    /// Create an expression from its operations.
+    ///
+    /// Evaluate the expression in reverse polish notation `ops` for the
+    /// numbers array `nrs`, subject to the search options in `opts`. Returns
+    /// `None` if `ops` is not a valid expression, if one of its operations
+    /// cannot be applied, e.g. a division by zero or a subtraction with a
+    /// negative result, or if a proper sub-expression evaluates to a forbidden
+    /// value.
+    pub fn from_ops(ops: Vec<Op>, nrs: &[u64], opts: &Options) -> Option<Self>
+    {
+        let mut stack = vec![];
+        let mut max = Rat::zero();
+        for (i, &op) in ops.iter().enumerate()
+        {
+            if (op as usize) < MAX_NUMBERS
+            {
+                let val = Rat::from_integer(*nrs.get(op as usize)?);
+                max = max.max(val);
+                stack.push(val);
+            }
+            else
+            {
+                let val = if let Some(u) = unary_op_index(op)
+                    {
+                        if opts.unary_ops.get(u)?.leaf_only()
+                            && !(i > 0 && (ops[i-1] as usize) < MAX_NUMBERS)
+                        {
+                            return None;
+                        }
+                        apply_unary(u, stack.pop()?, opts)?
+                    }
+                    else
+                    {
+                        let b = stack.pop()?;
+                        let a = stack.pop()?;
+                        apply(op, a, b, opts)?
+                    };
+                if i + 1 < ops.len() && opts.forbidden_values.contains(&val)
+                {
+                    return None;
+                }
+                max = max.max(val);
+                stack.push(val);
+            }
+        }
+
+        if stack.len() == 1
+        {
+            Some(Expr { ops, val: stack[0], max })
+        }
+        else
+        {
+            None
+        }
+    }
+
+    /// Check if this is an empty expression.
+    pub fn is_empty(&self) -> bool
+    {
+        self.ops.is_empty()
+    }
+
+    /// The indices of the numbers used in this expression.
+    pub fn indices(&self) -> impl Iterator<Item=Idx> + '_
+    {
+        self.ops.iter().cloned().filter(|&op| (op as usize) < MAX_NUMBERS)
+    }
+
+    /// The operations of this expression, using distinct indices for equal
+    /// numbers.
+    ///
+    /// Since equal numbers share the index of their first occurrence in
+    /// an expression, this returns the operations of this expression with the
+    /// occurrences of each number in the expression assigned to the positions
+    /// of that number in `nrs`, in order.
+    pub fn occurrence_ops(&self, nrs: &[u64]) -> Vec<Op>
+    {
+        let mut used = vec![0; nrs.len()];
+        self.ops.iter().map(|&op| {
+                if (op as usize) < MAX_NUMBERS
+                {
+                    let nr = nrs[op as usize];
+                    let pos = nrs.iter().enumerate()
+                        .filter(|&(_, &x)| x == nr)
+                        .nth(used[op as usize])
+                        .map(|(i, _)| i)
+                        .unwrap();
+                    used[op as usize] += 1;
+                    pos as Op
+                }
+                else
+                {
+                    op
+                }
+            }).collect()
+    }
+
+    /// Serialize this expression.
+    ///
+    /// Create a string describing this expression over the numbers array
+    /// `nrs` in reverse polish notation, using the positions `#i` of the
+    /// numbers instead of their values, e.g. `#3 #0 #1 #2 / - /`. Custom
+    /// binary operations are written as `b0`, `b1`, ..., and custom unary
+    /// operations as `u0`, `u1`, ..., by their position in the search
+    /// options. The expression can then be evaluated for a different numbers
+    /// array using `deserialize()`.
+    pub fn serialize(&self, nrs: &[u64]) -> String
+    {
+        self.occurrence_ops(nrs).iter().map(|&op| {
+                match op
+                {
+                    ADD => String::from("+"),
+                    SUB => String::from("-"),
+                    MUL => String::from("*"),
+                    DIV => String::from("/"),
+                    op if unary_op_index(op).is_some() => {
+                        format!("u{}", unary_op_index(op).unwrap())
+                    },
+                    op if (op as usize) >= MAX_NUMBERS => {
+                        format!("b{}", binary_op_index(op).unwrap())
+                    },
+                    idx => format!("#{}", idx)
+                }
+            }).collect::<Vec<_>>().join(" ")
+    }
+
+    /// Deserialize an expression.
+    ///
+    /// Parse the serialized expression `s`, as created by `serialize()`, and
+    /// evaluate it for the numbers array `nrs`, subject to the search options
+    /// in `opts`. Returns a description of the error if `s` is not a valid
+    /// serialized expression, if it refers to a number past the end of `nrs`,
+    /// or if it cannot be evaluated for these numbers.
+    pub fn deserialize(s: &str, nrs: &[u64], opts: &Options) -> Result<Self, String>
+    {
+        let mut ops = vec![];
+        for token in s.split_whitespace()
+        {
+            let op = match token
+                {
+                    "+" => ADD,
+                    "-" => SUB,
+                    "*" => MUL,
+                    "/" => DIV,
+                    _ if token.starts_with('b') => {
+                        token[1..].parse::<usize>().ok()
+                            .filter(|&i| i < opts.binary_ops.len())
+                            .map(binary_op_code)
+                            .ok_or_else(|| format!("invalid operation '{}'", token))?
+                    },
+                    _ if token.starts_with('u') => {
+                        token[1..].parse::<usize>().ok()
+                            .filter(|&i| i < opts.unary_ops.len())
+                            .map(unary_op_code)
+                            .ok_or_else(|| format!("invalid operation '{}'", token))?
+                    },
+                    _   => {
+                        let idx = token.strip_prefix('#')
+                            .and_then(|t| t.parse::<usize>().ok())
+                            .filter(|&idx| idx < MAX_NUMBERS)
+                            .ok_or_else(|| format!("invalid token '{}'", token))?;
+                        if idx >= nrs.len()
+                        {
+                            return Err(format!("number #{} does not exist", idx));
+                        }
+                        idx as Op
+                    }
+                };
+            ops.push(op);
+        }
+
+        Expr::from_ops(ops, nrs, opts)
+            .ok_or_else(|| String::from("the expression cannot be evaluated"))
+    }
+
+    /// The number of binary operations in this expression.
+    pub fn op_count(&self) -> usize
+    {
+        self.indices().count().saturating_sub(1)
+    }
+
+    pub(crate) fn possible_combinations(&self, expr: &Self, opts: &Options)
+        -> ArrayVec<(char, Rat), MAX_COMBINATIONS>
+    {
+        if let Some(max_ops) = opts.max_ops
+        {
+            if self.op_count() + expr.op_count() + 1 > max_ops
+            {
+                return ArrayVec::new();
+            }
+        }
+
+        // The operations skipped below for associative combinations are
+        // generated from a different split, which may not be balanced.
+        let op0 = *self.ops.last().unwrap();
+        let op1 = *expr.ops.last().unwrap();
+        let ops = match (op0, op1)
+            {
+                _ if opts.shape == Shape::Balanced => "+-*/_\\",
+                (ADD, ADD) => "*/\\",
+                (ADD, SUB) => "*/\\",
+                (ADD, MUL) => "+-\\",
+                (ADD, DIV) => "+-\\",
+                (ADD,   _) => "+-*/\\",
+                (SUB, ADD) => "*/\\",
+                (SUB, SUB) => "*/\\",
+                (SUB, MUL) => "-\\",
+                (SUB, DIV) => "-\\",
+                (SUB,   _) => "-*/\\",
+                (MUL, ADD) => "*/",
+                (MUL, SUB) => "*/",
+                (MUL, MUL) => "+-_",
+                (MUL, DIV) => "+-_",
+                (MUL,   _) => "+-*/_",
+                (DIV, ADD) => "/_",
+                (DIV, SUB) => "/_",
+                (DIV, MUL) => "+-_",
+                (DIV, DIV) => "+-_",
+                (DIV,   _) => "+-/_",
+                _          => "+-*/_\\"
+            };
+        self.combinations_with(expr, opts, ops)
+    }
+
+    /// All ways to combine this expression with `expr`, including those that
+    /// are skipped by `possible_combinations()` because they are generated
+    /// from a different split in an exhaustive search.
+    pub(crate) fn all_combinations(&self, expr: &Self, opts: &Options)
+        -> ArrayVec<(char, Rat), MAX_COMBINATIONS>
+    {
+        if opts.max_ops.is_some_and(|max_ops| self.op_count() + expr.op_count() + 1 > max_ops)
+        {
+            return ArrayVec::new();
+        }
+        self.combinations_with(expr, opts, "+-*/_\\")
+    }
+
+    /// The ways to combine this expression with `expr`, using the basic
+    /// operations in `ops`, and the custom operations in `opts`.
+    fn combinations_with(&self, expr: &Self, opts: &Options, ops: &str)
+        -> ArrayVec<(char, Rat), MAX_COMBINATIONS>
+    {
+        let mut res = ArrayVec::<_, MAX_COMBINATIONS>::new();
+
+        // In a chain, the number that is added must be the right hand side
+        // operand, so reversed operations are only allowed on two numbers.
+        let reverse_ok = opts.shape != Shape::Chain || self.ops.len() == 1;
+        for op in ops.chars().filter(|&op| reverse_ok || (op != '_' && op != '\\'))
+        {
+            match op
+            {
+                '+' => {
+                    res.push((op, self.val + expr.val));
+                },
+                '-' if self.val >= expr.val => {
+                    res.push((op, self.val - expr.val));
+                },
+                '*' => {
+                    res.push((op, self.val * expr.val));
+                },
+                '/' if !expr.val.is_zero() => {
+                    let val = self.val / expr.val;
+                    if !opts.integers_only || val.is_integer()
+                    {
+                        res.push((op, val));
+                    }
+                },
+                '_' if expr.val >= self.val => {
+                    res.push((op, expr.val - self.val));
+                },
+                '\\' if !self.val.is_zero() => {
+                    let val = expr.val / self.val;
+                    if !opts.integers_only || val.is_integer()
+                    {
+                        res.push((op, val));
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        // Custom operations are encoded as 'a', 'b', ..., or as 'A', 'B', ...
+        // when the operands are reversed.
+        for (i, op) in opts.binary_ops.iter().enumerate()
+        {
+            let orders = [('a', self.val, expr.val), ('A', expr.val, self.val)];
+            let count = if !op.commutative() && reverse_ok { 2 } else { 1 };
+            for &(base, a, b) in orders[..count].iter()
+            {
+                if let Some(val) = op.apply(a, b)
+                {
+                    if !opts.integers_only || val.is_integer()
+                    {
+                        res.push(((base as u8 + i as u8) as char, val));
+                    }
+                }
+            }
+        }
+
+        res
+    }
+
+    pub(crate) fn combine(&self, expr: &Self, op: char, val: Rat) -> Self
+    {
+        let ops = match op
+            {
+                '+' => {
+                    [&self.ops[..], &expr.ops[..], &[ADD]].concat()
+                },
+                '-' => {
+                    [&self.ops[..], &expr.ops[..], &[SUB]].concat()
+                },
+                '*' => {
+                    [&self.ops[..], &expr.ops[..], &[MUL]].concat()
+                },
+                '/' => {
+                    [&self.ops[..], &expr.ops[..], &[DIV]].concat()
+                },
+                '_' => {
+                    [&expr.ops[..], &self.ops[..], &[SUB]].concat()
+                },
+                '\\' => {
+                    [&expr.ops[..], &self.ops[..], &[DIV]].concat()
+                },
+                'a'..='z' => {
+                    let code = binary_op_code(op as usize - 'a' as usize);
+                    [&self.ops[..], &expr.ops[..], &[code]].concat()
+                },
+                'A'..='Z' => {
+                    let code = binary_op_code(op as usize - 'A' as usize);
+                    [&expr.ops[..], &self.ops[..], &[code]].concat()
+                },
+                _ => { panic!(); }
+            };
+
+        Expr { ops, val, max: self.max.max(expr.max).max(val) }
+    }
+
+    /// Format this expression.
+    ///
+    /// Create the infix notation of this expression for the numbers array
+    /// `nrs`, using a minimal number of parentheses.
+    pub fn to_string(&self, nrs: &[u64]) -> String
+    {
+        self.to_string_with(nrs, &Options::default())
+    }
+
+    /// Format this expression, including custom operations.
+    ///
+    /// Create the infix notation of this expression for the numbers array
+    /// `nrs`, like `to_string()`, formatting the custom operations in it using
+    /// the operations registered in `opts`.
+    pub fn to_string_with(&self, nrs: &[u64], opts: &Options) -> String
+    {
+        self.to_infix(opts, |idx| nrs[idx as usize].to_string())
+    }
+
+    /// Format this expression, using `leaf` to format the numbers.
+    ///
+    /// Create the infix notation of this expression, using a minimal number
+    /// of parentheses. Function `leaf` is called for every number index in
+    /// the expression, in order from left to right. Custom operations are
+    /// formatted using the operations registered in `opts`.
+    pub(crate) fn to_infix<F>(&self, opts: &Options, mut leaf: F) -> String
+    where F: FnMut(Idx) -> String
+    {
+        let mut ss = vec![];
+        for op in self.ops.iter()
+        {
+            match *op
+            {
+                ADD => {
+                    let (s0, _) = ss.pop().unwrap();
+                    let (s1, _) = ss.pop().unwrap();
+                    ss.push((format!("{}+{}", s1, s0), '+'));
+                },
+                SUB => {
+                    let (mut s0, o0) = ss.pop().unwrap();
+                    let (s1, _) = ss.pop().unwrap();
+                    if "+-".contains(o0)
+                    {
+                        s0 = format!("({})", s0);
+                    }
+                    ss.push((format!("{}-{}", s1, s0), '-'));
+                },
+                MUL => {
+                    let (mut s0, o0) = ss.pop().unwrap();
+                    let (mut s1, o1) = ss.pop().unwrap();
+                    if "+-".contains(o0)
+                    {
+                        s0 = format!("({})", s0);
+                    }
+                    if "+-/".contains(o1)
+                    {
+                        s1 = format!("({})", s1);
+                    }
+                    ss.push((format!("{}*{}", s1, s0), '*'));
+                },
+                DIV => {
+                    let (mut s0, o0) = ss.pop().unwrap();
+                    let (mut s1, o1) = ss.pop().unwrap();
+                    if "+-*/".contains(o0)
+                    {
+                        s0 = format!("({})", s0);
+                    }
+                    if "+-/".contains(o1)
+                    {
+                        s1 = format!("({})", s1);
+                    }
+                    ss.push((format!("{}/{}", s1, s0), '/'));
+                }
+                op if unary_op_index(op).is_some() => {
+                    let (s0, _) = ss.pop().unwrap();
+                    let s = match unary_op_index(op).and_then(|i| opts.unary_ops.get(i))
+                        {
+                            Some(uop) => uop.render(&s0),
+                            None => format!("op{}({})", Op::MAX - op, s0)
+                        };
+                    ss.push((s, 'n'));
+                },
+                op if (op as usize) >= MAX_NUMBERS => {
+                    let (s0, _) = ss.pop().unwrap();
+                    let (s1, _) = ss.pop().unwrap();
+                    let s = match binary_op_index(op).and_then(|i| opts.binary_ops.get(i))
+                        {
+                            Some(bop) => bop.render(&s1, &s0),
+                            None => format!("op{}({},{})", Op::MAX - op, s1, s0)
+                        };
+                    ss.push((s, 'n'));
+                },
+                idx => {
+                    ss.push((leaf(idx), 'n'));
+                },
+            }
+        }
+
+        let (res, _) = ss.pop().unwrap();
+        res
+    }
+}
+
+/// Apply a unary operation.
+///
+/// Compute the result of the `i`'th custom unary operation in `opts` on `a`,
+/// subject to the search options in `opts`. Returns `None` if the operation
+/// cannot be applied to `a`.
+pub(crate) fn apply_unary(i: usize, a: Rat, opts: &Options) -> Option<Rat>
+{
+    let op = opts.unary_ops.get(i)?;
+    if !op.domain(a)
+    {
+        return None;
+    }
+    let val = op.apply(a);
+    if !opts.integers_only || val.is_integer() { Some(val) } else { None }
+}
+
+/// Apply an operation.
+///
+/// Compute `a op b` for the operation `op`, subject to the search options in
+/// `opts`. Returns `None` if the operation cannot be applied to `a` and `b`.
+pub(crate) fn apply(op: Op, a: Rat, b: Rat, opts: &Options) -> Option<Rat>
+{
+    match op
+    {
+        ADD => Some(a + b),
+        SUB if a >= b => Some(a - b),
+        MUL => Some(a * b),
+        DIV if !b.is_zero() => {
+            let val = a / b;
+            if !opts.integers_only || val.is_integer() { Some(val) } else { None }
+        },
+        _ => {
+            let val = opts.binary_ops.get(binary_op_index(op)?)?.apply(a, b)?;
+            if !opts.integers_only || val.is_integer() { Some(val) } else { None }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn from_ops_evaluates()
+    {
+        let nrs = [1, 3, 4, 6];
+        let opts = Options::default();
+        let expr = Expr::from_ops(vec![3, 0, 1, 2, DIV, SUB, DIV], &nrs, &opts).unwrap();
+        assert_eq!(expr.val, 24.into());
+        assert_eq!(expr.max, 24.into());
+        assert_eq!(expr.to_string(&nrs), "6/(1-3/4)");
+        assert_eq!(expr.op_count(), 3);
+        // A negative intermediate result, a division by zero, and too few operands
+        assert!(Expr::from_ops(vec![0, 1, SUB], &nrs, &opts).is_none());
+        assert!(Expr::from_ops(vec![0, 0, 0, SUB, DIV], &[1], &opts).is_none());
+        assert!(Expr::from_ops(vec![0, ADD], &nrs, &opts).is_none());
+    }
+
+    #[test]
+    fn serialize_round_trip()
+    {
+        let nrs = [5, 2, 5];
+        let opts = Options::default();
+        let expr = Expr::from_ops(vec![0, 0, MUL, 1, SUB], &nrs, &opts).unwrap();
+        let s = expr.serialize(&nrs);
+        assert_eq!(s, "#0 #2 * #1 -");
+        let other = Expr::deserialize(&s, &[3, 4, 7], &opts).unwrap();
+        assert_eq!(other.val, 17.into());
+        assert!(Expr::deserialize("#0 #3 +", &nrs, &opts).is_err());
+        assert!(Expr::deserialize("#0 #1 ^", &nrs, &opts).is_err());
+    }
+}
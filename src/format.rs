@@ -0,0 +1,440 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::{format, vec};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::{elegance_score, to_roman, Expr, Op, Options, Rat, Target, ADD, DIV, MAX_NUMBERS, MUL,
+    SCHEMA_VERSION, SUB};
+use crate::expr::{apply, apply_unary};
+use crate::operator::{binary_op_index, unary_op_index};
+
+/// Output format for an expression
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format
+{
+    /// Plain infix notation, followed by the value, e.g. `6/(1-3/4) = 24`
+    #[default]
+    Plain,
+    /// A Rust floating point expression, e.g. `6.0/(1.0-3.0/4.0)`
+    Rust,
+    /// A Python floating point expression, e.g. `6.0/(1.0-3.0/4.0)`
+    Python,
+    /// A C floating point expression, e.g. `6.0/(1.0-3.0/4.0)`
+    C,
+    /// Postfix (reverse Polish) notation, e.g. `6 1 3 4 / - /`
+    Postfix,
+    /// Prefix (Polish) notation, e.g. `/ 6 - 1 / 3 4`
+    Prefix,
+    /// A JSON object with the schema version, the expression, its value and
+    /// its elegance score, e.g.
+    /// `{"schema_version":1,"expression":"6/(1-3/4)","value":"24","score":42}`.
+    /// See `EXPRESSION_SCHEMA` for its schema.
+    Json,
+    /// A spreadsheet formula, e.g. `=6/(1-3/4)`
+    Excel,
+    /// A spreadsheet formula referencing the input numbers in cells `A1`,
+    /// `A2`, ..., e.g. `=A4/(A1-A2/A3)`
+    ExcelCells,
+    /// The serialized expression, referencing the input numbers by position,
+    /// e.g. `#3 #0 #1 #2 / - /`
+    Serialized,
+    /// A Markdown fragment with the expression and its value as code,
+    /// followed by a table of its steps, every operation with the values of
+    /// its operands and its result
+    Markdown
+}
+
+impl Format
+{
+    /// Look up a format by its command line name.
+    pub fn from_name(name: &str) -> Option<Self>
+    {
+        match name
+        {
+            "plain"       => Some(Format::Plain),
+            "rust"        => Some(Format::Rust),
+            "python"      => Some(Format::Python),
+            "c"           => Some(Format::C),
+            "postfix"     => Some(Format::Postfix),
+            "prefix"      => Some(Format::Prefix),
+            "json"        => Some(Format::Json),
+            "excel"       => Some(Format::Excel),
+            "excel-cells" => Some(Format::ExcelCells),
+            "serialized"  => Some(Format::Serialized),
+            "markdown"    => Some(Format::Markdown),
+            _             => None
+        }
+    }
+}
+
+/// Style of the brackets in infix notation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Brackets
+{
+    /// Parentheses only, e.g. `(6-(1+3))*4`
+    #[default]
+    Round,
+    /// Parentheses, square brackets and braces, by nesting level from the
+    /// inside out, as is common in school material, e.g. `[6-(1+3)]*4`
+    Nested
+}
+
+impl Brackets
+{
+    /// Look up a bracket style by its command line name.
+    pub fn from_name(name: &str) -> Option<Self>
+    {
+        match name
+        {
+            "round"  => Some(Brackets::Round),
+            "nested" => Some(Brackets::Nested),
+            _        => None
+        }
+    }
+}
+
+/// Style of the plain output format
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Style
+{
+    /// The style of the brackets
+    pub brackets: Brackets,
+    /// The base in which the numbers are written, from 2 to 36. Numbers in
+    /// base 2, 8 or 16 are written with a prefix `0b`, `0o` or `0x`.
+    pub base: u32,
+    /// Whether to write the numbers in Roman numerals. Numbers that cannot be
+    /// written in Roman numerals, like zero, are written in `base`.
+    pub roman: bool
+}
+
+impl Default for Style
+{
+    fn default() -> Self
+    {
+        Style { brackets: Brackets::Round, base: 10, roman: false }
+    }
+}
+
+/// Format an expression.
+///
+/// Create the representation of expression `expr` over the numbers array
+/// `nrs` in output format `format`. Custom operations are formatted using the
+/// operations registered in `opts`. In the code formats, the numbers are
+/// written as floating point literals, so that divisions are not truncated.
+/// In format `ExcelCells`, input number `nrs[i]` is referenced as cell
+/// `A{i+1}`.
+pub fn format_expr(expr: &Expr, nrs: &[u64], opts: &Options, format: Format) -> String
+{
+    match format
+    {
+        Format::Plain => format!("{} = {}", expr.to_string_with(nrs, opts), expr.val),
+        Format::Rust | Format::Python | Format::C => {
+            expr.to_infix(opts, |idx| format!("{}.0", nrs[idx as usize]))
+        },
+        Format::Postfix => {
+            expr.ops.iter().map(|&op| op_token(op, nrs, opts)).collect::<Vec<_>>().join(" ")
+        },
+        Format::Prefix => {
+            let mut stack = vec![];
+            for &op in expr.ops.iter()
+            {
+                if (op as usize) < MAX_NUMBERS
+                {
+                    stack.push(op_token(op, nrs, opts));
+                }
+                else if unary_op_index(op).is_some()
+                {
+                    let s0 = stack.pop().unwrap();
+                    stack.push(format!("{} {}", op_token(op, nrs, opts), s0));
+                }
+                else
+                {
+                    let s1 = stack.pop().unwrap();
+                    let s0 = stack.pop().unwrap();
+                    stack.push(format!("{} {} {}", op_token(op, nrs, opts), s0, s1));
+                }
+            }
+            stack.pop().unwrap_or_default()
+        },
+        Format::Json => {
+            format!(concat!("{{\"schema_version\":{},\"expression\":\"{}\",\"value\":\"{}\",",
+                "\"score\":{}}}"), SCHEMA_VERSION, expr.to_string_with(nrs, opts), expr.val,
+                elegance_score(expr, nrs))
+        },
+        Format::Excel => format!("={}", expr.to_string_with(nrs, opts)),
+        Format::ExcelCells => {
+            let expr = Expr { ops: expr.occurrence_ops(nrs), val: expr.val, max: expr.max };
+            format!("={}", expr.to_infix(opts, |idx| format!("A{}", idx + 1)))
+        },
+        Format::Serialized => expr.serialize(nrs),
+        Format::Markdown => {
+            format!("`{} = {}`\n\n{}", expr.to_string_with(nrs, opts), expr.val,
+                markdown_steps(expr, nrs, opts))
+        }
+    }
+}
+
+/// Format an expression as Markdown, with its error.
+///
+/// As `format_expr()` in format `Markdown`, followed by the distance of the
+/// value of `expr` to `target`, if it does not hit the target.
+pub fn format_markdown(expr: &Expr, nrs: &[u64], target: &Target, opts: &Options) -> String
+{
+    let markdown = format_expr(expr, nrs, opts, Format::Markdown);
+    if target.contains(expr.val)
+    {
+        markdown
+    }
+    else
+    {
+        format!("{}\n\nOff by **{}** from the target {}.", markdown, target.distance(expr.val),
+            target)
+    }
+}
+
+/// The table of the steps of expression `expr` over the numbers `nrs`, in
+/// Markdown.
+///
+/// Every operation is shown on a row of its own, in the order in which it is
+/// evaluated, with the values of its operands and its result. Fractional
+/// operands are parenthesized, so that the operation reads correctly.
+fn markdown_steps(expr: &Expr, nrs: &[u64], opts: &Options) -> String
+{
+    let mut rows = vec![String::from("| Step | Operation | Result |"),
+        String::from("|-----:|-----------|-------:|")];
+    let mut stack = vec![];
+    for &op in expr.ops.iter()
+    {
+        if (op as usize) < MAX_NUMBERS
+        {
+            stack.push(Rat::from_integer(nrs[op as usize]));
+            continue;
+        }
+
+        let operands = if unary_op_index(op).is_some()
+            {
+                stack.split_off(stack.len() - 1)
+            }
+            else
+            {
+                stack.split_off(stack.len() - 2)
+            };
+        let val = match unary_op_index(op)
+            {
+                Some(i) => apply_unary(i, operands[0], opts),
+                None    => apply(op, operands[0], operands[1], opts)
+            };
+        let val = match val
+            {
+                Some(val) => val,
+                None      => break
+            };
+        let step = Expr { ops: (0..operands.len() as Op).chain([op]).collect(), val, max: val };
+        let calc = step.to_infix(opts, |idx| {
+                let operand = operands[idx as usize];
+                if operand.is_integer() { operand.to_string() } else { format!("({})", operand) }
+            });
+        rows.push(format!("| {} | `{}` | {} |", rows.len() - 1, calc.replace('|', "\\|"), val));
+        stack.push(val);
+    }
+    rows.join("\n")
+}
+
+/// Format an expression, with a style.
+///
+/// As `format_expr()`, but in format `Plain`, the brackets in the infix
+/// notation, and the way the numbers and the value are written, follow
+/// `style`. The other formats are meant to be read by programs, and always use
+/// parentheses and decimal numbers.
+pub fn format_expr_with(expr: &Expr, nrs: &[u64], opts: &Options, format: Format, style: Style)
+    -> String
+{
+    if format != Format::Plain || style == Style::default()
+    {
+        return format_expr(expr, nrs, opts, format);
+    }
+
+    let infix = expr.to_infix(opts, |idx| format_number(nrs[idx as usize], style));
+    let infix = match style.brackets
+        {
+            Brackets::Round  => infix,
+            Brackets::Nested => nested_brackets(&infix)
+        };
+    let val = if expr.val.is_integer()
+        {
+            format_number(*expr.val.numer(), style)
+        }
+        else
+        {
+            format!("{}/{}", format_number(*expr.val.numer(), style),
+                format_number(*expr.val.denom(), style))
+        };
+    format!("{} = {}", infix, val)
+}
+
+/// Write number `nr` in the style of the numbers in `style`.
+fn format_number(nr: u64, style: Style) -> String
+{
+    match to_roman(nr).filter(|_| style.roman)
+    {
+        Some(s) => s,
+        None    => format_in_base(nr, style.base)
+    }
+}
+
+/// Write number `nr` in base `base`, with a prefix for bases 2, 8 and 16.
+fn format_in_base(nr: u64, base: u32) -> String
+{
+    let prefix = match base
+        {
+            2  => "0b",
+            8  => "0o",
+            16 => "0x",
+            _  => ""
+        };
+    let mut digits = vec![];
+    let mut rest = nr;
+    loop
+    {
+        let digit = ::core::char::from_digit((rest % base as u64) as u32, base).unwrap();
+        digits.push(digit.to_ascii_uppercase());
+        rest /= base as u64;
+        if rest == 0
+        {
+            break;
+        }
+    }
+    format!("{}{}", prefix, digits.iter().rev().collect::<String>())
+}
+
+/// Replace the parentheses in infix expression `s` by parentheses, square
+/// brackets and braces, depending on the number of levels of brackets they
+/// enclose. The parentheses around the arguments of a function call, like
+/// `gcd(12,8)`, are kept, and do not count as a level.
+fn nested_brackets(s: &str) -> String
+{
+    let mut chars = s.chars().collect::<Vec<_>>();
+    // For every open bracket, its position, if it is not a function call, and
+    // the highest level of the brackets inside it
+    let mut stack: Vec<(Option<usize>, usize)> = vec![(None, 0)];
+    let mut levels = vec![0; chars.len()];
+    for i in 0..chars.len()
+    {
+        match chars[i]
+        {
+            '(' => {
+                let call = i > 0 && (chars[i-1].is_alphanumeric() || chars[i-1] == '_');
+                stack.push((if call { None } else { Some(i) }, 0));
+            },
+            ')' if stack.len() > 1 => {
+                let (open, inner) = stack.pop().unwrap();
+                let level = match open
+                    {
+                        Some(open) => {
+                            levels[open] = inner + 1;
+                            levels[i] = inner + 1;
+                            inner + 1
+                        },
+                        None => inner
+                    };
+                let top = stack.last_mut().unwrap();
+                top.1 = top.1.max(level);
+            },
+            _ => {}
+        }
+    }
+
+    for (c, &level) in chars.iter_mut().zip(levels.iter()).filter(|&(_, &level)| level > 0)
+    {
+        let (open, close) = [('(', ')'), ('[', ']'), ('{', '}')][(level - 1) % 3];
+        *c = if *c == '(' { open } else { close };
+    }
+    chars.into_iter().collect()
+}
+
+/// The token for a single operation or number in a Polish notation.
+fn op_token(op: Op, nrs: &[u64], opts: &Options) -> String
+{
+    match op
+    {
+        ADD => String::from("+"),
+        SUB => String::from("-"),
+        MUL => String::from("*"),
+        DIV => String::from("/"),
+        op if unary_op_index(op).is_some() => {
+            match unary_op_index(op).and_then(|i| opts.unary_ops.get(i))
+            {
+                Some(uop) => uop.symbol().to_string(),
+                None => format!("op{}", Op::MAX - op)
+            }
+        },
+        op if (op as usize) >= MAX_NUMBERS => {
+            match binary_op_index(op).and_then(|i| opts.binary_ops.get(i))
+            {
+                Some(bop) => bop.symbol().to_string(),
+                None => format!("op{}", Op::MAX - op)
+            }
+        },
+        idx => nrs[idx as usize].to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn documented_formats()
+    {
+        let nrs = [1, 3, 4, 6];
+        let opts = Options::default();
+        let expr = parse("6/(1-3/4)", &nrs).unwrap();
+        let cases = [
+            (Format::Plain, "6/(1-3/4) = 24"),
+            (Format::Rust, "6.0/(1.0-3.0/4.0)"),
+            (Format::Postfix, "6 1 3 4 / - /"),
+            (Format::Prefix, "/ 6 - 1 / 3 4"),
+            (Format::Excel, "=6/(1-3/4)"),
+            (Format::ExcelCells, "=A4/(A1-A2/A3)"),
+            (Format::Serialized, "#3 #0 #1 #2 / - /")
+        ];
+        for &(format, expected) in cases.iter()
+        {
+            assert_eq!(format_expr(&expr, &nrs, &opts, format), expected);
+        }
+        let json = format_expr(&expr, &nrs, &opts, Format::Json);
+        assert!(json.starts_with(r#"{"schema_version":1,"expression":"6/(1-3/4)","value":"24""#));
+    }
+
+    #[test]
+    fn styled_plain_format()
+    {
+        let nrs = [1, 3, 4, 6];
+        let opts = Options::default();
+        let expr = parse("(6-(1+3))*4", &nrs).unwrap();
+        let nested = Style { brackets: Brackets::Nested, ..Style::default() };
+        assert_eq!(format_expr_with(&expr, &nrs, &opts, Format::Plain, nested), "[6-(1+3)]*4 = 8");
+        let hex = Style { base: 16, ..Style::default() };
+        let expr = parse("6*4", &nrs).unwrap();
+        assert_eq!(format_expr_with(&expr, &nrs, &opts, Format::Plain, hex), "0x6*0x4 = 0x18");
+        let roman = Style { roman: true, ..Style::default() };
+        assert_eq!(format_expr_with(&expr, &nrs, &opts, Format::Plain, roman), "VI*IV = XXIV");
+        // Other formats do not use the style
+        assert_eq!(format_expr_with(&expr, &nrs, &opts, Format::Postfix, roman), "6 4 *");
+    }
+}
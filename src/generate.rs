@@ -0,0 +1,289 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use alloc::collections::BTreeSet;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{canonical_form, exact_solutions, Expr, Options, Rat, Target};
+use crate::shuffle::SplitMix;
+
+/// The large tiles in the Countdown numbers game, one of each
+pub const COUNTDOWN_LARGE: [u64; 4] = [25, 50, 75, 100];
+/// The small tiles in the Countdown numbers game, two of each
+pub const COUNTDOWN_SMALL: [u64; 20] = [
+    1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10
+];
+/// The number of tiles drawn in a Countdown numbers round
+pub const COUNTDOWN_TILES: usize = 6;
+
+/// Iterator over multisets of numbers
+///
+/// Struct `Multisets` iterates over all multisets of a fixed size of numbers
+/// in an inclusive range, each as a sorted vector, in lexicographic order.
+#[derive(Clone, Debug)]
+pub struct Multisets
+{
+    /// The next multiset, or `None` if all have been visited
+    next: Option<Vec<u64>>,
+    /// The largest number in a multiset
+    max: u64
+}
+
+impl Iterator for Multisets
+{
+    type Item = Vec<u64>;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        let cur = self.next.take()?;
+        if let Some(pos) = cur.iter().rposition(|&nr| nr < self.max)
+        {
+            let mut next = cur.clone();
+            let nr = next[pos] + 1;
+            for x in next[pos..].iter_mut()
+            {
+                *x = nr;
+            }
+            self.next = Some(next);
+        }
+        Some(cur)
+    }
+}
+
+/// Iterate over the multisets of `count` numbers from `min` to `max`
+/// (inclusive), in lexicographic order.
+pub fn multisets(count: usize, min: u64, max: u64) -> Multisets
+{
+    let next = if min <= max { Some(vec![min; count]) } else { None };
+    Multisets { next, max }
+}
+
+/// Find the essentially different exact solutions.
+///
+/// Find the exact solutions for `target` over the numbers in `nrs`, as in
+/// `exact_solutions()`, using all numbers unless `opts.subset` is set, and
+/// keep only one of the solutions that differ only by commutativity or
+/// associativity, i.e. that have the same `canonical_form()`.
+pub fn essential_solutions(nrs: &[u64], target: &Target, opts: &Options) -> Vec<Expr>
+{
+    let mut seen = BTreeSet::new();
+    let mut exprs = exact_solutions(nrs, target, !opts.subset, opts);
+    exprs.retain(|expr| seen.insert(canonical_form(expr, nrs)));
+    exprs
+}
+
+/// Find sets of numbers from which a target can be made.
+///
+/// The inverse of the normal search: iterate over the multisets of `count`
+/// numbers from `min` to `max`, in lexicographic order, and yield those for
+/// which the number of essentially different exact solutions for `target`, as
+/// found by `essential_solutions()`, lies in `solutions`. Every multiset is
+/// yielded together with its solutions. Since a full search is needed for
+/// every multiset, the iterator is lazy, so that the caller can stop once it
+/// has found enough.
+pub fn numbers_for<'a>(target: &'a Target, count: usize, min: u64, max: u64,
+    solutions: (usize, usize), opts: &'a Options)
+    -> impl Iterator<Item = (Vec<u64>, Vec<Expr>)> + 'a
+{
+    multisets(count, min, max).filter_map(move |nrs| {
+            let exprs = essential_solutions(&nrs, target, opts);
+            if (solutions.0..=solutions.1).contains(&exprs.len())
+            {
+                Some((nrs, exprs))
+            }
+            else
+            {
+                None
+            }
+        })
+}
+
+/// A Countdown numbers round
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Draw
+{
+    /// The tiles drawn, the large ones first
+    pub nrs: Vec<u64>,
+    /// The target, from 100 to 999
+    pub target: u64
+}
+
+/// Draw a Countdown numbers round.
+///
+/// Draw `large` tiles from `COUNTDOWN_LARGE`, and the rest of the
+/// `COUNTDOWN_TILES` tiles from `COUNTDOWN_SMALL`, without replacement, and
+/// pick a random three-digit target, as in the television show. The draw only
+/// depends on `seed`, so that the same round can be drawn again. Returns
+/// `None` if more large tiles are asked for than there are.
+pub fn countdown_draw(large: usize, seed: u64) -> Option<Draw>
+{
+    if large > COUNTDOWN_LARGE.len()
+    {
+        return None;
+    }
+
+    let mut rng = SplitMix::new(seed, &[]);
+    let mut large_tiles = COUNTDOWN_LARGE;
+    let mut small_tiles = COUNTDOWN_SMALL;
+    rng.shuffle(&mut large_tiles);
+    rng.shuffle(&mut small_tiles);
+    let mut nrs = large_tiles[..large].to_vec();
+    nrs.extend_from_slice(&small_tiles[..COUNTDOWN_TILES - large]);
+    let target = 100 + rng.next_u64() % 900;
+    Some(Draw { nrs, target })
+}
+
+/// Draw a Countdown numbers round with a unique solution.
+///
+/// Draw rounds as in `countdown_draw()`, for the seeds `seed`, `seed+1`, and
+/// so on, until a round is found with exactly one essentially different exact
+/// solution, as found by `essential_solutions()` subject to the options in
+/// `opts`, and return it with its solution. Since a full enumeration of the
+/// solutions is needed for every round drawn, this can take a while. Returns
+/// `None` if more large tiles are asked for than there are.
+pub fn unique_draw(large: usize, seed: u64, opts: &Options) -> Option<(Draw, Expr)>
+{
+    let mut seed = seed;
+    loop
+    {
+        let draw = countdown_draw(large, seed)?;
+        let mut exprs = essential_solutions(&draw.nrs, &Target::new(draw.target), opts);
+        if exprs.len() == 1
+        {
+            return Some((draw, exprs.remove(0)));
+        }
+        seed = seed.wrapping_add(1);
+    }
+}
+
+/// Score a declaration in a Countdown numbers round.
+///
+/// Award points for a declared expression `expr` for `target`, following the
+/// scoring rules of the television show: 10 points for hitting the target
+/// exactly, 7 points for being at most 5 away, and 5 points for being at most
+/// 10 away. A declaration further from the target scores nothing. Checking that
+/// the expression obeys the rules, i.e. uses every tile at most once and only
+/// has integer intermediate results, is left to the parser.
+pub fn countdown_points(expr: &Expr, target: u64) -> u32
+{
+    let distance = Target::new(target).distance(expr.val);
+    if distance == Rat::from_integer(0)
+    {
+        10
+    }
+    else if distance <= Rat::from_integer(5)
+    {
+        7
+    }
+    else if distance <= Rat::from_integer(10)
+    {
+        5
+    }
+    else
+    {
+        0
+    }
+}
+
+/// Draw the puzzle of the day.
+///
+/// Draw a Countdown numbers round as in `countdown_draw()`, with a seed and a
+/// number of large tiles, from one to four, that only depend on `day`, the
+/// number of days since 1 January 1970. Everyone drawing the puzzle for the
+/// same day therefore gets the same one.
+pub fn daily_draw(day: u64) -> Draw
+{
+    let seed = SplitMix::new(day, &[]).next_u64();
+    let large = 1 + (seed % COUNTDOWN_LARGE.len() as u64) as usize;
+    countdown_draw(large, seed).unwrap()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn multisets_in_order()
+    {
+        let sets = multisets(2, 1, 3).collect::<Vec<_>>();
+        assert_eq!(sets, [vec![1, 1], vec![1, 2], vec![1, 3], vec![2, 2], vec![2, 3], vec![3, 3]]);
+        assert_eq!(multisets(3, 1, 5).count(), 35);
+        assert_eq!(multisets(2, 3, 1).count(), 0);
+    }
+
+    #[test]
+    fn essentially_different_solutions()
+    {
+        let opts = Options::default();
+        let target = Target::new(24);
+        // 2*3*4, 3*2*4, (2*3)*4, ... are all the same
+        let exprs = essential_solutions(&[2, 3, 4], &target, &opts);
+        assert_eq!(exprs.len(), 1);
+        let found = numbers_for(&target, 2, 1, 12, (1, 1), &opts)
+            .map(|(nrs, _)| nrs)
+            .collect::<Vec<_>>();
+        assert_eq!(found, [vec![2, 12], vec![3, 8], vec![4, 6], vec![12, 12]]);
+    }
+
+    #[test]
+    fn draw_from_tiles()
+    {
+        for large in 0..=4
+        {
+            let draw = countdown_draw(large, 17).unwrap();
+            assert_eq!(draw, countdown_draw(large, 17).unwrap());
+            assert_eq!(draw.nrs.len(), COUNTDOWN_TILES);
+            assert!(draw.nrs[..large].iter().all(|nr| COUNTDOWN_LARGE.contains(nr)));
+            assert!(draw.nrs[large..].iter().all(|nr| COUNTDOWN_SMALL.contains(nr)));
+            assert!((100..=999).contains(&draw.target));
+        }
+        assert_eq!(countdown_draw(5, 17), None);
+    }
+
+    #[test]
+    fn points_for_declaration()
+    {
+        let nrs = [950, 2, 5, 10, 11];
+        let points = |s| countdown_points(&parse(s, &nrs).unwrap(), 952);
+        assert_eq!(points("950+2"), 10);
+        assert_eq!(points("950"), 7);
+        assert_eq!(points("950+2-5"), 7);
+        assert_eq!(points("950+2-10"), 5);
+        assert_eq!(points("950+2-11"), 0);
+    }
+
+    #[test]
+    fn daily_draw_depends_on_day()
+    {
+        assert_eq!(daily_draw(20000), daily_draw(20000));
+        assert_ne!(daily_draw(20000), daily_draw(20001));
+        // At least one large tile
+        assert!(COUNTDOWN_LARGE.contains(&daily_draw(20000).nrs[0]));
+    }
+
+    #[test]
+    fn draw_with_unique_solution()
+    {
+        // Solutions of at most three tiles, to keep the search short
+        let opts = Options { subset: true, max_ops: Some(2), ..Options::default() };
+        let (draw, expr) = unique_draw(1, 3, &opts).unwrap();
+        assert_eq!(expr.val, draw.target.into());
+        assert_eq!(essential_solutions(&draw.nrs, &Target::new(draw.target), &opts).len(), 1);
+        assert_eq!(unique_draw(5, 3, &opts), None);
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{Cache, Expr, Idx, Options, Target};
+use crate::partition::{difference, sub_multisets, unique_indices};
+use crate::search::get_nearest_expression_cached;
+
+/// Assign the numbers indexed by `idxs` to groups hitting `targets`.
+///
+/// Recursively try to find a group of numbers for the first target, and an
+/// assignment of the remaining numbers to the other targets. On success,
+/// the expressions for the groups are appended to `res`.
+fn assign(nrs: &[u64], idxs: &[Idx], targets: &[Target], opts: &Options, cache: &mut Cache,
+    res: &mut Vec<Expr>) -> bool
+{
+    if targets.len() == 1
+    {
+        let expr = get_nearest_expression_cached(nrs, idxs, &targets[0], opts, cache);
+        if !expr.is_empty() && targets[0].contains(expr.val)
+        {
+            res.push(expr);
+            return true;
+        }
+        return false;
+    }
+
+    let max_size = idxs.len() - (targets.len() - 1);
+    for sub_idxs in sub_multisets(idxs).into_iter().take_while(|s| s.len() <= max_size)
+    {
+        let expr = get_nearest_expression_cached(nrs, &sub_idxs, &targets[0], opts, cache);
+        if !expr.is_empty() && targets[0].contains(expr.val)
+        {
+            res.push(expr);
+            if assign(nrs, &difference(idxs, &sub_idxs), &targets[1..], opts, cache, res)
+            {
+                return true;
+            }
+            res.pop();
+        }
+    }
+
+    false
+}
+
+/// Split the numbers into groups that each hit their own target.
+///
+/// Find a way to split the numbers in `nrs` into disjoint groups, one for
+/// each target in `targets`, such that for every group an expression using
+/// all numbers in the group hits the corresponding target exactly. On
+/// success, the expressions for the groups are returned in the order of the
+/// targets, otherwise `None` is returned.
+pub fn solve_groups(nrs: &[u64], targets: &[Target], opts: &Options) -> Option<Vec<Expr>>
+{
+    if targets.is_empty() || targets.len() > nrs.len()
+    {
+        return None;
+    }
+
+    let group_opts = Options { subset: false, print_intermediate: false, ..opts.clone() };
+    let mut cache = Cache::default();
+    let mut res = vec![];
+    if assign(nrs, &unique_indices(nrs), targets, &group_opts, &mut cache, &mut res)
+    {
+        Some(res)
+    }
+    else
+    {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::partition::unique_indices;
+
+    #[test]
+    fn split_into_groups()
+    {
+        let nrs = [2, 3, 4, 5, 6];
+        let targets = [Target::new(20), Target::new(9)];
+        let exprs = solve_groups(&nrs, &targets, &Options::default()).unwrap();
+        assert_eq!(exprs[0].val, 20.into());
+        assert_eq!(exprs[1].val, 9.into());
+        // Every number is used in exactly one group
+        let mut used = exprs.iter().flat_map(|expr| expr.indices()).collect::<Vec<_>>();
+        used.sort_unstable();
+        assert_eq!(used, unique_indices(&nrs));
+
+        assert!(solve_groups(&nrs, &[Target::new(1000), Target::new(1)], &Options::default())
+            .is_none());
+        assert!(solve_groups(&[1], &targets, &Options::default()).is_none());
+    }
+}
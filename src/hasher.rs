@@ -0,0 +1,77 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use core::hash::BuildHasher;
+
+/// Hasher for the sets of values and the cache in the search
+///
+/// Struct `FastHash` builds the hashers used in the hot path of the search,
+/// for the sets of values seen for a set of numbers, and for the cache of
+/// sub-expressions. The hash function is chosen at compile time: with the
+/// feature `xxhash`, which is enabled by default, it is the 64-bit xxHash
+/// from the `fasthash` crate, otherwise it is FxHash from the `rustc-hash`
+/// crate, which also works without `std`, and `fasthash` is not needed.
+/// Neither is resistant to collision attacks, which is not a concern for the
+/// values made from a few numbers.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FastHash;
+
+#[cfg(feature = "xxhash")]
+impl BuildHasher for FastHash
+{
+    type Hasher = fasthash::xx::Hasher64;
+
+    fn build_hasher(&self) -> Self::Hasher
+    {
+        fasthash::xx::Hasher64::default()
+    }
+}
+
+#[cfg(not(feature = "xxhash"))]
+impl BuildHasher for FastHash
+{
+    type Hasher = rustc_hash::FxHasher;
+
+    fn build_hasher(&self) -> Self::Hasher
+    {
+        rustc_hash::FxHasher::default()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use core::hash::Hasher;
+
+    use super::*;
+    use crate::{HashSet, NormalizedRat, Rat};
+
+    #[test]
+    fn hashes_are_deterministic()
+    {
+        let hash = |bytes: &[u8]| {
+                let mut hasher = FastHash.build_hasher();
+                hasher.write(bytes);
+                hasher.finish()
+            };
+        assert_eq!(hash(b"0_1_2"), hash(b"0_1_2"));
+        assert_ne!(hash(b"0_1_2"), hash(b"0_1_3"));
+
+        let mut seen = HashSet::with_hasher(FastHash);
+        assert!(seen.insert(NormalizedRat(Rat::new(1, 2))));
+        assert!(!seen.insert(NormalizedRat(Rat::new(2, 4))));
+        assert!(seen.insert(NormalizedRat(Rat::new(1, 3))));
+    }
+}
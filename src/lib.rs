@@ -0,0 +1,272 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A library for creating arithemtic expression that evaluate to a certain number,
+//!
+//! This library receives a list of input numbers and a target number. Using
+//! all input numbers it creates an arithmetic expression using only addition,
+//! subtraction, multiplication and division operations, that evaluates to the
+//! target number. In case this is not possible, it finds the expression that
+//! evaluates to a number that is a close to the target as possible.
+//!
+//! The main entry point is `get_nearest_expression()`, e.g.
+//! ```
+//! let nrs = [1, 3, 4, 6];
+//! let target = makeexpr::Target::new(24);
+//! let expr = makeexpr::get_nearest_expression(&nrs, &target, &makeexpr::Options::default());
+//! assert_eq!(expr.to_string(&nrs), "6/(1-3/4)");
+//! ```
+//!
+//! Besides the four basic operations, custom binary operations implementing
+//! the `BinaryOp` trait can be registered in the search options with
+//! `Options::add_binary_op()`, and similarly custom unary operations
+//! implementing `UnaryOp` with `Options::add_unary_op()`. Use
+//! `Expr::to_string_with()` to format expressions containing such operations.
+//! A number of common extra operations, like `Gcd`, `Lcm`, `Min`, `Max`, `Pow`
+//! and `Log`, are provided by this library, as well as the unary operation
+//! `Decimal`, which allows a digit `n` to be used as `0.n`.
+//!
+//! A search can also be configured step by step with `Solver::builder()`,
+//! which returns a `SolverBuilder`, e.g.
+//! ```
+//! use std::time::Duration;
+//!
+//! let solver = makeexpr::Solver::builder().numbers(&[1, 3, 4, 6]).target(24)
+//!     .ops(makeexpr::OpSet::default()).timeout(Duration::from_secs(10)).build().unwrap();
+//! assert_eq!(solver.solve().to_string(&[1, 3, 4, 6]), "6/(1-3/4)");
+//! ```
+//!
+//! The solver core also works without the standard library, using only `core`
+//! and `alloc`, when the default feature `std` is disabled, e.g. to run it on
+//! an embedded device. Printing of intermediate results, timeouts, tracing
+//! and the analysis and statistics functions require `std`.
+//!
+//! To follow a search as it proceeds, e.g. in a user interface, register an
+//! implementation of the `Observer` trait as `Options::observer`. It is told
+//! about every new best expression, every completed step of the search, and
+//! the end of the search.
+//!
+//! When the progress is shown at a lower rate than the search reports it, a
+//! `ProgressChannel` collects the progress, best value and estimated time
+//! remaining of a search, and sends them as `ProgressUpdate`s over a channel
+//! at most once per configured interval.
+//!
+//! A search can be cancelled from another thread, e.g. by a user interface or
+//! a request timer, through a `CancellationToken`. `Solver::solve_cancellable()`
+//! then returns the best expression found before the search was cancelled, if
+//! any, together with the number of steps completed.
+//!
+//! With the `async` feature, a search can also be awaited from async code,
+//! with `Solver::solve_async()`, or followed as a stream of improvements, with
+//! `Solver::solve_stream()`. The searches run on a pool of worker threads, so
+//! that they do not block the threads of the async runtime.
+//!
+//! When many targets are asked for the same numbers, a `Session` enumerates
+//! the expressions over the numbers once, and then finds the nearest
+//! expression for every target with a binary search in the distinct values.
+//! It also lists all ways to make a given value, with
+//! `Session::expressions_for()`. When only a yes or no answer is needed for a
+//! single target, `is_reachable()` stops as soon as the target is made.
+//! Searches over different numbers can share the expressions over the numbers
+//! they have in common through a `SharedCache` in their options, e.g. on a
+//! server answering many puzzles.
+//!
+//! For the Countdown numbers game, a `CountdownTable` holds the targets that
+//! can be reached with every selection of tiles, so that the nearest reachable
+//! target is found without a search, and only an expression hitting it has to
+//! be searched for.
+//!
+//! A long search can be checkpointed with `SolverState`, which holds the
+//! puzzle, the search configuration and the progress of the search, and can be
+//! serialized to JSON, e.g. to resume a cancelled search later or elsewhere.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+mod analysis;
+#[cfg(feature = "std")]
+mod bank;
+#[cfg(feature = "std")]
+mod bitset;
+mod cancel;
+mod canonical;
+#[cfg(feature = "std")]
+mod countdown;
+#[cfg(feature = "std")]
+mod channel;
+#[cfg(feature = "std")]
+mod estimate;
+mod evolve;
+mod expr;
+mod format;
+mod generate;
+mod groups;
+mod hasher;
+#[cfg(feature = "async")]
+mod nonblocking;
+mod operator;
+mod options;
+mod parse;
+mod partition;
+mod progress;
+mod roman;
+mod schema;
+mod score;
+mod search;
+#[cfg(feature = "std")]
+mod seen;
+mod session;
+#[cfg(feature = "std")]
+mod shared;
+mod shuffle;
+mod simplify;
+mod small;
+#[cfg(feature = "std")]
+mod spill;
+mod solver;
+#[cfg(feature = "std")]
+mod state;
+#[cfg(feature = "std")]
+mod stats;
+mod strategy;
+#[cfg(feature = "std")]
+mod table;
+mod target;
+#[cfg(feature = "std")]
+mod trace;
+mod template;
+
+#[cfg(feature = "std")]
+pub use crate::analysis::{contributions, explain_infeasible, hardest_targets, leave_one_out,
+    pareto_front, reachability, suggest_extra, Contribution, TargetReach, MAX_SEARCHED_EXTRAS};
+#[cfg(feature = "xxhash")]
+pub use crate::analysis::{certify, Certificate, SubsetSummary};
+#[cfg(feature = "std")]
+pub use crate::bank::{bank_from_json, bank_to_json, puzzle_difficulty, Puzzle};
+#[cfg(feature = "std")]
+pub use crate::bitset::ReachableSet;
+pub use crate::cancel::CancellationToken;
+pub use crate::canonical::{canonical_expr, canonical_form};
+#[cfg(feature = "std")]
+pub use crate::countdown::{countdown_selections, CountdownTable, COUNTDOWN_MAX_TARGET,
+    COUNTDOWN_MIN_TARGET};
+#[cfg(feature = "std")]
+pub use crate::channel::{ProgressChannel, ProgressUpdate};
+#[cfg(feature = "std")]
+pub use crate::canonical::canonical_hash;
+#[cfg(feature = "std")]
+pub use crate::estimate::{estimate_search, SearchEstimate};
+pub use crate::expr::Expr;
+pub use crate::format::{format_expr, format_expr_with, format_markdown, Brackets, Format, Style};
+pub use crate::generate::{countdown_draw, countdown_points, daily_draw, essential_solutions,
+    multisets, numbers_for, unique_draw, Draw, Multisets, COUNTDOWN_LARGE, COUNTDOWN_SMALL,
+    COUNTDOWN_TILES};
+pub use crate::groups::solve_groups;
+pub use crate::hasher::FastHash;
+#[cfg(feature = "async")]
+pub use crate::nonblocking::{SolutionStream, SolveFuture};
+pub use crate::operator::{builtin_binary_op, builtin_unary_op, BinaryOp, Decimal, Gcd, Lcm, Log,
+    Max, Min, Pow, UnaryOp, MAX_BINARY_OPS, MAX_UNARY_OPS};
+pub use crate::options::{Options, Shape};
+pub use crate::parse::{parse, parse_styled, parse_with, ParseError};
+pub use crate::partition::{partitions, sub_multisets, unique_indices};
+pub use crate::progress::{Observer, Progress, Solution, Stats};
+pub use crate::roman::{parse_roman, to_roman, MAX_ROMAN};
+pub use crate::schema::{BANK_SCHEMA, EXPRESSION_SCHEMA, SCHEMA_VERSION};
+pub use crate::score::{elegance_score, preferred_solution, Scoring};
+pub use crate::search::{distinct_values, exact_solutions, get_nearest_expression,
+    get_nearest_expression_cached, get_nearest_expression_containing, is_reachable, Cache};
+pub use crate::session::Session;
+#[cfg(feature = "std")]
+pub use crate::shared::SharedCache;
+pub use crate::simplify::simplify;
+#[cfg(feature = "std")]
+pub use crate::spill::SpillCache;
+pub use crate::solver::{BuildError, OpSet, Solver, SolverBuilder};
+#[cfg(feature = "std")]
+pub use crate::state::{SolverConfig, SolverState};
+#[cfg(feature = "std")]
+pub use crate::stats::{cache_stats, CacheCounters, CacheSizeStats};
+pub use crate::strategy::{Strategy, DEFAULT_BEAM_WIDTH, DEFAULT_EVOLVE_GENERATIONS,
+    DEFAULT_HYBRID_DEPTH, DEFAULT_SAMPLE_ITERATIONS};
+#[cfg(feature = "std")]
+pub use crate::table::ValueTable;
+pub use crate::target::Target;
+#[cfg(feature = "std")]
+pub use crate::trace::Trace;
+pub use crate::template::{fill_template, Template, MAX_OPERATOR_BLANKS};
+
+/// Type alias for a rational number (i.e. fraction)
+pub type Rat = num_rational::Ratio<u64>;
+/// Type for an index.
+///
+/// Values of this type are used as an index in the array of input numbers.
+/// The highest four possible index values are reserved for encoding the
+/// operations.In the (highly unlikely) case that you wish to use this program
+/// with more than 252 input numbers, change this type to `u16` or wider.
+/// Typically, though, memory or time constraints limit the use of this program
+/// to approximately 10 distinct input numbers.
+pub type Idx = u8;
+/// The type for a single operation.
+///
+/// Values of this type are used either as an index in the array of input
+/// numbers, or are one of the special values `ADD`..`DIV` that indicate
+/// an operation on the previous two values in the stack.
+pub type Op = Idx;
+
+pub const ADD: Op = Op::MAX;
+pub const SUB: Op = Op::MAX - 1;
+pub const MUL: Op = Op::MAX - 2;
+pub const DIV: Op = Op::MAX - 3;
+
+/// The maximum number of input numbers that can be encoded in an `Idx`.
+///
+/// Below the four basic operations, `MAX_BINARY_OPS` values are reserved for
+/// encoding custom binary operations, followed by `MAX_UNARY_OPS` values for
+/// custom unary operations.
+pub const MAX_NUMBERS: usize = Op::MAX as usize - 4 - MAX_BINARY_OPS - MAX_UNARY_OPS;
+
+#[cfg(feature = "std")]
+pub(crate) use std::collections::{HashMap, HashSet};
+#[cfg(not(feature = "std"))]
+pub(crate) use hashbrown::{HashMap, HashSet};
+
+/// Wrapper for hashing rational numbers.
+///
+/// The default hash function for Ratio<T> goes out of its way to ensure that
+/// unnormalized numbers give the same hash as their normalized counterparts.
+/// However, the numbers constructed in this program are all normalized,
+/// so we can get away with a much simpler hashing function. To immplement that,
+/// the number is wrapped in a wrapper type, and a simple hash implementation is
+/// provided for the wrapper.
+struct NormalizedRat(Rat);
+
+impl PartialEq for NormalizedRat
+{
+    fn eq(&self, other: &Self) -> bool
+    {
+        self.0 == other.0
+    }
+}
+impl Eq for NormalizedRat {}
+impl ::core::hash::Hash for NormalizedRat
+{
+    fn hash<H: ::core::hash::Hasher>(&self, state: &mut H)
+    {
+        state.write_u64(*self.0.numer());
+        state.write_u64(*self.0.denom());
+    }
+}
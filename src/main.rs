@@ -22,525 +22,3669 @@
 //!
 //! The program can be used by passing the input numbers and the target number
 //! on the command line:
-//! ```
-//! makeexpr number [number ...] target
+//! ```text
+//! makeexpr [options] number [number ...] target
 //! ```
 //! The best expression found will then be printed on stdout.
 //! As an example,
-//! ```
+//! ```text
 //! makeexpr 1 3 4 6 24
 //! ```
 //! will result in output
-//! ```
+//! ```text
 //! 6/(1-3/4) = 24
 //! ```
+//! Numbers may be written with a leading `+`, with underscores between the
+//! digits, e.g. `1_000_000`, or in scientific notation, e.g. `1e6`.
+//!
+//! Solving is the default command of the program, which can also be given
+//! explicitly, as in `makeexpr solve 1 3 4 6 24`. The other tasks of the
+//! program are commands of their own, described below: `generate` (also
+//! `draw`) for drawing a puzzle, `verify` (also `import`) for checking a puzzle
+//! bank, `spectrum` (also `stats`) for the reachability of a range of targets,
+//! `bench` (also `compare`) for comparing search strategies, `serve` for the
+//! HTTP service, and `play` for the game, among others. The global options
+//! `--log-format`, `--base` and `--roman` apply to every command, and may be
+//! given before or after its name.
+//!
+//! A long search can be interrupted with Ctrl-C, after which the best
+//! expression found so far is printed. For large inputs, a progress bar with
+//! an estimate of the time remaining is shown on `stderr` instead of the
+//! intermediate results, if it is a terminal.
+//!
+//! The following options are supported:
+//! * `--without i`: find the best expression without using the `i`'th input
+//!   number (counting from 1). If `i` is `all`, the best result is reported
+//!   for every input number left out in turn.
+//! * `--contributions`: report for every input number whether it is required
+//!   in every exact solution, optional, or never useful. Exact solutions are
+//!   enumerated over all subsets of the input numbers.
+//! * `--all`: list all exact solutions, instead of only the first one found.
+//!   With `--subset`, solutions using any subset of the numbers are listed.
+//!   The solutions are sorted by elegance: shorter expressions, with fewer
+//!   divisions and smaller intermediate results, come first.
+//! * `--pareto`: list the trade-offs between closeness to the target and the
+//!   number of operations: for every number of operations, the nearest
+//!   expression is printed if it is nearer than any simpler one. Subsets of the
+//!   numbers are always considered.
+//! * `--distinct structural|value-path`: with `--all`, whether solutions that
+//!   differ only by commutativity or associativity, e.g. `1+2+3` and `3+(2+1)`,
+//!   are collapsed (`structural`), or all kept (`value-path`, the default).
+//! * `--quiet`: only print the final result, without intermediate results or
+//!   progress bar.
+//! * `--require-exact`: exit with status 3 if no exact solution is found, so
+//!   that scripts can tell whether a target can be hit. With `--quiet`,
+//!   nothing is printed in that case.
+//! * `--check`: only check if the target can be hit exactly, and print `yes`
+//!   or `no`. The search stops at the first exact value it makes, without
+//!   building an expression for it. If the target cannot be hit, the exit
+//!   status is 3, as with `--require-exact`, and with `--quiet` only the exit
+//!   status is given.
+//! * `--dry-run`: without solving, print the number of partitions at the top
+//!   level of the search, an estimate of the number of distinct values per
+//!   subset size, and the projected memory and time needed for the search. The
+//!   target number should be omitted from the command line.
+//! * `--integers-only`: only allow expressions whose intermediate results are
+//!   all integers.
+//! * `--explain`: if no exact solution is found, print a reason why the target
+//!   cannot be reached.
+//! * `--record file`: record the command line arguments, which determine the
+//!   configuration of the search and the seed for its order, the resulting
+//!   configuration, and the expression found, in `file`. Use
+//!   `makeexpr --replay file` to repeat the run, and check that it reproduces
+//!   the same configuration and expression.
+//! * `--trace file`: log every partition of the numbers considered in the
+//!   search, and every new best expression found, to `file`. See `Trace` in
+//!   the library for the format.
+//! * `--self-check`: parse the expression printed again, with the brackets and
+//!   numbers as written by `--brackets`, `--base` and `--roman`, evaluate it,
+//!   and check that its value is the value claimed, and that it uses all input
+//!   numbers (unless `--subset` is given), to catch formatting errors.
+//!   Expressions using custom operations are not checked.
+//! * `--dump-cache-stats`: after the search, print for every subset size the
+//!   number of entries in the cache of sub-expressions, the average number of
+//!   expressions per entry, their approximate memory use, and the fraction of
+//!   cache lookups that were hits. In order to report all entries, the cache
+//!   is not pruned during the search.
+//! * `--suggest-extra min..=max`: if no exact solution is found, report which
+//!   single extra number in the range would make the target reachable. If the
+//!   range holds more than 64 numbers, only the extra numbers combined with an
+//!   expression over the input numbers in a final operation are found.
+//! * `--certify`: if no exact solution is found, print a certificate listing
+//!   the number of distinct values, and a hash of these values, for every
+//!   subset of the input numbers, so that the exhaustiveness of the search can
+//!   be checked independently.
+//! * `--max-ops k`: only allow expressions with at most `k` binary operations.
+//! * `--strategy exhaustive|mitm|beam|sample|evolve|hybrid`: the strategy
+//!   used to search for the expression, exhaustive by default. Strategy `sample`
+//!   combines the numbers in random order, using random operations, for the
+//!   number of times given by `--iterations N`, and keeps the best expression
+//!   found, which gives a usable answer for inputs too large for an exhaustive
+//!   search. Strategy `evolve` improves a population of such expressions by
+//!   mutation and crossover, for `--iterations N` generations. Strategy
+//!   `hybrid` enumerates the values of groups of at most `--hybrid-depth k`
+//!   numbers exhaustively, and combines them heuristically.
+//! * `--memory-limit size`: keep at most `size` bytes (with an optional suffix
+//!   `K`, `M` or `G`) of sub-expressions in memory, and spill the rest to a
+//!   temporary file.
+//! * `--jobs N`: use `N` threads for the search, all cores by default. The
+//!   expression found does not depend on the number of threads.
+//! * `--deterministic`: only print results that do not depend on timing. The
+//!   expression found never depends on the number of threads, but with this
+//!   option, an interrupted search exits without printing the best expression
+//!   found so far, since that depends on how far the threads got.
+//! * `--target-range min..=max`: any value between `min` and `max` (inclusive)
+//!   counts as an exact hit. When this option is given, the target number
+//!   should be omitted from the command line.
+//! * `--targets-any t1,t2,...`: any value hitting one of the targets counts as
+//!   an exact hit, and otherwise the distance to the nearest target is
+//!   minimized. Each target can be a number or a range `min..=max`. When this
+//!   option is given, the target number should be omitted from the command
+//!   line.
+//! * `--groups "t1;t2;..."`: split the input numbers into disjoint groups, such
+//!   that the numbers in each group make an expression hitting the
+//!   corresponding target exactly. Each target can be a number or a range
+//!   `min..=max`. When this option is given, the target number should be
+//!   omitted from the command line.
+//! * `--input file`: solve every puzzle in `file`, one per line, either in the
+//!   form of the command line, e.g. `1 3 4 6 24 --integers-only`, or as a
+//!   JSON object, e.g. `{"numbers":[1,3,4,6],"target":24,"integers_only":true}`.
+//!   A line may give its own options `--integers-only`, `--subset`,
+//!   `--extra-ops`, `--max-ops` and `--target` (for a range), which add to the
+//!   options on the command line. The results are printed one per line, in
+//!   the format given with `--format`, to the file given with `--output`, or
+//!   to `stdout`. With `--parallel`, the puzzles are solved concurrently, on
+//!   as many threads as given with `--jobs`, rather than one after another.
+//!   The results are written in the order of the puzzles, every result as soon
+//!   as the puzzles before it are solved. The expressions over the numbers that
+//!   puzzles have in common, like `25 50 75`, are shared between the puzzles,
+//!   so that they are generated only once.
+//! * `--targets-stdin`: read targets from `stdin`, one per line, and print the
+//!   nearest expression for each on a line of its own. The target number
+//!   should be omitted from the command line. The expressions over the
+//!   numbers are enumerated once, before the first target is read, so that
+//!   every target is answered by a lookup in the distinct values.
+//! * `--watch file`: as `--input file`, but solve the puzzles again every time
+//!   the file is changed, e.g. while editing it, until interrupted.
+//! * `--numbers-cols cols`, `--target-col col`: with `--input`, read the
+//!   puzzles from the rows of a CSV file, with the numbers in columns `cols`,
+//!   e.g. `1-6` or `1,3,5-7`, and the target in column `col`, counting from 1.
+//!   By default, the numbers are in all columns but the last, which holds the
+//!   target; this also applies to files whose name ends in `.csv`. Every row
+//!   is written with the columns `expression`, `value` and `error` appended.
+//! * `--subset`: allow expressions that use only some of the input numbers. Of
+//!   the expressions that come equally close to the target, the one using the
+//!   fewest numbers is printed.
+//! * `--prune-dominated`: with `--subset`, do not keep sub-expressions whose
+//!   value can also be made with fewer of their numbers. The best expression
+//!   is still found, but `--all` no longer lists the solutions that use
+//!   numbers without need, like `x*1`.
+//! * `--target t`: the target, either a number or a range `min..=max`. When
+//!   this option is given, the target number should be omitted from the
+//!   command line.
+//! * `--format plain|rust|python|c|json|postfix|prefix|excel|excel-cells|serialized|markdown`:
+//!   print the expression in the given format. The code formats print only the
+//!   expression, with the input numbers written as floating point literals,
+//!   e.g. `6.0/(1.0-3.0/4.0)`. Format `json` prints a JSON object with the
+//!   schema version, the expression, its value, and its elegance score. Formats `postfix` and
+//!   `prefix` print the expression in (reverse) Polish notation, like
+//!   `/ 6 - 1 / 3 4`. Format `excel` prints a spreadsheet formula like
+//!   `=6/(1-3/4)`, and `excel-cells` a formula referencing the input numbers in
+//!   cells `A1`, `A2`, ..., like `=A4/(A1-A2/A3)`. Format `serialized` prints
+//!   the expression in a form referencing the input numbers by position, like
+//!   `#3 #0 #1 #2 / - /`, for use with the `eval` command below. Format
+//!   `markdown` prints a Markdown fragment, with the expression as code, a
+//!   table of its steps, and how far it is off if it misses the target, e.g.
+//!   for a chat bot or an issue. Intermediate results are not printed in these
+//!   formats.
+//! * `--shuffle`: visit the candidate expressions in a pseudo-random order, so
+//!   that a different, but equally good, expression may be found. The order
+//!   is determined by the seed given with `--seed N` (0 by default), so runs
+//!   with the same seed give the same result.
+//! * `--prefer-small-intermediates`: of the exact solutions, print the one
+//!   whose largest intermediate result is smallest, which is easiest to check
+//!   by mental arithmetic. With `--all`, the solutions are sorted this way.
+//! * `--prefer-avoid ops`: of the exact solutions, print one that uses the
+//!   fewest of the operations in `ops`, e.g. `/`. Unlike leaving operations
+//!   out, this still finds solutions that need them.
+//! * `--weights w,w,...`: the cost of using each of the numbers, in order. Of
+//!   the exact solutions, print one with the lowest total cost of the numbers
+//!   it uses. This is mostly useful with `--subset`.
+//! * `--brackets round|nested`: the style of the brackets in the plain output
+//!   format. With `nested`, parentheses, square brackets and braces are used
+//!   by nesting level, from the inside out, e.g. `{[6-(1+3)]*4-1}/7`.
+//! * `--base b`: read numbers without a prefix in base `b`, from 2 to 36, and
+//!   print the plain output in that base. Numbers can always be written with
+//!   a prefix `0x`, `0o` or `0b` for bases 16, 8 and 2, and without `--base`,
+//!   the output is printed in the base of the target if it has a prefix.
+//! * `--roman`: numbers may also be written in Roman numerals, e.g. `XXIV`.
+//!   If the target is, the plain output is printed in Roman numerals as well.
+//! * `--log-format plain|json`: with `json`, diagnostics like a file that
+//!   cannot be read, and events like the start and the result of a search,
+//!   are logged on `stderr` as JSON objects, one per line, with the phase of
+//!   the program, the puzzle, and the elapsed time in seconds. This option
+//!   also applies to the commands below, e.g. to log the requests to `serve`.
+//! * `--json-errors`: report invalid arguments, numbers and expressions that
+//!   cannot be parsed, violated limits and interrupted searches as a JSON
+//!   object on `stderr`, with an error code, a message and the offending
+//!   argument, instead of a message and the usage text.
+//! * `--schema expression|bank|service|log|error`: print the JSON Schema of
+//!   the `json` output format, puzzle banks, the responses of the service, the
+//!   log lines, or the errors respectively, and exit. Every JSON document written by the
+//!   program has a field `schema_version`, which is increased when a field is
+//!   removed or changes its meaning.
+//! * `--canonical`: print results in a fixed form that does not depend on the
+//!   order in which the search visits expressions: sums and products are
+//!   flattened, with their operands sorted by decreasing value, and the exact
+//!   solution printed is the most elegant one, with ties broken by its text.
+//!   With `--all`, the solutions are printed in this form and order as well.
+//!   Intermediate results are not printed.
+//! * `--simplify`: rewrite the expression found to remove parentheses where
+//!   possible without changing its value, e.g. `a-(b-c)` becomes `a-b+c`.
+//!   This option has no effect together with `--shape` or `--must-contain`.
+//! * `--forbid-value v`: no proper sub-expression may evaluate to `v`. This
+//!   option can be given multiple times to forbid several values.
+//! * `--extra-ops op,op,...`: allow extra binary operations besides the
+//!   basic four. Supported are `gcd` and `lcm`, the greatest common divisor
+//!   and least common multiple of two integers, `min` and `max`, `pow` for
+//!   raising to an integer power, and `log` for the integer logarithm, which is
+//!   only valid if the argument is an exact power of the base. These operations
+//!   are printed as function calls, e.g. `gcd(12,8)` or `log_2(8)`.
+//! * `--decimals`: allow every single digit input number `n` to be used as the
+//!   decimal fraction `0.n` as well, as in the "four fours" puzzle.
+//! * `--must-contain expr`: only allow expressions that contain the expression
+//!   `expr`, e.g. `"3/4"`, over some of the input numbers as a sub-expression.
+//! * `--shape balanced|chain`: constrain the shape of the expression tree. In a
+//!   `balanced` tree, every operation combines two halves using (nearly)
+//!   equally many numbers; a `chain` is strictly left-leaning, every operation
+//!   combining the expression thus far with a single number.
+//!
+//! Instead of searching over all expressions, the program can also fill in
+//! the blanks of a fixed expression template:
+//! ```text
+//! makeexpr fill "(_ + _) * _ - _" 3 5 7 9 --target 50
+//! ```
+//! Blanks `_` are filled with the input numbers, each number being used
+//! exactly once, and operator blanks `?` with any of the four operations.
+//!
+//! An expression printed with `--format serialized` and stored in a file can
+//! be evaluated for a different set of numbers:
+//! ```text
+//! makeexpr eval --expr-file expr.txt --numbers 2 5 7 9
+//! ```
+//! Pass `--decimals` to `eval` as well if the expression was found with it.
+//!
+//! The search strategies can be compared on a puzzle with
+//! ```text
+//! makeexpr compare --strategies exhaustive,mitm,beam -- 3 7 25 50 75 100 952
+//! ```
+//! which prints a table of the error, time and peak memory use of each
+//! strategy.
+//!
+//! The hardest targets in a range for a set of numbers, which need the most
+//! numbers, and have the fewest exact solutions, are listed with
+//! ```text
+//! makeexpr hardest 1..=999 -- 25 50 3 7 8 2
+//! ```
+//!
+//! Statistics of how well a set of numbers covers a range of targets are
+//! printed with
+//! ```text
+//! makeexpr stats 100..=999 -- 25 50 3 7 8 2
+//! ```
+//! With `--format histogram`, a bar chart of the fraction of reachable targets
+//! in every part of the range is printed instead. With `--bitset file`, the
+//! set of reachable targets is also written to `file` as a compact bitset.
+//!
+//! The targets in a range that can be reached by two sets of numbers are
+//! compared with
+//! ```text
+//! makeexpr diff --a 1,3,4,6 --b 2,5,8,9 --range 1..=100
+//! ```
+//!
+//! For a pool of numbers that is queried often, a table of all values that can
+//! be made from it can be generated once, and used instead of a search:
+//! ```text
+//! makeexpr table --output pool.tbl 1 2 3 4 5 6
+//! makeexpr --table pool.tbl 1 2 3 4 5 6 873
+//! ```
+//! For the Countdown numbers game, `--countdown` looks up the nearest
+//! reachable target in a table of every selection of tiles, which is
+//! generated on first use, or beforehand with
+//! `makeexpr table --countdown --output file`.
+//!
+//! The sub-expressions the search works with can be inspected interactively:
+//! ```text
+//! makeexpr inspect 1 3 4 6
+//! ```
+//! reads sets of numbers from the pool, like `3 4 6`, from standard input, and
+//! prints every value that can be made from each set, with an expression. A
+//! query `3 4 6 = 18` only prints the expression for 18, and `stats` prints
+//! the statistics of the cache.
+//!
+//! Conversely, sets of numbers from which a target can be made are found with
+//! ```text
+//! makeexpr numbers-for 2025 --count 5 --range 1..=20 --solutions 1..=3
+//! ```
+//! which tries all sets of 5 numbers from 1 to 20, and prints those with one
+//! to three essentially different exact solutions, i.e. solutions that differ
+//! by more than commutativity or associativity.
+//!
+//! A round of the Countdown numbers game, with two large tiles, is drawn and
+//! solved with
+//! ```text
+//! makeexpr draw --large 2
+//! ```
+//! Pass `--no-solve` to only print the tiles and the target, and `--unique`
+//! to only draw a round with a single essentially different solution. The
+//! round can also be played against the clock, with `makeexpr play --large 2`:
+//! after 30 seconds, or as many as given with `--time`, the expression typed
+//! is checked, and the best solution is revealed, together with a few other
+//! ways to make the value declared. A declaration is scored with
+//! ```text
+//! makeexpr score "75*4-6" 75 4 6 3 2 1 294
+//! ```
+//! which awards 10 points for an exact hit, 7 points for being within 5 of the
+//! target, and 5 points for being within 10, if the declaration keeps to the
+//! rules. `makeexpr daily` prints the puzzle of the day, which only depends on
+//! the date, so that a group can compete on the same puzzle. Past puzzles are
+//! printed with `--date yyyy-mm-dd`, and `--solve` reveals the solution.
+//!
+//! Rounds can be generated in bulk, with their solutions, into a puzzle bank
+//! file, a JSON object with the schema version and an array of objects with
+//! the numbers, the target, the solutions and a difficulty rating:
+//! ```text
+//! makeexpr export --count 100 --large 2 --output bank.json
+//! makeexpr import bank.json
+//! ```
+//! The `import` command verifies every solution in the bank.
+//!
+//! An interactive terminal interface, showing the progress of the search, the
+//! tree of the best expression found thus far, and afterwards a list of all
+//! exact solutions to browse, is started with
+//! ```text
+//! makeexpr tui 100 75 50 25 6 3 952
+//! ```
+//!
+//! The solver can also be run as an HTTP service, with
+//! ```text
+//! makeexpr serve --listen 127.0.0.1:8080
+//! ```
+//! which answers requests like `GET /solve?numbers=1,3,4,6&target=24` with a
+//! JSON object, and exposes Prometheus metrics at `GET /metrics`: the number of
+//! requests, a histogram of the solve durations, the number and fraction of
+//! exact solutions, and the number of results in its cache. As in batch mode,
+//! the expressions over numbers common to several requests are generated only
+//! once. A request may have at most 8 numbers (`--max-numbers N`), its search
+//! fails with status 503 after 10 seconds (`--timeout seconds`), and at most
+//! 10000 results are cached (`--max-cached N`). At most 256 connections are
+//! handled at the same time (`--max-connections N`), and a client that does
+//! not send its request within 30 seconds is disconnected.
 
+use makeexpr::{bank_from_json, bank_to_json, builtin_binary_op, cache_stats, canonical_expr,
+    canonical_hash, certify, contributions, countdown_draw, countdown_points, daily_draw,
+    distinct_values, estimate_search, exact_solutions, explain_infeasible, fill_template,
+    format_expr, format_expr_with, format_markdown, get_nearest_expression,
+    get_nearest_expression_cached, get_nearest_expression_containing, hardest_targets, is_reachable,
+    leave_one_out, numbers_for, pareto_front, parse_roman, parse_styled, parse_with,
+    preferred_solution, reachability, simplify, solve_groups, suggest_extra, unique_draw,
+    unique_indices, Brackets, Cache, CacheCounters, Contribution, CountdownTable, Decimal, Draw,
+    Expr, Format, Options, ParseError, Progress, Puzzle, Rat, ReachableSet, Scoring, Session, Shape,
+    SharedCache, SolverConfig, SpillCache, Strategy, Style, Target, TargetReach, Template, Trace,
+    ValueTable, ADD, BANK_SCHEMA, COUNTDOWN_LARGE, DIV, EXPRESSION_SCHEMA, MAX_BINARY_OPS,
+    MAX_NUMBERS, MUL, SCHEMA_VERSION, SUB};
+mod batch;
+mod serve;
+mod tui;
 
-use std::collections::HashMap;
-use arrayvec::ArrayVec;
-use fasthash::xx::Hash64;
-use num_traits::Zero;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
 
-/// Type alias for a rational number (i.e. fraction)
-type Rat = num_rational::Ratio<u64>;
-/// Type for an index.
-///
-/// Values of this type are used as an index in the array of input numbers.
-/// The highest four possible index values are reserved for encoding the
-/// operations.In the (highly unlikely) case that you wish to use this program
-/// with more than 252 input numbers, change this type to `u16` or wider.
-/// Typically, though, memory or time constraints limit the use of this program
-/// to approximately 10 distinct input numbers.
-type Idx = u8;
-/// The type for a single operation.
-///
-/// Values of this type are used either as an index in the array of input
-/// numbers, or are one of the special values `ADD`..`DIV` that indicate
-/// an operation on the previous two values in the stack.
-type Op = Idx;
+/// The flag set when the user interrupts the program
+static INTERRUPTED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
 
-const ADD: Op = Op::max_value();
-const SUB: Op = Op::max_value() - 1;
-const MUL: Op = Op::max_value() - 2;
-const DIV: Op = Op::max_value() - 3;
+/// The base of numbers without a prefix on the command line, if set by `--base`
+static INPUT_BASE: OnceLock<u32> = OnceLock::new();
+/// Whether numbers may be written in Roman numerals, as set by `--roman`
+static ROMAN_INPUT: AtomicBool = AtomicBool::new(false);
+/// Whether diagnostics are logged as JSON objects, as set by `--log-format json`
+static LOG_JSON: AtomicBool = AtomicBool::new(false);
+/// Whether errors are reported as JSON objects, as set by `--json-errors`
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+/// The command line argument being handled, reported as the offending argument
+/// of an error
+static CURRENT_ARG: ::std::sync::Mutex<Option<String>> = ::std::sync::Mutex::new(None);
+/// The time the program started, for the elapsed time in the log
+static LOG_START: OnceLock<::std::time::Instant> = OnceLock::new();
+/// The puzzle being solved, identifying it in the log
+static LOG_PUZZLE: OnceLock<String> = OnceLock::new();
 
-/// Wrapper for hashing rational numbers.
+/// Allocator keeping track of the memory in use
 ///
-/// The default hash function for Ratio<T> goes out of its way to ensure that
-/// unnormalized numbers give the same hash as their normalized counterparts.
-/// However, the numbers constructed in this program are all normalized,
-/// so we can get away with a much simpler hashing function. To immplement that,
-/// the number is wrapped in a wrapper type, and a simple hash implementation is
-/// provided for the wrapper.
-struct NormalizedRat(Rat);
+/// Struct `CountingAlloc` wraps the system allocator, counting the number of
+/// bytes currently allocated, and the peak of this number since it was last
+/// reset, so that the memory used by a search can be reported.
+struct CountingAlloc
+{
+    /// The number of bytes currently allocated
+    current: ::std::sync::atomic::AtomicUsize,
+    /// The highest number of bytes allocated since the last reset
+    peak: ::std::sync::atomic::AtomicUsize
+}
 
-impl PartialEq for NormalizedRat
+impl CountingAlloc
 {
-    fn eq(&self, other: &Self) -> bool
+    /// Reset the peak memory use to the current memory use.
+    fn reset_peak(&self) -> usize
+    {
+        let current = self.current.load(Ordering::Relaxed);
+        self.peak.store(current, Ordering::Relaxed);
+        current
+    }
+
+    /// The peak memory use since the last reset.
+    fn peak(&self) -> usize
     {
-        self.0 == other.0
+        self.peak.load(Ordering::Relaxed)
     }
 }
-impl Eq for NormalizedRat {}
-impl ::std::hash::Hash for NormalizedRat
+
+unsafe impl ::std::alloc::GlobalAlloc for CountingAlloc
 {
-    fn hash<H: ::std::hash::Hasher>(&self, state: &mut H)
+    unsafe fn alloc(&self, layout: ::std::alloc::Layout) -> *mut u8
+    {
+        let ptr = unsafe { ::std::alloc::System.alloc(layout) };
+        if !ptr.is_null()
+        {
+            let current = self.current.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            self.peak.fetch_max(current, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: ::std::alloc::Layout)
     {
-        state.write_u64(*self.0.numer());
-        state.write_u64(*self.0.denom());
+        unsafe { ::std::alloc::System.dealloc(ptr, layout) };
+        self.current.fetch_sub(layout.size(), Ordering::Relaxed);
     }
 }
 
-/// Structure describing an expression
-///
-/// Struct `Expr` stores an expression and the value it evaluates to. The
-/// expression is stored in reverse polish notation, and uses indices into
-/// a numbers array instead of the actual numbers themselves. The operators
-/// in the expression are encoded as the 4 greatest numbers that can be encoded
-/// in the index type. Thus, an expression like
-/// ```
-/// [0, 3, ADD, 2, MUL]
-/// ```
-/// will for a numbers array `nrs` evaluate to
-/// ```
-/// (nrs[0] + nrs[3]) * nrs[2]
-/// ```
-#[derive(Clone)]
-struct Expr
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc {
+    current: ::std::sync::atomic::AtomicUsize::new(0),
+    peak: ::std::sync::atomic::AtomicUsize::new(0)
+};
+
+/// The default number of bars in a histogram of reachable targets
+const HISTOGRAM_BUCKETS: usize = 20;
+/// The width of a full bar in a histogram of reachable targets
+const HISTOGRAM_WIDTH: usize = 50;
+
+/// The minimum number of input numbers for which a progress bar is shown
+const PROGRESS_MIN_NUMBERS: usize = 7;
+/// The exit status when no exact solution is found, with `--require-exact`, or
+/// when the target cannot be reached, with `--check`
+const INEXACT_STATUS: i32 = 3;
+
+/// The number of other ways to reach the declared value shown after a round of
+/// the `play` command
+const PLAY_WAYS: usize = 5;
+/// The interval at which a watched puzzle file is checked for changes
+const WATCH_INTERVAL: ::std::time::Duration = ::std::time::Duration::from_millis(500);
+/// The maximum number of bytes of sub-expressions shared between the puzzles
+/// solved in batch or server mode
+const SHARED_CACHE_LIMIT: usize = 256 << 20;
+
+/// What the program should compute
+enum Mode
 {
-    /// The expression itself
-    ops: Vec<Op>,
-    /// The resulting value of the expression
-    val: Rat
+    /// Find the expression nearest to the target
+    Solve,
+    /// Leave out the number at this (0-based) index
+    Without(usize),
+    /// Leave out every number in turn
+    WithoutAll,
+    /// Report the contribution of each number to the exact solutions
+    Contributions,
+    /// List all exact solutions
+    All,
+    /// List the trade-offs between closeness to the target and complexity
+    Pareto,
+    /// Only check if the target can be hit exactly
+    Check,
+    /// Split the numbers into groups, each hitting its own target
+    Groups(Vec<Target>),
+    /// Estimate the size of the search, without solving
+    DryRun,
+    /// Fill in the blanks of an expression template
+    Fill(Template),
+    /// Solve every puzzle in a puzzle file
+    Input(String),
+    /// Answer targets read from `stdin`, one per line
+    TargetsStdin
 }
 
-impl Expr
+/// Print a usage message, and exit.
+///
+/// With `--json-errors`, an error with code `invalid_arguments` is reported
+/// instead, naming the command line argument being handled, if any.
+fn usage() -> !
 {
-    /// Create a new expression.
-    ///
-    /// Create a new expression for the single number `nrs[idx]`.
-    fn new(nrs: &[u64], idx: Idx) -> Self
+    if JSON_ERRORS.load(Ordering::Relaxed)
     {
-        Expr { ops: vec![idx], val: Rat::from_integer(nrs[idx as usize]) }
+        let arg = CURRENT_ARG.lock().unwrap().clone();
+        json_error("invalid_arguments", "invalid command line arguments", arg.as_deref(), 1);
     }
+    println!("Usage: makeexpr [solve] [--without i|all] [--contributions] [--all] [--pareto]");
+    println!("                [--distinct structural] [--check]");
+    println!("                [--integers-only] [--explain] [--quiet] [--require-exact]");
+    println!("                [--dump-cache-stats] [--trace file] [--record file] [--self-check]");
+    println!("                [--certify] [--suggest-extra min..=max] [--max-ops k] [--subset]");
+    println!("                [--prune-dominated]");
+    println!("                [--shape balanced|chain]");
+    println!("                [--must-contain expr] [--forbid-value v ...] [--extra-ops op,...]");
+    println!("                [--decimals] [--jobs N] [--memory-limit size] [--table file]");
+    println!("                [--countdown] [--countdown-table file]");
+    println!("                [--strategy exhaustive|mitm|beam|sample|evolve|hybrid]");
+    println!("                [--iterations N] [--hybrid-depth k]");
+    println!("                [--format plain|rust|python|c|json|postfix|prefix|excel|excel-cells");
+    println!("                         |serialized|markdown]");
+    println!("                [--brackets round|nested] [--base b] [--roman]");
+    println!("                [--simplify] [--prefer-small-intermediates] [--prefer-avoid ops]");
+    println!("                [--weights w,w,...] [--deterministic] [--canonical]");
+    println!("                [--shuffle [--seed N]] number [number ...] target");
+    println!("       makeexpr [options] --target-range min..=max number [number ...]");
+    println!("       makeexpr [options] --targets-any t1,t2,... number [number ...]");
+    println!("       makeexpr [options] --groups \"t1;t2;...\" number [number ...]");
+    println!("       makeexpr [options] --dry-run number [number ...]");
+    println!("       makeexpr [options] --targets-stdin number [number ...]");
+    println!("       makeexpr [options] --input|--watch file [--output file] [--parallel]");
+    println!("                [--numbers-cols cols] [--target-col col]");
+    println!("       makeexpr --replay file");
+    println!("       makeexpr --schema expression|bank|service|log|error");
+    println!("       makeexpr bench [--strategies s,s,...] [--] number [number ...] target");
+    println!("       makeexpr hardest [--integers-only] [--count N] min..=max");
+    println!("                        [--] number [number ...]");
+    println!("       makeexpr spectrum [--integers-only] [--format plain|histogram] [--buckets N]");
+    println!("                         [--bitset file] min..=max [--] number [number ...]");
+    println!("       makeexpr diff [--integers-only] --a n,n,... --b n,n,... --range min..=max");
+    println!("       makeexpr table [--integers-only] [--subset] --output file");
+    println!("                      number [number ...]");
+    println!("       makeexpr table --countdown --output file");
+    println!("       makeexpr tui [--integers-only] [--subset] number [number ...] target");
+    println!("       makeexpr serve [--listen addr] [--integers-only] [--subset]");
+    println!("                      [--max-numbers N] [--timeout seconds] [--max-cached N]");
+    println!("                      [--max-connections N]");
+    println!("       makeexpr inspect [--integers-only] number [number ...]");
+    println!("       makeexpr generate [--large N] [--no-solve] [--unique] [--seed N]");
+    println!("       makeexpr play [--large N] [--time seconds] [--seed N]");
+    println!("       makeexpr daily [--date yyyy-mm-dd] [--solve]");
+    println!("       makeexpr export --output file [--count N] [--large N] [--unique] [--seed N]");
+    println!("       makeexpr verify [--integers-only] file");
+    println!("       makeexpr score expr number [number ...] target");
+    println!("       makeexpr numbers-for target --count N --range min..=max");
+    println!("                            [--solutions min..=max]");
+    println!("                            [--unique] [--limit N] [--integers-only] [--subset]");
+    println!("       makeexpr fill template [options] number [number ...] target");
+    println!("       makeexpr eval --expr-file file [--format f] [--decimals]");
+    println!("                     --numbers number [number ...]");
+    println!("Global options, for every command: [--log-format plain|json] [--json-errors]");
+    println!("                                   [--base b] [--roman]");
+    println!("The commands bench, spectrum, generate and verify can also be invoked as compare,");
+    println!("stats, draw and import.");
+    println!("--self-check does not check expressions using the operations of --extra-ops or");
+    println!("--decimals.");
+    ::std::process::exit(1);
+}
+
+/// Settings for printing the results
+struct Output
+{
+    /// The format in which expressions are printed
+    format: Format,
+    /// If true, the expression is simplified before it is printed
+    simplify: bool,
+    /// If true, and no exact solution was found, print the reason why the
+    /// target can not be reached
+    explain: bool,
+    /// If true, and no exact solution was found, print a certificate of the
+    /// exhaustive search
+    certificate: bool,
+    /// If true, print statistics of the cache of sub-expressions after the
+    /// search
+    cache_stats: bool,
+    /// If true, the printed expression is parsed and evaluated again, to
+    /// check that it is correct
+    self_check: bool,
+    /// If set, the maximum number of bytes of sub-expressions to keep in
+    /// memory in the search, the rest being spilled to disk
+    memory_limit: Option<usize>,
+    /// If set, a precomputed table of values, used instead of the search for
+    /// the numbers of its pool
+    table: Option<ValueTable>,
+    /// If set, the precomputed table of reachable Countdown targets, used to
+    /// find the nearest target that can be reached, see `--countdown`
+    countdown: Option<CountdownTable>,
+    /// The strategy used to search for the expression
+    strategy: Strategy,
+    /// If set, and no exact solution was found, suggest an extra number in
+    /// this range that makes the target reachable
+    suggest_extra: Option<(u64, u64)>,
+    /// The ranking of exact solutions
+    scoring: Scoring,
+    /// If true, don't print results of interrupted searches
+    deterministic: bool,
+    /// If true, print results in canonical order
+    canonical: bool,
+    /// The style of plain expressions
+    style: Style,
+    /// If true, exit with status `INEXACT_STATUS` if no exact solution is
+    /// found, see `--require-exact`
+    require_exact: bool,
+    /// If true, only print the final result, see `--quiet`
+    quiet: bool
+}
+
+/// Weight of the last step in the running average step time
+const ETA_SMOOTHING: f64 = 0.1;
+
+/// Estimate of the time remaining in a search
+///
+/// Struct `Eta` measures the time taken by each step of the search, and keeps
+/// an exponential moving average of it, so that the estimate adapts when the
+/// steps become faster or slower as the search proceeds.
+#[derive(Debug)]
+struct Eta
+{
+    /// The time at which the last step finished
+    last: ::std::time::Instant,
+    /// The average time per step in seconds, if any step has finished
+    mean: Option<f64>
+}
 
-    /// Create an empty expression.
-    ///
-    /// Create an empty expression that evaluates to zero.
-    fn empty() -> Self
+impl Eta
+{
+    fn new() -> Self
     {
-        Expr { ops: vec![], val: Rat::zero() }
+        Eta { last: ::std::time::Instant::now(), mean: None }
     }
 
-    fn possible_combinations(&self, expr: &Self) -> ArrayVec<[(char, Rat); 6]>
+    /// Record that a step has finished, and estimate the time needed for the
+    /// `remaining` steps still to be done.
+    fn step(&mut self, remaining: u64) -> ::std::time::Duration
     {
-        let mut res = ArrayVec::<[_; 6]>::new();
+        let now = ::std::time::Instant::now();
+        let secs = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
 
-        let op0 = *self.ops.last().unwrap();
-        let op1 = *expr.ops.last().unwrap();
-        let ops = match (op0, op1)
+        let mean = match self.mean
             {
-                (ADD, ADD) => "*/\\",
-                (ADD, SUB) => "*/\\",
-                (ADD, MUL) => "+-\\",
-                (ADD, DIV) => "+-\\",
-                (ADD,   _) => "+-*/\\",
-                (SUB, ADD) => "*/\\",
-                (SUB, SUB) => "*/\\",
-                (SUB, MUL) => "-\\",
-                (SUB, DIV) => "-\\",
-                (SUB,   _) => "-*/\\",
-                (MUL, ADD) => "*/",
-                (MUL, SUB) => "*/",
-                (MUL, MUL) => "+-_",
-                (MUL, DIV) => "+-_",
-                (MUL,   _) => "+-*/_",
-                (DIV, ADD) => "/_",
-                (DIV, SUB) => "/_",
-                (DIV, MUL) => "+-_",
-                (DIV, DIV) => "+-_",
-                (DIV,   _) => "+-/_",
-                _          => "+-*/_\\"
+                Some(mean) => ETA_SMOOTHING * secs + (1.0 - ETA_SMOOTHING) * mean,
+                None       => secs
             };
+        self.mean = Some(mean);
+        ::std::time::Duration::from_secs_f64(mean * remaining as f64)
+    }
+}
 
-        for op in ops.chars()
-        {
-            match op
-            {
-                '+' => {
-                    res.push((op, self.val + expr.val));
-                },
-                '-' => {
-                    if self.val >= expr.val
-                    {
-                        res.push((op, self.val - expr.val));
-                    }
-                },
-                '*' => {
-                    res.push((op, self.val * expr.val));
-                },
-                '/' => {
-                    if !expr.val.is_zero()
-                    {
-                        res.push((op, self.val / expr.val));
-                    }
-                },
-                '_' => {
-                    if expr.val >= self.val
-                    {
-                        res.push((op, expr.val - self.val));
-                    }
-                },
-                '\\' => {
-                    if !self.val.is_zero()
-                    {
-                        res.push((op, expr.val / self.val));
-                    }
-                },
-                _ => {}
-            }
-        }
+/// Progress bar for a long search
+///
+/// Struct `ProgressBar` shows the progress of the search on `stderr`, along
+/// with the distance to the target of the best expression found thus far,
+/// and an estimate of the time remaining.
+#[derive(Debug)]
+struct ProgressBar
+{
+    /// The progress bar itself
+    bar: indicatif::ProgressBar,
+    /// The estimate of the time remaining
+    eta: ::std::sync::Mutex<Eta>
+}
 
-        res
+impl ProgressBar
+{
+    fn new() -> Self
+    {
+        let bar = indicatif::ProgressBar::hidden();
+        let template = "[{elapsed_precise}] {bar:40} {pos}/{len} {msg}";
+        let style = indicatif::ProgressStyle::with_template(template)
+            .expect("invalid progress bar template");
+        bar.set_style(style);
+        ProgressBar { bar, eta: ::std::sync::Mutex::new(Eta::new()) }
     }
+}
 
-    fn combine(&self, expr: &Self, op: char, val: Rat) -> Self
+impl Progress for ProgressBar
+{
+    fn start(&self, total: usize)
     {
-        let ops = match op
-            {
-                '+' => {
-                    [&self.ops[..], &expr.ops[..], &[ADD]].concat()
-                },
-                '-' => {
-                    [&self.ops[..], &expr.ops[..], &[SUB]].concat()
-                },
-                '*' => {
-                    [&self.ops[..], &expr.ops[..], &[MUL]].concat()
-                },
-                '/' => {
-                    [&self.ops[..], &expr.ops[..], &[DIV]].concat()
-                },
-                '_' => {
-                    [&expr.ops[..], &self.ops[..], &[SUB]].concat()
-                },
-                '\\' => {
-                    [&expr.ops[..], &self.ops[..], &[DIV]].concat()
-                }
-                _ => { panic!(); }
-            };
-
-        Expr { ops: ops, val: val }
+        self.bar.set_draw_target(indicatif::ProgressDrawTarget::stderr());
+        self.bar.set_length(total as u64);
+        self.bar.set_position(0);
+        *self.eta.lock().unwrap() = Eta::new();
     }
 
-    fn to_string(&self, nrs: &[u64]) -> String
+    fn step(&self, best_distance: Option<Rat>)
     {
-        let mut ss = vec![];
-        for op in self.ops.iter()
+        self.bar.inc(1);
+        let remaining = self.bar.length().unwrap_or(0).saturating_sub(self.bar.position());
+        let eta = indicatif::HumanDuration(self.eta.lock().unwrap().step(remaining));
+        match best_distance
         {
-            match *op
-            {
-                ADD => {
-                    let (s0, _) = ss.pop().unwrap();
-                    let (s1, _) = ss.pop().unwrap();
-                    ss.push((format!("{}+{}", s1, s0), '+'));
-                },
-                SUB => {
-                    let (mut s0, o0) = ss.pop().unwrap();
-                    let (s1, _) = ss.pop().unwrap();
-                    if "+-".contains(o0)
-                    {
-                        s0 = format!("({})", s0);
-                    }
-                    ss.push((format!("{}-{}", s1, s0), '-'));
-                },
-                MUL => {
-                    let (mut s0, o0) = ss.pop().unwrap();
-                    let (mut s1, o1) = ss.pop().unwrap();
-                    if "+-".contains(o0)
-                    {
-                        s0 = format!("({})", s0);
-                    }
-                    if "+-/".contains(o1)
-                    {
-                        s1 = format!("({})", s1);
-                    }
-                    ss.push((format!("{}*{}", s1, s0), '*'));
-                },
-                DIV => {
-                    let (mut s0, o0) = ss.pop().unwrap();
-                    let (mut s1, o1) = ss.pop().unwrap();
-                    if "+-*/".contains(o0)
-                    {
-                        s0 = format!("({})", s0);
-                    }
-                    if "+-/".contains(o1)
-                    {
-                        s1 = format!("({})", s1);
-                    }
-                    ss.push((format!("{}/{}", s1, s0), '/'));
-                }
-                idx => {
-                    ss.push((nrs[idx as usize].to_string(), 'n'));
-                },
-            }
+            Some(diff) => self.bar.set_message(format!("best error {}, {} left", diff, eta)),
+            None       => self.bar.set_message(format!("{} left", eta))
         }
+    }
 
-        let (res, _) = ss.pop().unwrap();
-        res
+    fn finish(&self)
+    {
+        self.bar.finish_and_clear();
     }
 }
 
-fn partitions(idxs: &[Idx]) -> Vec<(Vec<Idx>, Vec<Idx>)>
+/// Handle an interrupt signal.
+///
+/// On the first interrupt, the search is cancelled, so that the best result
+/// found thus far can be printed. A second interrupt exits immediately.
+#[cfg(unix)]
+extern "C" fn handle_interrupt(_signal: libc::c_int)
 {
-    let mut res = vec![(vec![idxs[0]], vec![])];
-    for &idx in idxs[1..].iter()
+    if let Some(flag) = INTERRUPTED.get()
     {
-        let count = res.len();
-        res.append(&mut res.clone());
-        for (a, _) in res[..count].iter_mut()
-        {
-            a.push(idx);
-        }
-        for (a, b) in res[count..].iter_mut()
+        if flag.swap(true, Ordering::Relaxed)
         {
-            b.push(idx);
-            if b.len() > a.len() || (b.len() == a.len() && b < a)
-            {
-                ::std::mem::swap(a, b);
-            }
+            unsafe { libc::_exit(130) };
         }
     }
+}
 
-    res.sort();
-    res.dedup();
+/// The handler of the command named `name`, if any.
+///
+/// Besides their own names, some commands can be invoked by the name of the
+/// task they perform: `generate` for `draw`, `verify` for `import`, `spectrum`
+/// for `stats`, and `bench` for `compare`. The `solve` command, which is also
+/// the default, and `fill` are handled by `main()` itself.
+fn command_handler(name: &str) -> Option<fn(&[String])>
+{
+    let handler: fn(&[String]) = match name
+        {
+            "eval"               => eval_command,
+            "compare" | "bench"  => compare_command,
+            "hardest"            => hardest_command,
+            "stats" | "spectrum" => stats_command,
+            "diff"               => diff_command,
+            "table"              => table_command,
+            "draw" | "generate"  => draw_command,
+            "play"               => play_command,
+            "daily"              => daily_command,
+            "export"             => export_command,
+            "import" | "verify"  => import_command,
+            "score"              => score_command,
+            "numbers-for"        => numbers_for_command,
+            "inspect"            => inspect_command,
+            "serve"              => serve_command,
+            "tui"                => tui_command,
+            _                    => return None
+        };
+    Some(handler)
+}
 
-    res.sort_by_key(|(_, b)| b.len());
-    if res[0].1.is_empty()
+/// The command line arguments `args` without the global options `--base b` and
+/// `--roman`, which are handled in `main()` for every command.
+fn strip_global_options(args: &[String]) -> Vec<String>
+{
+    let mut res = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next()
     {
-        res.remove(0);
+        match arg.as_str()
+        {
+            "--base" => {
+                iter.next();
+            },
+            "--roman" => {},
+            _ => res.push(arg.clone())
+        }
     }
-
     res
 }
 
-fn expressions<'a>(nrs: &[u64], idxs: &[Idx],
-    cache: &'a mut HashMap<String, Vec<Expr>>) -> String
+/// Report an error with code `code` and message `msg`, about command line
+/// argument `arg` if given, and exit with status `status`.
+///
+/// By default, the message is printed, followed by the usage message if the
+/// status is 1. With `--json-errors`, the error is written on `stderr` as a
+/// JSON object with fields `code`, `message` and `argument` instead.
+fn fail(code: &str, msg: &str, arg: Option<&str>, status: i32) -> !
 {
-    let key = idxs.iter().map(|&i| nrs[i as usize].to_string()).collect::<Vec<_>>().join("_");
-    if !cache.contains_key(&key)
+    if JSON_ERRORS.load(Ordering::Relaxed)
     {
-        let mut map = vec![];
+        json_error(code, msg, arg, status);
+    }
+    println!("{}", msg);
+    if status == 1
+    {
+        usage();
+    }
+    ::std::process::exit(status);
+}
 
-        if idxs.len() == 1
-        {
-            map.push(Expr::new(nrs, idxs[0]));
-        }
-        else
-        {
-            let mut seen = ::std::collections::HashSet::with_hasher(Hash64);
-            for (idxs0, idxs1) in partitions(idxs)
-            {
-                let key0 = expressions(nrs, &idxs0, cache);
-                let key1 = expressions(nrs, &idxs1, cache);
-                for expr0 in cache[&key0].iter()
-                {
-                    for expr1 in cache[&key1].iter()
-                    {
-                        for (op, val) in expr0.possible_combinations(expr1)
-                        {
-                            if seen.insert(NormalizedRat(val))
-                            {
-                                map.push(expr0.combine(expr1, op, val));
-                            }
-                        }
-                    }
-                }
-            }
-        }
+/// Write an error as a JSON object on `stderr`, and exit with status `status`.
+fn json_error(code: &str, msg: &str, arg: Option<&str>, status: i32) -> !
+{
+    eprintln!("{}", serde_json::json!({
+            "schema_version": SCHEMA_VERSION,
+            "code": code,
+            "message": msg,
+            "argument": arg
+        }));
+    ::std::process::exit(status);
+}
 
-// println!("insert {} {}", key, map.len());
-        cache.insert(key.clone(), map);
+/// The JSON Schema of the lines logged with `--log-format json`
+const LOG_SCHEMA: &str = r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "title": "makeexpr log line",
+  "type": "object",
+  "properties": {
+    "schema_version": { "const": 1 },
+    "phase": { "type": "string", "description": "The phase of the program" },
+    "puzzle": {
+      "type": ["string", "null"],
+      "description": "The numbers and target of the puzzle, e.g. 1,3,4,6:24"
+    },
+    "elapsed": { "type": "number", "description": "Seconds since the program started" },
+    "message": { "type": "string" }
+  },
+  "required": ["schema_version", "phase", "puzzle", "elapsed", "message"]
+}"#;
+
+/// The JSON Schema of the errors reported with `--json-errors`
+const ERROR_SCHEMA: &str = r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "title": "makeexpr error",
+  "type": "object",
+  "properties": {
+    "schema_version": { "const": 1 },
+    "code": {
+      "enum": ["invalid_arguments", "invalid_number", "parse_error", "limit_violation",
+        "interrupted", "io_error"]
+    },
+    "message": { "type": "string" },
+    "argument": {
+      "type": ["string", "null"],
+      "description": "The offending command line argument, if known"
     }
+  },
+  "required": ["schema_version", "code", "message", "argument"]
+}"#;
 
-    key
+/// Print the JSON Schema of the output called `name`, and exit.
+fn print_schema(name: Option<&str>) -> !
+{
+    let schema = match name
+        {
+            Some("expression") => EXPRESSION_SCHEMA,
+            Some("bank")       => BANK_SCHEMA,
+            Some("service")    => serve::SOLVE_SCHEMA,
+            Some("log")        => LOG_SCHEMA,
+            Some("error")      => ERROR_SCHEMA,
+            _                  => usage()
+        };
+    println!("{}", schema);
+    ::std::process::exit(0);
 }
 
-/// Find the indexes of the unique numbers in an array.
+/// Log diagnostic message `msg` in phase `phase` of the program.
 ///
-/// For all elements in array `nrs`, find the index of the first occurrence of
-/// that element in `nrs`, and store it in the result. Afterwards, the indices
-/// array is sorted. Thus, equal numbers in the input array result in indices
-/// occuring with the same frequency in the output array, though not necessarily
-/// in the same order.
-fn unique_indices(nrs: &[u64]) -> Vec<Idx>
+/// By default, the message is printed as is on `stdout`. With
+/// `--log-format json`, it is written on `stderr` as a JSON object on a line
+/// of its own instead, with fields `phase`, `puzzle`, `elapsed` (the time in
+/// seconds since the program started) and `message`.
+fn log(phase: &str, msg: &str)
 {
-    let count = nrs.len();
-    let mut res = vec![];
-    for idx in 0..count
+    if LOG_JSON.load(Ordering::Relaxed)
     {
-        let uniq_idx = match nrs.iter().position(|&x| x == nrs[idx])
-            {
-                Some(dup_idx) => dup_idx,
-                None          => idx
-            };
-        res.push(uniq_idx as Idx);
+        log_event(phase, LOG_PUZZLE.get().map(|s| s.as_str()), msg);
+    }
+    else
+    {
+        println!("{}", msg);
     }
+}
 
-    res.sort();
-    res
+/// The identification of the puzzle with numbers `nrs` and target `target` in
+/// the log, e.g. `1,3,4,6:24`.
+fn puzzle_id(nrs: &[u64], target: &str) -> String
+{
+    let nrs = nrs.iter().map(|nr| nr.to_string()).collect::<Vec<_>>();
+    format!("{}:{}", nrs.join(","), target)
 }
 
-/// Find the expression nearest to target.
+/// Log event `msg` in phase `phase` for puzzle `puzzle`.
 ///
-/// Given more than two input numbers in `nrs`, and target number `target`,
-/// find an arithmetic expression using all the numbers in `nrs` that evaluates
-/// to a number as close as possible (or equal to) `target`. If
-/// `print_intermediate` is true, intermediate search  results are printed on
-/// `stdout`.
-fn get_nearest_expression_multiple(nrs: &[u64], target: u64, print_intermediate: bool) -> Expr
+/// Unlike `log()`, events are only logged with `--log-format json`, so that
+/// the plain output of the program is unaffected.
+fn log_event(phase: &str, puzzle: Option<&str>, msg: &str)
 {
-    let mut cache = HashMap::new();
+    if LOG_JSON.load(Ordering::Relaxed)
+    {
+        let elapsed = LOG_START.get().map_or(0.0, |start| start.elapsed().as_secs_f64());
+        eprintln!("{}", serde_json::json!({
+                "schema_version": SCHEMA_VERSION,
+                "phase": phase,
+                "puzzle": puzzle,
+                "elapsed": elapsed,
+                "message": msg
+            }));
+    }
+}
 
-    let rtarget = Rat::from_integer(target);
-    let mut best = Expr::empty();
-    let mut best_min = Rat::zero();
-    let mut best_max = Rat::from_integer(::std::u64::MAX);
+/// Install the interrupt handler, and return the flag it sets.
+fn install_interrupt_handler() -> Arc<AtomicBool>
+{
+    let flag = INTERRUPTED.get_or_init(|| Arc::new(AtomicBool::new(false))).clone();
+    #[cfg(unix)]
+    unsafe {
+        let handler = handle_interrupt as extern "C" fn(libc::c_int);
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+    flag
+}
 
-    let idxs = unique_indices(nrs);
-    'outer: for (idxs0, idxs1) in partitions(&idxs)
-    {
-        let key0 = expressions(nrs, &idxs0, &mut cache);
-        let key1 = expressions(nrs, &idxs1, &mut cache);
-        for expr0 in cache[&key0].iter()
+/// Find and print the expression nearest to the target.
+///
+/// If `must_contain` is given, the expression must contain it as a
+/// sub-expression. The result is printed according to the settings in
+/// `output`. If the search is interrupted, the best expression found thus far
+/// is printed, and the program exits. Returns the expression printed, which is
+/// empty if no expression satisfies the constraints.
+fn solve(nrs: &[u64], target: &Target, opts: &Options, must_contain: Option<&str>,
+    output: &Output) -> Expr
+{
+    let start = ::std::time::Instant::now();
+    let mut cache = Cache::default();
+    let search_opts = Options {
+            spill: output.memory_limit.map(|limit| Arc::new(SpillCache::new(limit))),
+            ..opts.clone()
+        };
+    let mut expr = match must_contain.map(|s| parse_with(s, nrs, opts))
         {
-            for expr1 in cache[&key1].iter()
-            {
-                for (op, val) in expr0.possible_combinations(expr1)
+            Some(Ok(unit)) => get_nearest_expression_containing(nrs, &unit, target, &search_opts),
+            Some(Err(err)) => {
+                fail("parse_error", &format!("Invalid required sub-expression: {}", err),
+                    must_contain, 1)
+            },
+            // The sub-expressions are kept for the statistics, or for reuse in
+            // the search for an extra number
+            None if output.cache_stats || output.suggest_extra.is_some() => {
+                get_nearest_expression_cached(nrs, &unique_indices(nrs), target, &search_opts,
+                    &mut cache)
+            },
+            None => {
+                let countdown = output.countdown.as_ref()
+                    .and_then(|table| table.solve(nrs, target, &search_opts));
+                match (countdown, output.table.as_ref().filter(|table| table.matches(nrs, opts)))
                 {
-                    if val > best_min && val < best_max
-                    {
-                        let diff = if val < rtarget { rtarget - val } else { val - rtarget };
-
-                        best = expr0.combine(expr1, op, val);
-                        best_min = if diff > rtarget { Rat::zero() } else { rtarget - diff };
-                        best_max = rtarget + diff;
-
-                        if diff.is_zero()
-                        {
-                            break 'outer;
-                        }
-
-                        if print_intermediate
-                        {
-                            println!("{} = {}", best.to_string(nrs), val);
-                        }
-                    }
+                    (Some(expr), _)     => expr,
+                    (None, Some(table)) => table.nearest(nrs, target),
+                    (None, None)        => output.strategy.solve(nrs, target, &search_opts)
                 }
             }
-        }
-
-        cache.remove(&key0);
-        if idxs1.len() >= idxs0.len()
+        };
+    if output.deterministic && opts.cancelled()
+    {
+        fail("interrupted", "Search interrupted, no expression is printed in deterministic mode",
+            None, 130);
+    }
+    if output.scoring != Scoring::default() && must_contain.is_none() && target.contains(expr.val)
+        && !opts.cancelled()
+    {
+        if let Some(preferred) = preferred_solution(nrs, target, opts, &output.scoring)
         {
-            cache.remove(&key1);
+            expr = preferred;
         }
     }
-
-    best
-}
-
-/// Find the expression nearest to target.
-///
-/// Given two input numbers in `nrs`, and target number `target`, find an arithmetic
-/// expression using all the numbers in `nrs` that evaluates to a number as close
-/// as possible (or equal to) `target`. If `print_intermediate` is true,
-/// intermediate search  results are printed on `stdout`.
-fn get_nearest_expression_2(nrs: &[u64], target: u64, print_intermediate: bool) -> Expr
-{
-    let rtarget = Rat::from_integer(target);
-    let mut best = Expr::empty();
-    let mut best_diff = Rat::from_integer(::std::u64::MAX);
-
-    let expr0 = Expr::new(nrs, 0);
-    let expr1 = Expr::new(nrs, 1);
-    for (op, val) in expr0.possible_combinations(&expr1)
+    if output.simplify && must_contain.is_none()
     {
-        let diff = if val > rtarget { val - rtarget } else { rtarget - val };
-        if diff < best_diff
-        {
-            best = expr0.combine(&expr1, op, val);
-            best_diff = diff;
-
-            if diff.is_zero()
+        expr = simplify(&expr, nrs, opts);
+    }
+    if output.canonical && must_contain.is_none() && !opts.cancelled()
+    {
+        expr = if target.contains(expr.val)
             {
-                break;
+                let mut exprs = exact_solutions(nrs, target, !opts.subset, opts);
+                exprs.push(expr);
+                canonical_solutions(exprs, nrs, opts, output).swap_remove(0)
             }
+            else
+            {
+                canonical_expr(&expr, nrs, opts)
+            };
+    }
+    if expr.is_empty()
+    {
+        if opts.cancelled()
+        {
+            fail("interrupted", "Search interrupted before any expression was found", None, 130);
+        }
+        if !(output.require_exact && output.quiet)
+        {
+            println!("No expression satisfies the constraints");
+        }
+        return expr;
+    }
+    if output.require_exact && output.quiet && !target.contains(expr.val) && !opts.cancelled()
+    {
+        return expr;
+    }
 
-            if print_intermediate
+    let formatted = match output.format
+        {
+            Format::Markdown => format_markdown(&expr, nrs, target, opts),
+            format => format_expr_with(&expr, nrs, opts, format, output.style)
+        };
+    println!("{}", formatted);
+    if !output.scoring.weights.is_empty()
+    {
+        println!("Total cost of the numbers used: {}", output.scoring.cost(&expr, nrs));
+    }
+    if output.self_check
+    {
+        // The other formats always write the expression as in plain format
+        // with the default style
+        let printed = if output.format == Format::Plain
             {
-                println!("{} = {}", best.to_string(nrs), val);
+                formatted
             }
+            else
+            {
+                format_expr(&expr, nrs, opts, Format::Plain)
+            };
+        self_check(&printed, &expr, nrs, opts, output.style);
+    }
+    if output.cache_stats
+    {
+        print_cache_stats(&cache, opts);
+    }
+    if opts.cancelled()
+    {
+        if let Some(trace) = &opts.trace
+        {
+            let _ = trace.flush();
+        }
+        println!("Search interrupted after {:.1} seconds, this is the best expression found so far",
+            start.elapsed().as_secs_f64());
+        ::std::process::exit(130);
+    }
+    if !target.contains(expr.val)
+    {
+        if output.explain
+        {
+            println!("No exact solution: {}", explain_infeasible(nrs, target, opts));
+        }
+        if output.certificate
+        {
+            print_certificate(nrs, target, opts);
+        }
+        if let Some((min, max)) = output.suggest_extra
+        {
+            print_suggestions(nrs, target, opts, min, max, output, &mut cache);
         }
     }
 
-    best
+    expr
 }
 
-/// Find the expression nearest to target.
+/// Check a printed expression.
 ///
-/// Given input numbers `nrs`, and target number `target`, find an arithmetic
-/// expression using all the numbers in `nrs` that evaluates to a number as close
-/// as possible (or equal to) `target`. If `print_intermediate` is true,
-/// intermediate search  results are printed on `stdout`.
-fn get_nearest_expression(nrs: &[u64], target: u64, print_intermediate: bool) -> Expr
+/// Parse the expression in `printed`, as printed in plain format in style
+/// `style` for expression `expr` over the numbers `nrs`, again, evaluate it,
+/// and check that its value is the value of `expr` and the value printed, and
+/// that it uses all numbers, unless `opts.subset` is set. If the check fails,
+/// print the reason to standard error, and exit. Expressions containing custom operations, which
+/// cannot be parsed, are not checked.
+fn self_check(printed: &str, expr: &Expr, nrs: &[u64], opts: &Options, style: Style)
 {
-    match nrs.len()
+    if expr.ops.iter().any(|&op| (op as usize) >= MAX_NUMBERS && op < DIV)
     {
-        1 => Expr::new(nrs, 0),
-        2 => get_nearest_expression_2(nrs, target, print_intermediate),
-        _ => get_nearest_expression_multiple(nrs, target, print_intermediate)
+        eprintln!("Self-check skipped: the expression uses custom operations");
+        return;
     }
-}
 
-/// Print a usage message, and exit.
-fn usage() -> !
-{
-    println!("Usage: makeexpr number [number ...] target");
-    ::std::process::exit(1);
+    let (s, printed_val) = printed.rsplit_once(" = ").unwrap_or((printed, ""));
+    let res = match parse_styled(s, nrs, opts, style)
+        {
+            Ok(parsed) if parsed.val != expr.val => {
+                Err(format!("\"{}\" evaluates to {}, not {}", s, parsed.val, expr.val))
+            },
+            Ok(parsed) if !format_expr_with(&parsed, nrs, opts, Format::Plain, style)
+                .ends_with(&format!(" = {}", printed_val)) => {
+                Err(format!("\"{}\" is printed with value {}, not {}", s, printed_val, parsed.val))
+            },
+            Ok(parsed) if !opts.subset && parsed.indices().count() != nrs.len() => {
+                Err(format!("\"{}\" does not use all {} numbers", s, nrs.len()))
+            },
+            Ok(_) => Ok(()),
+            Err(msg) => Err(format!("\"{}\" cannot be parsed: {}", s, msg))
+        };
+    if let Err(msg) = res
+    {
+        eprintln!("Self-check failed: {}", msg);
+        ::std::process::exit(2);
+    }
 }
 
-fn main()
+/// Print all exact solutions.
+///
+/// The solutions are printed in the order given by the scoring in `output`,
+/// which by default is in order of elegance, the most elegant first. If
+/// `structural` is true, solutions that differ only by commutativity or
+/// associativity are printed only once. The number of solutions is printed
+/// after them in the plain format only, so that the other formats can be read
+/// line by line.
+fn list_solutions(nrs: &[u64], target: &Target, opts: &Options, structural: bool, output: &Output)
 {
-    let print_intermediate = true;
-    let args: Vec<String> = ::std::env::args().collect();
-    if args.len() < 3
+    let mut exprs = exact_solutions(nrs, target, !opts.subset, opts);
+    if structural
     {
-        usage()
+        let mut seen = ::std::collections::HashSet::new();
+        exprs.retain(|expr| seen.insert(canonical_hash(expr, nrs)));
     }
-
-    let mut nrs = vec![];
-    for arg in args[1..args.len()-1].iter()
+    if output.canonical
     {
-        match arg.parse::<u64>()
-        {
-            Ok(nr) => nrs.push(nr),
-            _ => usage()
-        }
+        exprs = canonical_solutions(exprs, nrs, opts, output);
     }
-
-    let count = nrs.len();
-    if count > Op::max_value() as usize - 4
+    else
     {
-        panic!("Too many numbers for {}-bit indices", 8*::std::mem::size_of::<Idx>());
+        exprs.sort_by_key(|expr| output.scoring.key(expr, nrs));
     }
 
-    let target;
-    match args.last().unwrap().parse::<u64>()
+    for expr in exprs.iter()
+    {
+        println!("{}", format_expr_with(expr, nrs, opts, output.format, output.style));
+    }
+    if output.format == Format::Plain
     {
-        Ok(nr) => { target = nr },
-        _ => usage()
+        println!("{} solution{}", exprs.len(), if exprs.len() == 1 { "" } else { "s" });
     }
+}
+
+/// Rewrite the solutions `exprs` into canonical order, remove duplicates, and
+/// sort them, in an order that does not depend on the order of `exprs`: by
+/// the scoring in `output`, and then by their infix form.
+fn canonical_solutions(exprs: Vec<Expr>, nrs: &[u64], opts: &Options, output: &Output) -> Vec<Expr>
+{
+    let mut keyed = exprs.iter()
+        .map(|expr| {
+            let expr = canonical_expr(expr, nrs, opts);
+            ((output.scoring.key(&expr, nrs), expr.to_string_with(nrs, opts)), expr)
+        })
+        .collect::<Vec<_>>();
+    keyed.sort_by(|(key0, _), (key1, _)| key0.cmp(key1));
+    keyed.dedup_by(|(key0, _), (key1, _)| key0 == key1);
+    keyed.into_iter().map(|(_, expr)| expr).collect()
+}
 
-    let expr = get_nearest_expression(&nrs, target, print_intermediate);
-    println!("{} = {}", expr.to_string(&nrs), expr.val);
+/// Print the extra numbers in the range from `min` to `max` that make the
+/// target reachable, with their witness expressions. The sub-expressions from
+/// `cache`, filled by the search over `nrs`, are reused.
+fn print_suggestions(nrs: &[u64], target: &Target, opts: &Options, min: u64, max: u64,
+    output: &Output, cache: &mut Cache)
+{
+    let suggestions = suggest_extra(nrs, target, min, max, opts, cache);
+    if suggestions.is_empty()
+    {
+        println!("No extra number from {} to {} makes the target reachable", min, max);
+    }
+    for (nr, expr) in suggestions.iter()
+    {
+        let extended = [nrs, &[*nr]].concat();
+        println!("with extra number {}: {}", nr,
+            format_expr_with(expr, &extended, opts, output.format, output.style));
+    }
+}
+
+/// Print the expressions on the Pareto front of distance to the target and
+/// number of operations, the simplest first.
+fn print_pareto(nrs: &[u64], target: &Target, opts: &Options, output: &Output)
+{
+    for expr in pareto_front(nrs, target, opts).iter()
+    {
+        let closeness = if target.contains(expr.val)
+            {
+                String::from("exact")
+            }
+            else
+            {
+                format!("off by {}", target.distance(expr.val))
+            };
+        println!("{} operations, {}: {}", expr.op_count(), closeness,
+            format_expr_with(expr, nrs, opts, output.format, output.style));
+    }
+}
+
+/// Print a certificate of an exhaustive search.
+fn print_certificate(nrs: &[u64], target: &Target, opts: &Options)
+{
+    let cert = certify(nrs, target, opts);
+    println!("certificate numbers={} target={} integers-only={}",
+        nrs.iter().map(|nr| nr.to_string()).collect::<Vec<_>>().join(","), target,
+        opts.integers_only);
+    for summary in cert.subsets.iter()
+    {
+        println!("subset {} values={} xxh64={:016x}", summary.key, summary.count, summary.hash);
+    }
+    println!("target-found={}", cert.target_found);
+}
+
+/// Print statistics of the sub-expressions in a cache.
+fn print_cache_stats(cache: &Cache, opts: &Options)
+{
+    for stats in cache_stats(cache, opts.cache_counters.as_deref())
+    {
+        println!("cache size {}: {} entries, {:.1} expressions per entry, {}, hit rate {:.1}%",
+            stats.size, stats.entries, stats.average_expressions(),
+            indicatif::HumanBytes(stats.bytes as u64), 100.0 * stats.hit_rate());
+    }
+}
+
+/// Print an estimate of the size of the search.
+fn print_estimate(nrs: &[u64], opts: &Options)
+{
+    let est = estimate_search(nrs, opts);
+    println!("numbers: {}", nrs.len());
+    println!("top-level partitions: {}", est.partitions);
+    for (k, &values) in est.values.iter().enumerate()
+    {
+        if k < est.sampled_size
+        {
+            println!("subset size {}: {:.0} values (measured)", k+1, values);
+        }
+        else
+        {
+            println!("subset size {}: ~{:.0} values (estimated)", k+1, values);
+        }
+    }
+    println!("projected memory: {}", indicatif::HumanBytes(est.memory as u64));
+    println!("projected time: {}",
+        indicatif::HumanDuration(::std::time::Duration::from_secs_f64(est.seconds)));
+}
+
+/// Evaluate a serialized expression for new numbers.
+///
+/// Handle the `eval` command, with command line arguments `args` following
+/// the command itself. The expression is read from the file given with
+/// `--expr-file`, and evaluated for the numbers following `--numbers`.
+fn eval_command(args: &[String])
+{
+    let mut path = None;
+    let mut format = Format::Plain;
+    let mut opts = Options::default();
+    let mut positional = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next()
+    {
+        match arg.as_str()
+        {
+            "--expr-file" => {
+                path = match iter.next()
+                    {
+                        Some(p) => Some(p),
+                        None => usage()
+                    };
+            },
+            "--format" => {
+                format = match iter.next().and_then(|s| Format::from_name(s))
+                    {
+                        Some(f) => f,
+                        None => usage()
+                    };
+            },
+            "--decimals" => {
+                opts.add_unary_op(Decimal);
+            },
+            "--numbers" => {},
+            _ => positional.push(arg.clone())
+        }
+    }
+
+    let path = match path
+        {
+            Some(path) => path,
+            None => usage()
+        };
+    let nrs = parse_numbers(&positional);
+    let s = match ::std::fs::read_to_string(path)
+        {
+            Ok(s) => s,
+            Err(err) => {
+                log("io", &format!("Failed to read {}: {}", path, err));
+                ::std::process::exit(1);
+            }
+        };
+    match Expr::deserialize(&s, &nrs, &opts)
+    {
+        Ok(expr) => println!("{}", format_expr(&expr, &nrs, &opts, format)),
+        Err(msg) => {
+            println!("Invalid expression: {}", msg);
+            ::std::process::exit(1);
+        }
+    }
+}
+
+/// Handle the `compare` command, with command line arguments `args` following
+/// the command.
+///
+/// The puzzle given by the positional arguments is solved with each of the
+/// strategies following `--strategies`, and the quality of the result, the
+/// time taken and the peak memory use are printed in a table.
+fn compare_command(args: &[String])
+{
+    let mut strategies = vec![Strategy::Exhaustive];
+    let mut positional = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next()
+    {
+        match arg.as_str()
+        {
+            "--strategies" => {
+                strategies = match iter.next()
+                    .map(|s| s.split(',').map(Strategy::from_name).collect::<Option<Vec<_>>>())
+                    {
+                        Some(Some(strategies)) => strategies,
+                        _ => usage()
+                    };
+            },
+            "--" => {},
+            _ => positional.push(arg.clone())
+        }
+    }
+
+    let target = match positional.pop().map(|s| s.parse::<u64>())
+        {
+            Some(Ok(nr)) => Target::new(nr),
+            _ => usage()
+        };
+    let nrs = parse_numbers(&positional);
+    let opts = Options::default();
+
+    println!("{:<12} {:>12} {:>10} {:>12}  expression", "strategy", "error", "time", "memory");
+    for strategy in strategies
+    {
+        let base = ALLOC.reset_peak();
+        let start = ::std::time::Instant::now();
+        let expr = strategy.solve(&nrs, &target, &opts);
+        let elapsed = start.elapsed();
+        let memory = ALLOC.peak().saturating_sub(base);
+
+        if expr.is_empty()
+        {
+            println!("{:<12} {:>12} {:>10.3?} {:>12}  -", strategy.name(), "-", elapsed,
+                indicatif::HumanBytes(memory as u64).to_string());
+        }
+        else
+        {
+            println!("{:<12} {:>12} {:>10.3?} {:>12}  {} = {}", strategy.name(),
+                target.distance(expr.val).to_string(), elapsed,
+                indicatif::HumanBytes(memory as u64).to_string(), expr.to_string(&nrs), expr.val);
+        }
+    }
+}
+
+/// Handle the `hardest` command, with command line arguments `args` following
+/// the command.
+///
+/// For the numbers given by the positional arguments, the targets in the range
+/// given by the first positional argument are ranked by difficulty, and the
+/// hardest ones, as many as given with `--count`, are printed.
+fn hardest_command(args: &[String])
+{
+    let mut opts = Options { subset: true, ..Options::default() };
+    let mut count = 10;
+    let mut positional = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next()
+    {
+        match arg.as_str()
+        {
+            "--integers-only" => {
+                opts.integers_only = true;
+            },
+            "--count" => {
+                count = match iter.next().map(|s| s.parse::<usize>())
+                    {
+                        Some(Ok(n)) => n,
+                        _ => usage()
+                    };
+            },
+            "--" => {},
+            _ => positional.push(arg.clone())
+        }
+    }
+
+    let (min, max) = match positional.first().and_then(|s| parse_range(s))
+        {
+            Some(range) => range,
+            None => usage()
+        };
+    let nrs = parse_numbers(&positional[1..]);
+    let hardest = hardest_targets(&nrs, min, max, &opts);
+    for reach in hardest.iter().take(count)
+    {
+        let expr = reach.example.as_ref().unwrap();
+        println!("{}: {} numbers, {} solutions, e.g. {} = {}", reach.target,
+            reach.min_numbers.unwrap(), reach.solutions, expr.to_string(&nrs), expr.val);
+    }
+    let unreachable = (max - min + 1) as usize - hardest.len();
+    if unreachable > 0
+    {
+        println!("{} targets cannot be reached", unreachable);
+    }
+}
+
+/// Handle the `stats` command, with command line arguments `args` following
+/// the command.
+///
+/// For the numbers given by the positional arguments, print how many of the
+/// targets in the range given by the first positional argument can be hit
+/// exactly, the mean and median error of the nearest expression for the
+/// others, and the distribution of the fewest numbers needed for the targets
+/// that can be hit. With `--format histogram`, print a bar chart of the
+/// fraction of reachable targets in every part of the range instead. With
+/// `--bitset`, the set of reachable targets is also written to a file.
+fn stats_command(args: &[String])
+{
+    let mut opts = Options { subset: true, ..Options::default() };
+    let mut bitset = None;
+    let mut histogram = false;
+    let mut buckets = HISTOGRAM_BUCKETS;
+    let mut positional = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next()
+    {
+        match arg.as_str()
+        {
+            "--integers-only" => {
+                opts.integers_only = true;
+            },
+            "--format" => {
+                histogram = match iter.next().map(|s| s.as_str())
+                    {
+                        Some("plain") => false,
+                        Some("histogram") => true,
+                        _ => usage()
+                    };
+            },
+            "--buckets" => {
+                buckets = match iter.next().map(|s| s.parse::<usize>())
+                    {
+                        Some(Ok(n)) if n > 0 => n,
+                        _ => usage()
+                    };
+            },
+            "--bitset" => {
+                bitset = match iter.next()
+                    {
+                        Some(path) => Some(path.clone()),
+                        None => usage()
+                    };
+            },
+            "--" => {},
+            _ => positional.push(arg.clone())
+        }
+    }
+
+    let (min, max) = match positional.first().and_then(|s| parse_range(s))
+        {
+            Some(range) => range,
+            None => usage()
+        };
+    let nrs = parse_numbers(&positional[1..]);
+    let reach = reachability(&nrs, min, max, &opts);
+    if let Some(path) = bitset
+    {
+        if let Err(err) = ReachableSet::from_reach(&reach).write(&path)
+        {
+            log("io", &format!("Failed to write {}: {}", path, err));
+            ::std::process::exit(1);
+        }
+    }
+    if histogram
+    {
+        print_histogram(&reach, buckets);
+        return;
+    }
+
+    let total = reach.len();
+    let reachable = reach.iter().filter(|r| r.min_numbers.is_some()).count();
+    println!("reachable: {} of {} targets ({:.1}%)", reachable, total,
+        100.0 * reachable as f64 / total as f64);
+
+    let mut errors = reach.iter().filter(|r| r.min_numbers.is_none()).map(|r| r.error)
+        .collect::<Vec<_>>();
+    if !errors.is_empty()
+    {
+        errors.sort();
+        let mean = errors.iter().map(|e| *e.numer() as f64 / *e.denom() as f64).sum::<f64>()
+            / errors.len() as f64;
+        println!("unreachable: {} targets, mean error {:.3}, median error {}", errors.len(), mean,
+            errors[(errors.len() - 1) / 2]);
+    }
+
+    let mut sizes = vec![0; nrs.len() + 1];
+    for count in reach.iter().filter_map(|r| r.min_numbers)
+    {
+        sizes[count] += 1;
+    }
+    println!("fewest numbers needed:");
+    for (count, &targets) in sizes.iter().enumerate().skip(1)
+    {
+        println!("{:>4}: {} targets", count, targets);
+    }
+}
+
+/// Handle the `diff` command, with command line arguments `args` following
+/// the command.
+///
+/// For the two sets of numbers following `--a` and `--b`, print the targets
+/// in the range following `--range` that can be hit exactly only using the
+/// first set, only using the second, using both, or using neither.
+fn diff_command(args: &[String])
+{
+    let mut opts = Options { subset: true, ..Options::default() };
+    let (mut nrs_a, mut nrs_b, mut range) = (None, None, None);
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next()
+    {
+        match arg.as_str()
+        {
+            "--integers-only" => {
+                opts.integers_only = true;
+            },
+            "--a" | "--b" => {
+                let nrs = match iter.next()
+                    {
+                        Some(s) => s.split(',').map(String::from).collect::<Vec<_>>(),
+                        None => usage()
+                    };
+                let nrs = parse_numbers(&nrs);
+                if arg == "--a" { nrs_a = Some(nrs); } else { nrs_b = Some(nrs); }
+            },
+            "--range" => {
+                range = match iter.next().and_then(|s| parse_range(s))
+                    {
+                        Some(range) => Some(range),
+                        None => usage()
+                    };
+            },
+            _ => usage()
+        }
+    }
+
+    let (nrs_a, nrs_b, (min, max)) = match (nrs_a, nrs_b, range)
+        {
+            (Some(a), Some(b), Some(range)) => (a, b, range),
+            _ => usage()
+        };
+    let reach_a = reachability(&nrs_a, min, max, &opts);
+    let reach_b = reachability(&nrs_b, min, max, &opts);
+    let mut groups = [vec![], vec![], vec![], vec![]];
+    for (a, b) in reach_a.iter().zip(reach_b.iter())
+    {
+        let group = match (a.min_numbers.is_some(), b.min_numbers.is_some())
+            {
+                (true, false)  => 0,
+                (false, true)  => 1,
+                (true, true)   => 2,
+                (false, false) => 3
+            };
+        groups[group].push(a.target);
+    }
+
+    for (name, targets) in ["only A", "only B", "both", "neither"].iter().zip(groups.iter())
+    {
+        println!("{} ({}): {}", name, targets.len(), format_targets(targets));
+    }
+}
+
+/// Format a sorted list of targets, joining runs of consecutive targets into
+/// ranges like `3-7`.
+fn format_targets(targets: &[u64]) -> String
+{
+    let mut parts = vec![];
+    let mut i = 0;
+    while i < targets.len()
+    {
+        let mut j = i;
+        while j + 1 < targets.len() && targets[j + 1] == targets[j] + 1
+        {
+            j += 1;
+        }
+        if j == i
+        {
+            parts.push(targets[i].to_string());
+        }
+        else
+        {
+            parts.push(format!("{}-{}", targets[i], targets[j]));
+        }
+        i = j + 1;
+    }
+    parts.join(", ")
+}
+
+/// Print a histogram of reachable targets.
+///
+/// Split the targets in `reach` into `buckets` consecutive parts of equal
+/// size, and print a bar for every part, whose length is proportional to the
+/// fraction of targets in that part that can be hit exactly.
+fn print_histogram(reach: &[TargetReach], buckets: usize)
+{
+    let size = reach.len().div_ceil(buckets);
+    let label_width = reach.last().map_or(1, |r| r.target.to_string().len());
+    for part in reach.chunks(size)
+    {
+        let reachable = part.iter().filter(|r| r.min_numbers.is_some()).count();
+        let fraction = reachable as f64 / part.len() as f64;
+        let bar = (fraction * HISTOGRAM_WIDTH as f64).round() as usize;
+        println!("{:>w$}-{:<w$} |{:<width$}| {:5.1}%", part[0].target, part[part.len()-1].target,
+            "#".repeat(bar), 100.0 * fraction, w = label_width, width = HISTOGRAM_WIDTH);
+    }
+}
+
+/// Solve the puzzles in the puzzle file at `path`.
+///
+/// The puzzles are solved with the search options in `opts` as defaults, and
+/// the results are written in the output format of `output` to the file at
+/// `results_path`, or `stdout` if it is not given, while the puzzles are
+/// solved. With `parallel`, the puzzles are solved concurrently, on as many
+/// threads as `opts.threads`, and the results are still written in the order
+/// of the puzzles.
+///
+/// If the columns `csv_cols` are given, or the file name ends in `.csv`, the
+/// puzzles are read from the rows of a CSV file, and the rows are written with
+/// the results appended.
+///
+/// With `watch`, the file is solved again every time it is modified, until the
+/// program is interrupted. Errors in the file are then reported without
+/// exiting, so that the file can be fixed.
+fn solve_file(path: &str, results_path: Option<&str>, parallel: bool, watch: bool,
+    csv_cols: Option<batch::CsvColumns>, mut opts: Options, output: &Output)
+{
+    opts.print_intermediate = false;
+    opts.shared_cache = Some(Arc::new(SharedCache::new(SHARED_CACHE_LIMIT)));
+    let jobs = if parallel { ::std::mem::replace(&mut opts.threads, 1) } else { 1 };
+    let csv_cols = csv_cols.or_else(|| {
+            path.to_ascii_lowercase().ends_with(".csv").then(batch::CsvColumns::default)
+        });
+    if !watch
+    {
+        match solve_file_once(path, results_path, jobs, csv_cols.as_ref(), &opts, output)
+        {
+            Ok(()) => {},
+            Err((code, msg)) if code == "parse_error" => fail(code, &msg, Some(path), 2),
+            Err((code, msg)) if JSON_ERRORS.load(Ordering::Relaxed) => {
+                json_error(code, &msg, results_path, 1)
+            },
+            Err((_, msg)) => {
+                log("io", &msg);
+                ::std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let clear = results_path.is_none() && ::std::io::stdout().is_terminal();
+    let mut last_modified = None;
+    loop
+    {
+        let modified = ::std::fs::metadata(path).and_then(|meta| meta.modified()).ok();
+        if modified.is_some() && modified != last_modified
+        {
+            last_modified = modified;
+            if clear
+            {
+                print!("\x1b[2J\x1b[H");
+            }
+            log("watch", &format!("Solving the puzzles in {}", path));
+            if let Err((_, msg)) = solve_file_once(path, results_path, jobs, csv_cols.as_ref(),
+                &opts, output)
+            {
+                log("watch", &msg);
+            }
+        }
+        ::std::thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// Solve the puzzles in the puzzle file at `path` once, on `jobs` threads.
+///
+/// See `solve_file()` for the other arguments. Returns the error code and
+/// message if the file cannot be read or the results cannot be written.
+fn solve_file_once(path: &str, results_path: Option<&str>, jobs: usize,
+    csv_cols: Option<&batch::CsvColumns>, opts: &Options, output: &Output)
+    -> Result<(), (&'static str, String)>
+{
+    let read = match csv_cols
+        {
+            Some(cols) => {
+                batch::read_csv(path, cols, opts).map(|(rows, solvers)| (Some(rows), solvers))
+            },
+            None => batch::read_puzzles(path, opts).map(|solvers| (None, solvers))
+        };
+    let (rows, solvers) = read
+        .map_err(|msg| ("parse_error", format!("Failed to read {}: {}", path, msg)))?;
+
+    // The results are written as they come in, so that they can be followed
+    // while a large file is solved
+    let write = |out: &mut dyn ::std::io::Write| {
+            if let Some(rows) = rows.as_ref()
+            {
+                batch::write_csv_header(out, rows)?;
+            }
+            batch::solve_puzzles(&solvers, jobs, |idx, expr| match rows.as_ref()
+                {
+                    Some(rows) => batch::write_csv_row(out, rows, idx, &solvers[idx], expr),
+                    None => batch::write_result(out, &solvers[idx], expr, output.format,
+                        output.style)
+                })?;
+            out.flush()
+        };
+    let res = match results_path
+        {
+            Some(results_path) => ::std::fs::File::create(results_path)
+                .and_then(|file| write(&mut ::std::io::BufWriter::new(file))),
+            None => write(&mut ::std::io::stdout().lock())
+        };
+    match res
+    {
+        // The reader of the results went away, e.g. at the end of a pipeline
+        Err(err) if err.kind() == ::std::io::ErrorKind::BrokenPipe => Ok(()),
+        res => res.map_err(|err| {
+                let path = results_path.unwrap_or("results");
+                ("io_error", format!("Failed to write {}: {}", path, err))
+            })
+    }
+}
+
+/// Answer the targets read from `stdin` for the numbers `nrs`.
+///
+/// For every line read, the expression nearest to the target on that line, a
+/// number or a range `min..=max`, is printed on a line of its own, in the
+/// output format of `output`. The expressions over the numbers are enumerated
+/// once, in a `Session`, so that every target is answered by a lookup.
+fn targets_from_stdin(nrs: &[u64], opts: &Options, output: &Output)
+{
+    use ::std::io::{BufRead, Write};
+
+    let opts = Options { print_intermediate: false, ..opts.clone() };
+    let session = Session::new(nrs, &opts);
+    let mut out = ::std::io::stdout().lock();
+    for line in ::std::io::stdin().lock().lines()
+    {
+        let line = match line
+            {
+                Ok(line) => line,
+                Err(err) => {
+                    log("io", &format!("Failed to read stdin: {}", err));
+                    ::std::process::exit(1);
+                }
+            };
+        let res = match parse_target(line.trim())
+            {
+                Some(target) => {
+                    let expr = session.nearest(&target);
+                    if expr.is_empty()
+                    {
+                        String::from("No expression satisfies the constraints")
+                    }
+                    else
+                    {
+                        format_expr_with(&expr, nrs, &opts, output.format, output.style)
+                    }
+                },
+                None => format!("Invalid target {}", line.trim())
+            };
+        // Flush every answer, so that the program can be used in a pipeline
+        if writeln!(out, "{}", res).and_then(|_| out.flush()).is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Handle the `serve` command, with command line arguments `args` following
+/// the command.
+///
+/// Run the solver as an HTTP service on the address given with `--listen`,
+/// `127.0.0.1:8080` by default.
+fn serve_command(args: &[String])
+{
+    let threads = ::std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut opts = Options {
+            threads,
+            shared_cache: Some(Arc::new(SharedCache::new(SHARED_CACHE_LIMIT))),
+            ..Options::default()
+        };
+    let mut addr = String::from("127.0.0.1:8080");
+    let mut limits = serve::Limits::default();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next()
+    {
+        match arg.as_str()
+        {
+            "--listen" => {
+                addr = iter.next().cloned().unwrap_or_else(|| usage());
+            },
+            "--max-numbers" => {
+                limits.max_numbers = match iter.next().map(|s| s.parse::<usize>())
+                    {
+                        Some(Ok(n)) if n > 0 => n,
+                        _ => usage()
+                    };
+            },
+            "--timeout" => {
+                limits.timeout = match iter.next().map(|s| s.parse::<u64>())
+                    {
+                        Some(Ok(secs)) if secs > 0 => ::std::time::Duration::from_secs(secs),
+                        _ => usage()
+                    };
+            },
+            "--max-cached" => {
+                limits.max_cached = match iter.next().map(|s| s.parse::<usize>())
+                    {
+                        Some(Ok(n)) => n,
+                        _ => usage()
+                    };
+            },
+            "--max-connections" => {
+                limits.max_connections = match iter.next().map(|s| s.parse::<usize>())
+                    {
+                        Some(Ok(n)) if n > 0 => n,
+                        _ => usage()
+                    };
+            },
+            "--integers-only" => {
+                opts.integers_only = true;
+            },
+            "--subset" => {
+                opts.subset = true;
+            },
+            _ => usage()
+        }
+    }
+
+    if let Err(err) = serve::run(&addr, opts, limits)
+    {
+        log("serve", &format!("Failed to run the service on {}: {}", addr, err));
+        ::std::process::exit(1);
+    }
+}
+
+/// Handle the `tui` command, with command line arguments `args` following the
+/// command.
+///
+/// Run the search for the numbers and target given by the positional arguments
+/// in an interactive terminal interface.
+fn tui_command(args: &[String])
+{
+    let threads = ::std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut opts = Options { threads, ..Options::default() };
+    let mut positional = vec![];
+    for arg in args.iter()
+    {
+        match arg.as_str()
+        {
+            "--integers-only" => {
+                opts.integers_only = true;
+            },
+            "--subset" => {
+                opts.subset = true;
+            },
+            "--" => {},
+            _ => positional.push(arg.clone())
+        }
+    }
+
+    let target = match positional.pop().map(|s| s.parse::<u64>())
+        {
+            Some(Ok(nr)) => Target::new(nr),
+            _ => usage()
+        };
+    let nrs = parse_numbers(&positional);
+    if let Err(err) = tui::run(nrs, target, opts)
+    {
+        log("tui", &format!("Failed to run the terminal interface: {}", err));
+        ::std::process::exit(1);
+    }
+}
+
+/// Handle the `numbers-for` command, with command line arguments `args`
+/// following the command.
+///
+/// Search for sets of numbers from which the target given by the positional
+/// argument can be made: all multisets of as many numbers as given with
+/// `--count`, in the range given with `--range`, are tried in order, and those
+/// with a number of essentially different exact solutions in the range given
+/// with `--solutions` are printed, up to the number given with `--limit`.
+/// `--unique` is short for `--solutions 1`.
+fn numbers_for_command(args: &[String])
+{
+    let mut opts = Options::default();
+    let mut count = None;
+    let mut range = None;
+    let mut solutions = (1, usize::MAX);
+    let mut limit = 10;
+    let mut positional = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next()
+    {
+        match arg.as_str()
+        {
+            "--integers-only" => {
+                opts.integers_only = true;
+            },
+            "--subset" => {
+                opts.subset = true;
+            },
+            "--count" => {
+                count = match iter.next().map(|s| s.parse::<usize>())
+                    {
+                        Some(Ok(n)) if (1..=MAX_NUMBERS).contains(&n) => Some(n),
+                        _ => usage()
+                    };
+            },
+            "--range" => {
+                range = match iter.next().and_then(|s| parse_range(s))
+                    {
+                        Some(range) => Some(range),
+                        None => usage()
+                    };
+            },
+            "--solutions" => {
+                let range = iter.next().and_then(|s| {
+                        if s.contains("..=")
+                        {
+                            parse_range(s)
+                        }
+                        else
+                        {
+                            parse_number(s).map(|nr| (nr, nr))
+                        }
+                    });
+                solutions = match range
+                    {
+                        Some((min, max)) => (min as usize, max as usize),
+                        None => usage()
+                    };
+            },
+            "--unique" => {
+                solutions = (1, 1);
+            },
+            "--limit" => {
+                limit = match iter.next().map(|s| s.parse::<usize>())
+                    {
+                        Some(Ok(n)) => n,
+                        _ => usage()
+                    };
+            },
+            "--" => {},
+            _ => positional.push(arg.clone())
+        }
+    }
+
+    let (count, (min, max)) = match (count, range)
+        {
+            (Some(count), Some(range)) => (count, range),
+            _ => usage()
+        };
+    let target = match positional.as_slice()
+        {
+            [s] => parse_target(s).unwrap_or_else(|| usage()),
+            _ => usage()
+        };
+    let mut found = 0;
+    for (nrs, exprs) in numbers_for(&target, count, min, max, solutions, &opts).take(limit)
+    {
+        let nrs_str = nrs.iter().map(|nr| nr.to_string()).collect::<Vec<_>>().join(" ");
+        println!("{}: {} solutions, e.g. {} = {}", nrs_str, exprs.len(), exprs[0].to_string(&nrs),
+            exprs[0].val);
+        found += 1;
+    }
+    if found == 0
+    {
+        println!("No sets of {} numbers from {} to {} found", count, min, max);
+    }
+}
+
+/// Handle the `draw` command, with command line arguments `args` following
+/// the command.
+///
+/// Draw a Countdown numbers round with as many large tiles as given with
+/// `--large`, print the tiles and the target, and the best solution under the
+/// rules of the game: every tile can be used at most once, and all
+/// intermediate results must be integers. With `--no-solve`, the solution is
+/// not printed. The draw is random, unless a seed is given with `--seed`. With
+/// `--unique`, rounds are drawn until one has a single essentially different
+/// exact solution.
+fn draw_command(args: &[String])
+{
+    let mut large = 1;
+    let mut solve = true;
+    let mut unique = false;
+    let mut seed = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next()
+    {
+        match arg.as_str()
+        {
+            "--large" => {
+                large = match iter.next().map(|s| s.parse::<usize>())
+                    {
+                        Some(Ok(n)) if n <= COUNTDOWN_LARGE.len() => n,
+                        _ => usage()
+                    };
+            },
+            "--no-solve" => {
+                solve = false;
+            },
+            "--unique" => {
+                unique = true;
+            },
+            "--seed" => {
+                seed = match iter.next().map(|s| s.parse::<u64>())
+                    {
+                        Some(Ok(seed)) => Some(seed),
+                        _ => usage()
+                    };
+            },
+            _ => usage()
+        }
+    }
+
+    let seed = seed.unwrap_or_else(random_seed);
+    let (draw, expr) = if unique
+        {
+            unique_draw(large, seed, &countdown_options()).unwrap()
+        }
+        else
+        {
+            let draw = countdown_draw(large, seed).unwrap();
+            let target = Target::new(draw.target);
+            let expr = get_nearest_expression(&draw.nrs, &target, &countdown_options());
+            (draw, expr)
+        };
+    print_draw(&draw);
+    if solve
+    {
+        println!("{} = {}", expr.to_string(&draw.nrs), expr.val);
+    }
+}
+
+/// Handle the `play` command, with command line arguments `args` following
+/// the command.
+///
+/// Draw a Countdown numbers round as in the `draw` command, and give the
+/// player the number of seconds given with `--time`, 30 by default, to type an
+/// expression for the target. The expression is checked against the rules of
+/// the game, and afterwards the best solution is revealed for comparison, as
+/// well as up to `PLAY_WAYS` other ways to make the value of the expression.
+fn play_command(args: &[String])
+{
+    let mut large = 1;
+    let mut time = 30;
+    let mut seed = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next()
+    {
+        match arg.as_str()
+        {
+            "--large" => {
+                large = match iter.next().map(|s| s.parse::<usize>())
+                    {
+                        Some(Ok(n)) if n <= COUNTDOWN_LARGE.len() => n,
+                        _ => usage()
+                    };
+            },
+            "--time" => {
+                time = match iter.next().map(|s| s.parse::<u64>())
+                    {
+                        Some(Ok(secs)) if secs > 0 => secs,
+                        _ => usage()
+                    };
+            },
+            "--seed" => {
+                seed = match iter.next().map(|s| s.parse::<u64>())
+                    {
+                        Some(Ok(seed)) => Some(seed),
+                        _ => usage()
+                    };
+            },
+            _ => usage()
+        }
+    }
+
+    let draw = countdown_draw(large, seed.unwrap_or_else(random_seed)).unwrap();
+    let target = Target::new(draw.target);
+    let opts = countdown_options();
+
+    // Solve the round while the player thinks
+    let solver = {
+            let (nrs, opts) = (draw.nrs.clone(), opts.clone());
+            ::std::thread::spawn(move || Session::new(&nrs, &opts))
+        };
+    let (sender, receiver) = ::std::sync::mpsc::channel();
+    ::std::thread::spawn(move || {
+            let mut line = String::new();
+            if ::std::io::stdin().read_line(&mut line).is_ok()
+            {
+                let _ = sender.send(line);
+            }
+        });
+
+    print_draw(&draw);
+    println!("You have {} seconds. Type your expression and press Enter:", time);
+    let declared = match receiver.recv_timeout(::std::time::Duration::from_secs(time))
+        {
+            Ok(line) if line.trim().is_empty() => {
+                println!("No expression given");
+                None
+            },
+            Ok(line) => match parse_with(line.trim(), &draw.nrs, &opts)
+                {
+                    Ok(expr) if target.contains(expr.val) => {
+                        println!("Well done! {} = {}, {} points", expr.to_string(&draw.nrs),
+                            expr.val, countdown_points(&expr, draw.target));
+                        Some(expr)
+                    },
+                    Ok(expr) => {
+                        println!("{} = {}, which is {} away, {} points",
+                            expr.to_string(&draw.nrs), expr.val, target.distance(expr.val),
+                            countdown_points(&expr, draw.target));
+                        Some(expr)
+                    },
+                    Err(err) => {
+                        println!("Invalid expression: {}", err);
+                        None
+                    }
+                },
+            Err(_) => {
+                println!("\nTime is up!");
+                None
+            }
+        };
+
+    let session = solver.join().unwrap();
+    let expr = session.nearest(&target);
+    println!("Best solution: {} = {}", expr.to_string(&draw.nrs), expr.val);
+    if let Some(declared) = declared
+    {
+        // Show ways to make the declared value that differ from the player's
+        // own in more than the order of the operands
+        let mut seen = ::std::collections::HashSet::new();
+        seen.insert(canonical_hash(&declared, &draw.nrs));
+        let ways = session.expressions_for(declared.val).into_iter()
+            .filter(|expr| seen.insert(canonical_hash(expr, &draw.nrs)))
+            .take(PLAY_WAYS)
+            .collect::<Vec<_>>();
+        if !ways.is_empty()
+        {
+            println!("Other ways to make {}:", declared.val);
+            for expr in ways
+            {
+                println!("  {}", expr.to_string(&draw.nrs));
+            }
+        }
+    }
+}
+
+/// Handle the `daily` command, with command line arguments `args` following
+/// the command.
+///
+/// Print the Countdown numbers round for today, in UTC, or for the date given
+/// with `--date` in the form `yyyy-mm-dd`. The round only depends on the date,
+/// so that everyone gets the same puzzle on the same day. With `--solve`, the
+/// best solution is printed as well.
+fn daily_command(args: &[String])
+{
+    let mut day = None;
+    let mut solve = false;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next()
+    {
+        match arg.as_str()
+        {
+            "--date" => {
+                day = match iter.next().and_then(|s| parse_date(s))
+                    {
+                        Some(day) => Some(day),
+                        None => usage()
+                    };
+            },
+            "--solve" => {
+                solve = true;
+            },
+            _ => usage()
+        }
+    }
+
+    let day = day.unwrap_or_else(|| {
+            ::std::time::SystemTime::now().duration_since(::std::time::UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs() / 86400)
+        });
+    let (year, month, date) = civil_from_days(day);
+    let draw = daily_draw(day);
+    println!("Puzzle of {:04}-{:02}-{:02}", year, month, date);
+    print_draw(&draw);
+    if solve
+    {
+        let target = Target::new(draw.target);
+        let expr = get_nearest_expression(&draw.nrs, &target, &countdown_options());
+        println!("{} = {}", expr.to_string(&draw.nrs), expr.val);
+    }
+}
+
+/// Parse a date.
+///
+/// Parse a date of the form `yyyy-mm-dd`, from 1970 onwards, into the number
+/// of days since 1 January 1970. Returns `None` if the date is invalid.
+fn parse_date(s: &str) -> Option<u64>
+{
+    let mut parts = s.splitn(3, '-').map(|part| part.parse::<u64>().ok());
+    let (year, month, date) = (parts.next()??, parts.next()??, parts.next()??);
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = [31, if leap { 29 } else { 28 }, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    if year < 1970 || !(1..=12).contains(&month)
+        || !(1..=days_in_month[month as usize - 1]).contains(&date)
+    {
+        return None;
+    }
+
+    // Count the days from 1 March of year 0, so that leap days come last
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let days = 365*y + y/4 - y/100 + y/400 + (153*m + 2)/5 + date - 1;
+    Some(days - 719468)
+}
+
+/// Convert a number of days since 1 January 1970 into a date, as
+/// `(year, month, day)`.
+fn civil_from_days(days: u64) -> (u64, u64, u64)
+{
+    // Count the days from 1 March of year 0, in eras of 400 years
+    let days = days + 719468;
+    let era = days / 146097;
+    let doe = days % 146097;
+    let yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365;
+    let doy = doe - (365*yoe + yoe/4 - yoe/100);
+    let mp = (5*doy + 2) / 153;
+    let date = doy - (153*mp + 2)/5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = era*400 + yoe + if month <= 2 { 1 } else { 0 };
+    (year, month, date)
+}
+
+/// Handle the `score` command, with command line arguments `args` following
+/// the command.
+///
+/// Check the declaration given by the first positional argument against the
+/// rules of the Countdown numbers game, for the tiles given by the following
+/// positional arguments, and the target given by the last, and print the
+/// points it is awarded.
+fn score_command(args: &[String])
+{
+    let mut positional = args.iter().filter(|arg| *arg != "--").cloned().collect::<Vec<_>>();
+    if positional.len() < 3
+    {
+        usage();
+    }
+    let target = parse_number(&positional.pop().unwrap()).unwrap_or_else(|| usage());
+    let nrs = parse_numbers(&positional[1..]);
+    let fractional = || parse_with(&positional[0], &nrs, &Options::default()).is_ok();
+    match parse_with(&positional[0], &nrs, &countdown_options())
+    {
+        Ok(expr) => {
+            println!("{} = {}, {} points", expr.to_string(&nrs), expr.val,
+                countdown_points(&expr, target));
+        },
+        Err(ParseError::Invalid) if fractional() => {
+            println!("Invalid declaration: not all intermediate results are integers, 0 points");
+        },
+        Err(err) => println!("Invalid declaration: {}, 0 points", err)
+    }
+}
+
+/// Handle the `export` command, with command line arguments `args` following
+/// the command.
+///
+/// Draw as many Countdown numbers rounds as given with `--count`, 10 by
+/// default, that can be solved exactly under the rules of the game, and write
+/// them with their solutions to the puzzle bank file given with `--output`.
+/// The options `--large`, `--unique` and `--seed` are as for `draw`.
+fn export_command(args: &[String])
+{
+    let mut count = 10;
+    let mut large = 1;
+    let mut unique = false;
+    let mut seed = None;
+    let mut output = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next()
+    {
+        match arg.as_str()
+        {
+            "--count" => {
+                count = match iter.next().map(|s| s.parse::<usize>())
+                    {
+                        Some(Ok(n)) => n,
+                        _ => usage()
+                    };
+            },
+            "--large" => {
+                large = match iter.next().map(|s| s.parse::<usize>())
+                    {
+                        Some(Ok(n)) if n <= COUNTDOWN_LARGE.len() => n,
+                        _ => usage()
+                    };
+            },
+            "--unique" => {
+                unique = true;
+            },
+            "--seed" => {
+                seed = match iter.next().map(|s| s.parse::<u64>())
+                    {
+                        Some(Ok(seed)) => Some(seed),
+                        _ => usage()
+                    };
+            },
+            "--output" => {
+                output = match iter.next()
+                    {
+                        Some(path) => Some(path.clone()),
+                        None => usage()
+                    };
+            },
+            _ => usage()
+        }
+    }
+
+    let output = output.unwrap_or_else(|| usage());
+    let opts = countdown_options();
+    let mut puzzles = vec![];
+    let mut seed = seed.unwrap_or_else(random_seed);
+    while puzzles.len() < count
+    {
+        let draw = countdown_draw(large, seed).unwrap();
+        if let Some(puzzle) = Puzzle::new(&draw.nrs, draw.target, &opts)
+            .filter(|puzzle| !unique || puzzle.solutions.len() == 1)
+        {
+            puzzles.push(puzzle);
+        }
+        seed = seed.wrapping_add(1);
+    }
+
+    if let Err(err) = ::std::fs::write(&output, bank_to_json(&puzzles))
+    {
+        log("io", &format!("Failed to write puzzle bank {}: {}", output, err));
+        ::std::process::exit(1);
+    }
+    println!("Wrote {} puzzles to {}", puzzles.len(), output);
+}
+
+/// Handle the `import` command, with command line arguments `args` following
+/// the command.
+///
+/// Read the puzzle bank file given by the positional argument, and verify the
+/// solutions of every puzzle in it, printing the puzzles with the result. With
+/// `--integers-only`, the solutions must only have integer intermediate
+/// results. Exits with a non-zero status if a puzzle fails verification.
+fn import_command(args: &[String])
+{
+    let mut opts = Options::default();
+    let mut positional = vec![];
+    for arg in args.iter()
+    {
+        match arg.as_str()
+        {
+            "--integers-only" => {
+                opts.integers_only = true;
+            },
+            "--" => {},
+            _ => positional.push(arg.clone())
+        }
+    }
+    let path = match positional.as_slice()
+        {
+            [path] => path,
+            _ => usage()
+        };
+
+    let puzzles = match ::std::fs::read_to_string(path).map_err(|err| err.to_string())
+        .and_then(|s| bank_from_json(&s))
+        {
+            Ok(puzzles) => puzzles,
+            Err(err) => {
+                log("io", &format!("Failed to read puzzle bank {}: {}", path, err));
+                ::std::process::exit(1);
+            }
+        };
+    let mut failed = 0;
+    for puzzle in puzzles.iter()
+    {
+        let nrs_str = puzzle.numbers.iter().map(|nr| nr.to_string()).collect::<Vec<_>>().join(" ");
+        match puzzle.verify(&opts)
+        {
+            Ok(()) => {
+                println!("{} -> {}: {} solutions, difficulty {}, e.g. {}", nrs_str, puzzle.target,
+                    puzzle.solutions.len(), puzzle.difficulty, puzzle.solutions[0]);
+            },
+            Err(err) => {
+                println!("{} -> {}: {}", nrs_str, puzzle.target, err);
+                failed += 1;
+            }
+        }
+    }
+    println!("{} puzzles, {} verified", puzzles.len(), puzzles.len() - failed);
+    if failed > 0
+    {
+        ::std::process::exit(1);
+    }
+}
+
+/// Generate a seed from the current time, for a random draw.
+fn random_seed() -> u64
+{
+    ::std::time::SystemTime::now().duration_since(::std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+/// The search options for the rules of the Countdown numbers game: every tile
+/// is used at most once, and all intermediate results must be integers.
+fn countdown_options() -> Options
+{
+    Options { integers_only: true, subset: true, ..Options::default() }
+}
+
+/// Print the tiles and the target of a Countdown numbers round.
+fn print_draw(draw: &Draw)
+{
+    let nrs_str = draw.nrs.iter().map(|nr| nr.to_string()).collect::<Vec<_>>().join(" ");
+    println!("Numbers: {}", nrs_str);
+    println!("Target: {}", draw.target);
+}
+
+/// Handle the `inspect` command, with command line arguments `args` following
+/// the command.
+///
+/// Read queries from standard input, one per line, and answer them from a
+/// cache of the sub-expressions of the pool of numbers given by the positional
+/// arguments. A query is a list of numbers from the pool, for which every
+/// distinct value that can be made is printed, optionally followed by `= v` to
+/// only look up value `v`. The query `stats` prints the cache statistics, and
+/// `quit` or the end of the input ends the session.
+fn inspect_command(args: &[String])
+{
+    let mut opts = Options { cache_counters: Some(Arc::new(CacheCounters::default())),
+        ..Options::default() };
+    let mut positional = vec![];
+    for arg in args.iter()
+    {
+        match arg.as_str()
+        {
+            "--integers-only" => {
+                opts.integers_only = true;
+            },
+            "--" => {},
+            _ => positional.push(arg.clone())
+        }
+    }
+
+    let nrs = parse_numbers(&positional);
+    let interactive = ::std::io::stdin().is_terminal();
+    let mut cache = Cache::default();
+    let mut lines = ::std::io::stdin().lines();
+    loop
+    {
+        if interactive
+        {
+            print!("> ");
+            let _ = ::std::io::Write::flush(&mut ::std::io::stdout());
+        }
+        let line = match lines.next()
+            {
+                Some(Ok(line)) => line,
+                _ => break
+            };
+        let query = line.trim();
+        match query
+        {
+            "" => continue,
+            "quit" => break,
+            "stats" => {
+                print_cache_stats(&cache, &opts);
+                continue;
+            },
+            _ => {}
+        }
+
+        let (set, value) = match query.split_once('=')
+            {
+                Some((set, value)) => match value.trim().parse::<u64>()
+                    {
+                        Ok(value) => (set, Some(Rat::from_integer(value))),
+                        Err(_) => {
+                            println!("Invalid value \"{}\"", value.trim());
+                            continue;
+                        }
+                    },
+                None => (query, None)
+            };
+        let idxs = match query_indices(&nrs, set)
+            {
+                Ok(idxs) => idxs,
+                Err(msg) => {
+                    println!("{}", msg);
+                    continue;
+                }
+            };
+
+        let exprs = distinct_values(&nrs, &idxs, &opts, &mut cache);
+        match value
+        {
+            Some(value) => match exprs.iter().find(|expr| expr.val == value)
+                {
+                    Some(expr) => println!("{} = {}", expr.to_string(&nrs), value),
+                    None => println!("{} cannot be made from these numbers", value)
+                },
+            None => {
+                for expr in exprs.iter()
+                {
+                    println!("{} = {}", expr.val, expr.to_string(&nrs));
+                }
+                println!("{} values", exprs.len());
+            }
+        }
+    }
+}
+
+/// Map the numbers in query `set`, separated by spaces or commas, to unique
+/// indices into the pool `nrs`, sorted.
+///
+/// Returns an error message if a number is invalid, does not occur in the
+/// pool, or is used more often than it occurs there.
+fn query_indices(nrs: &[u64], set: &str) -> Result<Vec<makeexpr::Idx>, String>
+{
+    let mut idxs = vec![];
+    for s in set.split([' ', ',']).filter(|s| !s.is_empty())
+    {
+        let nr = s.parse::<u64>().map_err(|_| format!("Invalid number \"{}\"", s))?;
+        let idx = nrs.iter().position(|&x| x == nr)
+            .ok_or_else(|| format!("{} is not in the pool of numbers", nr))?;
+        idxs.push(idx as makeexpr::Idx);
+        let used = idxs.iter().filter(|&&i| i as usize == idx).count();
+        if used > nrs.iter().filter(|&&x| x == nr).count()
+        {
+            return Err(format!("{} is used more often than it occurs in the pool", nr));
+        }
+    }
+    if idxs.is_empty()
+    {
+        return Err(String::from("No numbers given"));
+    }
+    idxs.sort_unstable();
+    Ok(idxs)
+}
+
+/// The file in which the Countdown table is stored when it is generated on
+/// first use: `makeexpr/countdown.tbl` in the user's cache directory, or in
+/// the current directory if there is none.
+fn countdown_table_path() -> String
+{
+    let dir = ::std::env::var("XDG_CACHE_HOME").ok().filter(|dir| !dir.is_empty())
+        .or_else(|| ::std::env::var("HOME").ok().map(|home| format!("{}/.cache", home)));
+    match dir
+    {
+        Some(dir) => format!("{}/makeexpr/countdown.tbl", dir),
+        None      => String::from("countdown.tbl")
+    }
+}
+
+/// Read the Countdown table from the file at `path`. If the file does not
+/// exist, the table is generated using `threads` threads, and written to
+/// `path`, so that this is only done once.
+fn load_countdown_table(path: &str, threads: usize) -> CountdownTable
+{
+    match CountdownTable::read(path)
+    {
+        Ok(table) => return table,
+        Err(err) if err.kind() != ::std::io::ErrorKind::NotFound => {
+            fail("io_error", &format!("Failed to read {}: {}", path, err), Some(path), 1);
+        },
+        Err(_) => {}
+    }
+
+    log("table", &format!("Generating the Countdown table in {}, this is only done once", path));
+    let table = CountdownTable::generate(threads);
+    let dir = ::std::path::Path::new(path).parent().filter(|dir| !dir.as_os_str().is_empty());
+    let res = dir.map_or(Ok(()), ::std::fs::create_dir_all).and_then(|_| table.write(path));
+    if let Err(err) = res
+    {
+        log("io", &format!("Failed to write {}: {}", path, err));
+    }
+    table
+}
+
+/// Handle the `table` command, with command line arguments `args` following
+/// the command.
+///
+/// A table of all values that can be made from the numbers given by the
+/// positional arguments is generated, and written to the file following
+/// `--output`. With `--countdown`, the table of reachable targets for every
+/// selection of Countdown tiles is generated instead.
+fn table_command(args: &[String])
+{
+    let mut opts = Options::default();
+    let mut path = None;
+    let mut countdown = false;
+    let mut positional = vec![];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next()
+    {
+        match arg.as_str()
+        {
+            "--integers-only" => {
+                opts.integers_only = true;
+            },
+            "--subset" => {
+                opts.subset = true;
+            },
+            "--countdown" => {
+                countdown = true;
+            },
+            "--output" => {
+                path = match iter.next()
+                    {
+                        Some(path) => Some(path.clone()),
+                        None => usage()
+                    };
+            },
+            _ => positional.push(arg.clone())
+        }
+    }
+
+    let path = match path
+        {
+            Some(path) => path,
+            None => usage()
+        };
+    if countdown
+    {
+        let threads = ::std::thread::available_parallelism().map_or(1, |n| n.get());
+        let table = CountdownTable::generate(threads);
+        if let Err(err) = table.write(&path)
+        {
+            log("io", &format!("Failed to write {}: {}", path, err));
+            ::std::process::exit(1);
+        }
+        println!("Wrote the reachable targets of {} selections to {}", table.len(), path);
+        return;
+    }
+    let nrs = parse_numbers(&positional);
+    if let Err(err) = ValueTable::generate(&nrs, &opts, &path)
+    {
+        log("io", &format!("Failed to write {}: {}", path, err));
+        ::std::process::exit(1);
+    }
+    match ValueTable::open(&path)
+    {
+        Ok(table) => println!("Wrote {} values to {}", table.len(), path),
+        Err(err) => {
+            log("io", &format!("Failed to read {}: {}", path, err));
+            ::std::process::exit(1);
+        }
+    }
+}
+
+/// Parse the input numbers.
+///
+/// Parse the input numbers from the command line arguments `args`. If no
+/// numbers are given, or one of them is invalid, print a usage message and
+/// exit.
+fn parse_numbers(args: &[String]) -> Vec<u64>
+{
+    if args.is_empty()
+    {
+        usage()
+    }
+
+    let mut nrs = vec![];
+    for arg in args.iter()
+    {
+        match parse_number(arg)
+        {
+            Some(nr) => nrs.push(nr),
+            None => fail("invalid_number", &format!("Invalid number {}", arg), Some(arg), 1)
+        }
+    }
+
+    if nrs.len() > MAX_NUMBERS
+    {
+        fail("limit_violation", &format!("Too many numbers, at most {} are supported",
+            MAX_NUMBERS), None, 1);
+    }
+
+    nrs
+}
+
+/// Split the base prefix `0x`, `0o` or `0b` off number `s`.
+///
+/// Returns the digits following the prefix and the base it denotes, or `None`
+/// if `s` has no prefix.
+fn prefix_base(s: &str) -> Option<(&str, u32)>
+{
+    match s.get(..2)?.to_ascii_lowercase().as_str()
+    {
+        "0x" => Some((&s[2..], 16)),
+        "0o" => Some((&s[2..], 8)),
+        "0b" => Some((&s[2..], 2)),
+        _    => None
+    }
+}
+
+/// Normalize a number.
+///
+/// Remove a leading `+` sign and the underscores separating groups of digits
+/// from number `s`, e.g. `+1_000_000` becomes `1000000`. Returns `None` if `s`
+/// has more than one sign, or starts or ends with an underscore, or has two
+/// underscores in a row, as in `++1`, `_1`, `1_` or `1__0`.
+fn normalize_number(s: &str) -> Option<String>
+{
+    let s = s.strip_prefix('+').unwrap_or(s);
+    if s.starts_with(['+', '-']) || s.starts_with('_') || s.ends_with('_') || s.contains("__")
+    {
+        return None;
+    }
+    Some(s.chars().filter(|&c| c != '_').collect())
+}
+
+/// Parse a number.
+///
+/// Parse a number in the base given by its prefix, as in `prefix_base()`, or
+/// without a prefix in the base given by `--base`, decimal by default. With
+/// `--roman`, a number may also be written in Roman numerals. The number may
+/// have a leading `+` sign, and underscores between its digits, and a decimal
+/// number may be written in scientific notation, as in `parse_scientific()`.
+fn parse_number(s: &str) -> Option<u64>
+{
+    let s = normalize_number(s)?;
+    if let Some(nr) = parse_roman(&s).filter(|_| ROMAN_INPUT.load(Ordering::Relaxed))
+    {
+        return Some(nr);
+    }
+    let (digits, base) = prefix_base(&s).unwrap_or((&s, INPUT_BASE.get().cloned().unwrap_or(10)));
+    // The digits may not have a sign of their own, as from_str_radix() allows
+    if digits.starts_with(['+', '-'])
+    {
+        None
+    }
+    else if base == 10 && digits.contains(['e', 'E'])
+    {
+        parse_scientific(digits)
+    }
+    else
+    {
+        u64::from_str_radix(digits, base).ok()
+    }
+}
+
+/// Parse a number in scientific notation.
+///
+/// Parse a decimal number of the form `m e x`, with an optional fractional part
+/// in mantissa `m`, e.g. `1e6` or `2.5E3`. The exponent `x` may be negative, but
+/// the value of the number must be an integer, so that `25e-1` is rejected,
+/// while `2500e-2` is accepted.
+fn parse_scientific(s: &str) -> Option<u64>
+{
+    let (mantissa, exponent) = s.split_once(['e', 'E'])?;
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if int.is_empty() || !int.chars().chain(frac.chars()).all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let nr = format!("{}{}", int, frac).parse::<u64>().ok()?;
+    let scale = exponent.parse::<i32>().ok()?.checked_sub(frac.len() as i32)?;
+    if scale >= 0
+    {
+        10u64.checked_pow(scale as u32).and_then(|pow| nr.checked_mul(pow))
+    }
+    else
+    {
+        let pow = 10u64.checked_pow(scale.unsigned_abs()).unwrap_or(u64::MAX);
+        if nr % pow == 0 { Some(nr / pow) } else { None }
+    }
+}
+
+/// Parse a target.
+///
+/// Parse a string that is either a single number, or an inclusive range of
+/// the form `min..=max`.
+fn parse_target(s: &str) -> Option<Target>
+{
+    if s.contains("..=")
+    {
+        parse_range(s).map(|(min, max)| Target::range(min, max))
+    }
+    else
+    {
+        parse_number(s).map(Target::new)
+    }
+}
+
+/// Parse a size in bytes.
+///
+/// Parse a number of bytes, optionally followed by a suffix `K`, `M` or `G`
+/// for kibibytes, mebibytes or gibibytes respectively.
+fn parse_size(s: &str) -> Option<usize>
+{
+    let (digits, unit) = match s.chars().last()?.to_ascii_uppercase()
+        {
+            'K' => (&s[..s.len()-1], 1 << 10),
+            'M' => (&s[..s.len()-1], 1 << 20),
+            'G' => (&s[..s.len()-1], 1 << 30),
+            _   => (s, 1)
+        };
+    digits.parse::<usize>().ok()?.checked_mul(unit)
+}
+
+/// Parse an inclusive range.
+///
+/// Parse a string of the form `min..=max` into the bounds `(min, max)`.
+/// Returns `None` if the string is not a valid, non-empty, range.
+fn parse_range(s: &str) -> Option<(u64, u64)>
+{
+    let mut parts = s.splitn(2, "..=");
+    let min = parse_number(parts.next()?)?;
+    let max = parse_number(parts.next()?)?;
+    if min <= max { Some((min, max)) } else { None }
+}
+
+/// Recording of a run of the program
+///
+/// Struct `Record` holds the command line arguments of a run, which determine
+/// the search configuration and, through the seed, the order in which the
+/// search visits the expressions, together with the resulting search
+/// configuration and expression. A recorded run can be replayed to check that
+/// it reproduces the same result.
+struct Record
+{
+    /// The command line arguments, except for the `--record` option
+    args: Vec<String>,
+    /// The seed for shuffling the search order
+    seed: u64,
+    /// The search configuration, if recorded
+    config: Option<SolverConfig>,
+    /// The serialized expression found, if any
+    result: Option<String>
+}
+
+impl Record
+{
+    /// Write this record to the file at `path`.
+    fn write(&self, path: &str) -> ::std::io::Result<()>
+    {
+        let mut s = String::from("makeexpr-record 1\n");
+        for arg in self.args.iter()
+        {
+            s += &format!("arg {}\n", arg);
+        }
+        s += &format!("seed {}\n", self.seed);
+        if let Some(config) = &self.config
+        {
+            s += &format!("config {}\n", serde_json::to_string(config).unwrap());
+        }
+        if let Some(result) = &self.result
+        {
+            s += &format!("result {}\n", result);
+        }
+        ::std::fs::write(path, s)
+    }
+
+    /// Read a record from the file at `path`.
+    fn read(path: &str) -> Result<Self, String>
+    {
+        let s = ::std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let mut lines = s.lines();
+        if lines.next() != Some("makeexpr-record 1")
+        {
+            return Err(String::from("not a recorded run"));
+        }
+
+        let mut record = Record { args: vec![], seed: 0, config: None, result: None };
+        for line in lines
+        {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key
+            {
+                "arg"    => record.args.push(value.to_string()),
+                "seed"   => {
+                    record.seed = value.parse().map_err(|_| format!("invalid seed {}", value))?;
+                },
+                "config" => {
+                    let config = serde_json::from_str(value).map_err(|err| err.to_string())?;
+                    record.config = Some(config);
+                },
+                "result" => record.result = Some(value.to_string()),
+                _        => return Err(format!("invalid line \"{}\"", line))
+            }
+        }
+        Ok(record)
+    }
+}
+
+/// Finish a recorded or replayed run.
+///
+/// Write the arguments `args`, the seed `seed`, the search configuration of
+/// `opts` and the expression `expr` found over the numbers `nrs` to the file at
+/// `record`, if given. If `replay` is given, check that the configuration and
+/// the expression are the same as in the replayed run.
+fn finish_run(record: Option<&str>, replay: Option<&Record>, args: &[String], seed: u64,
+    opts: &Options, nrs: &[u64], expr: Option<&Expr>)
+{
+    let config = SolverConfig::from_options(opts);
+    let result = expr.filter(|expr| !expr.is_empty()).map(|expr| expr.serialize(nrs));
+    if let Some(path) = record
+    {
+        let rec = Record { args: args.to_vec(), seed, config: Some(config.clone()),
+            result: result.clone() };
+        if let Err(err) = rec.write(path)
+        {
+            log("io", &format!("Failed to write {}: {}", path, err));
+            ::std::process::exit(1);
+        }
+    }
+    if let Some(rec) = replay
+    {
+        if rec.config.as_ref().is_some_and(|rec_config| *rec_config != config)
+        {
+            println!("Replay differs from the recorded run: the search configuration changed");
+            ::std::process::exit(1);
+        }
+        if rec.result == result
+        {
+            println!("Replay reproduces the recorded run");
+        }
+        else
+        {
+            println!("Replay differs from the recorded run: recorded {}, found {}",
+                rec.result.as_deref().unwrap_or("nothing"), result.as_deref().unwrap_or("nothing"));
+            ::std::process::exit(1);
+        }
+    }
+}
+
+fn main()
+{
+    let _ = LOG_START.set(::std::time::Instant::now());
+    let threads = ::std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut opts = Options { print_intermediate: true, threads, ..Options::default() };
+    let mut mode = Mode::Solve;
+    let mut output = Output { format: Format::Plain, simplify: false, explain: false,
+        certificate: false, cache_stats: false, self_check: false, memory_limit: None,
+        table: None, countdown: None, strategy: Strategy::Exhaustive, suggest_extra: None,
+        scoring: Scoring::default(), deterministic: false, canonical: false,
+        style: Style::default(), require_exact: false, quiet: false };
+    let mut iterations = None;
+    let mut hybrid_depth = None;
+    let mut must_contain = None;
+    let mut structural = false;
+    let mut shuffle = false;
+    let mut seed = 0;
+    let mut target = None;
+    let mut positional = vec![];
+    let mut results_path = None;
+    let mut parallel = false;
+    let mut watch = false;
+    let mut csv_cols = None::<batch::CsvColumns>;
+
+    let mut replay = None;
+    let mut record = None;
+    let mut run_args = ::std::env::args().skip(1).collect::<Vec<_>>();
+    if let Some(pos) = run_args.iter().position(|arg| arg == "--json-errors")
+    {
+        JSON_ERRORS.store(true, Ordering::Relaxed);
+        run_args.remove(pos);
+    }
+    if run_args.first().map(|s| s.as_str()) == Some("--schema")
+    {
+        print_schema(run_args.get(1).map(|s| s.as_str()));
+    }
+    if run_args.first().map(|s| s.as_str()) == Some("--replay")
+    {
+        let rec = match run_args.get(1).map(|path| (path, Record::read(path)))
+            {
+                Some((_, Ok(rec))) => rec,
+                Some((path, Err(msg))) => {
+                    log("io", &format!("Failed to read {}: {}", path, msg));
+                    ::std::process::exit(1);
+                },
+                None => usage()
+            };
+        run_args = rec.args.clone();
+        replay = Some(rec);
+    }
+    if let Some(pos) = run_args.iter().position(|arg| arg == "--record")
+    {
+        if pos + 1 >= run_args.len()
+        {
+            usage();
+        }
+        record = Some(run_args.remove(pos + 1));
+        run_args.remove(pos);
+    }
+
+    if let Some(pos) = run_args.iter().position(|arg| arg == "--log-format")
+    {
+        let json = match run_args.get(pos + 1).map(|s| s.as_str())
+            {
+                Some("plain") => false,
+                Some("json") => true,
+                _ => usage()
+            };
+        LOG_JSON.store(json, Ordering::Relaxed);
+        run_args.drain(pos..pos+2);
+    }
+    if let Some(pos) = run_args.iter().position(|arg| arg == "--base")
+    {
+        let base = match run_args.get(pos + 1).map(|s| s.parse::<u32>())
+            {
+                Some(Ok(base)) if (2..=36).contains(&base) => base,
+                _ => usage()
+            };
+        let _ = INPUT_BASE.set(base);
+        output.style.base = base;
+    }
+    if run_args.iter().any(|arg| arg == "--roman")
+    {
+        ROMAN_INPUT.store(true, Ordering::Relaxed);
+    }
+
+    // The global options apply to every command, and are not passed on to it
+    let command_args = strip_global_options(&run_args);
+    if let Some(handler) = command_args.first().and_then(|name| command_handler(name))
+    {
+        handler(&command_args[1..]);
+        return;
+    }
+    if command_args.first().map(|s| s.as_str()) == Some("solve")
+    {
+        let pos = run_args.iter().position(|arg| arg == "solve").unwrap();
+        run_args.remove(pos);
+    }
+
+    let mut args = run_args.clone().into_iter().peekable();
+    if args.peek().map(|s| s.as_str()) == Some("fill")
+    {
+        args.next();
+        mode = match args.next().map(|s| (Template::parse(&s), s))
+            {
+                Some((Ok(template), _)) => Mode::Fill(template),
+                Some((Err(msg), s)) => {
+                    fail("parse_error", &format!("Invalid template: {}", msg), Some(&s), 1)
+                },
+                None => usage()
+            };
+    }
+
+    while let Some(arg) = args.next()
+    {
+        *CURRENT_ARG.lock().unwrap() = Some(arg.clone());
+        match arg.as_str()
+        {
+            "--without" => {
+                mode = match args.next().as_deref()
+                    {
+                        Some("all") => Mode::WithoutAll,
+                        Some(s) => match s.parse::<usize>()
+                            {
+                                Ok(i) if i > 0 => Mode::Without(i - 1),
+                                _ => usage()
+                            },
+                        None => usage()
+                    };
+            },
+            "--contributions" => {
+                mode = Mode::Contributions;
+            },
+            "--all" => {
+                mode = Mode::All;
+            },
+            "--pareto" => {
+                mode = Mode::Pareto;
+            },
+            "--dry-run" => {
+                mode = Mode::DryRun;
+            },
+            "--check" => {
+                mode = Mode::Check;
+            },
+            "--distinct" => {
+                structural = match args.next().as_deref()
+                    {
+                        Some("structural") => true,
+                        Some("value-path") => false,
+                        _ => usage()
+                    };
+            },
+            "--require-exact" => {
+                output.require_exact = true;
+            },
+            "--quiet" => {
+                output.quiet = true;
+            },
+            "--integers-only" => {
+                opts.integers_only = true;
+            },
+            "--explain" => {
+                output.explain = true;
+            },
+            "--trace" => {
+                let path = match args.next()
+                    {
+                        Some(path) => path,
+                        None => usage()
+                    };
+                match ::std::fs::File::create(&path)
+                {
+                    Ok(file) => {
+                        opts.trace = Some(Arc::new(Trace::new(::std::io::BufWriter::new(file))));
+                    },
+                    Err(err) => {
+                        log("io", &format!("Failed to create {}: {}", path, err));
+                        ::std::process::exit(1);
+                    }
+                }
+            },
+            "--self-check" => {
+                output.self_check = true;
+            },
+            "--memory-limit" => {
+                output.memory_limit = match args.next().as_deref().and_then(parse_size)
+                    {
+                        Some(bytes) => Some(bytes),
+                        None => usage()
+                    };
+            },
+            "--table" => {
+                let path = match args.next()
+                    {
+                        Some(path) => path,
+                        None => usage()
+                    };
+                match ValueTable::open(&path)
+                {
+                    Ok(table) => {
+                        output.table = Some(table);
+                    },
+                    Err(err) => {
+                        log("io", &format!("Failed to read {}: {}", path, err));
+                        ::std::process::exit(1);
+                    }
+                }
+            },
+            "--countdown" => {
+                opts.integers_only = true;
+                opts.subset = true;
+                output.countdown = Some(load_countdown_table(&countdown_table_path(),
+                    opts.threads));
+            },
+            "--countdown-table" => {
+                let path = match args.next()
+                    {
+                        Some(path) => path,
+                        None => usage()
+                    };
+                opts.integers_only = true;
+                opts.subset = true;
+                output.countdown = Some(load_countdown_table(&path, opts.threads));
+            },
+            "--dump-cache-stats" => {
+                output.cache_stats = true;
+                opts.cache_counters = Some(Arc::new(CacheCounters::default()));
+            },
+            "--certify" => {
+                output.certificate = true;
+            },
+            "--subset" => {
+                opts.subset = true;
+            },
+            "--prune-dominated" => {
+                opts.prune_dominated = true;
+            },
+            "--jobs" => {
+                opts.threads = match args.next().map(|s| s.parse::<usize>())
+                    {
+                        Some(Ok(n)) if n > 0 => n,
+                        _ => usage()
+                    };
+            },
+            "--max-ops" => {
+                opts.max_ops = match args.next().map(|s| s.parse::<usize>())
+                    {
+                        Some(Ok(k)) => Some(k),
+                        _ => usage()
+                    };
+            },
+            "--strategy" => {
+                output.strategy = match args.next().as_deref().and_then(Strategy::from_name)
+                    {
+                        Some(strategy) => strategy,
+                        None => usage()
+                    };
+            },
+            "--iterations" => {
+                iterations = match args.next().map(|s| s.parse::<usize>())
+                    {
+                        Some(Ok(n)) => Some(n),
+                        _ => usage()
+                    };
+            },
+            "--suggest-extra" => {
+                output.suggest_extra = match args.next().as_deref().and_then(parse_range)
+                    {
+                        Some(range) => Some(range),
+                        None => usage()
+                    };
+            },
+            "--hybrid-depth" => {
+                hybrid_depth = match args.next().map(|s| s.parse::<usize>())
+                    {
+                        Some(Ok(k)) if k > 0 => Some(k),
+                        _ => usage()
+                    };
+            },
+            "--target-range" => {
+                target = match args.next().as_deref().and_then(parse_range)
+                    {
+                        Some((min, max)) => Some(Target::range(min, max)),
+                        None => usage()
+                    };
+            },
+            "--targets-any" => {
+                let targets = match args.next()
+                    {
+                        Some(s) => s.split(',').map(parse_target).collect::<Option<Vec<_>>>(),
+                        None => None
+                    };
+                target = match targets
+                    {
+                        Some(targets) => Some(Target::any(&targets)),
+                        None => usage()
+                    };
+            },
+            "--shuffle" => {
+                shuffle = true;
+            },
+            "--seed" => {
+                seed = match args.next().map(|s| s.parse::<u64>())
+                    {
+                        Some(Ok(n)) => n,
+                        _ => usage()
+                    };
+            },
+            "--decimals" => {
+                opts.add_unary_op(Decimal);
+            },
+            "--prefer-small-intermediates" => {
+                output.scoring.small_intermediates = true;
+            },
+            "--prefer-avoid" => {
+                let ops = match args.next()
+                    {
+                        Some(s) => s,
+                        None => usage()
+                    };
+                for c in ops.chars()
+                {
+                    let op = match c
+                        {
+                            '+' => ADD,
+                            '-' => SUB,
+                            '*' => MUL,
+                            '/' => DIV,
+                            _   => usage()
+                        };
+                    output.scoring.avoid.push(op);
+                }
+            },
+            "--weights" => {
+                let weights = args.next()
+                    .map(|s| s.split(',').map(str::parse).collect::<Result<Vec<u64>, _>>());
+                output.scoring.weights = match weights
+                    {
+                        Some(Ok(weights)) => weights,
+                        _ => usage()
+                    };
+            },
+            "--brackets" => {
+                output.style.brackets = match args.next().as_deref().and_then(Brackets::from_name)
+                    {
+                        Some(brackets) => brackets,
+                        None => usage()
+                    };
+            },
+            "--base" => {
+                // Handled before the other arguments, since it affects them
+                args.next();
+            },
+            "--roman" => {
+                // Handled before the other arguments, since it affects them
+            },
+            "--canonical" => {
+                output.canonical = true;
+            },
+            "--deterministic" => {
+                output.deterministic = true;
+            },
+            "--simplify" => {
+                output.simplify = true;
+            },
+            "--format" => {
+                output.format = match args.next().as_deref().and_then(Format::from_name)
+                    {
+                        Some(f) => f,
+                        None => usage()
+                    };
+            },
+            "--forbid-value" => {
+                match args.next().map(|s| s.parse::<u64>())
+                {
+                    Some(Ok(v)) => opts.forbidden_values.push(Rat::from_integer(v)),
+                    _ => usage()
+                }
+            },
+            "--must-contain" => {
+                must_contain = match args.next()
+                    {
+                        Some(s) => Some(s),
+                        None => usage()
+                    };
+            },
+            "--extra-ops" => {
+                let names = match args.next()
+                    {
+                        Some(s) => s,
+                        None => usage()
+                    };
+                for name in names.split(',')
+                {
+                    match builtin_binary_op(name)
+                    {
+                        Some(op) if opts.binary_ops.len() < MAX_BINARY_OPS => {
+                            opts.binary_ops.push(op);
+                        },
+                        _ => usage()
+                    }
+                }
+            },
+            "--shape" => {
+                opts.shape = match args.next().as_deref()
+                    {
+                        Some("any") => Shape::Any,
+                        Some("balanced") => Shape::Balanced,
+                        Some("chain") => Shape::Chain,
+                        _ => usage()
+                    };
+            },
+            "--target" => {
+                target = match args.next().as_deref().and_then(parse_target)
+                    {
+                        Some(t) => Some(t),
+                        None => usage()
+                    };
+            },
+            "--groups" => {
+                let targets = match args.next()
+                    {
+                        Some(s) => s.split(';').map(parse_target).collect::<Option<Vec<_>>>(),
+                        None => None
+                    };
+                mode = match targets
+                    {
+                        Some(targets) => Mode::Groups(targets),
+                        None => usage()
+                    };
+            },
+            "--input" => {
+                mode = Mode::Input(args.next().unwrap_or_else(|| usage()));
+            },
+            "--targets-stdin" => {
+                mode = Mode::TargetsStdin;
+            },
+            "--watch" => {
+                mode = Mode::Input(args.next().unwrap_or_else(|| usage()));
+                watch = true;
+            },
+            "--output" => {
+                results_path = Some(args.next().unwrap_or_else(|| usage()));
+            },
+            "--parallel" => {
+                parallel = true;
+            },
+            "--numbers-cols" => {
+                let cols = args.next().as_deref().and_then(batch::parse_columns);
+                csv_cols.get_or_insert_with(batch::CsvColumns::default).numbers =
+                    Some(cols.unwrap_or_else(|| usage()));
+            },
+            "--target-col" => {
+                let col = match args.next().as_deref().and_then(batch::parse_columns).as_deref()
+                    {
+                        Some(&[col]) => col,
+                        _ => usage()
+                    };
+                csv_cols.get_or_insert_with(batch::CsvColumns::default).target = Some(col);
+            },
+            _ => positional.push(arg)
+        }
+    }
+    *CURRENT_ARG.lock().unwrap() = None;
+
+    output.strategy = match (output.strategy, iterations)
+        {
+            (Strategy::Sample(_), Some(n)) => Strategy::Sample(n),
+            (Strategy::Evolve(_), Some(n)) => Strategy::Evolve(n),
+            (strategy, _) => strategy
+        };
+    if let (Strategy::Hybrid(_), Some(k)) = (output.strategy, hybrid_depth)
+    {
+        output.strategy = Strategy::Hybrid(k);
+    }
+    if shuffle || matches!(output.strategy, Strategy::Sample(_) | Strategy::Evolve(_))
+    {
+        opts.shuffle_seed = Some(seed);
+    }
+
+    if let Mode::Input(path) = &mode
+    {
+        solve_file(path, results_path.as_deref(), parallel, watch, csv_cols, opts, &output);
+        return;
+    }
+
+    if let Mode::Groups(targets) = &mode
+    {
+        let nrs = parse_numbers(&positional);
+        match solve_groups(&nrs, targets, &opts)
+        {
+            Some(exprs) => {
+                for expr in exprs.iter()
+                {
+                    println!("{}",
+                        format_expr_with(expr, &nrs, &opts, output.format, output.style));
+                }
+            },
+            None => {
+                println!("No split of the numbers into groups hits every target");
+            }
+        }
+        finish_run(record.as_deref(), replay.as_ref(), &run_args, seed, &opts, &nrs, None);
+        return;
+    }
+
+    if let Mode::TargetsStdin = mode
+    {
+        targets_from_stdin(&parse_numbers(&positional), &opts, &output);
+        return;
+    }
+
+    if let Mode::DryRun = mode
+    {
+        print_estimate(&parse_numbers(&positional), &opts);
+        return;
+    }
+
+    let target = match target
+        {
+            Some(target) => target,
+            None => {
+                let arg = positional.pop().unwrap_or_else(|| usage());
+                let s = normalize_number(&arg).unwrap_or_else(|| {
+                        fail("invalid_number", &format!("Invalid target {}", arg), Some(&arg), 1)
+                    });
+                if let (None, Some((_, base))) = (INPUT_BASE.get(), prefix_base(&s))
+                {
+                    output.style.base = base;
+                }
+                output.style.roman = ROMAN_INPUT.load(Ordering::Relaxed)
+                    && parse_roman(&s).is_some();
+                parse_number(&s).map_or_else(|| {
+                        fail("invalid_number", &format!("Invalid target {}", s), Some(&s), 1)
+                    }, Target::new)
+            }
+        };
+
+    let styled = output.style.base != 10 || output.style.roman;
+    if output.quiet || output.format != Format::Plain || output.canonical || styled
+    {
+        opts.print_intermediate = false;
+    }
+
+    let mut nrs = parse_numbers(&positional);
+    let count = nrs.len();
+    if !output.scoring.weights.is_empty() && output.scoring.weights.len() != count
+    {
+        fail("limit_violation", &format!("{} weights are given for {} numbers",
+            output.scoring.weights.len(), count), Some("--weights"), 1);
+    }
+    let _ = LOG_PUZZLE.set(puzzle_id(&nrs, &target.to_string()));
+    opts.cancel = Some(install_interrupt_handler());
+    if matches!(mode, Mode::Solve | Mode::Without(_)) && !output.quiet
+        && output.format == Format::Plain
+        && count >= PROGRESS_MIN_NUMBERS && ::std::io::stderr().is_terminal()
+        && !LOG_JSON.load(Ordering::Relaxed)
+    {
+        opts.progress = Some(Arc::new(ProgressBar::new()));
+        opts.print_intermediate = false;
+    }
+    log_event("search", LOG_PUZZLE.get().map(|s| s.as_str()), "search started");
+    let found = match mode
+    {
+        Mode::WithoutAll => {
+            if count < 2
+            {
+                usage();
+            }
+            opts.print_intermediate = false;
+            for (idx, expr) in leave_one_out(&nrs, &target, &opts).iter().enumerate()
+            {
+                if expr.is_empty()
+                {
+                    println!("without #{} ({}): no expression", idx+1, nrs[idx]);
+                }
+                else
+                {
+                    println!("without #{} ({}): {}", idx+1, nrs[idx],
+                        format_expr_with(expr, &nrs, &opts, output.format, output.style));
+                }
+            }
+            None
+        },
+        Mode::Without(idx) => {
+            if count < 2 || idx >= count
+            {
+                usage();
+            }
+            nrs.remove(idx);
+            if !output.scoring.weights.is_empty()
+            {
+                output.scoring.weights.remove(idx);
+            }
+            Some(solve(&nrs, &target, &opts, must_contain.as_deref(), &output))
+        },
+        Mode::Contributions => {
+            match contributions(&nrs, &target, &opts)
+            {
+                Some(contribs) => {
+                    for (idx, contrib) in contribs.iter().enumerate()
+                    {
+                        let desc = match contrib
+                            {
+                                Contribution::Required => "required",
+                                Contribution::Optional => "optional",
+                                Contribution::Never    => "never useful"
+                            };
+                        println!("#{} ({}): {}", idx+1, nrs[idx], desc);
+                    }
+                },
+                None => {
+                    println!("No exact solutions for {}", target);
+                }
+            }
+            None
+        },
+        Mode::All => {
+            list_solutions(&nrs, &target, &opts, structural, &output);
+            None
+        },
+        Mode::Pareto => {
+            print_pareto(&nrs, &target, &opts, &output);
+            None
+        },
+        Mode::Check => {
+            let reachable = is_reachable(&nrs, &target, &opts);
+            if !reachable && opts.cancelled()
+            {
+                fail("interrupted", "Search interrupted before the target was reached", None, 130);
+            }
+            if !output.quiet
+            {
+                println!("{}", if reachable { "yes" } else { "no" });
+            }
+            if !reachable
+            {
+                ::std::process::exit(INEXACT_STATUS);
+            }
+            None
+        },
+        Mode::Solve => {
+            Some(solve(&nrs, &target, &opts, must_contain.as_deref(), &output))
+        },
+        Mode::Fill(template) => {
+            if template.number_count() != count
+            {
+                fail("limit_violation", &format!("The template has {} blanks for {} numbers",
+                    template.number_count(), count), None, 1);
+            }
+            let expr = fill_template(&template, &nrs, &target, &opts);
+            let silent = output.require_exact && output.quiet;
+            if expr.is_empty()
+            {
+                if !silent
+                {
+                    println!("No valid way to fill in the template");
+                }
+            }
+            else if !silent || target.contains(expr.val)
+            {
+                println!("{}",
+                    format_expr_with(&expr, &nrs, &opts, output.format, output.style));
+            }
+            Some(expr)
+        },
+        Mode::Groups(_) | Mode::DryRun | Mode::Input(_) | Mode::TargetsStdin => unreachable!()
+    };
+    let result = match &found
+        {
+            Some(expr) if !expr.is_empty() => {
+                format!("search finished: {} = {}", expr.to_string(&nrs), expr.val)
+            },
+            Some(_) => String::from("search finished: no expression"),
+            None => String::from("search finished")
+        };
+    log_event("result", LOG_PUZZLE.get().map(|s| s.as_str()), &result);
+
+    finish_run(record.as_deref(), replay.as_ref(), &run_args, seed, &opts, &nrs,
+        found.as_ref());
+    let inexact = found.is_some_and(|expr| expr.is_empty() || !target.contains(expr.val));
+    if output.require_exact && inexact
+    {
+        ::std::process::exit(INEXACT_STATUS);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn number_separators_and_signs()
+    {
+        assert_eq!(parse_number("1_000_000"), Some(1_000_000));
+        assert_eq!(parse_number("+0x1_F"), Some(0x1f));
+        assert_eq!(parse_number("2.5e+3"), Some(2500));
+        for s in ["_1", "1_", "1__0", "++5", "+-5", "0x+5", "-5", "+"]
+        {
+            assert_eq!(parse_number(s), None, "{}", s);
+        }
+    }
 }
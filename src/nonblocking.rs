@@ -0,0 +1,399 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+
+use crate::{CancellationToken, Expr, Observer, Solution, Solver, Stats};
+
+/// A search to be run on the worker pool
+type Job = Box<dyn FnOnce() + Send>;
+
+/// The queue of the pool of worker threads running searches for the async
+/// interface, started on first use
+static POOL: OnceLock<Sender<Job>> = OnceLock::new();
+
+/// Run `job` on the worker pool, which has a thread for every available CPU.
+fn spawn(job: Job)
+{
+    let sender = POOL.get_or_init(|| {
+            let (sender, receiver) = channel::<Job>();
+            let receiver = Arc::new(Mutex::new(receiver));
+            let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+            for _ in 0..threads
+            {
+                let receiver = receiver.clone();
+                std::thread::spawn(move || loop
+                    {
+                        // The lock is released before running the job, so that
+                        // the other workers can pick up jobs in the meantime
+                        let job = receiver.lock().unwrap().recv();
+                        match job
+                        {
+                            // A panicking job should not take the worker down
+                            // with it
+                            Ok(job) => { let _ = catch_unwind(AssertUnwindSafe(job)); },
+                            Err(_)  => break
+                        }
+                    });
+            }
+            sender
+        });
+    sender.send(job).expect("the worker pool has stopped");
+}
+
+/// State shared between a search running on the worker pool and the future
+/// or stream waiting for it
+#[derive(Default)]
+struct Shared
+{
+    /// The improvements found that have not been taken from the stream yet
+    solutions: VecDeque<Solution>,
+    /// The result of the search, once it has finished
+    result: Option<Expr>,
+    /// The waker of the task waiting for the search, if any
+    waker: Option<Waker>
+}
+
+impl Shared
+{
+    /// Wake the task waiting for the search, if any.
+    fn wake(&mut self)
+    {
+        if let Some(waker) = self.waker.take()
+        {
+            waker.wake();
+        }
+    }
+}
+
+/// Observer passing the improvements found in a search on to a stream
+struct StreamObserver
+{
+    /// The state shared with the stream
+    shared: Arc<Mutex<Shared>>,
+    /// The observer registered in the search options, if any, which is
+    /// notified as well
+    inner: Option<Arc<dyn Observer>>
+}
+
+impl ::core::fmt::Debug for StreamObserver
+{
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result
+    {
+        f.debug_struct("StreamObserver").field("inner", &self.inner).finish()
+    }
+}
+
+impl Observer for StreamObserver
+{
+    fn on_new_best(&self, solution: &Solution)
+    {
+        let mut shared = self.shared.lock().unwrap();
+        shared.solutions.push_back(solution.clone());
+        shared.wake();
+        drop(shared);
+        if let Some(inner) = self.inner.as_ref()
+        {
+            inner.on_new_best(solution);
+        }
+    }
+
+    fn on_partition_done(&self, mask: &[bool])
+    {
+        if let Some(inner) = self.inner.as_ref()
+        {
+            inner.on_partition_done(mask);
+        }
+    }
+
+    fn on_finished(&self, stats: &Stats)
+    {
+        if let Some(inner) = self.inner.as_ref()
+        {
+            inner.on_finished(stats);
+        }
+    }
+}
+
+/// A search running on the worker pool
+///
+/// Struct `Handle` holds the state shared with the search, and the flag that
+/// cancels it when the future or stream waiting for it is dropped before the
+/// search finishes.
+struct Handle
+{
+    /// The state shared with the search
+    shared: Arc<Mutex<Shared>>,
+    /// The token cancelling the search, if it was created for this search
+    /// rather than given in the search options
+    cancel: Option<CancellationToken>
+}
+
+impl Handle
+{
+    /// Start the search of `solver` on the worker pool. If `stream` is true,
+    /// the improvements found are collected for a stream.
+    fn start(solver: &Solver, stream: bool) -> Self
+    {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let mut solver = solver.clone();
+        let cancel = if solver.opts.cancel.is_none()
+            {
+                let token = CancellationToken::new();
+                solver.opts.cancel = Some(token.flag());
+                Some(token)
+            }
+            else
+            {
+                None
+            };
+        if stream
+        {
+            let inner = solver.opts.observer.take();
+            let observer = StreamObserver { shared: shared.clone(), inner };
+            solver.opts.observer = Some(Arc::new(observer));
+        }
+
+        let job_shared = shared.clone();
+        spawn(Box::new(move || {
+                // A search that panics resolves to an empty expression, as
+                // when no expression is found, rather than never resolving
+                let expr = catch_unwind(AssertUnwindSafe(|| solver.solve()))
+                    .unwrap_or_else(|_| Expr::empty());
+                let mut shared = job_shared.lock().unwrap();
+                shared.result = Some(expr);
+                shared.wake();
+            }));
+        Handle { shared, cancel }
+    }
+}
+
+impl Drop for Handle
+{
+    fn drop(&mut self)
+    {
+        if let Some(cancel) = self.cancel.as_ref()
+        {
+            cancel.cancel();
+        }
+    }
+}
+
+/// Future of the result of a search
+///
+/// Struct `SolveFuture` is returned by `Solver::solve_async()`, and resolves
+/// to the expression nearest to the target when the search, which runs on a
+/// pool of worker threads, finishes. It does not depend on a particular async
+/// runtime. Dropping the future before it resolves cancels the search, unless
+/// a cancellation flag was given in the search options. If the search panics,
+/// the future resolves to an empty expression.
+pub struct SolveFuture
+{
+    /// The search
+    handle: Handle
+}
+
+impl Future for SolveFuture
+{
+    type Output = Expr;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Expr>
+    {
+        let mut shared = self.handle.shared.lock().unwrap();
+        match shared.result.take()
+        {
+            Some(expr) => Poll::Ready(expr),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Stream of the improvements found in a search
+///
+/// Struct `SolutionStream` is returned by `Solver::solve_stream()`, and yields
+/// every expression found that is nearer to the target than those before it,
+/// as the search, which runs on a pool of worker threads, finds them. The
+/// stream ends when the search finishes, with the nearest expression as the
+/// last item. Dropping the stream before it ends cancels the search, unless a
+/// cancellation flag was given in the search options. If the search panics,
+/// the stream ends.
+pub struct SolutionStream
+{
+    /// The search
+    handle: Handle
+}
+
+impl futures_core::Stream for SolutionStream
+{
+    type Item = Solution;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Solution>>
+    {
+        let mut shared = self.handle.shared.lock().unwrap();
+        if let Some(solution) = shared.solutions.pop_front()
+        {
+            Poll::Ready(Some(solution))
+        }
+        else if shared.result.is_some()
+        {
+            Poll::Ready(None)
+        }
+        else
+        {
+            shared.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl Solver
+{
+    /// Find the expression nearest to the target, asynchronously.
+    ///
+    /// As `solve()`, but the search runs on a pool of worker threads, so that
+    /// an async task can await the result without blocking the thread it runs
+    /// on. Requires the `async` feature.
+    pub fn solve_async(&self) -> SolveFuture
+    {
+        SolveFuture { handle: Handle::start(self, false) }
+    }
+
+    /// Stream the improvements found in a search.
+    ///
+    /// As `solve_async()`, but rather than only the final result, every
+    /// expression found that is nearer to the target than the ones before it
+    /// is yielded, e.g. to show intermediate results while the search runs.
+    /// Requires the `async` feature.
+    pub fn solve_stream(&self) -> SolutionStream
+    {
+        SolutionStream { handle: Handle::start(self, true) }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::task::Wake;
+    use std::thread::Thread;
+
+    use futures_core::Stream;
+
+    use super::*;
+
+    /// Waker unparking the thread blocked on a future
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker
+    {
+        fn wake(self: Arc<Self>)
+        {
+            self.0.unpark();
+        }
+    }
+
+    /// Poll `poll` until it is ready, parking the thread in between, and
+    /// return its result.
+    fn block_on<T, F>(mut poll: F) -> T
+    where F: FnMut(&mut Context) -> Poll<T>
+    {
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop
+        {
+            match poll(&mut cx)
+            {
+                Poll::Ready(res) => return res,
+                Poll::Pending    => std::thread::park()
+            }
+        }
+    }
+
+    /// Operation that panics, to make a search panic
+    #[derive(Debug)]
+    struct Panic;
+
+    impl crate::BinaryOp for Panic
+    {
+        fn symbol(&self) -> &str
+        {
+            "panic"
+        }
+
+        fn apply(&self, _a: crate::Rat, _b: crate::Rat) -> Option<crate::Rat>
+        {
+            panic!("panicking operation")
+        }
+
+        fn commutative(&self) -> bool
+        {
+            true
+        }
+    }
+
+    fn solver() -> Solver
+    {
+        Solver::builder().numbers(&[1, 3, 4, 6, 7]).target(5000).build().unwrap()
+    }
+
+    #[test]
+    fn future_matches_solve()
+    {
+        let solver = solver();
+        let mut future = solver.solve_async();
+        let expr = block_on(|cx| Pin::new(&mut future).poll(cx));
+        assert_eq!(expr.val, solver.solve().val);
+    }
+
+    #[test]
+    fn panicking_search_resolves()
+    {
+        let ops = crate::OpSet::default().binary_op(Panic);
+        let panicking = Solver::builder().numbers(&[1, 3, 4, 6]).target(24).ops(ops).build()
+            .unwrap();
+        let mut future = panicking.solve_async();
+        assert!(block_on(|cx| Pin::new(&mut future).poll(cx)).is_empty());
+        let mut stream = panicking.solve_stream();
+        assert!(block_on(|cx| Pin::new(&mut stream).poll_next(cx)).is_none());
+
+        // The workers survive the panics
+        let solver = solver();
+        let mut future = solver.solve_async();
+        assert_eq!(block_on(|cx| Pin::new(&mut future).poll(cx)).val, solver.solve().val);
+    }
+
+    #[test]
+    fn stream_ends_with_nearest()
+    {
+        let solver = solver();
+        let mut stream = solver.solve_stream();
+        let mut solutions = vec![];
+        while let Some(solution) = block_on(|cx| Pin::new(&mut stream).poll_next(cx))
+        {
+            solutions.push(solution);
+        }
+        assert!(solutions.windows(2).all(|pair| pair[1].distance < pair[0].distance));
+        let expr = solver.solve();
+        assert_eq!(solutions.last().map(|solution| solution.expr.val), Some(expr.val));
+    }
+}
@@ -0,0 +1,427 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+
+use crate::{Op, Rat, DIV};
+
+/// The maximum number of custom binary operations in a search.
+pub const MAX_BINARY_OPS: usize = 8;
+/// The maximum number of custom unary operations in a search.
+pub const MAX_UNARY_OPS: usize = 8;
+
+/// A custom binary operation
+///
+/// Trait `BinaryOp` describes an operation on two numbers, in addition to the
+/// four basic operations. Custom operations are registered in the search
+/// options with `Options::add_binary_op()`, after which they participate in
+/// the search like the basic operations do.
+pub trait BinaryOp: ::core::fmt::Debug + Send + Sync
+{
+    /// The name or symbol of the operation, e.g. `avg`.
+    fn symbol(&self) -> &str;
+    /// Compute the result of the operation on `a` and `b`, or `None` if the
+    /// operation cannot be applied to these numbers.
+    fn apply(&self, a: Rat, b: Rat) -> Option<Rat>;
+    /// Whether the result of the operation is independent of the order of its
+    /// operands. For a non-commutative operation, both orders are tried.
+    fn commutative(&self) -> bool;
+    /// Format the operation on the formatted operands `a` and `b`. By
+    /// default, the operation is written as a function call, e.g.
+    /// `avg(a,b)`.
+    fn render(&self, a: &str, b: &str) -> String
+    {
+        format!("{}({},{})", self.symbol(), a, b)
+    }
+}
+
+/// A custom unary operation
+///
+/// Trait `UnaryOp` describes an operation on a single number, like negation
+/// or squaring. Custom unary operations are registered in the search options
+/// with `Options::add_unary_op()`, after which they are applied to the
+/// numbers and intermediate results in the search, up to the depth given by
+/// `Options::max_unary_depth`.
+pub trait UnaryOp: ::core::fmt::Debug + Send + Sync
+{
+    /// The name or symbol of the operation, e.g. `sqr`.
+    fn symbol(&self) -> &str;
+    /// Check if the operation can be applied to `a`.
+    fn domain(&self, a: Rat) -> bool;
+    /// Compute the result of the operation on `a`, which is in the domain of
+    /// the operation.
+    fn apply(&self, a: Rat) -> Rat;
+    /// Format the operation on the formatted operand `a`. By default, the
+    /// operation is written as a function call, e.g. `sqr(a)`.
+    fn render(&self, a: &str) -> String
+    {
+        format!("{}({})", self.symbol(), a)
+    }
+    /// Whether the operation can only be applied to the input numbers
+    /// themselves, providing an alternative value for a number rather than
+    /// an operation on intermediate results. By default, this is false.
+    fn leaf_only(&self) -> bool
+    {
+        false
+    }
+}
+
+/// Look up one of the binary operations provided by this library.
+///
+/// Returns the operation whose symbol is `name`, e.g. `gcd`, or `None` if no
+/// such operation is provided.
+pub fn builtin_binary_op(name: &str) -> Option<Arc<dyn BinaryOp>>
+{
+    match name
+    {
+        "gcd" => Some(Arc::new(Gcd)),
+        "lcm" => Some(Arc::new(Lcm)),
+        "min" => Some(Arc::new(Min)),
+        "max" => Some(Arc::new(Max)),
+        "pow" => Some(Arc::new(Pow)),
+        "log" => Some(Arc::new(Log)),
+        _     => None
+    }
+}
+
+/// Look up one of the unary operations provided by this library.
+///
+/// Returns the operation whose symbol is `name`, e.g. `dec`, or `None` if no
+/// such operation is provided.
+pub fn builtin_unary_op(name: &str) -> Option<Arc<dyn UnaryOp>>
+{
+    match name
+    {
+        "dec" => Some(Arc::new(Decimal)),
+        _     => None
+    }
+}
+
+/// The operation code for the `i`'th custom binary operation.
+pub(crate) fn binary_op_code(i: usize) -> Op
+{
+    DIV - 1 - i as Op
+}
+
+/// The index of the custom binary operation with operation code `op`, if
+/// `op` encodes a custom binary operation.
+pub(crate) fn binary_op_index(op: Op) -> Option<usize>
+{
+    let i = (DIV as usize).checked_sub(1 + op as usize)?;
+    if i < MAX_BINARY_OPS { Some(i) } else { None }
+}
+
+/// The operation code for the `i`'th custom unary operation.
+pub(crate) fn unary_op_code(i: usize) -> Op
+{
+    DIV - 1 - (MAX_BINARY_OPS + i) as Op
+}
+
+/// The index of the custom unary operation with operation code `op`, if
+/// `op` encodes a custom unary operation.
+pub(crate) fn unary_op_index(op: Op) -> Option<usize>
+{
+    let i = (DIV as usize).checked_sub(1 + MAX_BINARY_OPS + op as usize)?;
+    if i < MAX_UNARY_OPS { Some(i) } else { None }
+}
+
+/// Compute the greatest common divisor of `a` and `b`.
+fn gcd(mut a: u64, mut b: u64) -> u64
+{
+    while b != 0
+    {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a
+}
+
+/// Greatest common divisor operation
+///
+/// Computes `gcd(a,b)`, for integer operands only.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Gcd;
+
+impl BinaryOp for Gcd
+{
+    fn symbol(&self) -> &str
+    {
+        "gcd"
+    }
+
+    fn apply(&self, a: Rat, b: Rat) -> Option<Rat>
+    {
+        if a.is_integer() && b.is_integer()
+        {
+            Some(Rat::from_integer(gcd(*a.numer(), *b.numer())))
+        }
+        else
+        {
+            None
+        }
+    }
+
+    fn commutative(&self) -> bool
+    {
+        true
+    }
+}
+
+/// Least common multiple operation
+///
+/// Computes `lcm(a,b)`, for integer operands only. The least common multiple
+/// involving zero is zero.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Lcm;
+
+impl BinaryOp for Lcm
+{
+    fn symbol(&self) -> &str
+    {
+        "lcm"
+    }
+
+    fn apply(&self, a: Rat, b: Rat) -> Option<Rat>
+    {
+        if !a.is_integer() || !b.is_integer()
+        {
+            return None;
+        }
+
+        let (a, b) = (*a.numer(), *b.numer());
+        if a == 0 || b == 0
+        {
+            Some(Rat::from_integer(0))
+        }
+        else
+        {
+            (a / gcd(a, b)).checked_mul(b).map(Rat::from_integer)
+        }
+    }
+
+    fn commutative(&self) -> bool
+    {
+        true
+    }
+}
+
+/// Minimum operation
+///
+/// Computes `min(a,b)`, the smaller of its two operands.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Min;
+
+impl BinaryOp for Min
+{
+    fn symbol(&self) -> &str
+    {
+        "min"
+    }
+
+    fn apply(&self, a: Rat, b: Rat) -> Option<Rat>
+    {
+        Some(a.min(b))
+    }
+
+    fn commutative(&self) -> bool
+    {
+        true
+    }
+}
+
+/// Maximum operation
+///
+/// Computes `max(a,b)`, the larger of its two operands.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Max;
+
+impl BinaryOp for Max
+{
+    fn symbol(&self) -> &str
+    {
+        "max"
+    }
+
+    fn apply(&self, a: Rat, b: Rat) -> Option<Rat>
+    {
+        Some(a.max(b))
+    }
+
+    fn commutative(&self) -> bool
+    {
+        true
+    }
+}
+
+/// Exponentiation operation
+///
+/// Computes `pow(a,b)`, i.e. `a` raised to the power `b`, for integer
+/// exponents `b` only. Since powers grow quickly, and further operations on
+/// them would overflow, the operation fails if the numerator or denominator
+/// of the result does not fit in 32 bits.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Pow;
+
+impl BinaryOp for Pow
+{
+    fn symbol(&self) -> &str
+    {
+        "pow"
+    }
+
+    fn apply(&self, a: Rat, b: Rat) -> Option<Rat>
+    {
+        if !b.is_integer() || *b.numer() > u32::MAX as u64
+        {
+            return None;
+        }
+
+        let exp = *b.numer() as u32;
+        let numer = a.numer().checked_pow(exp).filter(|&n| n <= u32::MAX as u64)?;
+        let denom = a.denom().checked_pow(exp).filter(|&d| d <= u32::MAX as u64)?;
+        Some(Rat::new_raw(numer, denom))
+    }
+
+    fn commutative(&self) -> bool
+    {
+        false
+    }
+}
+
+/// Integer logarithm operation
+///
+/// Computes `log_b(a)`, the logarithm of `a` to base `b`, which is only valid
+/// when `a` is an exact integer power of `b`, e.g. `log_2(8) = 3`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Log;
+
+impl BinaryOp for Log
+{
+    fn symbol(&self) -> &str
+    {
+        "log"
+    }
+
+    fn apply(&self, a: Rat, b: Rat) -> Option<Rat>
+    {
+        if !a.is_integer() || !b.is_integer()
+        {
+            return None;
+        }
+
+        let (mut a, b) = (*a.numer(), *b.numer());
+        if a == 0 || b < 2
+        {
+            return None;
+        }
+
+        let mut exp = 0;
+        while a % b == 0
+        {
+            a /= b;
+            exp += 1;
+        }
+        if a == 1 { Some(Rat::from_integer(exp)) } else { None }
+    }
+
+    fn commutative(&self) -> bool
+    {
+        false
+    }
+
+    fn render(&self, a: &str, b: &str) -> String
+    {
+        // A compound base is parenthesized, so that it does not read as the
+        // base of its first term only
+        if b.chars().all(|c| c.is_ascii_alphanumeric() || c == '.')
+        {
+            format!("log_{}({})", b, a)
+        }
+        else
+        {
+            format!("log_({})({})", b, a)
+        }
+    }
+}
+
+/// Decimal point operation
+///
+/// Reinterprets a single digit `n` as the decimal fraction `0.n`, i.e. `n/10`,
+/// as in the "four fours" puzzle. This operation can only be applied to the
+/// input numbers themselves.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Decimal;
+
+impl UnaryOp for Decimal
+{
+    fn symbol(&self) -> &str
+    {
+        "dec"
+    }
+
+    fn domain(&self, a: Rat) -> bool
+    {
+        a.is_integer() && *a.numer() < 10
+    }
+
+    fn apply(&self, a: Rat) -> Rat
+    {
+        a / 10
+    }
+
+    fn render(&self, a: &str) -> String
+    {
+        if a.chars().all(|c| c.is_ascii_digit())
+        {
+            format!("0.{}", a)
+        }
+        else
+        {
+            format!("({}/10)", a)
+        }
+    }
+
+    fn leaf_only(&self) -> bool
+    {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn log_parenthesizes_compound_base()
+    {
+        assert_eq!(Log.render("8", "2"), "log_2(8)");
+        assert_eq!(Log.render("8", "0x2"), "log_0x2(8)");
+        assert_eq!(Log.render("8", "1+1"), "log_(1+1)(8)");
+        assert_eq!(Log.render("4*2", "6/3"), "log_(6/3)(4*2)");
+    }
+
+    #[test]
+    fn log_of_powers_only()
+    {
+        let int = Rat::from_integer;
+        assert_eq!(Log.apply(int(8), int(2)), Some(int(3)));
+        assert_eq!(Log.apply(int(1), int(7)), Some(int(0)));
+        assert_eq!(Log.apply(int(12), int(2)), None);
+        assert_eq!(Log.apply(int(8), int(1)), None);
+    }
+}
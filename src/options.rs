@@ -0,0 +1,174 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+use crate::{BinaryOp, Observer, Progress, Rat, UnaryOp, MAX_BINARY_OPS, MAX_UNARY_OPS};
+#[cfg(feature = "std")]
+use crate::{CacheCounters, SharedCache, SpillCache, Trace};
+
+/// Options controlling the search
+///
+/// Struct `Options` collects the settings that influence which expressions
+/// are generated. Note that a `Cache` filled in a search with one set of
+/// options should not be reused for a search with different options.
+#[derive(Clone, Debug, Default)]
+pub struct Options
+{
+    /// If true, only expressions whose intermediate results are all integers
+    /// are generated, i.e. a division is only allowed if it is exact.
+    pub integers_only: bool,
+    /// The maximum number of binary operations in an expression, if any.
+    pub max_ops: Option<usize>,
+    /// If true, an expression may use any non-empty subset of the input
+    /// numbers, instead of all of them.
+    pub subset: bool,
+    /// If true, and `subset` is true and `shape` is `Shape::Any`, the cache
+    /// does not store expressions for a set of numbers whose value can also be
+    /// made from a proper subset of these numbers, like `x*1` or `x+y-y`. Any
+    /// expression containing such a sub-expression has the same value as one
+    /// using fewer numbers, which is considered as well, so the nearest value
+    /// is still found, and the cache is smaller. The price is that lists of
+    /// solutions, as from `exact_solutions()` or `distinct_values()`, no longer
+    /// contain the expressions that use numbers without need.
+    pub prune_dominated: bool,
+    /// The shape the expression tree should have.
+    pub shape: Shape,
+    /// Values that no proper sub-expression may evaluate to. The value of
+    /// the expression as a whole is not restricted.
+    pub forbidden_values: Vec<Rat>,
+    /// If set, the order in which partitions and sub-expressions are visited
+    /// is shuffled deterministically using this seed, so that different
+    /// seeds may surface different, equally good, expressions.
+    pub shuffle_seed: Option<u64>,
+    /// Custom binary operations that may be used in addition to the four
+    /// basic operations.
+    pub binary_ops: Vec<Arc<dyn BinaryOp>>,
+    /// Custom unary operations that may be applied to the numbers and
+    /// intermediate results.
+    pub unary_ops: Vec<Arc<dyn UnaryOp>>,
+    /// The maximum number of unary operations applied in succession to a
+    /// single sub-expression.
+    pub max_unary_depth: usize,
+    /// If set, the search stops as soon as possible after this flag is set,
+    /// e.g. from a signal handler, and returns the best expression found thus
+    /// far. Sub-expressions in a cache filled by a cancelled search may be
+    /// incomplete.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// If set, the search is cancelled as if through `cancel` when this time
+    /// has passed.
+    #[cfg(feature = "std")]
+    pub deadline: Option<Instant>,
+    /// The number of threads used to generate sub-expressions. If this is zero
+    /// or one, the search runs on the calling thread only. The expression found
+    /// does not depend on the number of threads.
+    #[cfg(feature = "std")]
+    pub threads: usize,
+    /// If set, cache entries that do not fit in the memory limit of this
+    /// spill cache are written to disk. The spill cache should only be used
+    /// with a single cache.
+    #[cfg(feature = "std")]
+    pub spill: Option<Arc<SpillCache>>,
+    /// If set, the expressions for sets of numbers are looked up in, and
+    /// stored in, this cache shared with other searches. The expressions for
+    /// a set of numbers are then generated as for the numbers in increasing
+    /// order, which may give a different expression for the same value.
+    #[cfg(feature = "std")]
+    pub shared_cache: Option<Arc<SharedCache>>,
+    /// If set, this observer is notified of the progress of the search.
+    pub progress: Option<Arc<dyn Progress>>,
+    /// If set, this observer is notified of the events in the search.
+    pub observer: Option<Arc<dyn Observer>>,
+    /// If set, lookups of sub-expressions in the cache are counted here.
+    #[cfg(feature = "std")]
+    pub cache_counters: Option<Arc<CacheCounters>>,
+    /// If set, the partitions considered and the best expressions found in
+    /// the search are logged here.
+    #[cfg(feature = "std")]
+    pub trace: Option<Arc<Trace>>,
+    /// If true, intermediate search results are printed on `stdout`. Without
+    /// the `std` feature, this option has no effect.
+    pub print_intermediate: bool
+}
+
+impl Options
+{
+    /// Register a custom binary operation.
+    ///
+    /// Add `op` to the operations that may be used in a search with these
+    /// options. At most `MAX_BINARY_OPS` custom binary operations can be
+    /// registered.
+    pub fn add_binary_op<T: BinaryOp + 'static>(&mut self, op: T)
+    {
+        assert!(self.binary_ops.len() < MAX_BINARY_OPS, "too many custom binary operations");
+        self.binary_ops.push(Arc::new(op));
+    }
+
+    /// Register a custom unary operation.
+    ///
+    /// Add `op` to the unary operations that may be used in a search with
+    /// these options. At most `MAX_UNARY_OPS` custom unary operations can be
+    /// registered. If `max_unary_depth` is zero, it is set to one, so that the
+    /// operation is actually used.
+    pub fn add_unary_op<T: UnaryOp + 'static>(&mut self, op: T)
+    {
+        assert!(self.unary_ops.len() < MAX_UNARY_OPS, "too many custom unary operations");
+        self.unary_ops.push(Arc::new(op));
+        self.max_unary_depth = self.max_unary_depth.max(1);
+    }
+
+    /// Check if the search with these options has been cancelled, or has
+    /// passed its deadline.
+    pub fn cancelled(&self) -> bool
+    {
+        let cancelled = self.cancel.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed));
+        #[cfg(feature = "std")]
+        let cancelled = cancelled
+            || self.deadline.is_some_and(|deadline| Instant::now() >= deadline);
+        cancelled
+    }
+}
+
+/// Constraint on the shape of the expression tree
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Shape
+{
+    /// Any shape is allowed
+    #[default]
+    Any,
+    /// Every operation combines two halves whose number of numbers differs
+    /// by at most one
+    Balanced,
+    /// Every operation combines the expression thus far with a single
+    /// number on its right hand side, i.e. the tree is strictly left-leaning
+    Chain
+}
+
+impl Shape
+{
+    /// Check if an expression over `count0` numbers may be combined with an
+    /// expression over `count1` numbers, where `count0 >= count1`.
+    pub fn allows_split(self, count0: usize, count1: usize) -> bool
+    {
+        match self
+        {
+            Shape::Any      => true,
+            Shape::Balanced => count0 - count1 <= 1,
+            Shape::Chain    => count1 == 1
+        }
+    }
+}
@@ -0,0 +1,380 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::{parse_roman, Brackets, Expr, Idx, Op, Options, Style, ADD, DIV, MUL, SUB};
+
+/// Error in parsing an expression
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError
+{
+    /// An unexpected character, at the given (0-based) character position
+    UnexpectedChar(char, usize),
+    /// The expression ends prematurely
+    UnexpectedEnd,
+    /// An opening parenthesis at the given position is not closed
+    MissingParenthesis(usize),
+    /// A number does not fit in 64 bits
+    NumberTooLarge(String),
+    /// A number in the expression is not one of the input numbers
+    UnknownNumber(u64),
+    /// A number is used more often than it occurs in the input numbers
+    NumberUsedTooOften(u64),
+    /// A blank occurs in an expression, rather than a template
+    BlankNotAllowed,
+    /// A number occurs in a template, rather than an expression
+    NumberNotAllowed,
+    /// A template has more operator blanks than the given maximum
+    TooManyOperatorBlanks(usize),
+    /// The expression cannot be evaluated, e.g. because it divides by zero
+    Invalid
+}
+
+impl ::core::fmt::Display for ParseError
+{
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result
+    {
+        match self
+        {
+            ParseError::UnexpectedChar(c, pos) => {
+                write!(f, "unexpected character '{}' at position {}", c, pos)
+            },
+            ParseError::UnexpectedEnd => write!(f, "unexpected end of expression"),
+            ParseError::MissingParenthesis(pos) => {
+                write!(f, "missing closing parenthesis for '(' at position {}", pos)
+            },
+            ParseError::NumberTooLarge(digits) => write!(f, "number {} is too large", digits),
+            ParseError::UnknownNumber(nr) => {
+                write!(f, "number {} is not one of the input numbers", nr)
+            },
+            ParseError::NumberUsedTooOften(nr) => write!(f, "number {} is used too often", nr),
+            ParseError::BlankNotAllowed => write!(f, "blanks are not allowed in an expression"),
+            ParseError::NumberNotAllowed => {
+                write!(f, "numbers are not allowed in a template, use blanks")
+            },
+            ParseError::TooManyOperatorBlanks(max) => {
+                write!(f, "a template can have at most {} operator blanks", max)
+            },
+            ParseError::Invalid => write!(f, "the expression cannot be evaluated")
+        }
+    }
+}
+
+impl ::core::error::Error for ParseError {}
+
+/// A single element of a parsed infix expression
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Item
+{
+    /// A literal number
+    Number(u64),
+    /// A blank to be filled with a number
+    Blank,
+    /// A fixed operation
+    Op(Op),
+    /// A blank to be filled with any operation
+    AnyOp
+}
+
+/// Simple recursive descent parser for infix expressions
+///
+/// Struct `InfixParser` converts an infix expression using the four basic
+/// operations and parentheses into reverse polish notation. Besides literal
+/// numbers, the expression may contain number blanks `_` and operator blanks
+/// `?`. Operator blanks have the precedence of addition and subtraction. The
+/// brackets and numbers are read as written in the given style.
+struct InfixParser<'a>
+{
+    /// The characters of the expression
+    chars: ::core::iter::Peekable<::core::str::Chars<'a>>,
+    /// The position of the next character in the expression
+    pos: usize,
+    /// The style in which the brackets and numbers are written
+    style: Style,
+    /// The expression parsed thus far, in reverse polish notation
+    items: Vec<Item>
+}
+
+impl<'a> InfixParser<'a>
+{
+    fn peek(&mut self) -> Option<char>
+    {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace())
+        {
+            self.next();
+        }
+        self.chars.peek().cloned()
+    }
+
+    fn next(&mut self)
+    {
+        self.chars.next();
+        self.pos += 1;
+    }
+
+    fn parse_sum(&mut self) -> Result<(), ParseError>
+    {
+        self.parse_product()?;
+        while let Some(c) = self.peek()
+        {
+            let item = match c
+                {
+                    '+' => Item::Op(ADD),
+                    '-' => Item::Op(SUB),
+                    '?' => Item::AnyOp,
+                    _   => break
+                };
+            self.next();
+            self.parse_product()?;
+            self.items.push(item);
+        }
+        Ok(())
+    }
+
+    fn parse_product(&mut self) -> Result<(), ParseError>
+    {
+        self.parse_atom()?;
+        while let Some(c) = self.peek()
+        {
+            let item = match c
+                {
+                    '*' => Item::Op(MUL),
+                    '/' => Item::Op(DIV),
+                    _   => break
+                };
+            self.next();
+            self.parse_atom()?;
+            self.items.push(item);
+        }
+        Ok(())
+    }
+
+    /// Read `token`, starting at position `start`, as a number written in
+    /// the style of the parser.
+    fn styled_number(&self, token: &str, start: usize) -> Result<u64, ParseError>
+    {
+        if let Some(nr) = parse_roman(token).filter(|_| self.style.roman)
+        {
+            return Ok(nr);
+        }
+
+        let base = self.style.base;
+        let prefix = match base
+            {
+                2  => "0b",
+                8  => "0o",
+                16 => "0x",
+                _  => ""
+            };
+        let digits = token.strip_prefix(prefix).filter(|digits| !digits.is_empty())
+            .unwrap_or(token);
+        let offset = start + token.len() - digits.len();
+        let mut nr = 0u64;
+        for (i, c) in digits.chars().enumerate()
+        {
+            let digit = c.to_digit(base).ok_or(ParseError::UnexpectedChar(c, offset + i))?;
+            nr = nr.checked_mul(base as u64).and_then(|nr| nr.checked_add(digit as u64))
+                .ok_or_else(|| ParseError::NumberTooLarge(String::from(token)))?;
+        }
+        Ok(nr)
+    }
+
+    fn parse_atom(&mut self) -> Result<(), ParseError>
+    {
+        match self.peek()
+        {
+            Some('_') => {
+                self.next();
+                self.items.push(Item::Blank);
+                Ok(())
+            },
+            Some(open) if open == '('
+                || (self.style.brackets == Brackets::Nested && (open == '[' || open == '{')) => {
+                let close = match open
+                    {
+                        '[' => ']',
+                        '{' => '}',
+                        _   => ')'
+                    };
+                let start = self.pos;
+                self.next();
+                self.parse_sum()?;
+                if self.peek() == Some(close)
+                {
+                    self.next();
+                    Ok(())
+                }
+                else
+                {
+                    Err(ParseError::MissingParenthesis(start))
+                }
+            },
+            Some(c) if self.style != Style::default() && c.is_ascii_alphanumeric() => {
+                let start = self.pos;
+                let mut token = String::new();
+                while let Some(&c) = self.chars.peek().filter(|c| c.is_ascii_alphanumeric())
+                {
+                    token.push(c);
+                    self.next();
+                }
+                let nr = self.styled_number(&token, start)?;
+                self.items.push(Item::Number(nr));
+                Ok(())
+            },
+            Some(c) if c.is_ascii_digit() => {
+                let mut digits = String::new();
+                while let Some(&c) = self.chars.peek().filter(|c| c.is_ascii_digit())
+                {
+                    digits.push(c);
+                    self.next();
+                }
+                match digits.parse::<u64>()
+                {
+                    Ok(nr) => {
+                        self.items.push(Item::Number(nr));
+                        Ok(())
+                    },
+                    Err(_) => Err(ParseError::NumberTooLarge(digits))
+                }
+            },
+            Some(c) => Err(ParseError::UnexpectedChar(c, self.pos)),
+            None    => Err(ParseError::UnexpectedEnd)
+        }
+    }
+}
+
+/// Parse an infix expression.
+///
+/// Parse the infix expression `s` into reverse polish notation, returning the
+/// error if it is not a valid expression.
+pub(crate) fn parse_infix(s: &str) -> Result<Vec<Item>, ParseError>
+{
+    parse_infix_styled(s, Style::default())
+}
+
+/// Parse an infix expression written in a style.
+///
+/// As `parse_infix()`, but the brackets and numbers in `s` are read as
+/// written in style `style`.
+fn parse_infix_styled(s: &str, style: Style) -> Result<Vec<Item>, ParseError>
+{
+    let mut parser = InfixParser { chars: s.chars().peekable(), pos: 0, style, items: vec![] };
+    parser.parse_sum()?;
+    match parser.peek()
+    {
+        None    => Ok(parser.items),
+        Some(c) => Err(ParseError::UnexpectedChar(c, parser.pos))
+    }
+}
+
+/// Parse an expression over a numbers array.
+///
+/// Parse the infix expression `s`, and convert it into an expression using
+/// indices into the numbers array `nrs`. Every number in the expression must
+/// occur in `nrs`, and can be used at most as often as it occurs there. As in
+/// `unique_indices()`, equal numbers are mapped to the index of their first
+/// occurrence. The expression is evaluated using the default options.
+pub fn parse(s: &str, nrs: &[u64]) -> Result<Expr, ParseError>
+{
+    parse_with(s, nrs, &Options::default())
+}
+
+/// Parse an expression over a numbers array, subject to search options.
+///
+/// As `parse()`, but the expression is evaluated subject to the options in
+/// `opts`, so that e.g. a division with a fractional result is rejected when
+/// `opts.integers_only` is set.
+pub fn parse_with(s: &str, nrs: &[u64], opts: &Options) -> Result<Expr, ParseError>
+{
+    parse_styled(s, nrs, opts, Style::default())
+}
+
+/// Parse an expression written in a style.
+///
+/// As `parse_with()`, but the brackets and numbers in `s` are read as written
+/// by `format_expr_with()` in style `style`, e.g. with square brackets, or with
+/// the numbers in hexadecimal or Roman numerals.
+pub fn parse_styled(s: &str, nrs: &[u64], opts: &Options, style: Style)
+    -> Result<Expr, ParseError>
+{
+    let mut used = vec![0; nrs.len()];
+    let mut ops = vec![];
+    for item in parse_infix_styled(s, style)?
+    {
+        match item
+        {
+            Item::Number(nr) => {
+                let idx = match nrs.iter().position(|&x| x == nr)
+                    {
+                        Some(idx) => idx,
+                        None => return Err(ParseError::UnknownNumber(nr))
+                    };
+                used[idx] += 1;
+                if used[idx] > nrs.iter().filter(|&&x| x == nr).count()
+                {
+                    return Err(ParseError::NumberUsedTooOften(nr));
+                }
+                ops.push(idx as Idx);
+            },
+            Item::Op(op) => ops.push(op),
+            Item::Blank | Item::AnyOp => return Err(ParseError::BlankNotAllowed)
+        }
+    }
+
+    Expr::from_ops(ops, nrs, opts).ok_or(ParseError::Invalid)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{format_expr_with, Format};
+
+    #[test]
+    fn parse_styled_reads_formatted_expressions()
+    {
+        let nrs = [2, 3, 5, 7, 11, 2000];
+        let expr = parse("(2-(3+5)/(7+11))*2000", &nrs).unwrap();
+        let styles = [
+            Style::default(),
+            Style { brackets: Brackets::Nested, ..Style::default() },
+            Style { base: 16, ..Style::default() },
+            Style { base: 36, ..Style::default() },
+            Style { roman: true, ..Style::default() },
+            Style { brackets: Brackets::Nested, base: 2, roman: true }
+        ];
+        for &style in styles.iter()
+        {
+            let printed = format_expr_with(&expr, &nrs, &Options::default(), Format::Plain, style);
+            let (infix, _) = printed.rsplit_once(" = ").unwrap();
+            let parsed = parse_styled(infix, &nrs, &Options::default(), style);
+            assert_eq!(parsed.map(|parsed| parsed.ops), Ok(expr.ops.clone()), "{}", printed);
+        }
+    }
+
+    #[test]
+    fn parse_styled_rejects_other_styles()
+    {
+        let nrs = [12, 3];
+        let hex = Style { base: 16, ..Style::default() };
+        assert!(parse_styled("12/3", &nrs, &Options::default(), hex).is_err());
+        assert!(parse_styled("0xC/0x3", &nrs, &Options::default(), hex).is_ok());
+        assert!(parse_styled("[12/3]", &nrs, &Options::default(), Style::default()).is_err());
+        let nested = Style { brackets: Brackets::Nested, ..Style::default() };
+        assert!(parse_styled("[12/3)", &nrs, &Options::default(), nested).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::Idx;
+
+/// Group equal indices.
+///
+/// Given the sorted indices `idxs`, return every distinct index together with
+/// the number of times it occurs.
+fn multiplicities(idxs: &[Idx]) -> Vec<(Idx, usize)>
+{
+    let mut res: Vec<(Idx, usize)> = vec![];
+    for &idx in idxs.iter()
+    {
+        match res.last_mut()
+        {
+            Some((last, count)) if *last == idx => *count += 1,
+            _ => res.push((idx, 1))
+        }
+    }
+    res
+}
+
+/// Generate the splits of a set of indices with a second part of fixed size.
+///
+/// Choose the number of copies `counts[pos]` of every index in `groups[pos..]`
+/// that go into the second part of the split, such that `remaining` indices
+/// are added to it in total, and append the resulting splits to `res`. Here
+/// `capacity[pos]` is the total number of indices in `groups[pos..]`. The
+/// counts are chosen in increasing lexicographic order, so that the first
+/// parts of the splits are generated in increasing lexicographic order, and
+/// the second parts in decreasing order. If `equal` is true, both parts have
+/// the same size, and generation stops as soon as the first part would be
+/// greater than the second, since the remaining splits are mirror images of
+/// those generated before. Returns false if generation was stopped.
+fn push_splits(groups: &[(Idx, usize)], capacity: &[usize], counts: &mut [usize], pos: usize,
+    remaining: usize, equal: bool, res: &mut Vec<(Vec<Idx>, Vec<Idx>)>) -> bool
+{
+    if pos == groups.len()
+    {
+        let mut a = vec![];
+        let mut b = vec![];
+        for (&k, &(idx, count)) in counts.iter().zip(groups.iter())
+        {
+            a.extend(::core::iter::repeat_n(idx, count - k));
+            b.extend(::core::iter::repeat_n(idx, k));
+        }
+        if equal && a > b
+        {
+            return false;
+        }
+        res.push((a, b));
+        return true;
+    }
+
+    let count = groups[pos].1;
+    let rest = capacity[pos] - count;
+    for k in remaining.saturating_sub(rest)..=count.min(remaining)
+    {
+        counts[pos] = k;
+        if !push_splits(groups, capacity, counts, pos + 1, remaining - k, equal, res)
+        {
+            return false;
+        }
+    }
+    true
+}
+
+/// Split a set of indices in two.
+///
+/// Generate all distinct ways to split the sorted indices in `idxs`, as
+/// returned by `unique_indices()`, into two non-empty parts. The larger part
+/// is always the first in the pair, and the result is sorted by the length of
+/// the second, smaller, part, and lexicographically for parts of equal
+/// length. Equal indices are interchangeable, so the splits are generated from
+/// the multiplicities of the indices, and symmetric splits of duplicate
+/// numbers are only generated once. Every split is generated exactly once, in
+/// order, so no sorting or deduplication is needed.
+pub fn partitions(idxs: &[Idx]) -> Vec<(Vec<Idx>, Vec<Idx>)>
+{
+    let groups = multiplicities(idxs);
+    let mut capacity = vec![0; groups.len() + 1];
+    for pos in (0..groups.len()).rev()
+    {
+        capacity[pos] = capacity[pos + 1] + groups[pos].1;
+    }
+
+    let mut res = vec![];
+    let mut counts = vec![0; groups.len()];
+    for size in 1..=idxs.len() / 2
+    {
+        push_splits(&groups, &capacity, &mut counts, 0, size, 2 * size == idxs.len(), &mut res);
+    }
+    res
+}
+
+/// Find the indexes of the unique numbers in an array.
+///
+/// For all elements in array `nrs`, find the index of the first occurrence of
+/// that element in `nrs`, and store it in the result. Afterwards, the indices
+/// array is sorted. Thus, equal numbers in the input array result in indices
+/// occuring with the same frequency in the output array, though not necessarily
+/// in the same order.
+pub fn unique_indices(nrs: &[u64]) -> Vec<Idx>
+{
+    let count = nrs.len();
+    let mut res = vec![];
+    for idx in 0..count
+    {
+        let uniq_idx = match nrs.iter().position(|&x| x == nrs[idx])
+            {
+                Some(dup_idx) => dup_idx,
+                None          => idx
+            };
+        res.push(uniq_idx as Idx);
+    }
+
+    res.sort();
+    res
+}
+
+/// Find all sub-multisets of a set of indices.
+///
+/// Given the sorted unique indices `idxs` as returned by `unique_indices()`,
+/// generate all distinct non-empty sub-multisets, i.e. subsets where equal
+/// indices are considered interchangeable. The result is sorted by the size
+/// of the subsets, and lexicographically for subsets of equal size, the full
+/// set of indices being the last element.
+pub fn sub_multisets(idxs: &[Idx]) -> Vec<Vec<Idx>>
+{
+    let mut res = vec![vec![]];
+    let mut start = 0;
+    while start < idxs.len()
+    {
+        let idx = idxs[start];
+        let count = idxs[start..].iter().take_while(|&&i| i == idx).count();
+
+        let mut next = vec![];
+        for sub in res.iter()
+        {
+            for k in 0..=count
+            {
+                let mut s = sub.clone();
+                s.extend(::core::iter::repeat_n(idx, k));
+                next.push(s);
+            }
+        }
+        res = next;
+        start += count;
+    }
+
+    res.remove(0);
+    res.sort_by(|s0, s1| s0.len().cmp(&s1.len()).then_with(|| s0.cmp(s1)));
+    res
+}
+
+/// Remove the indices in `sub` from `idxs`.
+///
+/// Both `idxs` and `sub` should be sorted, and `sub` should be a sub-multiset
+/// of `idxs`.
+pub(crate) fn difference(idxs: &[Idx], sub: &[Idx]) -> Vec<Idx>
+{
+    let mut res = vec![];
+    let mut sub_iter = sub.iter().peekable();
+    for &idx in idxs.iter()
+    {
+        if sub_iter.peek() == Some(&&idx)
+        {
+            sub_iter.next();
+        }
+        else
+        {
+            res.push(idx);
+        }
+    }
+    res
+}
+
+/// The input positions of the numbers in `nrs` indexed by `idxs`.
+///
+/// Returns a vector whose element `i` is true if the `i`'th input number is
+/// used. When a number occurs multiple times in the input, its occurrences are
+/// assigned to the positions in the input in order.
+pub(crate) fn used_positions(nrs: &[u64], idxs: &[Idx]) -> Vec<bool>
+{
+    let mut used = vec![false; nrs.len()];
+    for &idx in idxs.iter()
+    {
+        let nr = nrs[idx as usize];
+        if let Some(pos) = (0..nrs.len()).find(|&pos| nrs[pos] == nr && !used[pos])
+        {
+            used[pos] = true;
+        }
+    }
+    used
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Generate the splits of `idxs` by branching on every index, and
+    /// sorting and deduplicating afterwards.
+    fn all_partitions(idxs: &[Idx]) -> Vec<(Vec<Idx>, Vec<Idx>)>
+    {
+        let mut res = vec![(vec![idxs[0]], vec![])];
+        for &idx in idxs[1..].iter()
+        {
+            let count = res.len();
+            res.append(&mut res.clone());
+            for (a, _) in res[..count].iter_mut()
+            {
+                a.push(idx);
+            }
+            for (a, b) in res[count..].iter_mut()
+            {
+                b.push(idx);
+                if b.len() > a.len() || (b.len() == a.len() && b < a)
+                {
+                    ::core::mem::swap(a, b);
+                }
+            }
+        }
+
+        res.sort();
+        res.dedup();
+        res.sort_by_key(|(_, b)| b.len());
+        if res[0].1.is_empty()
+        {
+            res.remove(0);
+        }
+        res
+    }
+
+    /// All sorted index arrays of length `len`, with indices below `max`.
+    fn sorted_indices(len: usize, max: Idx) -> Vec<Vec<Idx>>
+    {
+        let mut res = vec![vec![]];
+        for _ in 0..len
+        {
+            res = res.iter().flat_map(|idxs: &Vec<Idx>| {
+                    let start = idxs.last().cloned().unwrap_or(0);
+                    (start..max).map(move |idx| {
+                        let mut next = idxs.clone();
+                        next.push(idx);
+                        next
+                    })
+                }).collect();
+        }
+        res
+    }
+
+    #[test]
+    fn partitions_match_exhaustive()
+    {
+        for len in 1..=7
+        {
+            for idxs in sorted_indices(len, 4)
+            {
+                assert_eq!(partitions(&idxs), all_partitions(&idxs), "{:?}", idxs);
+            }
+        }
+    }
+
+    #[test]
+    fn unique_indices_of_duplicates()
+    {
+        assert_eq!(unique_indices(&[3, 7, 3, 1, 7, 7]), [0, 0, 1, 1, 1, 3]);
+        assert_eq!(unique_indices(&[]), [] as [Idx; 0]);
+    }
+
+    #[test]
+    fn sub_multisets_of_duplicates()
+    {
+        let subs = sub_multisets(&[0, 0, 2]);
+        assert_eq!(subs, [vec![0], vec![2], vec![0, 0], vec![0, 2], vec![0, 0, 2]]);
+    }
+}
@@ -0,0 +1,184 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{Expr, Rat};
+
+/// Observer of the progress of a search
+///
+/// Trait `Progress` is notified as a search proceeds through the partitions
+/// of the input numbers at its top level (or through the subsets of the
+/// numbers, when searching over subsets), e.g. to show a progress bar. An
+/// observer is registered in the search options as `Options::progress`.
+pub trait Progress: ::core::fmt::Debug + Send + Sync
+{
+    /// Called when the search starts, with the number of steps it consists of.
+    fn start(&self, total: usize);
+    /// Called after each step of the search, with the distance to the target
+    /// of the best expression found thus far, if any.
+    fn step(&self, best_distance: Option<Rat>);
+    /// Called when the search finishes, or is cancelled.
+    fn finish(&self);
+}
+
+/// A new best expression found in a search
+#[derive(Clone, Debug)]
+pub struct Solution
+{
+    /// The expression
+    pub expr: Expr,
+    /// The distance of the value of the expression to the target
+    pub distance: Rat
+}
+
+/// Summary of a finished search
+#[derive(Clone, Debug)]
+pub struct Stats
+{
+    /// The number of steps of the search that were completed, i.e. the number
+    /// of partitions of the numbers at the top level, or the number of subsets
+    /// of the numbers when searching over subsets
+    pub steps: usize,
+    /// The best expression found, if any
+    pub best: Option<Solution>,
+    /// Whether the search was cancelled before it was completed
+    pub cancelled: bool
+}
+
+/// Observer of the events of a search
+///
+/// Trait `Observer` is notified of the events in a search, so that e.g. a user
+/// interface can show the best expression found thus far without parsing the
+/// output of the search. All methods do nothing by default, so an observer
+/// only needs to implement the events it is interested in. An observer is
+/// registered in the search options as `Options::observer`. Note that the
+/// methods may be called from a different thread than the one that started
+/// the search.
+pub trait Observer: ::core::fmt::Debug + Send + Sync
+{
+    /// Called when an expression is found that is nearer to the target than
+    /// any expression found before it.
+    fn on_new_best(&self, _solution: &Solution) {}
+    /// Called after each step of the search, with a mask whose element `i` is
+    /// true if the `i`'th input number is used on the left hand side of the
+    /// partition, or in the subset when searching over subsets.
+    fn on_partition_done(&self, _mask: &[bool]) {}
+    /// Called when the search finishes, or is cancelled.
+    fn on_finished(&self, _stats: &Stats) {}
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests
+{
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{get_nearest_expression, Options, Target};
+
+    /// Progress observer recording the calls made to it
+    #[derive(Debug, Default)]
+    struct RecordProgress
+    {
+        /// The totals passed to `start()`
+        starts: Mutex<Vec<usize>>,
+        /// The number of steps completed
+        steps: Mutex<usize>,
+        /// The number of calls to `finish()`
+        finishes: Mutex<usize>
+    }
+
+    impl Progress for RecordProgress
+    {
+        fn start(&self, total: usize)
+        {
+            self.starts.lock().unwrap().push(total);
+        }
+
+        fn step(&self, _best_distance: Option<Rat>)
+        {
+            *self.steps.lock().unwrap() += 1;
+        }
+
+        fn finish(&self)
+        {
+            *self.finishes.lock().unwrap() += 1;
+        }
+    }
+
+    #[test]
+    fn progress_of_search()
+    {
+        for subset in [false, true]
+        {
+            let progress = Arc::new(RecordProgress::default());
+            let opts = Options { progress: Some(progress.clone()), subset, ..Options::default() };
+            // The target cannot be reached, so that every step is completed
+            get_nearest_expression(&[1, 3, 4, 6], &Target::new(1000), &opts);
+            let steps = *progress.steps.lock().unwrap();
+            assert_eq!(*progress.starts.lock().unwrap(), [steps]);
+            assert!(steps > 0);
+            assert_eq!(*progress.finishes.lock().unwrap(), 1);
+        }
+    }
+
+    /// Observer recording the events of a search
+    #[derive(Debug, Default)]
+    struct RecordEvents
+    {
+        /// The distances of the new best expressions
+        distances: Mutex<Vec<Rat>>,
+        /// The masks of the completed steps
+        masks: Mutex<Vec<Vec<bool>>>,
+        /// The summaries of the finished search
+        stats: Mutex<Vec<Stats>>
+    }
+
+    impl Observer for RecordEvents
+    {
+        fn on_new_best(&self, solution: &Solution)
+        {
+            self.distances.lock().unwrap().push(solution.distance);
+        }
+
+        fn on_partition_done(&self, mask: &[bool])
+        {
+            self.masks.lock().unwrap().push(mask.to_vec());
+        }
+
+        fn on_finished(&self, stats: &Stats)
+        {
+            self.stats.lock().unwrap().push(stats.clone());
+        }
+    }
+
+    #[test]
+    fn events_of_search()
+    {
+        let nrs = [1, 3, 4, 6];
+        let target = Target::new(1000);
+        let observer = Arc::new(RecordEvents::default());
+        let opts = Options { observer: Some(observer.clone()), ..Options::default() };
+        let expr = get_nearest_expression(&nrs, &target, &opts);
+        let distances = observer.distances.lock().unwrap();
+        let masks = observer.masks.lock().unwrap();
+        let stats = observer.stats.lock().unwrap();
+        assert!(distances.windows(2).all(|pair| pair[1] < pair[0]));
+        assert_eq!(distances.last(), Some(&target.distance(expr.val)));
+        assert!(masks.iter().all(|mask| mask.len() == nrs.len() && mask.iter().any(|&used| used)));
+
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].steps, masks.len());
+        assert!(!stats[0].cancelled);
+        assert_eq!(stats[0].best.as_ref().map(|solution| solution.expr.val), Some(expr.val));
+    }
+}
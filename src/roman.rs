@@ -0,0 +1,99 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use alloc::string::String;
+
+/// The largest number that can be written in Roman numerals
+pub const MAX_ROMAN: u64 = 3999;
+
+/// The Roman numerals, and the subtractive pairs, with their values, from
+/// large to small
+const NUMERALS: [(&str, u64); 13] = [
+    ("M", 1000), ("CM", 900), ("D", 500), ("CD", 400),
+    ("C", 100), ("XC", 90), ("L", 50), ("XL", 40),
+    ("X", 10), ("IX", 9), ("V", 5), ("IV", 4),
+    ("I", 1)
+];
+
+/// Write a number in Roman numerals.
+///
+/// Write number `nr` in the standard form of Roman numerals, using the
+/// subtractive pairs `IV`, `IX`, `XL`, `XC`, `CD` and `CM`, e.g. 1994 becomes
+/// `MCMXCIV`. Returns `None` if `nr` is zero or larger than `MAX_ROMAN`,
+/// since these cannot be written in Roman numerals.
+pub fn to_roman(nr: u64) -> Option<String>
+{
+    if nr == 0 || nr > MAX_ROMAN
+    {
+        return None;
+    }
+
+    let mut s = String::new();
+    let mut rest = nr;
+    for &(numeral, val) in NUMERALS.iter()
+    {
+        while rest >= val
+        {
+            s.push_str(numeral);
+            rest -= val;
+        }
+    }
+    Some(s)
+}
+
+/// Parse a number in Roman numerals.
+///
+/// Parse string `s` as a number in Roman numerals, in upper or lower case.
+/// Only the standard form, as written by `to_roman()`, is accepted, so that
+/// e.g. `IIII` and `IC` are rejected. Returns `None` if `s` is not a valid
+/// Roman numeral.
+pub fn parse_roman(s: &str) -> Option<u64>
+{
+    let upper = s.to_ascii_uppercase();
+    let mut rest = upper.as_str();
+    let mut nr = 0;
+    for &(numeral, val) in NUMERALS.iter()
+    {
+        while let Some(tail) = rest.strip_prefix(numeral)
+        {
+            nr += val;
+            rest = tail;
+        }
+    }
+
+    if to_roman(nr).as_deref() == Some(upper.as_str()) { Some(nr) } else { None }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn roman_round_trip()
+    {
+        assert_eq!(to_roman(1994).as_deref(), Some("MCMXCIV"));
+        assert_eq!(to_roman(0), None);
+        assert_eq!(to_roman(MAX_ROMAN + 1), None);
+        for nr in 1..=MAX_ROMAN
+        {
+            assert_eq!(parse_roman(&to_roman(nr).unwrap()), Some(nr));
+        }
+        assert_eq!(parse_roman("xlii"), Some(42));
+        assert_eq!(parse_roman("IIII"), None);
+        assert_eq!(parse_roman("IC"), None);
+        assert_eq!(parse_roman(""), None);
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+/// The version of the JSON documents written by this library
+///
+/// Every JSON document written by the library, like the `Json` output format
+/// and puzzle banks, has a field `schema_version` with this number. It is
+/// increased whenever a field is removed, or changes its meaning, so that a
+/// program reading the documents can check whether it understands them.
+/// Adding a field does not change the version.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The JSON Schema of an expression in the `Json` output format
+pub const EXPRESSION_SCHEMA: &str = r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "title": "makeexpr expression",
+  "type": "object",
+  "properties": {
+    "schema_version": { "const": 1 },
+    "expression": { "type": "string", "description": "The expression in infix notation" },
+    "value": { "type": "string", "description": "The value, as an integer or fraction" },
+    "score": { "type": "integer", "description": "The elegance score, lower is better" }
+  },
+  "required": ["schema_version", "expression", "value", "score"]
+}"#;
+
+/// The JSON Schema of a puzzle bank
+pub const BANK_SCHEMA: &str = r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "title": "makeexpr puzzle bank",
+  "type": "object",
+  "properties": {
+    "schema_version": { "const": 1 },
+    "puzzles": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "properties": {
+          "numbers": { "type": "array", "items": { "type": "integer", "minimum": 0 } },
+          "target": { "type": "integer", "minimum": 0 },
+          "solutions": { "type": "array", "items": { "type": "string" } },
+          "difficulty": { "type": "integer", "minimum": 1, "maximum": 5 }
+        },
+        "required": ["numbers", "target", "solutions", "difficulty"]
+      }
+    }
+  },
+  "required": ["schema_version", "puzzles"]
+}"#;
+
+#[cfg(all(test, feature = "std"))]
+mod tests
+{
+    use serde_json::Value;
+    use super::*;
+    use crate::{bank_to_json, format_expr, parse, Format, Options, Puzzle};
+
+    /// Check that `doc` has the version and the required fields of `schema`.
+    fn check_required(schema: &str, doc: &Value)
+    {
+        let schema = serde_json::from_str::<Value>(schema).unwrap();
+        assert_eq!(schema["properties"]["schema_version"]["const"], SCHEMA_VERSION);
+        for field in schema["required"].as_array().unwrap()
+        {
+            assert!(doc.get(field.as_str().unwrap()).is_some(), "{} missing", field);
+        }
+        assert_eq!(doc["schema_version"], SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn documents_follow_schemas()
+    {
+        let nrs = [1, 3, 4, 6];
+        let opts = Options::default();
+        let expr = parse("6/(1-3/4)", &nrs).unwrap();
+        let json = format_expr(&expr, &nrs, &opts, Format::Json);
+        check_required(EXPRESSION_SCHEMA, &serde_json::from_str(&json).unwrap());
+
+        let bank = bank_to_json(&[Puzzle::new(&nrs, 24, &opts).unwrap()]);
+        check_required(BANK_SCHEMA, &serde_json::from_str(&bank).unwrap());
+    }
+}
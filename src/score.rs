@@ -0,0 +1,211 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use num_traits::{One, Zero};
+
+use crate::{exact_solutions, Expr, Op, Options, Rat, Target, ADD, DIV, MAX_NUMBERS, MUL, SUB};
+use crate::expr::apply;
+use crate::operator::unary_op_index;
+
+/// Penalty for every operation in the expression
+const OP_PENALTY: u64 = 10;
+/// Additional penalty for every division
+const DIV_PENALTY: u64 = 5;
+/// Penalty for an operation that leaves its left operand unchanged, like
+/// multiplying by one or adding zero
+const IDENTITY_PENALTY: u64 = 20;
+
+/// The number of decimal digits in `n`.
+fn digit_count(n: u64) -> u64
+{
+    n.checked_ilog10().unwrap_or(0) as u64 + 1
+}
+
+/// Compute the elegance score of an expression.
+///
+/// Compute a score for expression `expr` over the numbers array `nrs`, where
+/// a lower score means a more elegant expression. Every operation adds to the
+/// score, divisions more so than other operations, and operations that leave
+/// their left operand unchanged (`x*1`, `x/1`, `x+0`, `x-0`) are penalized
+/// heavily. Finally, every intermediate result adds the number of digits
+/// needed to write it down beyond the first, so that expressions with small,
+/// integer, intermediate results are preferred. Custom operations only add
+/// the penalty for an operation, since their results are not known here.
+pub fn elegance_score(expr: &Expr, nrs: &[u64]) -> u64
+{
+    let opts = Options::default();
+    let mut score = 0;
+    let mut stack = vec![];
+    for (i, &op) in expr.ops.iter().enumerate()
+    {
+        if (op as usize) < MAX_NUMBERS
+        {
+            stack.push(Rat::from_integer(nrs[op as usize]));
+            continue;
+        }
+        if unary_op_index(op).is_some()
+        {
+            score += OP_PENALTY;
+            continue;
+        }
+
+        let b = stack.pop().unwrap();
+        let a = stack.pop().unwrap();
+        let val = apply(op, a, b, &opts).unwrap_or_else(Rat::zero);
+
+        score += OP_PENALTY;
+        if op == DIV
+        {
+            score += DIV_PENALTY;
+        }
+        let identity = match op
+            {
+                ADD | SUB   => b.is_zero(),
+                MUL | DIV   => b.is_one(),
+                _           => false
+            };
+        if identity
+        {
+            score += IDENTITY_PENALTY;
+        }
+        if i + 1 < expr.ops.len()
+        {
+            score += digit_count(*val.numer()) - 1;
+            if !val.is_integer()
+            {
+                score += digit_count(*val.denom());
+            }
+        }
+
+        stack.push(val);
+    }
+
+    score
+}
+
+/// Ranking of exact solutions
+///
+/// Struct `Scoring` determines which of several exact solutions is preferred.
+/// By default, the most elegant solution, as measured by `elegance_score()`,
+/// comes first. Operations can be avoided without forbidding them: solutions
+/// using fewer of the operations in `avoid` are preferred over all others, so
+/// that these operations are only used when no solution without them exists.
+/// When weights are given for the numbers, the total weight of the numbers
+/// used in a solution is minimized before anything else.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Scoring
+{
+    /// If true, solutions whose largest sub-expression value is smallest are
+    /// preferred, and elegance only breaks ties, so that solutions are easy to
+    /// check by mental arithmetic.
+    pub small_intermediates: bool,
+    /// The operations whose use is penalized
+    pub avoid: Vec<Op>,
+    /// The cost of using each of the input numbers, by position
+    pub weights: Vec<u64>
+}
+
+impl Scoring
+{
+    /// The key by which expression `expr` over the numbers array `nrs` is
+    /// ranked, where a lower key means a better expression.
+    pub fn key(&self, expr: &Expr, nrs: &[u64]) -> (u64, usize, Rat, u64)
+    {
+        let avoided = expr.ops.iter().filter(|op| self.avoid.contains(op)).count();
+        let max = if self.small_intermediates { expr.max } else { Rat::zero() };
+        (self.cost(expr, nrs), avoided, max, elegance_score(expr, nrs))
+    }
+
+    /// The total weight of the numbers used in expression `expr` over the
+    /// numbers array `nrs`.
+    ///
+    /// Since equal numbers share an index in an expression, the occurrences of
+    /// a number are assigned to the positions of that number in `nrs` with the
+    /// lowest weights. Numbers without a weight cost nothing.
+    pub fn cost(&self, expr: &Expr, nrs: &[u64]) -> u64
+    {
+        let mut used = vec![0; nrs.len()];
+        for idx in expr.indices()
+        {
+            used[idx as usize] += 1;
+        }
+
+        let mut total = 0;
+        for (idx, &count) in used.iter().enumerate().filter(|&(_, &count)| count > 0)
+        {
+            let mut weights = nrs.iter().enumerate()
+                .filter(|&(_, &nr)| nr == nrs[idx])
+                .map(|(i, _)| self.weights.get(i).cloned().unwrap_or(0))
+                .collect::<Vec<_>>();
+            weights.sort_unstable();
+            total += weights.iter().take(count).sum::<u64>();
+        }
+        total
+    }
+}
+
+/// Find the preferred exact solution.
+///
+/// Enumerate the exact solutions for `target` over the numbers in `nrs`, as in
+/// `exact_solutions()`, using all numbers unless `opts.subset` is set, and
+/// return the best one according to `scoring`. Returns `None` if there is no
+/// exact solution.
+pub fn preferred_solution(nrs: &[u64], target: &Target, opts: &Options, scoring: &Scoring)
+    -> Option<Expr>
+{
+    exact_solutions(nrs, target, !opts.subset, opts).into_iter()
+        .min_by_key(|expr| scoring.key(expr, nrs))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn elegance_penalties()
+    {
+        let nrs = [1, 3, 4, 6];
+        let score = |s| elegance_score(&parse(s, &nrs).unwrap(), &nrs);
+        assert_eq!(score("6*4"), OP_PENALTY);
+        assert_eq!(score("6*4*1"), 2 * OP_PENALTY + 1 + IDENTITY_PENALTY);
+        // Two divisions, and the fractions 3/4 and 1/4 as intermediate results
+        assert_eq!(score("6/(1-3/4)"), 3 * OP_PENALTY + 2 * DIV_PENALTY + 2);
+        assert!(score("(6-1-3)*4") < score("6/(1-3/4)"));
+    }
+
+    #[test]
+    fn preferred_solution_by_scoring()
+    {
+        let nrs = [1, 3, 4, 6];
+        let target = Target::new(24);
+        let opts = Options { subset: true, ..Options::default() };
+        let best = preferred_solution(&nrs, &target, &opts, &Scoring::default()).unwrap();
+        assert_eq!(best.to_string(&nrs), "4*6");
+
+        let scoring = Scoring { avoid: vec![MUL], ..Scoring::default() };
+        let best = preferred_solution(&nrs, &target, &opts, &scoring).unwrap();
+        assert_eq!(best.to_string(&nrs), "6/(1-3/4)");
+
+        // The weights come first
+        let scoring = Scoring { avoid: vec![MUL], weights: vec![100], ..Scoring::default() };
+        let best = preferred_solution(&nrs, &target, &opts, &scoring).unwrap();
+        assert_eq!(best.val, 24.into());
+        assert_eq!(scoring.cost(&best, &nrs), 0);
+        assert!(preferred_solution(&[2, 2], &target, &opts, &scoring).is_none());
+    }
+}
@@ -0,0 +1,1380 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::borrow::Cow;
+use alloc::vec;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+use num_traits::Zero;
+
+use crate::{Expr, FastHash, HashMap, HashSet, Idx, NormalizedRat, Op, Options, Rat, Shape,
+    Solution, Stats, Target, MAX_NUMBERS};
+use crate::partition::{difference, partitions, sub_multisets, unique_indices, used_positions};
+use crate::expr::apply_unary;
+use crate::operator::unary_op_code;
+use crate::shuffle::SplitMix;
+use crate::small::{get_nearest_expression_small, reachable_small};
+#[cfg(feature = "std")]
+use crate::seen::ConcurrentSeen;
+#[cfg(feature = "std")]
+use crate::shared::{is_canonical, localize, sorted_numbers};
+#[cfg(feature = "std")]
+use crate::SharedCache;
+
+/// Cache of sub-expressions.
+///
+/// The cache maps a key describing a set of input numbers to the list of
+/// expressions using exactly those numbers, one for each distinct value
+/// that can be made from them. Create an empty cache with `Cache::default()`.
+pub type Cache = HashMap<String, Vec<Expr>, FastHash>;
+
+/// Split a set of indices in two.
+///
+/// Generate the splits of `idxs` as returned by `partitions()`, keeping only
+/// those allowed by the expression shape in `opts`. If `opts.shuffle_seed` is
+/// set, the splits are returned in a pseudo-random order.
+pub(crate) fn allowed_partitions(idxs: &[Idx], opts: &Options) -> Vec<(Vec<Idx>, Vec<Idx>)>
+{
+    let mut parts = partitions(idxs);
+    parts.retain(|(idxs0, idxs1)| opts.shape.allows_split(idxs0.len(), idxs1.len()));
+    if let Some(seed) = opts.shuffle_seed
+    {
+        SplitMix::new(seed, idxs).shuffle(&mut parts);
+    }
+    parts
+}
+
+/// Apply unary operations to a list of expressions.
+///
+/// Extend `map` with the results of applying the custom unary operations in
+/// `opts` to the expressions in it, for up to `opts.max_unary_depth` operations
+/// in succession. Only results whose value is not yet in `seen` are added.
+fn apply_unary_ops(map: &mut Vec<Expr>, seen: &mut HashSet<NormalizedRat, FastHash>,
+    opts: &Options)
+{
+    let mut start = 0;
+    for _ in 0..opts.max_unary_depth
+    {
+        let end = map.len();
+        for j in start..end
+        {
+            for i in 0..opts.unary_ops.len()
+            {
+                if opts.unary_ops[i].leaf_only() && map[j].ops.len() != 1
+                {
+                    continue;
+                }
+                if let Some(val) = apply_unary(i, map[j].val, opts)
+                {
+                    if !opts.forbidden_values.contains(&val) && seen.insert(NormalizedRat(val))
+                    {
+                        let ops = [&map[j].ops[..], &[unary_op_code(i)]].concat();
+                        map.push(Expr { ops, val, max: map[j].max.max(val) });
+                    }
+                }
+            }
+        }
+        start = end;
+    }
+}
+
+/// Apply unary operations at the top level of an expression.
+///
+/// Return the operation codes and the values of the expressions made by
+/// applying up to `opts.max_unary_depth` of the custom unary operations in
+/// `opts` in succession to a compound expression with value `val`, other than
+/// that expression itself. Since the expression then becomes a proper
+/// sub-expression, no operations are applied to forbidden values.
+fn top_level_unary(val: Rat, opts: &Options) -> Vec<(Vec<Op>, Rat)>
+{
+    let mut res = vec![];
+    if opts.unary_ops.is_empty()
+    {
+        return res;
+    }
+
+    let mut start = 0;
+    for depth in 0..opts.max_unary_depth
+    {
+        let end = res.len();
+        let prev = if depth == 0 { vec![(vec![], val)] } else { res[start..end].to_vec() };
+        for (ops, val) in prev.into_iter().filter(|(_, val)| !opts.forbidden_values.contains(val))
+        {
+            for i in (0..opts.unary_ops.len()).filter(|&i| !opts.unary_ops[i].leaf_only())
+            {
+                if let Some(uval) = apply_unary(i, val, opts)
+                {
+                    res.push(([&ops[..], &[unary_op_code(i)]].concat(), uval));
+                }
+            }
+        }
+        start = end;
+    }
+    res
+}
+
+/// Look up the expressions for key `key`.
+///
+/// Return the expressions stored in `cache` under `key`, reading them back
+/// from disk if they were spilled by `opts.spill`, or computing them again if
+/// reading them fails. Panics if the entry does not exist.
+#[cfg_attr(not(feature = "std"), allow(unused_variables))]
+pub(crate) fn cached<'a>(cache: &'a Cache, key: &str, opts: &Options) -> Cow<'a, [Expr]>
+{
+    if let Some(exprs) = cache.get(key)
+    {
+        return Cow::Borrowed(exprs);
+    }
+    #[cfg(feature = "std")]
+    if let Some(spill) = opts.spill.as_ref()
+    {
+        if let Some(exprs) = spill.load(key)
+        {
+            return Cow::Owned(exprs);
+        }
+        if let Some((nrs, idxs)) = spill.origin(key)
+        {
+            let opts = Options { spill: None, cache_counters: None, trace: None, ..opts.clone() };
+            let mut cache = Cache::default();
+            let key = expressions(&nrs, &idxs, &opts, &mut cache);
+            return Cow::Owned(cache.remove(&key).unwrap_or_default());
+        }
+    }
+    panic!("No expressions for {} in the cache", key);
+}
+
+/// Check if the expressions for key `key` are stored in `cache`, or were
+/// spilled to disk by `opts.spill`.
+#[cfg_attr(not(feature = "std"), allow(unused_variables))]
+fn is_cached(cache: &Cache, key: &str, opts: &Options) -> bool
+{
+    #[cfg(feature = "std")]
+    if opts.spill.as_ref().is_some_and(|spill| spill.contains(key))
+    {
+        return true;
+    }
+    cache.contains_key(key)
+}
+
+/// The key under which the expressions for the numbers `nrs[i]`, for the
+/// indices `i` in `idxs`, are stored in the cache.
+pub(crate) fn cache_key(nrs: &[u64], idxs: &[Idx]) -> String
+{
+    idxs.iter().map(|&i| nrs[i as usize].to_string()).collect::<Vec<_>>().join("_")
+}
+
+/// The values that are dominated for a set of numbers.
+///
+/// If `opts.prune_dominated` applies, return the values of the expressions in
+/// `cache` over proper sub-multisets of the numbers `nrs[i]` for the indices
+/// `i` in `idxs`, which need not be stored again for `idxs` itself. Otherwise,
+/// return an empty set.
+fn dominated_values(nrs: &[u64], idxs: &[Idx], opts: &Options, cache: &Cache)
+    -> HashSet<NormalizedRat, FastHash>
+{
+    let mut dominated = HashSet::with_hasher(FastHash);
+    if opts.prune_dominated && opts.subset && opts.shape == Shape::Any
+    {
+        for sub_idxs in sub_multisets(idxs).iter().filter(|sub_idxs| sub_idxs.len() < idxs.len())
+        {
+            let key = cache_key(nrs, sub_idxs);
+            if is_cached(cache, &key, opts)
+            {
+                dominated.extend(cached(cache, &key, opts).iter()
+                    .map(|expr| NormalizedRat(expr.val)));
+            }
+        }
+    }
+    dominated
+}
+
+/// Generate all distinct values for a set of numbers from its parts.
+///
+/// Create an expression for every distinct value that can be made from the
+/// numbers `nrs[i]` for the indices `i` in `idxs`, by combining the
+/// expressions for both parts of every split. The expressions for the parts
+/// should be in `cache` already. Values that are dominated, as determined by
+/// `dominated_values()`, are skipped.
+fn combine_parts(nrs: &[u64], idxs: &[Idx], opts: &Options, cache: &Cache) -> Vec<Expr>
+{
+    let mut map = vec![];
+    let mut seen = dominated_values(nrs, idxs, opts, cache);
+
+    if idxs.len() == 1
+    {
+        let expr = Expr::new(nrs, idxs[0]);
+        seen.insert(NormalizedRat(expr.val));
+        map.push(expr);
+    }
+    else
+    {
+        for (idxs0, idxs1) in allowed_partitions(idxs, opts)
+        {
+            if opts.cancelled()
+            {
+                break;
+            }
+            #[cfg(feature = "std")]
+            if let Some(trace) = &opts.trace
+            {
+                trace.partition(nrs, &idxs0, &idxs1, false);
+            }
+            let key0 = cache_key(nrs, &idxs0);
+            let key1 = cache_key(nrs, &idxs1);
+            #[cfg(feature = "std")]
+            if let Some(counters) = &opts.cache_counters
+            {
+                counters.record(idxs0.len(), true);
+                counters.record(idxs1.len(), true);
+            }
+            let exprs0 = cached(cache, &key0, opts);
+            let exprs1 = cached(cache, &key1, opts);
+            for expr0 in exprs0.iter()
+            {
+                for expr1 in exprs1.iter()
+                {
+                    for (op, val) in expr0.possible_combinations(expr1, opts)
+                    {
+                        if !opts.forbidden_values.contains(&val) && seen.insert(NormalizedRat(val))
+                        {
+                            map.push(expr0.combine(expr1, op, val));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if !opts.unary_ops.is_empty()
+    {
+        apply_unary_ops(&mut map, &mut seen, opts);
+    }
+
+    if let Some(seed) = opts.shuffle_seed
+    {
+        SplitMix::new(seed.wrapping_add(1), idxs).shuffle(&mut map);
+    }
+
+    map
+}
+
+/// The shared cache of `opts` to use for the numbers with indices `idxs`.
+///
+/// Sets of a single number are cheaper to generate than to look up, and
+/// since a shuffled search depends on the positions of the numbers, it does
+/// not use the shared cache at all.
+#[cfg(feature = "std")]
+fn shared_cache<'a>(idxs: &[Idx], opts: &'a Options) -> Option<&'a SharedCache>
+{
+    opts.shared_cache.as_deref().filter(|_| idxs.len() > 1 && opts.shuffle_seed.is_none())
+}
+
+/// Look up the expressions for a set of numbers in the shared cache.
+///
+/// Store the expressions for the numbers `nrs[i]`, for the indices `i` in
+/// `idxs`, from the shared cache in `opts` in `cache`, if the shared cache
+/// applies. If the entry is not shared yet and the indices are not in
+/// canonical order, the expressions are generated for the sorted numbers and
+/// converted, together with those for the subsets needed for them, so that
+/// they are the same whether they were shared before or not. Returns false if
+/// the expressions should be generated as usual, after which they can be
+/// shared.
+#[cfg(feature = "std")]
+fn shared_expressions(nrs: &[u64], idxs: &[Idx], opts: &Options, cache: &mut Cache) -> bool
+{
+    let shared = match shared_cache(idxs, opts)
+        {
+            Some(shared) => shared,
+            None => return false
+        };
+    if let Some(exprs) = shared.load(nrs, idxs, opts)
+    {
+        cache.insert(cache_key(nrs, idxs), exprs);
+        return true;
+    }
+    if is_canonical(nrs, idxs)
+    {
+        return false;
+    }
+
+    // The spill cache holds the entries of the caller's cache only
+    let sorted_opts = Options { spill: None, ..opts.clone() };
+    let sorted = sorted_numbers(nrs, idxs);
+    let mut sorted_cache = Cache::default();
+    expressions(&sorted, &unique_indices(&sorted), &sorted_opts, &mut sorted_cache);
+    for sub_idxs in sub_multisets(idxs)
+    {
+        let sub_sorted = sorted_numbers(nrs, &sub_idxs);
+        let sorted_key = cache_key(&sub_sorted, &unique_indices(&sub_sorted));
+        if let Some(mut exprs) = sorted_cache.remove(&sorted_key)
+        {
+            localize(nrs, idxs, &mut exprs);
+            cache.insert(cache_key(nrs, &sub_idxs), exprs);
+        }
+    }
+    true
+}
+
+/// Generate all distinct values for a set of numbers.
+///
+/// Ensure that `cache` holds an expression for every distinct value that
+/// can be made from the numbers `nrs[i]` for the indices `i` in `idxs`, and
+/// return the key under which these expressions are stored. The sets of
+/// numbers needed are first collected top-down, following the allowed splits
+/// of the sets not yet in the cache, and their expressions are then generated
+/// bottom-up, from the smallest sets to the largest, so that the expressions
+/// for both parts of a split are always available when they are combined. If
+/// the search is cancelled, the expressions for the remaining sets may be
+/// incomplete, but every set needed is still stored in the cache.
+pub(crate) fn expressions(nrs: &[u64], idxs: &[Idx], opts: &Options, cache: &mut Cache) -> String
+{
+    let key = cache_key(nrs, idxs);
+    #[cfg(feature = "std")]
+    if let Some(counters) = &opts.cache_counters
+    {
+        counters.record(idxs.len(), is_cached(cache, &key, opts));
+    }
+    if is_cached(cache, &key, opts)
+    {
+        return key;
+    }
+    #[cfg(feature = "std")]
+    if shared_expressions(nrs, idxs, opts, cache)
+    {
+        return key;
+    }
+
+    let mut needed = vec![idxs.to_vec()];
+    let mut visited = HashSet::with_hasher(FastHash);
+    visited.insert(idxs.to_vec());
+    let mut i = 0;
+    while i < needed.len()
+    {
+        if needed[i].len() > 1
+        {
+            for (idxs0, idxs1) in allowed_partitions(&needed[i], opts)
+            {
+                for part in [idxs0, idxs1]
+                {
+                    if visited.contains(&part) || is_cached(cache, &cache_key(nrs, &part), opts)
+                    {
+                        continue;
+                    }
+                    #[cfg(feature = "std")]
+                    if shared_expressions(nrs, &part, opts, cache)
+                    {
+                        continue;
+                    }
+                    visited.insert(part.clone());
+                    needed.push(part);
+                }
+            }
+        }
+        i += 1;
+    }
+
+    needed.sort_by_key(|sub| sub.len());
+    let mut start = 0;
+    while start < needed.len()
+    {
+        let size = needed[start].len();
+        let end = start + needed[start..].iter().take_while(|sub| sub.len() == size).count();
+        let maps = combine_level(nrs, &needed[start..end], opts, cache);
+        for (sub, map) in needed[start..end].iter().zip(maps)
+        {
+            #[cfg(feature = "std")]
+            if let Some(shared) = shared_cache(sub, opts).filter(|_| !opts.cancelled())
+            {
+                shared.store(nrs, sub, opts, &map);
+            }
+            cache.insert(cache_key(nrs, sub), map);
+        }
+        #[cfg(feature = "std")]
+        if let Some(spill) = &opts.spill
+        {
+            spill.spill_excess(nrs, cache);
+        }
+        start = end;
+    }
+
+    key
+}
+
+/// Generate all distinct values for a set of numbers on several threads.
+///
+/// As `combine_parts()`, but the splits of `idxs` are divided dynamically
+/// over `opts.threads` threads, which deduplicate the values they generate in
+/// a shared `ConcurrentSeen` set. The result is the same as that of
+/// `combine_parts()`.
+#[cfg(feature = "std")]
+fn combine_parts_parallel(nrs: &[u64], idxs: &[Idx], opts: &Options, cache: &Cache) -> Vec<Expr>
+{
+    let parts = allowed_partitions(idxs, opts);
+    let seen = ConcurrentSeen::new();
+    let next = AtomicUsize::new(0);
+    ::std::thread::scope(|scope| {
+            for _ in 0..opts.threads
+            {
+                scope.spawn(|| {
+                        loop
+                        {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            if i >= parts.len() || opts.cancelled()
+                            {
+                                break;
+                            }
+                            let (idxs0, idxs1) = &parts[i];
+                            if let Some(trace) = &opts.trace
+                            {
+                                trace.partition(nrs, idxs0, idxs1, false);
+                            }
+                            if let Some(counters) = &opts.cache_counters
+                            {
+                                counters.record(idxs0.len(), true);
+                                counters.record(idxs1.len(), true);
+                            }
+                            let exprs0 = cached(cache, &cache_key(nrs, idxs0), opts);
+                            let exprs1 = cached(cache, &cache_key(nrs, idxs1), opts);
+                            for (j, expr0) in exprs0.iter().enumerate()
+                            {
+                                for (k, expr1) in exprs1.iter().enumerate()
+                                {
+                                    for (l, (op, val)) in expr0.possible_combinations(expr1, opts)
+                                        .into_iter().enumerate()
+                                    {
+                                        if !opts.forbidden_values.contains(&val)
+                                        {
+                                            let order = (i as u32, j as u32, k as u32, l as u8);
+                                            seen.insert(val, order,
+                                                || expr0.combine(expr1, op, val));
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    });
+            }
+        });
+
+    let mut map = seen.into_sorted();
+    let mut seen = dominated_values(nrs, idxs, opts, cache);
+    if !seen.is_empty()
+    {
+        map.retain(|expr| !seen.contains(&NormalizedRat(expr.val)));
+    }
+    if !opts.unary_ops.is_empty()
+    {
+        seen.extend(map.iter().map(|expr| NormalizedRat(expr.val)));
+        apply_unary_ops(&mut map, &mut seen, opts);
+    }
+
+    if let Some(seed) = opts.shuffle_seed
+    {
+        SplitMix::new(seed.wrapping_add(1), idxs).shuffle(&mut map);
+    }
+
+    map
+}
+
+/// Generate all distinct values for sets of numbers of equal size.
+///
+/// Call `combine_parts()` for every set of indices in `subs`, which should all
+/// have the same size, and return the results in the same order. Since the
+/// sets only depend on smaller sets in `cache`, they are independent, and if
+/// `opts.threads` is greater than one they are divided over that many threads.
+/// If there are fewer sets than threads, the splits of every set are divided
+/// over the threads instead. The result does not depend on the number of
+/// threads.
+fn combine_level(nrs: &[u64], subs: &[Vec<Idx>], opts: &Options, cache: &Cache) -> Vec<Vec<Expr>>
+{
+    #[cfg(feature = "std")]
+    if opts.threads > 1 && subs.len() < opts.threads && subs[0].len() > 1
+    {
+        return subs.iter().map(|sub| combine_parts_parallel(nrs, sub, opts, cache)).collect();
+    }
+    #[cfg(feature = "std")]
+    if opts.threads > 1 && subs.len() > 1
+    {
+        let chunk_size = subs.len().div_ceil(opts.threads);
+        return ::std::thread::scope(|scope| {
+                let handles = subs.chunks(chunk_size)
+                    .map(|chunk| scope.spawn(move || {
+                            chunk.iter().map(|sub| combine_parts(nrs, sub, opts, cache))
+                                .collect::<Vec<_>>()
+                        }))
+                    .collect::<Vec<_>>();
+                handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+            });
+    }
+
+    subs.iter().map(|sub| combine_parts(nrs, sub, opts, cache)).collect()
+}
+
+/// The number of expressions of the second part of a split above which the
+/// combinations of every expression of the first part are bounded separately
+const MIN_BOUNDED_EXPRESSIONS: usize = 16;
+
+/// Bounds on the values of a set of expressions
+///
+/// Struct `Bounds` holds the lowest and highest value of a set of expressions,
+/// and their lowest positive value, if any. These bound the values that can be
+/// made by combining an expression from the set with another expression.
+struct Bounds
+{
+    /// The lowest value
+    min: Rat,
+    /// The highest value
+    max: Rat,
+    /// The lowest positive value, if any
+    min_positive: Option<Rat>
+}
+
+impl Bounds
+{
+    /// The bounds of the single value `val`.
+    fn value(val: Rat) -> Self
+    {
+        Bounds { min: val, max: val, min_positive: (!val.is_zero()).then_some(val) }
+    }
+
+    /// The bounds of the values of the expressions in `exprs`, or `None` if
+    /// there are none.
+    fn of(exprs: &[Expr]) -> Option<Self>
+    {
+        let min = exprs.iter().map(|expr| expr.val).min()?;
+        let max = exprs.iter().map(|expr| expr.val).max()?;
+        let min_positive = exprs.iter().map(|expr| expr.val).filter(|val| !val.is_zero()).min();
+        Some(Bounds { min, max, min_positive })
+    }
+
+    /// The lowest and highest value that can be made by combining a value
+    /// within these bounds with a value within `other`, using one of the basic
+    /// operations.
+    fn combine(&self, other: &Bounds) -> (Rat, Rat)
+    {
+        let gap = if self.min > other.max
+            {
+                self.min - other.max
+            }
+            else if other.min > self.max
+            {
+                other.min - self.max
+            }
+            else
+            {
+                Rat::zero()
+            };
+        let mut lo = gap.min(self.min * other.min);
+        let mut hi = (self.max + other.max).max(self.max * other.max);
+        if let Some(min_positive) = other.min_positive
+        {
+            lo = lo.min(self.min / other.max);
+            hi = hi.max(self.max / min_positive);
+        }
+        if let Some(min_positive) = self.min_positive
+        {
+            lo = lo.min(other.min / self.max);
+            hi = hi.max(other.max / min_positive);
+        }
+        (lo, hi)
+    }
+}
+
+/// Progress of the top level of a search
+///
+/// Struct `Checkpoint` records how many of the partitions at the top level of
+/// a search have been completed, and the best expression found in them, so
+/// that an interrupted search can be resumed.
+#[derive(Clone)]
+pub(crate) struct Checkpoint
+{
+    /// The number of partitions completed
+    pub(crate) done: usize,
+    /// The best expression found thus far, or an empty expression if none
+    pub(crate) best: Expr
+}
+
+impl Checkpoint
+{
+    /// Create a checkpoint for a search that has not started yet.
+    pub(crate) fn new() -> Self
+    {
+        Checkpoint { done: 0, best: Expr::empty() }
+    }
+}
+
+/// Find the expression nearest to target.
+///
+/// Given at least two input numbers in `nrs` indexed by `idxs`, and target
+/// number `target`, find an arithmetic expression using all these numbers
+/// that evaluates to a number as close as possible (or equal to) `target`.
+/// If `prune_cache` is true, sub-expressions that are no longer needed for
+/// this search are removed from `cache`. The search continues from, and
+/// updates, `checkpoint`.
+pub(crate) fn get_nearest_expression_multiple(nrs: &[u64], idxs: &[Idx], target: &Target,
+    opts: &Options, cache: &mut Cache, prune_cache: bool, checkpoint: &mut Checkpoint) -> Expr
+{
+    let mut best = ::core::mem::replace(&mut checkpoint.best, Expr::empty());
+    let mut window = if best.is_empty()
+        {
+            target.window(None)
+        }
+        else
+        {
+            target.window(Some(target.distance(best.val)))
+        };
+
+    let parts = allowed_partitions(idxs, opts);
+    if let Some(progress) = &opts.progress
+    {
+        progress.start(parts.len().saturating_sub(checkpoint.done));
+    }
+
+    'outer: for (idxs0, idxs1) in parts.into_iter().skip(checkpoint.done)
+    {
+        #[cfg(feature = "std")]
+        if let Some(trace) = &opts.trace
+        {
+            trace.partition(nrs, &idxs0, &idxs1, true);
+        }
+        let key0 = expressions(nrs, &idxs0, opts, cache);
+        let key1 = expressions(nrs, &idxs1, opts, cache);
+        let exprs0 = cached(cache, &key0, opts);
+        let exprs1 = cached(cache, &key1, opts);
+        // Unless custom operations are used, the bounds on the values of both
+        // parts bound the values of their combinations, so that a split, or a
+        // single expression of the first part, whose combinations cannot come
+        // closer to the target than the best expression thus far is skipped.
+        // Checking single expressions only pays off if the second part has
+        // many expressions.
+        let (bounds0, bounds1) = if opts.binary_ops.is_empty() && opts.unary_ops.is_empty()
+            {
+                (Bounds::of(&exprs0), Bounds::of(&exprs1))
+            }
+            else
+            {
+                (None, None)
+            };
+        let hopeless = match (&bounds0, &bounds1)
+            {
+                (Some(bounds0), Some(bounds1)) => {
+                    let (lo, hi) = bounds0.combine(bounds1);
+                    !window.intersects(lo, hi)
+                },
+                _ => false
+            };
+        let bounds1 = bounds1.filter(|_| exprs1.len() >= MIN_BOUNDED_EXPRESSIONS);
+        for expr0 in exprs0.iter().filter(|_| !hopeless)
+        {
+            if opts.cancelled()
+            {
+                break 'outer;
+            }
+            if let Some(bounds1) = &bounds1
+            {
+                let (lo, hi) = Bounds::value(expr0.val).combine(bounds1);
+                if !window.intersects(lo, hi)
+                {
+                    continue;
+                }
+            }
+            for expr1 in exprs1.iter()
+            {
+                for (op, val) in expr0.possible_combinations(expr1, opts)
+                {
+                    let unary = top_level_unary(val, opts);
+                    if unary.is_empty() && !window.contains(val)
+                    {
+                        continue;
+                    }
+                    for (unary, uval) in ::core::iter::once((vec![], val)).chain(unary)
+                    {
+                        if !window.contains(uval)
+                        {
+                            continue;
+                        }
+                        let diff = target.distance(uval);
+
+                        best = expr0.combine(expr1, op, val);
+                        best.ops.extend_from_slice(&unary);
+                        best.max = best.max.max(uval);
+                        best.val = uval;
+                        window = target.window(Some(diff));
+                        if let Some(observer) = &opts.observer
+                        {
+                            observer.on_new_best(&Solution { expr: best.clone(), distance: diff });
+                        }
+
+                        #[cfg(feature = "std")]
+                        if let Some(trace) = &opts.trace
+                        {
+                            trace.best(nrs, &best, diff);
+                        }
+
+                        if diff.is_zero()
+                        {
+                            break 'outer;
+                        }
+
+                        #[cfg(feature = "std")]
+                        if opts.print_intermediate
+                        {
+                            println!("{} = {}", best.to_string_with(nrs, opts), uval);
+                        }
+                    }
+                }
+            }
+        }
+
+        if opts.cancelled()
+        {
+            // The sub-expressions may be incomplete, don't count this partition
+            break;
+        }
+
+        drop((exprs0, exprs1));
+        if prune_cache
+        {
+            cache.remove(&key0);
+            if idxs1.len() >= idxs0.len()
+            {
+                cache.remove(&key1);
+            }
+        }
+
+        checkpoint.done += 1;
+        if let Some(progress) = &opts.progress
+        {
+            progress.step(if best.is_empty() { None } else { Some(target.distance(best.val)) });
+        }
+        if let Some(observer) = &opts.observer
+        {
+            observer.on_partition_done(&used_positions(nrs, &idxs0));
+        }
+    }
+
+    if let Some(progress) = &opts.progress
+    {
+        progress.finish();
+    }
+    notify_finished(opts, &best, target, checkpoint.done);
+
+    checkpoint.best = best.clone();
+    best
+}
+
+/// Notify the observer in `opts`, if any, that a search finished after `steps`
+/// steps, with best expression `best` for target `target`.
+fn notify_finished(opts: &Options, best: &Expr, target: &Target, steps: usize)
+{
+    if let Some(observer) = &opts.observer
+    {
+        let best = if best.is_empty()
+            {
+                None
+            }
+            else
+            {
+                Some(Solution { expr: best.clone(), distance: target.distance(best.val) })
+            };
+        observer.on_finished(&Stats { steps, best, cancelled: opts.cancelled() });
+    }
+}
+
+/// Find the expression nearest to target, using a subset of the numbers.
+///
+/// Find an arithmetic expression using one of the sets of numbers `nrs[i]`
+/// for the indices `i` in `subsets`, that evaluates to a number as close as
+/// possible to `target`. The subsets should be sorted by increasing size, as
+/// returned by `sub_multisets()`. A later subset only replaces the best
+/// expression if it is strictly closer, hence of the equally close
+/// expressions, the one using the fewest numbers (and therefore the fewest
+/// operations) is returned. The search continues from, and updates,
+/// `checkpoint`, counting the subsets completed.
+pub(crate) fn get_nearest_expression_subset(nrs: &[u64], subsets: Vec<Vec<Idx>>, target: &Target,
+    opts: &Options, cache: &mut Cache, checkpoint: &mut Checkpoint) -> Expr
+{
+    let sub_opts = Options { print_intermediate: false, progress: None, observer: None,
+        ..opts.clone() };
+    let mut best = ::core::mem::replace(&mut checkpoint.best, Expr::empty());
+    let mut best_diff = target.distance(best.val);
+
+    if let Some(progress) = &opts.progress
+    {
+        progress.start(subsets.len().saturating_sub(checkpoint.done));
+    }
+
+    for sub_idxs in subsets.into_iter().skip(checkpoint.done)
+    {
+        if opts.cancelled()
+        {
+            break;
+        }
+        let expr = if sub_idxs.len() < 2
+            {
+                get_nearest_expression_idxs(nrs, &sub_idxs, target, &sub_opts, cache, false)
+            }
+            else
+            {
+                // Start from the best expression thus far, so that only the
+                // expressions that improve on it are looked at
+                let mut sub_checkpoint = Checkpoint { done: 0, best: best.clone() };
+                get_nearest_expression_multiple(nrs, &sub_idxs, target, &sub_opts, cache, false,
+                    &mut sub_checkpoint)
+            };
+        if !opts.cancelled()
+        {
+            checkpoint.done += 1;
+        }
+        if !expr.is_empty()
+        {
+            let diff = target.distance(expr.val);
+            if best.is_empty() || diff < best_diff
+            {
+                best = expr;
+                best_diff = diff;
+                if let Some(observer) = &opts.observer
+                {
+                    observer.on_new_best(&Solution { expr: best.clone(), distance: diff });
+                }
+
+                if diff.is_zero()
+                {
+                    break;
+                }
+
+                #[cfg(feature = "std")]
+                if opts.print_intermediate
+                {
+                    println!("{} = {}", best.to_string_with(nrs, opts), best.val);
+                }
+            }
+        }
+
+        if let Some(progress) = &opts.progress
+        {
+            progress.step(if best.is_empty() { None } else { Some(best_diff) });
+        }
+        if let Some(observer) = &opts.observer
+        {
+            observer.on_partition_done(&used_positions(nrs, &sub_idxs));
+        }
+    }
+
+    if let Some(progress) = &opts.progress
+    {
+        progress.finish();
+    }
+    notify_finished(opts, &best, target, checkpoint.done);
+
+    checkpoint.best = best.clone();
+    best
+}
+
+/// Find the expression nearest to target.
+///
+/// Given input numbers `nrs`, and target `target`, find an arithmetic
+/// expression using all the numbers in `nrs` that evaluates to a number as close
+/// as possible to (or inside) `target`, subject to the search options in `opts`.
+/// If `opts.subset` is true, the expression may use any subset of the numbers,
+/// and of equally good expressions the one using the fewest numbers is
+/// returned. For at most six numbers, and options that only use the basic
+/// operations, a specialized search on fixed-size arrays is tried first, which
+/// finds most exact expressions in a fraction of the time.
+pub fn get_nearest_expression(nrs: &[u64], target: &Target, opts: &Options) -> Expr
+{
+    let idxs = unique_indices(nrs);
+    if let Some(expr) = get_nearest_expression_small(nrs, &idxs, target, opts)
+    {
+        return expr;
+    }
+
+    let mut cache = Cache::default();
+    if opts.subset
+    {
+        get_nearest_expression_subset(nrs, sub_multisets(&idxs), target, opts, &mut cache,
+            &mut Checkpoint::new())
+    }
+    else
+    {
+        get_nearest_expression_idxs(nrs, &idxs, target, opts, &mut cache, true)
+    }
+}
+
+/// Find the expression nearest to target, using a shared cache.
+///
+/// Find an arithmetic expression using exactly the numbers `nrs[i]` for the
+/// indices `i` in `idxs`, that evaluates to a number as close as possible to
+/// `target`. The indices should be unique indices as returned by
+/// `unique_indices()`, or a subset thereof. Sub-expressions are taken from,
+/// and stored in, `cache`, so that the work can be shared between multiple
+/// searches over subsets of the same numbers array with the same `opts`.
+/// If `opts.subset` is true, any subset of the indexed numbers may be used.
+pub fn get_nearest_expression_cached(nrs: &[u64], idxs: &[Idx], target: &Target,
+    opts: &Options, cache: &mut Cache) -> Expr
+{
+    if opts.subset
+    {
+        get_nearest_expression_subset(nrs, sub_multisets(idxs), target, opts, cache,
+            &mut Checkpoint::new())
+    }
+    else
+    {
+        get_nearest_expression_idxs(nrs, idxs, target, opts, cache, false)
+    }
+}
+
+/// List the distinct values that can be made from a set of numbers.
+///
+/// Return an expression for every distinct value that can be made from exactly
+/// the numbers `nrs[i]` for the indices `i` in `idxs`, sorted by value. As in
+/// `get_nearest_expression_cached()`, the indices should be unique indices,
+/// and the expressions are taken from, and stored in, `cache`. This exposes
+/// the contents of the cache, e.g. for inspecting what a search works with.
+pub fn distinct_values(nrs: &[u64], idxs: &[Idx], opts: &Options, cache: &mut Cache) -> Vec<Expr>
+{
+    if idxs.is_empty()
+    {
+        return vec![];
+    }
+
+    let key = expressions(nrs, idxs, opts, cache);
+    let mut exprs = cached(cache, &key, opts).into_owned();
+    exprs.sort_by_key(|expr| expr.val);
+    exprs
+}
+
+fn get_nearest_expression_idxs(nrs: &[u64], idxs: &[Idx], target: &Target,
+    opts: &Options, cache: &mut Cache, prune_cache: bool) -> Expr
+{
+    match idxs.len()
+    {
+        0 => Expr::empty(),
+        1 => {
+            let key = expressions(nrs, idxs, opts, cache);
+            cached(cache, &key, opts).iter().min_by_key(|expr| target.distance(expr.val)).unwrap()
+                .clone()
+        },
+        _ => get_nearest_expression_multiple(nrs, idxs, target, opts, cache, prune_cache,
+            &mut Checkpoint::new())
+    }
+}
+
+/// Find the expression nearest to target, containing a given sub-expression.
+///
+/// Find an arithmetic expression using all numbers in `nrs`, that evaluates
+/// to a number as close as possible to `target`, and that contains `unit` as
+/// a sub-expression. Expression `unit` should index into `nrs`, e.g. as
+/// returned by `parse()`. The search treats `unit` as a single pseudo-number,
+/// standing in for the numbers it uses, so the shape constraint in `opts`
+/// applies to the remainder of the expression only. The operations in `unit`
+/// do count towards `opts.max_ops`. If `opts.subset` is true, any subset of
+/// the remaining numbers may be combined with `unit`.
+pub fn get_nearest_expression_containing(nrs: &[u64], unit: &Expr, target: &Target,
+    opts: &Options) -> Expr
+{
+    assert!(nrs.len() < MAX_NUMBERS, "no index left for the required sub-expression");
+
+    let unit_ops = unit.op_count();
+    let max_ops = match opts.max_ops
+        {
+            Some(max_ops) if max_ops < unit_ops => return Expr::empty(),
+            Some(max_ops) => Some(max_ops - unit_ops),
+            None => None
+        };
+    // All expressions must contain the unit, so an expression over a subset of
+    // the numbers cannot stand in for one containing the unit
+    #[cfg_attr(not(feature = "std"), allow(unused_mut))]
+    let mut sub_opts = Options { max_ops, prune_dominated: false, print_intermediate: false,
+        ..opts.clone() };
+    // The placeholder standing in for the unit is not a real number, so the
+    // expressions made with it cannot be shared with other searches
+    #[cfg(feature = "std")]
+    {
+        sub_opts.shared_cache = None;
+    }
+
+    // The pseudo-number gets its own index, past the end of the numbers. Its
+    // placeholder value only serves to give it a unique cache key.
+    let unit_idx = nrs.len() as Idx;
+    let placeholder = (0..).find(|nr| !nrs.contains(nr)).unwrap();
+    let ext_nrs = [nrs, &[placeholder]].concat();
+    let mut cache = Cache::default();
+    cache.insert(placeholder.to_string(), vec![Expr { ops: vec![unit_idx], val: unit.val,
+        max: unit.max }]);
+
+    let mut unit_idxs = unit.indices().collect::<Vec<_>>();
+    unit_idxs.sort();
+    let rest = difference(&unique_indices(nrs), &unit_idxs);
+    let best = if opts.subset
+        {
+            let subsets = ::core::iter::once(vec![]).chain(sub_multisets(&rest))
+                .map(|mut idxs| { idxs.push(unit_idx); idxs })
+                .collect();
+            get_nearest_expression_subset(&ext_nrs, subsets, target, &sub_opts, &mut cache,
+                &mut Checkpoint::new())
+        }
+        else
+        {
+            let idxs = [&rest[..], &[unit_idx]].concat();
+            get_nearest_expression_idxs(&ext_nrs, &idxs, target, &sub_opts, &mut cache, true)
+        };
+
+    let ops = best.ops.iter().flat_map(|&op| {
+            if op == unit_idx { unit.ops.clone() } else { vec![op] }
+        }).collect();
+    Expr { ops, val: best.val, max: best.max }
+}
+
+/// Check if a target can be hit exactly.
+///
+/// Check if an expression over the numbers in `nrs`, subject to the search
+/// options in `opts`, evaluates to a value in `target`. If `opts.subset` is
+/// true, expressions over any subset of the numbers count as well. Unlike
+/// `get_nearest_expression()`, the search does not keep track of the best
+/// expression, and does not build the expressions at the top level, but stops
+/// as soon as any value in the target is made. Returns false if the search is
+/// cancelled before that.
+pub fn is_reachable(nrs: &[u64], target: &Target, opts: &Options) -> bool
+{
+    let idxs = unique_indices(nrs);
+    if let Some(reachable) = reachable_small(nrs, &idxs, target, opts)
+    {
+        return reachable;
+    }
+
+    let mut cache = Cache::default();
+    let subsets = if opts.subset { sub_multisets(&idxs) } else { vec![idxs] };
+    for sub_idxs in subsets.iter().filter(|sub_idxs| !sub_idxs.is_empty())
+    {
+        if sub_idxs.len() == 1
+        {
+            let key = expressions(nrs, sub_idxs, opts, &mut cache);
+            if cached(&cache, &key, opts).iter().any(|expr| target.contains(expr.val))
+            {
+                return true;
+            }
+            continue;
+        }
+
+        for (idxs0, idxs1) in allowed_partitions(sub_idxs, opts)
+        {
+            let key0 = expressions(nrs, &idxs0, opts, &mut cache);
+            let key1 = expressions(nrs, &idxs1, opts, &mut cache);
+            let exprs0 = cached(&cache, &key0, opts);
+            let exprs1 = cached(&cache, &key1, opts);
+            for expr0 in exprs0.iter()
+            {
+                if opts.cancelled()
+                {
+                    return false;
+                }
+                let hit = exprs1.iter().any(|expr1| {
+                        expr0.possible_combinations(expr1, opts).iter().any(|&(_, val)| {
+                                target.contains(val) || top_level_unary(val, opts).iter()
+                                    .any(|&(_, uval)| target.contains(uval))
+                            })
+                    });
+                if hit
+                {
+                    return true;
+                }
+            }
+
+            // Remove the sub-expressions that are no longer needed, as in
+            // get_nearest_expression_multiple()
+            drop((exprs0, exprs1));
+            if !opts.subset
+            {
+                cache.remove(&key0);
+                if idxs1.len() >= idxs0.len()
+                {
+                    cache.remove(&key1);
+                }
+            }
+        }
+    }
+
+    false
+}
+
+/// Find all exact solutions.
+///
+/// Find all expressions over the numbers in `nrs` that evaluate to a value
+/// in `target`. If `use_all` is true, only expressions using all numbers are
+/// returned, otherwise expressions over any non-empty subset of the numbers
+/// are considered. Note that for every sub-expression only a single
+/// representative expression per distinct value is kept, so solutions that
+/// differ only in the way their sub-expressions are formed are reported once.
+pub fn exact_solutions(nrs: &[u64], target: &Target, use_all: bool, opts: &Options) -> Vec<Expr>
+{
+    let mut cache = Cache::default();
+    let idxs = unique_indices(nrs);
+
+    let subsets = if use_all { vec![idxs] } else { sub_multisets(&idxs) };
+    for sub_idxs in subsets.iter().filter(|sub_idxs| sub_idxs.len() > 1)
+    {
+        for (idxs0, idxs1) in allowed_partitions(sub_idxs, opts)
+        {
+            expressions(nrs, &idxs0, opts, &mut cache);
+            expressions(nrs, &idxs1, opts, &mut cache);
+        }
+    }
+
+    exact_solutions_cached(nrs, &subsets, target, opts, &cache, usize::MAX)
+}
+
+/// Find exact solutions from the sub-expressions in a cache.
+///
+/// As `exact_solutions()`, but only expressions using exactly the numbers of
+/// one of the sets of indices in `subsets` are returned, and the search stops
+/// after `limit` solutions. The expressions for both parts of every allowed
+/// split of these sets should be in `cache` already.
+pub(crate) fn exact_solutions_cached(nrs: &[u64], subsets: &[Vec<Idx>], target: &Target,
+    opts: &Options, cache: &Cache, limit: usize) -> Vec<Expr>
+{
+    let mut res = vec![];
+    for sub_idxs in subsets
+    {
+        if sub_idxs.len() == 1
+        {
+            let expr = Expr::new(nrs, sub_idxs[0]);
+            if target.contains(expr.val) && res.len() < limit
+            {
+                res.push(expr);
+            }
+            continue;
+        }
+
+        for (idxs0, idxs1) in allowed_partitions(sub_idxs, opts)
+        {
+            let exprs0 = cached(cache, &cache_key(nrs, &idxs0), opts);
+            let exprs1 = cached(cache, &cache_key(nrs, &idxs1), opts);
+            for expr0 in exprs0.iter()
+            {
+                for expr1 in exprs1.iter()
+                {
+                    for (op, val) in expr0.possible_combinations(expr1, opts)
+                    {
+                        let candidates = ::core::iter::once((vec![], val))
+                            .chain(top_level_unary(val, opts));
+                        for (unary, uval) in candidates.filter(|&(_, uval)| target.contains(uval))
+                        {
+                            if res.len() >= limit
+                            {
+                                return res;
+                            }
+                            let mut expr = expr0.combine(expr1, op, val);
+                            expr.ops.extend_from_slice(&unary);
+                            expr.max = expr.max.max(uval);
+                            expr.val = uval;
+                            res.push(expr);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    res
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests
+{
+    use std::sync::Arc;
+    use super::*;
+    use crate::{parse, SharedCache, Target, UnaryOp};
+
+    /// Squaring, as a custom unary operation
+    #[derive(Debug)]
+    struct Sqr;
+
+    impl UnaryOp for Sqr
+    {
+        fn symbol(&self) -> &str
+        {
+            "sqr"
+        }
+
+        fn domain(&self, a: Rat) -> bool
+        {
+            *a.numer() < 1 << 32 && *a.denom() < 1 << 32
+        }
+
+        fn apply(&self, a: Rat) -> Rat
+        {
+            a * a
+        }
+    }
+
+    fn shared_opts() -> Options
+    {
+        Options { shared_cache: Some(Arc::new(SharedCache::new(1 << 20))), ..Options::default() }
+    }
+
+    /// Puzzles with an exact solution, and puzzles without
+    const PUZZLES: [(&[u64], u64); 8] = [
+        (&[1, 3, 4, 6], 24),
+        (&[1, 5, 5, 5], 24),
+        (&[3, 3, 8, 8], 24),
+        (&[2, 2, 2, 2], 17),
+        (&[1, 1, 1, 1], 30),
+        (&[25, 50, 75, 100, 3], 695),
+        (&[4, 7, 7, 9, 10], 811),
+        (&[6, 6, 6, 6, 6], 1000)
+    ];
+
+    /// The distance to `target` of the nearest value in the full list of
+    /// values that can be made from all numbers in `nrs`.
+    fn exhaustive_distance(nrs: &[u64], target: &Target, opts: &Options) -> Rat
+    {
+        let idxs = unique_indices(nrs);
+        let subsets = if opts.subset { sub_multisets(&idxs) } else { vec![idxs] };
+        subsets.iter()
+            .flat_map(|idxs| distinct_values(nrs, idxs, opts, &mut Cache::default()))
+            .map(|expr| target.distance(expr.val))
+            .min()
+            .unwrap()
+    }
+
+    #[test]
+    fn nearest_expression()
+    {
+        let nrs = [1, 3, 4, 6];
+        let expr = get_nearest_expression(&nrs, &Target::new(24), &Options::default());
+        assert_eq!(expr.to_string(&nrs), "6/(1-3/4)");
+        let expr = get_nearest_expression(&[2, 2], &Target::new(5), &Options::default());
+        assert_eq!(expr.val, 4.into());
+    }
+
+    #[test]
+    fn nearest_matches_exhaustive()
+    {
+        let opts = Options::default();
+        for &(nrs, target) in PUZZLES.iter()
+        {
+            let target = Target::new(target);
+            let idxs = unique_indices(nrs);
+            let expr = get_nearest_expression_cached(nrs, &idxs, &target, &opts,
+                &mut Cache::default());
+            assert_eq!(target.distance(expr.val), exhaustive_distance(nrs, &target, &opts),
+                "{:?}", nrs);
+            assert_eq!(Expr::from_ops(expr.ops.clone(), nrs, &opts).map(|e| e.val), Some(expr.val));
+        }
+    }
+
+    #[test]
+    fn bounds_keep_nearest()
+    {
+        // Targets far below and far above all values, so that most
+        // combinations are skipped on their bounds, and targets in between
+        let variants = [Options::default(), Options { integers_only: true, ..Options::default() }];
+        let nrs = [25, 50, 75, 100, 3];
+        let targets = [Target::new(0), Target::new(1), Target::range(400, 401),
+            Target::any(&[Target::new(3), Target::new(9999)]), Target::new(1_000_000_000)];
+        for opts in variants.iter()
+        {
+            for target in targets.iter()
+            {
+                let idxs = unique_indices(&nrs);
+                let expr = get_nearest_expression_cached(&nrs, &idxs, target, opts,
+                    &mut Cache::default());
+                assert_eq!(target.distance(expr.val), exhaustive_distance(&nrs, target, opts),
+                    "{:?}", target);
+            }
+        }
+    }
+
+    #[test]
+    fn prune_dominated_keeps_nearest()
+    {
+        let plain = Options { subset: true, ..Options::default() };
+        let pruned = Options { prune_dominated: true, ..plain.clone() };
+        for &(nrs, target) in PUZZLES.iter()
+        {
+            let target = Target::new(target);
+            let idxs = unique_indices(nrs);
+            let expr = get_nearest_expression_cached(nrs, &idxs, &target, &plain,
+                &mut Cache::default());
+            let pexpr = get_nearest_expression_cached(nrs, &idxs, &target, &pruned,
+                &mut Cache::default());
+            assert_eq!(target.distance(pexpr.val), target.distance(expr.val), "{:?}", nrs);
+            assert_eq!(target.distance(pexpr.val), exhaustive_distance(nrs, &target, &plain));
+            assert_eq!(pexpr.indices().count(), expr.indices().count(), "{:?}", nrs);
+        }
+    }
+
+    #[test]
+    fn unary_ops_at_top_level()
+    {
+        let mut opts = Options::default();
+        opts.add_unary_op(Sqr);
+        let nrs = [2, 3];
+        let target = Target::new(25);
+        let expr = get_nearest_expression(&nrs, &target, &opts);
+        assert_eq!(expr.to_string_with(&nrs, &opts), "sqr(2+3)");
+        assert!(is_reachable(&nrs, &target, &opts));
+        let exprs = exact_solutions(&nrs, &target, true, &opts);
+        assert!(!exprs.is_empty() && exprs.iter().all(|expr| expr.val == 25.into()));
+
+        let nrs = [1, 2, 4, 5];
+        let target = Target::new(144);
+        assert_eq!(get_nearest_expression(&nrs, &target, &opts).val, 144.into());
+        assert!(is_reachable(&nrs, &target, &opts));
+    }
+
+    #[test]
+    fn shared_cache_keeps_nearest()
+    {
+        let opts = shared_opts();
+        let exprs = PUZZLES.iter().chain(PUZZLES.iter().rev())
+            .map(|&(nrs, target)| {
+                // Use the numbers in reverse order as well, so that the
+                // shared entries are made for non-canonical indices
+                let rnrs = nrs.iter().rev().cloned().collect::<Vec<_>>();
+                let target = Target::new(target);
+                let idxs = unique_indices(&rnrs);
+                let plain = get_nearest_expression_cached(&rnrs, &idxs, &target,
+                    &Options::default(), &mut Cache::default());
+                let shared = get_nearest_expression_cached(&rnrs, &idxs, &target, &opts,
+                    &mut Cache::default());
+                assert_eq!(target.distance(shared.val), target.distance(plain.val), "{:?}", nrs);
+                assert_eq!(Expr::from_ops(shared.ops.clone(), &rnrs, &opts).map(|e| e.val),
+                    Some(shared.val));
+                shared.to_string(&rnrs)
+            })
+            .collect::<Vec<_>>();
+        // The expressions found do not depend on the searches run before
+        let count = PUZZLES.len();
+        assert!(exprs[..count].iter().eq(exprs[count..].iter().rev()));
+    }
+
+    #[test]
+    fn containing_ignores_shared_cache()
+    {
+        let cases: [(&[u64], &str, u64); 2] =
+            [(&[1, 2, 3, 4], "3+4", 8), (&[2, 5, 7, 9], "7*9", 40)];
+        for &(nrs, unit, target) in cases.iter()
+        {
+            let unit = parse(unit, nrs).unwrap();
+            let target = Target::new(target);
+            let plain = get_nearest_expression_containing(nrs, &unit, &target, &Options::default());
+            let shared = get_nearest_expression_containing(nrs, &unit, &target, &shared_opts());
+            assert_eq!(shared.val, plain.val);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::sync::Mutex;
+
+use crate::{Expr, FastHash, HashMap, NormalizedRat, Rat};
+
+/// The number of shards in a `ConcurrentSeen` set, a power of two
+const SHARD_COUNT: usize = 64;
+
+/// Position of a candidate expression in the sequential search order
+///
+/// The index of the partition, the indices of the two sub-expressions
+/// combined, and the index of the operation in the possible combinations.
+pub(crate) type Order = (u32, u32, u32, u8);
+
+/// A single shard of a `ConcurrentSeen` set, mapping a value to the first
+/// expression found for it
+type Shard = Mutex<HashMap<NormalizedRat, (Order, Expr), FastHash>>;
+
+/// Set of values shared between threads
+///
+/// Struct `ConcurrentSeen` deduplicates the values generated for a single set
+/// of numbers by several threads. It is split in shards, each protected by its
+/// own lock, and a value is assigned to a shard based on its numerator and
+/// denominator, so that threads inserting different values rarely wait for
+/// each other. For every value, the expression that comes first in the
+/// sequential search order is kept, so that the result does not depend on the
+/// order in which the threads insert their values.
+pub(crate) struct ConcurrentSeen
+{
+    /// The shards of the set
+    shards: Vec<Shard>
+}
+
+impl ConcurrentSeen
+{
+    /// Create a new, empty, set.
+    pub(crate) fn new() -> Self
+    {
+        let shards = (0..SHARD_COUNT).map(|_| Mutex::new(HashMap::with_hasher(FastHash))).collect();
+        ConcurrentSeen { shards }
+    }
+
+    /// The shard holding value `val`.
+    fn shard(&self, val: Rat) -> &Shard
+    {
+        let mix = (val.numer() ^ val.denom().rotate_left(32)).wrapping_mul(0x9e3779b97f4a7c15);
+        &self.shards[(mix >> 58) as usize % SHARD_COUNT]
+    }
+
+    /// Insert value `val`, found at position `order` in the search.
+    ///
+    /// If the value is new, or was only found later in the search order, the
+    /// expression for it is created by calling `make`, and stored.
+    pub(crate) fn insert<F>(&self, val: Rat, order: Order, make: F)
+    where F: FnOnce() -> Expr
+    {
+        let mut shard = self.shard(val).lock().unwrap();
+        match shard.get_mut(&NormalizedRat(val))
+        {
+            Some(entry) => {
+                if order < entry.0
+                {
+                    *entry = (order, make());
+                }
+            },
+            None => {
+                shard.insert(NormalizedRat(val), (order, make()));
+            }
+        }
+    }
+
+    /// The expressions in this set, in search order.
+    pub(crate) fn into_sorted(self) -> Vec<Expr>
+    {
+        let mut entries = self.shards.into_iter()
+            .flat_map(|shard| shard.into_inner().unwrap().into_values())
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|&(order, _)| order);
+        entries.into_iter().map(|(_, expr)| expr).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{get_nearest_expression_cached, unique_indices, Cache, Options, Target, ADD};
+
+    #[test]
+    fn first_in_search_order_kept()
+    {
+        let nrs = [2, 3, 5];
+        let sum = Expr::from_ops(vec![0, 1, ADD], &nrs, &Options::default()).unwrap();
+        let seen = ConcurrentSeen::new();
+        seen.insert(5.into(), (1, 0, 0, 0), || Expr::new(&nrs, 2));
+        seen.insert(5.into(), (0, 3, 0, 0), || sum.clone());
+        seen.insert(5.into(), (0, 4, 0, 0), || unreachable!());
+        seen.insert(Rat::new(2, 3), (2, 0, 0, 0), Expr::empty);
+        let exprs = seen.into_sorted();
+        assert_eq!(exprs.len(), 2);
+        assert_eq!(exprs[0].to_string(&nrs), "2+3");
+        assert!(exprs[1].is_empty());
+    }
+
+    #[test]
+    fn threads_find_same_expression()
+    {
+        let nrs = [25, 50, 75, 100, 3, 6];
+        let idxs = unique_indices(&nrs);
+        for &target in [952, 1].iter()
+        {
+            let target = Target::new(target);
+            let single = get_nearest_expression_cached(&nrs, &idxs, &target, &Options::default(),
+                &mut Cache::default());
+            let opts = Options { threads: 4, ..Options::default() };
+            let multi = get_nearest_expression_cached(&nrs, &idxs, &target, &opts,
+                &mut Cache::default());
+            assert_eq!(multi.to_string(&nrs), single.to_string(&nrs));
+        }
+    }
+}
@@ -0,0 +1,493 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+//! Solver service for the `serve` command of the program.
+//!
+//! The service answers HTTP requests of the form
+//! `GET /solve?numbers=1,3,4,6&target=24` with the nearest expression as a
+//! JSON object, and exposes metrics on the requests and searches in the
+//! Prometheus text format at `GET /metrics`. Results are cached, so that
+//! repeated requests for the same puzzle are answered without a search, and
+//! the searches share a cache of sub-expressions through their options. The
+//! work done for a single request, and the number of connections handled at
+//! the same time, are bounded by the `Limits` of the service.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use makeexpr::{get_nearest_expression, Options, Target, MAX_NUMBERS, SCHEMA_VERSION};
+
+use crate::{log, log_event, puzzle_id};
+
+/// The JSON Schema of the responses to solve requests
+pub const SOLVE_SCHEMA: &str = r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "title": "makeexpr solve response",
+  "type": "object",
+  "properties": {
+    "schema_version": { "const": 1 },
+    "expression": { "type": "string", "description": "The nearest expression, if any" },
+    "value": { "type": "string", "description": "Its value, as an integer or fraction" },
+    "exact": { "type": "boolean", "description": "Whether the value is the target" },
+    "error": { "type": "string", "description": "Why the request is invalid" }
+  },
+  "required": ["schema_version"]
+}"#;
+
+/// The default maximum number of input numbers in a request
+pub const DEFAULT_MAX_NUMBERS: usize = 8;
+/// The default maximum duration of the search for a request
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+/// The default maximum number of results in the cache
+pub const DEFAULT_MAX_CACHED: usize = 10_000;
+/// The default maximum number of connections handled at the same time
+pub const DEFAULT_MAX_CONNECTIONS: usize = 256;
+/// The default maximum time to wait for a client to send its request, or to
+/// accept the response
+pub const DEFAULT_IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The maximum size of the request line and headers of a request, in bytes
+const MAX_HEAD_BYTES: u64 = 8192;
+/// The maximum number of header lines in a request
+const MAX_HEADER_LINES: usize = 100;
+
+/// The upper bounds of the buckets of the solve duration histogram, in seconds
+const DURATION_BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 60.0];
+
+/// Metrics on the requests to the service
+#[derive(Debug, Default)]
+struct Metrics
+{
+    /// The number of requests, by path and status code. Unknown paths are
+    /// counted as `other`.
+    requests: BTreeMap<(String, u16), u64>,
+    /// The number of searches run
+    solves: u64,
+    /// The number of searches that found an exact solution
+    exact_hits: u64,
+    /// The number of searches per bucket in `DURATION_BUCKETS`, not cumulative
+    duration_counts: [u64; DURATION_BUCKETS.len()],
+    /// The total duration of all searches, in seconds
+    duration_sum: f64
+}
+
+impl Metrics
+{
+    /// Record a search that took `secs` seconds, and found an exact solution
+    /// if `exact` is true.
+    fn add_solve(&mut self, secs: f64, exact: bool)
+    {
+        self.solves += 1;
+        if exact
+        {
+            self.exact_hits += 1;
+        }
+        if let Some(idx) = DURATION_BUCKETS.iter().position(|&bound| secs <= bound)
+        {
+            self.duration_counts[idx] += 1;
+        }
+        self.duration_sum += secs;
+    }
+
+    /// Format the metrics in the Prometheus text exposition format, with
+    /// `cache_size` results in the cache.
+    fn render(&self, cache_size: usize) -> String
+    {
+        let mut out = String::new();
+        out += "# HELP makeexpr_requests_total Number of HTTP requests.\n";
+        out += "# TYPE makeexpr_requests_total counter\n";
+        for ((path, status), count) in self.requests.iter()
+        {
+            out += &format!("makeexpr_requests_total{{path=\"{}\",status=\"{}\"}} {}\n",
+                path, status, count);
+        }
+
+        out += "# HELP makeexpr_solve_duration_seconds Duration of the searches.\n";
+        out += "# TYPE makeexpr_solve_duration_seconds histogram\n";
+        let mut cumulative = 0;
+        for (bound, count) in DURATION_BUCKETS.iter().zip(self.duration_counts.iter())
+        {
+            cumulative += count;
+            out += &format!("makeexpr_solve_duration_seconds_bucket{{le=\"{}\"}} {}\n",
+                bound, cumulative);
+        }
+        out += &format!("makeexpr_solve_duration_seconds_bucket{{le=\"+Inf\"}} {}\n",
+            self.solves);
+        out += &format!("makeexpr_solve_duration_seconds_sum {}\n", self.duration_sum);
+        out += &format!("makeexpr_solve_duration_seconds_count {}\n", self.solves);
+
+        out += "# HELP makeexpr_exact_hits_total Number of searches finding an exact solution.\n";
+        out += "# TYPE makeexpr_exact_hits_total counter\n";
+        out += &format!("makeexpr_exact_hits_total {}\n", self.exact_hits);
+        out += "# HELP makeexpr_exact_hit_ratio Fraction of searches finding an exact solution.\n";
+        out += "# TYPE makeexpr_exact_hit_ratio gauge\n";
+        let ratio = if self.solves == 0 { 0.0 }
+            else { self.exact_hits as f64 / self.solves as f64 };
+        out += &format!("makeexpr_exact_hit_ratio {}\n", ratio);
+
+        out += "# HELP makeexpr_cache_entries Number of results in the cache.\n";
+        out += "# TYPE makeexpr_cache_entries gauge\n";
+        out += &format!("makeexpr_cache_entries {}\n", cache_size);
+        out
+    }
+}
+
+/// Limits on the work done for the requests to the service
+#[derive(Clone, Copy, Debug)]
+pub struct Limits
+{
+    /// The maximum number of input numbers in a request. Requests with more
+    /// numbers are rejected.
+    pub max_numbers: usize,
+    /// The maximum duration of the search for a request. If the search takes
+    /// longer, the request fails, and its result is not cached.
+    pub timeout: Duration,
+    /// The maximum number of results in the cache. When the cache is full, new
+    /// results are no longer cached.
+    pub max_cached: usize,
+    /// The maximum number of connections handled at the same time. Further
+    /// connections are answered with status 503 until one is closed.
+    pub max_connections: usize,
+    /// The maximum time to wait for a client when reading its request or
+    /// writing the response, after which the connection is closed.
+    pub io_timeout: Duration
+}
+
+impl Default for Limits
+{
+    fn default() -> Self
+    {
+        Limits { max_numbers: DEFAULT_MAX_NUMBERS, timeout: DEFAULT_TIMEOUT,
+            max_cached: DEFAULT_MAX_CACHED, max_connections: DEFAULT_MAX_CONNECTIONS,
+            io_timeout: DEFAULT_IO_TIMEOUT }
+    }
+}
+
+/// The state of the service, shared between the connections
+#[derive(Debug)]
+struct Service
+{
+    /// The search options for every request
+    opts: Options,
+    /// The limits on the work for a request
+    limits: Limits,
+    /// The metrics on the requests
+    metrics: Mutex<Metrics>,
+    /// The response for every puzzle solved thus far, by numbers and target
+    cache: Mutex<HashMap<(Vec<u64>, u64), String>>
+}
+
+impl Service
+{
+    /// Handle a request for `path` with query string `query`, returning the
+    /// status code, content type and body of the response.
+    fn handle(&self, path: &str, query: &str) -> (u16, &'static str, String)
+    {
+        match path
+        {
+            "/solve" => {
+                let res = parse_query(query, self.limits.max_numbers).map_err(|msg| (400, msg))
+                    .and_then(|(nrs, target)| self.solve(nrs, target).map_err(|msg| (503, msg)));
+                match res
+                {
+                    Ok(body) => (200, "application/json", body),
+                    Err((status, msg)) => {
+                        let body = serde_json::json!({
+                                "schema_version": SCHEMA_VERSION,
+                                "error": msg
+                            }).to_string();
+                        (status, "application/json", body)
+                    }
+                }
+            },
+            "/metrics" => {
+                let cache_size = self.cache.lock().unwrap().len();
+                let body = self.metrics.lock().unwrap().render(cache_size);
+                (200, "text/plain; version=0.0.4", body)
+            },
+            _ => (404, "text/plain", String::from("not found\n"))
+        }
+    }
+
+    /// Find the nearest expression for target `target` over numbers `nrs`,
+    /// using the cache, and return it as a JSON object. Returns an error if
+    /// the search does not finish within the time limit.
+    fn solve(&self, nrs: Vec<u64>, target: u64) -> Result<String, String>
+    {
+        let key = (nrs, target);
+        if let Some(body) = self.cache.lock().unwrap().get(&key)
+        {
+            return Ok(body.clone());
+        }
+
+        let start = Instant::now();
+        let opts = Options { deadline: Some(start + self.limits.timeout), ..self.opts.clone() };
+        let expr = get_nearest_expression(&key.0, &Target::new(target), &opts);
+        let exact = !expr.is_empty() && expr.val == target.into();
+        self.metrics.lock().unwrap().add_solve(start.elapsed().as_secs_f64(), exact);
+        if opts.cancelled()
+        {
+            return Err(format!("the search did not finish within {} seconds",
+                self.limits.timeout.as_secs_f64()));
+        }
+
+        let body = serde_json::json!({
+                "schema_version": SCHEMA_VERSION,
+                "expression": expr.to_string(&key.0),
+                "value": expr.val.to_string(),
+                "exact": exact
+            }).to_string();
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() < self.limits.max_cached
+        {
+            cache.insert(key, body.clone());
+        }
+        Ok(body)
+    }
+
+    /// Read a single request from `stream`, and write the response.
+    fn serve(&self, mut stream: TcpStream) -> ::std::io::Result<()>
+    {
+        stream.set_read_timeout(Some(self.limits.io_timeout))?;
+        stream.set_write_timeout(Some(self.limits.io_timeout))?;
+        let head = read_head(BufReader::new(stream.try_clone()?))?;
+
+        let start = Instant::now();
+        let mut puzzle = None;
+        let line = head.as_deref().unwrap_or("");
+        let mut words = line.split_whitespace();
+        let (path, status, content_type, body) = match (&head, words.next(), words.next())
+            {
+                (Err(status), _, _) => {
+                    let body = String::from("request too large\n");
+                    (String::from("-"), *status, "text/plain", body)
+                },
+                (Ok(_), Some("GET"), Some(target)) => {
+                    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+                    if let ("/solve", Ok((nrs, target)))
+                        = (path, parse_query(query, self.limits.max_numbers))
+                    {
+                        puzzle = Some(puzzle_id(&nrs, &target.to_string()));
+                    }
+                    let (status, content_type, body) = self.handle(path, query);
+                    // Keep the number of distinct labels in the metrics bounded
+                    let path = if status == 404 { "other" } else { path };
+                    (path.to_string(), status, content_type, body)
+                },
+                _ => (String::from("-"), 400, "text/plain", String::from("bad request\n"))
+            };
+        log_event("request", puzzle.as_deref(), &format!("GET {} {} in {:.6}s", path, status,
+            start.elapsed().as_secs_f64()));
+        *self.metrics.lock().unwrap().requests.entry((path, status)).or_insert(0) += 1;
+
+        respond(&mut stream, status, content_type, &body)
+    }
+}
+
+/// Read the request line and the headers of a request from `reader`, and
+/// return the request line. The headers are skipped, as a request has no
+/// body. Returns status 431 if the request line and headers together are
+/// larger than `MAX_HEAD_BYTES`, or have more than `MAX_HEADER_LINES` header
+/// lines.
+fn read_head<R: BufRead>(reader: R) -> ::std::io::Result<Result<String, u16>>
+{
+    let mut reader = reader.take(MAX_HEAD_BYTES);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut header = String::new();
+    let mut count = 0;
+    loop
+    {
+        if reader.limit() == 0
+        {
+            return Ok(Err(431));
+        }
+        header.clear();
+        if reader.read_line(&mut header)? <= 2
+        {
+            return Ok(Ok(line));
+        }
+        count += 1;
+        if count > MAX_HEADER_LINES
+        {
+            return Ok(Err(431));
+        }
+    }
+}
+
+/// Write a response with status `status`, content type `content_type` and
+/// body `body` to `stream`.
+fn respond(stream: &mut TcpStream, status: u16, content_type: &str, body: &str)
+    -> ::std::io::Result<()>
+{
+    let reason = match status
+        {
+            200 => "OK",
+            400 => "Bad Request",
+            431 => "Request Header Fields Too Large",
+            503 => "Service Unavailable",
+            _   => "Not Found"
+        };
+    write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+        Connection: close\r\n\r\n{}", status, reason, content_type, body.len(), body)?;
+    stream.flush()
+}
+
+/// Parse the query string `query` of a solve request into the input numbers,
+/// at most `max_numbers` of them, and the target number.
+fn parse_query(query: &str, max_numbers: usize) -> Result<(Vec<u64>, u64), String>
+{
+    let mut nrs = None;
+    let mut target = None;
+    for param in query.split('&').filter(|param| !param.is_empty())
+    {
+        match param.split_once('=')
+        {
+            Some(("numbers", value)) => {
+                let parsed = value.split(',').map(|s| s.parse::<u64>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| format!("invalid numbers \"{}\"", value))?;
+                nrs = Some(parsed);
+            },
+            Some(("target", value)) => {
+                target = Some(value.parse::<u64>()
+                    .map_err(|_| format!("invalid target \"{}\"", value))?);
+            },
+            _ => return Err(format!("unknown parameter \"{}\"", param))
+        }
+    }
+
+    let nrs = nrs.ok_or_else(|| String::from("missing parameter \"numbers\""))?;
+    let max_numbers = max_numbers.min(MAX_NUMBERS);
+    if nrs.len() > max_numbers
+    {
+        return Err(format!("at most {} numbers are supported", max_numbers));
+    }
+    let target = target.ok_or_else(|| String::from("missing parameter \"target\""))?;
+    Ok((nrs, target))
+}
+
+/// Run the service on address `addr`, searching with options `opts`, within
+/// the limits `limits`.
+///
+/// Every connection is handled in a thread of its own, for at most
+/// `limits.max_connections` connections at the same time. This only returns
+/// if the address cannot be bound.
+pub fn run(addr: &str, opts: Options, limits: Limits) -> ::std::io::Result<()>
+{
+    let listener = TcpListener::bind(addr)?;
+    let service = Arc::new(Service {
+            opts,
+            limits,
+            metrics: Mutex::new(Metrics::default()),
+            cache: Mutex::new(HashMap::new())
+        });
+    log("serve", &format!("Listening on {}", listener.local_addr()?));
+    let active = Arc::new(AtomicUsize::new(0));
+    for mut stream in listener.incoming().flatten()
+    {
+        if active.fetch_add(1, Ordering::SeqCst) >= limits.max_connections
+        {
+            active.fetch_sub(1, Ordering::SeqCst);
+            let _ = stream.set_write_timeout(Some(limits.io_timeout));
+            let _ = respond(&mut stream, 503, "text/plain", "too many connections\n");
+            continue;
+        }
+        let service = Arc::clone(&service);
+        let active = Arc::clone(&active);
+        ::std::thread::spawn(move || {
+                let _ = service.serve(stream);
+                active.fetch_sub(1, Ordering::SeqCst);
+            });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn service(limits: Limits) -> Service
+    {
+        Service { opts: Options::default(), limits, metrics: Mutex::new(Metrics::default()),
+            cache: Mutex::new(HashMap::new()) }
+    }
+
+    #[test]
+    fn too_many_numbers_rejected()
+    {
+        let limits = Limits { max_numbers: 4, ..Limits::default() };
+        assert!(parse_query("numbers=1,3,4,6&target=24", limits.max_numbers).is_ok());
+        let (status, _, body) = service(limits).handle("/solve", "numbers=1,2,3,4,5&target=24");
+        assert_eq!(status, 400);
+        assert!(body.contains("at most 4 numbers"), "{}", body);
+    }
+
+    #[test]
+    fn timeout_fails_without_caching()
+    {
+        let service = service(Limits { timeout: Duration::ZERO, ..Limits::default() });
+        let (status, _, _) = service.handle("/solve", "numbers=1,3,4,6&target=24");
+        assert_eq!(status, 503);
+        assert!(service.cache.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn cache_bounded()
+    {
+        let service = service(Limits { max_cached: 1, ..Limits::default() });
+        for target in [24, 25]
+        {
+            let (status, _, _) = service.handle("/solve",
+                &format!("numbers=1,3,4,6&target={}", target));
+            assert_eq!(status, 200);
+        }
+        assert_eq!(service.cache.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn request_head_bounded()
+    {
+        let request = "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        assert_eq!(read_head(request.as_bytes()).unwrap(),
+            Ok(String::from("GET /metrics HTTP/1.1\r\n")));
+        let headers = "X: y\r\n".repeat(MAX_HEADER_LINES + 1);
+        let request = format!("GET /metrics HTTP/1.1\r\n{}\r\n", headers);
+        assert_eq!(read_head(request.as_bytes()).unwrap(), Err(431));
+        let value = "y".repeat(MAX_HEAD_BYTES as usize);
+        let request = format!("GET /metrics HTTP/1.1\r\nX: {}\r\n\r\n", value);
+        assert_eq!(read_head(request.as_bytes()).unwrap(), Err(431));
+        let request = format!("GET /{} HTTP/1.1\r\n\r\n", value);
+        assert_eq!(read_head(request.as_bytes()).unwrap(), Err(431));
+    }
+
+    #[test]
+    fn silent_client_disconnected()
+    {
+        let limits = Limits { io_timeout: Duration::from_millis(50), ..Limits::default() };
+        let service = service(limits);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let start = Instant::now();
+        assert!(service.serve(stream).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
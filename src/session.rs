@@ -0,0 +1,215 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{Cache, Expr, Idx, Options, Rat, Target};
+use crate::partition::{sub_multisets, unique_indices};
+use crate::search::{distinct_values, exact_solutions_cached};
+
+/// Index of the values that can be made from a set of numbers
+///
+/// Struct `Session` enumerates the expressions over a set of numbers once, and
+/// keeps an expression for every distinct value, sorted by value. The nearest
+/// expression for a target is then found by a binary search in this index,
+/// rather than by a new search, so that many targets for the same numbers are
+/// answered quickly. The sub-expressions are kept as well, so that all
+/// expressions for a value can be listed without a new enumeration. The price
+/// is that all of these are kept in memory, which for many input numbers may
+/// be much more than a single search needs.
+#[derive(Clone, Debug)]
+pub struct Session
+{
+    /// The input numbers
+    nrs: Vec<u64>,
+    /// The search options
+    opts: Options,
+    /// The sets of indices of the numbers the expressions may use
+    subsets: Vec<Vec<Idx>>,
+    /// The expressions for every set of numbers needed
+    cache: Cache,
+    /// An expression for every distinct value, sorted by value
+    exprs: Vec<Expr>
+}
+
+impl Session
+{
+    /// Create a new session for the numbers `nrs`.
+    ///
+    /// Enumerate the expressions over all numbers in `nrs`, subject to the
+    /// search options in `opts`. If `opts.subset` is true, the expressions
+    /// over every non-empty subset of the numbers are included, and of the
+    /// expressions with equal value, the one using the fewest numbers is kept.
+    pub fn new(nrs: &[u64], opts: &Options) -> Self
+    {
+        let idxs = unique_indices(nrs);
+        let subsets = if opts.subset { sub_multisets(&idxs) } else { vec![idxs] };
+        let mut cache = Cache::default();
+        // The subsets are sorted by size, so after the stable sort the first
+        // expression for every value uses the fewest numbers
+        let mut exprs = subsets.iter()
+            .flat_map(|sub_idxs| distinct_values(nrs, sub_idxs, opts, &mut cache))
+            .collect::<Vec<_>>();
+        exprs.sort_by_key(|expr| expr.val);
+        exprs.dedup_by_key(|expr| expr.val);
+        Session { nrs: nrs.to_vec(), opts: opts.clone(), subsets, cache, exprs }
+    }
+
+    /// The input numbers of this session.
+    pub fn numbers(&self) -> &[u64]
+    {
+        &self.nrs
+    }
+
+    /// The number of distinct values that can be made.
+    pub fn len(&self) -> usize
+    {
+        self.exprs.len()
+    }
+
+    /// Check if no value can be made at all.
+    pub fn is_empty(&self) -> bool
+    {
+        self.exprs.is_empty()
+    }
+
+    /// An expression for every distinct value that can be made, sorted by
+    /// value.
+    pub fn expressions(&self) -> &[Expr]
+    {
+        &self.exprs
+    }
+
+    /// The candidates for the nearest expression to the interval `min..=max`.
+    ///
+    /// If values inside the interval can be made, these are the expressions
+    /// for these values, or only the first of them if all expressions use all
+    /// numbers. Otherwise, they are the last expression below the interval
+    /// and the first expression above it, as far as these exist.
+    fn candidates(&self, min: Rat, max: Rat) -> &[Expr]
+    {
+        let start = self.exprs.partition_point(|expr| expr.val < min);
+        let end = start + self.exprs[start..].partition_point(|expr| expr.val <= max);
+        if start < end
+        {
+            &self.exprs[start..if self.opts.subset { end } else { start + 1 }]
+        }
+        else
+        {
+            &self.exprs[start.saturating_sub(1)..(start + 1).min(self.exprs.len())]
+        }
+    }
+
+    /// Find the expression nearest to target.
+    ///
+    /// Return the expression whose value is as close as possible to (or
+    /// inside) `target`, or an empty expression if no value can be made. Of
+    /// two values at equal distance, the one using the fewest numbers is
+    /// returned. Every interval of the target takes a binary search in the
+    /// index of values, and if the session was created with `opts.subset`, a
+    /// scan of the values inside the interval for the expression using the
+    /// fewest numbers.
+    pub fn nearest(&self, target: &Target) -> Expr
+    {
+        target.intervals().iter()
+            .flat_map(|&(min, max)| self.candidates(min, max))
+            .min_by_key(|expr| (target.distance(expr.val), expr.indices().count()))
+            .cloned()
+            .unwrap_or_else(Expr::empty)
+    }
+
+    /// Find all expressions for a value.
+    ///
+    /// Return the expressions over the numbers of this session that evaluate
+    /// to `value`, as in `exact_solutions()`: for every sub-expression only a
+    /// single representative expression per distinct value is kept, so
+    /// expressions that differ only in the way their sub-expressions are formed
+    /// are reported once. If the session was created with `opts.subset`, the
+    /// expressions over every subset of the numbers are included.
+    pub fn expressions_for(&self, value: Rat) -> Vec<Expr>
+    {
+        self.expressions_for_up_to(value, usize::MAX)
+    }
+
+    /// Find at most `limit` expressions for a value.
+    ///
+    /// As `expressions_for()`, but the search stops after `limit` expressions
+    /// have been found.
+    pub fn expressions_for_up_to(&self, value: Rat, limit: usize) -> Vec<Expr>
+    {
+        if self.exprs.binary_search_by_key(&value, |expr| expr.val).is_err()
+        {
+            return vec![];
+        }
+        exact_solutions_cached(&self.nrs, &self.subsets, &Target::from_rat(value), &self.opts,
+            &self.cache, limit)
+    }
+
+    /// Check if `target` can be hit exactly.
+    ///
+    /// This takes a binary search in the index of values for every interval
+    /// of the target. To check a single target without enumerating all
+    /// values first, use the function `is_reachable()` instead.
+    pub fn is_reachable(&self, target: &Target) -> bool
+    {
+        target.intervals().iter().any(|&(min, max)| {
+                let pos = self.exprs.partition_point(|expr| expr.val < min);
+                self.exprs.get(pos).is_some_and(|expr| expr.val <= max)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{get_nearest_expression, is_reachable};
+
+    #[test]
+    fn session_matches_search()
+    {
+        let nrs = [25, 50, 75, 100, 3];
+        for opts in [Options::default(), Options { subset: true, ..Options::default() }].iter()
+        {
+            let session = Session::new(&nrs, opts);
+            assert!(!session.is_empty());
+            for &target in [1, 695, 811, 1000, 100_000].iter()
+            {
+                let target = Target::new(target);
+                let expr = session.nearest(&target);
+                let nearest = get_nearest_expression(&nrs, &target, opts);
+                assert_eq!(target.distance(expr.val), target.distance(nearest.val));
+                assert_eq!(session.is_reachable(&target), is_reachable(&nrs, &target, opts));
+                if opts.subset
+                {
+                    assert_eq!(expr.indices().count(), nearest.indices().count());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn all_expressions_for_value()
+    {
+        let nrs = [1, 3, 4, 6];
+        let opts = Options::default();
+        let session = Session::new(&nrs, &opts);
+        let exprs = session.expressions_for(24.into());
+        assert!(exprs.iter().any(|expr| expr.to_string(&nrs) == "6/(1-3/4)"));
+        assert!(exprs.iter().all(|expr| expr.val == 24.into() && expr.indices().count() == 4));
+        assert_eq!(session.expressions_for_up_to(24.into(), 1).len(), 1);
+        assert!(session.expressions_for(1000.into()).is_empty());
+    }
+}
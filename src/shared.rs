@@ -0,0 +1,248 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::{Expr, Idx, Options, MAX_NUMBERS};
+use crate::spill::entry_bytes;
+
+/// The stored entries of a shared cache
+#[derive(Default)]
+struct Entries
+{
+    /// The expressions, by numbers and search options
+    exprs: HashMap<String, Arc<Vec<Expr>>>,
+    /// The approximate number of bytes used by the expressions
+    bytes: usize
+}
+
+/// Sub-expressions shared between searches
+///
+/// Struct `SharedCache` stores cache entries by the values of the numbers
+/// they were made from, rather than by their positions in the numbers array,
+/// so that searches over different numbers can reuse each other's entries.
+/// E.g. when many puzzles are solved in a single process, as in batch or
+/// server mode, the expressions over numbers `25, 50, 75` are only generated
+/// for the first puzzle containing these numbers. The entries are generated
+/// for the numbers in increasing order, so that the expressions found do not
+/// depend on which searches were run before. Since entries are also keyed by
+/// the search options that determine them, a shared cache can be used for
+/// searches with different options. Once the approximate size of the stored
+/// expressions reaches the limit, no more entries are added.
+pub struct SharedCache
+{
+    /// The maximum number of bytes of expressions to store
+    limit: usize,
+    /// The stored entries
+    entries: Mutex<Entries>
+}
+
+impl SharedCache
+{
+    /// Create a new shared cache, storing at most `limit` bytes of
+    /// expressions.
+    pub fn new(limit: usize) -> Self
+    {
+        SharedCache { limit, entries: Mutex::new(Entries::default()) }
+    }
+
+    /// The number of entries stored.
+    pub fn len(&self) -> usize
+    {
+        self.entries.lock().unwrap().exprs.len()
+    }
+
+    /// Check if no entries are stored.
+    pub fn is_empty(&self) -> bool
+    {
+        self.len() == 0
+    }
+
+    /// The expressions stored for the numbers `nrs[i]`, for the indices `i`
+    /// in `idxs`, and search options `opts`, if any, as expressions over
+    /// `nrs`.
+    pub(crate) fn load(&self, nrs: &[u64], idxs: &[Idx], opts: &Options) -> Option<Vec<Expr>>
+    {
+        let key = entry_key(nrs, idxs, opts);
+        let shared = self.entries.lock().unwrap().exprs.get(&key).cloned()?;
+        let mut exprs = shared.to_vec();
+        localize(nrs, idxs, &mut exprs);
+        Some(exprs)
+    }
+
+    /// Store the expressions `exprs` over `nrs` for the numbers `nrs[i]`, for
+    /// the indices `i` in `idxs`, made with search options `opts`.
+    ///
+    /// The indices should be in canonical order, as checked by
+    /// `is_canonical()`.
+    pub(crate) fn store(&self, nrs: &[u64], idxs: &[Idx], opts: &Options, exprs: &[Expr])
+    {
+        let key = entry_key(nrs, idxs, opts);
+        let bytes = entry_bytes(&key, exprs);
+        let fits = |entries: &Entries| {
+                entries.bytes + bytes <= self.limit && !entries.exprs.contains_key(&key)
+            };
+        if !fits(&self.entries.lock().unwrap())
+        {
+            return;
+        }
+
+        // Convert the expressions before taking the lock again, so that other
+        // searches are not kept waiting
+        let vals = sorted_numbers(nrs, idxs);
+        let mut sorted = [0; MAX_NUMBERS];
+        for &idx in idxs.iter()
+        {
+            sorted[idx as usize] = vals.partition_point(|&nr| nr < nrs[idx as usize]) as Idx;
+        }
+        let exprs = exprs.iter()
+            .map(|expr| {
+                let ops = expr.ops.iter()
+                    .map(|&op| if (op as usize) < MAX_NUMBERS { sorted[op as usize] } else { op })
+                    .collect();
+                Expr { ops, val: expr.val, max: expr.max }
+            })
+            .collect();
+
+        let mut entries = self.entries.lock().unwrap();
+        if fits(&entries)
+        {
+            entries.exprs.insert(key, Arc::new(exprs));
+            entries.bytes += bytes;
+        }
+    }
+}
+
+impl ::std::fmt::Debug for SharedCache
+{
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+    {
+        f.debug_struct("SharedCache")
+            .field("limit", &self.limit)
+            .field("entries", &self.len())
+            .finish()
+    }
+}
+
+/// The numbers `nrs[i]`, for the indices `i` in `idxs`, in increasing order.
+pub(crate) fn sorted_numbers(nrs: &[u64], idxs: &[Idx]) -> Vec<u64>
+{
+    let mut vals = idxs.iter().map(|&i| nrs[i as usize]).collect::<Vec<_>>();
+    vals.sort_unstable();
+    vals
+}
+
+/// Check if the indices `idxs` are in canonical order.
+///
+/// The indices are in canonical order if their numbers in `nrs` are in
+/// increasing order, and equal numbers have equal indices, as returned by
+/// `unique_indices()`. The expressions generated for such indices are the
+/// same as those for the sorted numbers themselves.
+pub(crate) fn is_canonical(nrs: &[u64], idxs: &[Idx]) -> bool
+{
+    idxs.windows(2).all(|pair| {
+            let (nr0, nr1) = (nrs[pair[0] as usize], nrs[pair[1] as usize]);
+            (pair[0] == pair[1] && nr0 == nr1) || (pair[0] < pair[1] && nr0 < nr1)
+        })
+}
+
+/// Convert the expressions `exprs` over the sorted numbers of `idxs`, as
+/// returned by `sorted_numbers()`, in place into expressions over `nrs`.
+/// Equal numbers are mapped to the first of their indices in `idxs`.
+pub(crate) fn localize(nrs: &[u64], idxs: &[Idx], exprs: &mut [Expr])
+{
+    let vals = sorted_numbers(nrs, idxs);
+    let mut local = [0; MAX_NUMBERS];
+    for &idx in idxs.iter().rev()
+    {
+        local[vals.partition_point(|&nr| nr < nrs[idx as usize])] = idx;
+    }
+
+    for op in exprs.iter_mut().flat_map(|expr| expr.ops.iter_mut())
+    {
+        if (*op as usize) < MAX_NUMBERS
+        {
+            *op = local[*op as usize];
+        }
+    }
+}
+
+/// The key of the entry for the numbers `nrs[i]`, for the indices `i` in
+/// `idxs`, made with search options `opts`.
+///
+/// Besides the numbers, the key holds the options that determine the
+/// expressions generated for them.
+fn entry_key(nrs: &[u64], idxs: &[Idx], opts: &Options) -> String
+{
+    let binary_ops = opts.binary_ops.iter().map(|op| op.symbol()).collect::<Vec<_>>();
+    let unary_ops = opts.unary_ops.iter().map(|op| op.symbol()).collect::<Vec<_>>();
+    format!("{:?} {} {:?} {} {:?} {:?} {:?} {:?} {}", sorted_numbers(nrs, idxs),
+        opts.integers_only, opts.max_ops, opts.prune_dominated && opts.subset, opts.shape,
+        opts.forbidden_values, binary_ops, unary_ops, opts.max_unary_depth)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{distinct_values, unique_indices, Cache};
+
+    #[test]
+    fn canonical_indices()
+    {
+        let nrs = [7, 3, 3, 9];
+        assert_eq!(sorted_numbers(&nrs, &[0, 1, 3]), [3, 7, 9]);
+        assert!(is_canonical(&[3, 3, 7, 9], &[0, 0, 2, 3]));
+        assert!(is_canonical(&nrs, &[1, 1, 3]));
+        assert!(!is_canonical(&nrs, &[0, 1]));
+        assert!(!is_canonical(&nrs, &[1, 2]));
+    }
+
+    #[test]
+    fn load_localizes_stored_expressions()
+    {
+        let opts = Options::default();
+        let shared = SharedCache::new(1 << 20);
+        let nrs = [3, 5, 5];
+        let idxs = unique_indices(&nrs);
+        let exprs = distinct_values(&nrs, &idxs, &opts, &mut Cache::default());
+        shared.store(&nrs, &idxs, &opts, &exprs);
+        assert_eq!(shared.len(), 1);
+
+        let other = [5, 8, 3, 5];
+        let loaded = shared.load(&other, &[0, 0, 2], &opts).unwrap();
+        assert_eq!(loaded.len(), exprs.len());
+        for (expr, local) in exprs.iter().zip(loaded.iter())
+        {
+            let val = Expr::from_ops(local.ops.clone(), &other, &opts).map(|e| e.val);
+            assert_eq!(val, Some(expr.val));
+        }
+        let integers = Options { integers_only: true, ..Options::default() };
+        assert!(shared.load(&other, &[0, 0, 2], &integers).is_none());
+        assert!(shared.load(&other, &[0, 2], &opts).is_none());
+    }
+
+    #[test]
+    fn store_respects_limit()
+    {
+        let opts = Options::default();
+        let shared = SharedCache::new(16);
+        let nrs = [3, 5, 7];
+        let idxs = unique_indices(&nrs);
+        let exprs = distinct_values(&nrs, &idxs, &opts, &mut Cache::default());
+        shared.store(&nrs, &idxs, &opts, &exprs);
+        assert!(shared.is_empty());
+    }
+}
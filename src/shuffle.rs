@@ -0,0 +1,98 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::Idx;
+
+/// Simple deterministic pseudo-random number generator
+///
+/// Struct `SplitMix` implements the SplitMix64 generator. It is not suitable
+/// for anything requiring statistical rigor, but it is fast and more than
+/// good enough for varying the order in which the search visits expressions.
+pub(crate) struct SplitMix
+{
+    state: u64
+}
+
+impl SplitMix
+{
+    /// Create a new generator for a given seed, and set of indices.
+    ///
+    /// The generator is seeded with `seed` mixed with the indices in `idxs`,
+    /// so that different sets of numbers are shuffled differently, while the
+    /// order for a given set only depends on the seed.
+    pub(crate) fn new(seed: u64, idxs: &[Idx]) -> Self
+    {
+        let mut rng = SplitMix { state: seed };
+        for &idx in idxs.iter()
+        {
+            rng.state = rng.next_u64() ^ idx as u64;
+        }
+        rng
+    }
+
+    /// Generate the next pseudo-random number.
+    pub(crate) fn next_u64(&mut self) -> u64
+    {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Randomly permute `items`, using a Fisher-Yates shuffle.
+    pub(crate) fn shuffle<T>(&mut self, items: &mut [T])
+    {
+        for i in (1..items.len()).rev()
+        {
+            let j = (self.next_u64() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use alloc::vec::Vec;
+    use super::*;
+    use crate::{get_nearest_expression, Options, Target};
+
+    #[test]
+    fn shuffle_is_deterministic_permutation()
+    {
+        let mut items = (0..20).collect::<Vec<u32>>();
+        SplitMix::new(42, &[0, 1, 2]).shuffle(&mut items);
+        let mut again = (0..20).collect::<Vec<u32>>();
+        SplitMix::new(42, &[0, 1, 2]).shuffle(&mut again);
+        assert_eq!(items, again);
+        assert_ne!(items, (0..20).collect::<Vec<_>>());
+        items.sort_unstable();
+        assert_eq!(items, (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn shuffled_search_keeps_distance()
+    {
+        let nrs = [25, 50, 75, 100, 3];
+        for &target in [695, 811, 1].iter()
+        {
+            let target = Target::new(target);
+            let plain = get_nearest_expression(&nrs, &target, &Options::default());
+            let opts = Options { shuffle_seed: Some(7), ..Options::default() };
+            let shuffled = get_nearest_expression(&nrs, &target, &opts);
+            assert_eq!(target.distance(shuffled.val), target.distance(plain.val));
+        }
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{Expr, Op, Options, Shape, ADD, DIV, MAX_NUMBERS, MUL, SUB};
+use crate::operator::unary_op_index;
+
+/// Expression tree
+///
+/// Enum `Node` is a node in the tree form of an expression, which is more
+/// convenient for rewriting than reverse polish notation.
+#[derive(Clone, PartialEq, Eq)]
+enum Node
+{
+    /// A single number, by index
+    Leaf(Op),
+    /// A unary operation on a sub-expression
+    Un(Op, Box<Node>),
+    /// An operation on two sub-expressions
+    Bin(Op, Box<Node>, Box<Node>)
+}
+
+impl Node
+{
+    /// Build the tree for the expression in reverse polish notation `ops`.
+    fn from_ops(ops: &[Op]) -> Self
+    {
+        let mut stack = vec![];
+        for &op in ops.iter()
+        {
+            if (op as usize) < MAX_NUMBERS
+            {
+                stack.push(Node::Leaf(op));
+            }
+            else if unary_op_index(op).is_some()
+            {
+                let a = stack.pop().unwrap();
+                stack.push(Node::Un(op, Box::new(a)));
+            }
+            else
+            {
+                let b = stack.pop().unwrap();
+                let a = stack.pop().unwrap();
+                stack.push(Node::Bin(op, Box::new(a), Box::new(b)));
+            }
+        }
+        stack.pop().unwrap()
+    }
+
+    /// Append the reverse polish notation of this tree to `ops`.
+    fn to_ops(&self, ops: &mut Vec<Op>)
+    {
+        match self
+        {
+            Node::Leaf(idx) => ops.push(*idx),
+            Node::Un(op, a) => {
+                a.to_ops(ops);
+                ops.push(*op);
+            },
+            Node::Bin(op, a, b) => {
+                a.to_ops(ops);
+                b.to_ops(ops);
+                ops.push(*op);
+            }
+        }
+    }
+
+    /// Shorthand for creating an operation node.
+    fn bin(op: Op, a: &Node, b: &Node) -> Self
+    {
+        Node::Bin(op, Box::new(a.clone()), Box::new(b.clone()))
+    }
+
+    /// The rewrites of the root of this tree that remove a pair of
+    /// parentheses, in order of preference.
+    fn rewrites(&self) -> Vec<Node>
+    {
+        let (op, a, rhs) = match self
+            {
+                Node::Bin(op, a, rhs) => (*op, a, rhs),
+                _ => return vec![]
+            };
+        let (rop, b, c) = match &**rhs
+            {
+                Node::Bin(rop, b, c) => (*rop, b, c),
+                _ => return vec![]
+            };
+
+        match (op, rop)
+        {
+            // a - (b - c) = a - b + c = a + c - b
+            (SUB, SUB) => vec![
+                Node::bin(ADD, &Node::bin(SUB, a, b), c),
+                Node::bin(SUB, &Node::bin(ADD, a, c), b)
+            ],
+            // a - (b + c) = a - b - c
+            (SUB, ADD) => vec![Node::bin(SUB, &Node::bin(SUB, a, b), c)],
+            // a + (b - c) = a + b - c
+            (ADD, SUB) => vec![Node::bin(SUB, &Node::bin(ADD, a, b), c)],
+            // a / (b / c) = a * c / b
+            (DIV, DIV) => vec![Node::bin(DIV, &Node::bin(MUL, a, c), b)],
+            // a * (b / c) = a * b / c
+            (MUL, DIV) => vec![Node::bin(DIV, &Node::bin(MUL, a, b), c)],
+            _ => vec![]
+        }
+    }
+
+    /// Apply the first acceptable rewrite anywhere in this tree.
+    ///
+    /// Rewrites are tried at the root first, and then in the sub-trees. A
+    /// rewrite is only acceptable if `accept` returns true for the tree that
+    /// results from it. Returns `None` if no acceptable rewrite is found.
+    fn rewrite_once(&self, accept: &mut dyn FnMut(&Node) -> bool) -> Option<Node>
+    {
+        for node in self.rewrites()
+        {
+            if accept(&node)
+            {
+                return Some(node);
+            }
+        }
+
+        if let Node::Un(op, a) = self
+        {
+            let mut accept_a = |n: &Node| accept(&Node::Un(*op, Box::new(n.clone())));
+            return a.rewrite_once(&mut accept_a).map(|node| Node::Un(*op, Box::new(node)));
+        }
+
+        if let Node::Bin(op, a, b) = self
+        {
+            let mut accept_a = |n: &Node| accept(&Node::bin(*op, n, b));
+            if let Some(node) = a.rewrite_once(&mut accept_a)
+            {
+                return Some(Node::bin(*op, &node, b));
+            }
+            let mut accept_b = |n: &Node| accept(&Node::bin(*op, a, n));
+            if let Some(node) = b.rewrite_once(&mut accept_b)
+            {
+                return Some(Node::bin(*op, a, &node));
+            }
+        }
+
+        None
+    }
+}
+
+/// Simplify an expression.
+///
+/// Apply value-preserving rewrites to expression `expr` over the numbers array
+/// `nrs` that remove parentheses, e.g. `a-(b-c)` becomes `a-b+c`, and
+/// `a/(b/c)` becomes `a*c/b`. Every rewrite is checked by evaluating the
+/// rewritten expression subject to the options in `opts`, and is only kept if
+/// the expression is still valid, and its value is unchanged. Since the
+/// rewrites change the shape of the expression tree, the expression is
+/// returned unchanged if `opts.shape` constrains the shape.
+pub fn simplify(expr: &Expr, nrs: &[u64], opts: &Options) -> Expr
+{
+    if expr.is_empty() || opts.shape != Shape::Any
+    {
+        return expr.clone();
+    }
+
+    let mut accept = |node: &Node| {
+            let mut ops = vec![];
+            node.to_ops(&mut ops);
+            Expr::from_ops(ops, nrs, opts).is_some_and(|e| e.val == expr.val)
+        };
+    let mut tree = Node::from_ops(&expr.ops);
+    while let Some(node) = tree.rewrite_once(&mut accept)
+    {
+        tree = node;
+    }
+
+    let mut ops = vec![];
+    tree.to_ops(&mut ops);
+    Expr::from_ops(ops, nrs, opts).unwrap_or_else(|| expr.clone())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use alloc::string::String;
+    use super::*;
+    use crate::parse;
+
+    fn simplified(s: &str, nrs: &[u64], opts: &Options) -> String
+    {
+        simplify(&parse(s, nrs).unwrap(), nrs, opts).to_string(nrs)
+    }
+
+    #[test]
+    fn remove_parentheses()
+    {
+        let opts = Options::default();
+        assert_eq!(simplified("7-(5-2)", &[2, 5, 7], &opts), "7-5+2");
+        assert_eq!(simplified("8/(4/2)", &[2, 4, 8], &opts), "8*2/4");
+        assert_eq!(simplified("6/(1-3/4)", &[1, 3, 4, 6], &opts), "6/(1-3/4)");
+    }
+
+    #[test]
+    fn keep_value_and_shape()
+    {
+        // Not 2-5+4, which has a negative intermediate result
+        let opts = Options::default();
+        assert_eq!(simplified("2-(5-4)", &[2, 4, 5], &opts), "2+4-5");
+        let opts = Options { shape: Shape::Chain, ..Options::default() };
+        assert_eq!(simplified("7-(5-2)", &[2, 5, 7], &opts), "7-(5-2)");
+    }
+}
@@ -0,0 +1,465 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use alloc::vec::Vec;
+use arrayvec::ArrayVec;
+use core::convert::TryInto;
+use num_traits::{One, Zero};
+
+use crate::expr::apply;
+use crate::target::Window;
+use crate::{Expr, Idx, Op, Options, Rat, Shape, Target, ADD, DIV, MUL, SUB};
+
+/// The number of combinations the fixed-size search tries before giving up in
+/// favour of the general search
+const MAX_SMALL_STEPS: usize = 1 << 16;
+
+/// A number or intermediate result in the fixed-size search
+#[derive(Clone, Copy)]
+struct Slot
+{
+    /// The value of the sub-expression
+    val: Rat,
+    /// The node of the expression tree computing the value
+    node: u8,
+    /// The number of input numbers used in the sub-expression
+    count: u8,
+    /// The smallest position of the input numbers used in the sub-expression
+    first: u8
+}
+
+/// Search over a fixed number of input numbers
+///
+/// Struct `SmallSearch` finds the expression nearest to a target for exactly
+/// `N` input numbers, by repeatedly replacing two of the remaining values by
+/// a combination of them, depth first. All state is kept in fixed-size arrays
+/// on the stack: node `i < N` of the expression tree is the input number
+/// `nrs[idxs[i]]`, and node `N + k` is the operation `nodes[k]` on two other
+/// nodes. Two successive combinations that are independent of each other
+/// give the same expressions in either order, so they are only tried in the
+/// order of the first positions of the numbers they use. Unlike the general
+/// search, this search does not build the lists of sub-expressions for every
+/// subset of the numbers, so that it usually finds an exact expression within
+/// a fraction of the time. It does not remove duplicate values however, which
+/// makes an exhaustive search slower.
+struct SmallSearch<'a, const N: usize>
+{
+    /// The input numbers array
+    nrs: &'a [u64],
+    /// The indices of the numbers used in the search
+    idxs: &'a [Idx; N],
+    /// The target to approach
+    target: &'a Target,
+    /// The search options
+    opts: &'a Options,
+    /// The operations of the intermediate results, with the nodes of
+    /// their operands
+    nodes: [(Op, u8, u8); N],
+    /// The operations of the best expression found thus far
+    best_nodes: [(Op, u8, u8); N],
+    /// The root node, distance to the target, and number count of the best
+    /// expression found thus far
+    best: Option<(u8, Rat, u8)>,
+    /// The values closer to the target than the best expression
+    window: Window,
+    /// Whether the search is done, because an exact expression was found,
+    /// the search was cancelled, or too many combinations were tried
+    done: bool,
+    /// The number of combinations tried
+    steps: usize,
+    /// If true, the search stops at the first exact expression, and does not
+    /// give up
+    any_exact: bool
+}
+
+impl<'a, const N: usize> SmallSearch<'a, N>
+{
+    /// Consider the value in `slot` as a result of the search.
+    fn consider(&mut self, slot: Slot)
+    {
+        if !self.opts.subset && slot.count as usize != N
+        {
+            return;
+        }
+
+        // Of equally good expressions, the one using the fewest numbers is
+        // preferred
+        let better = self.window.contains(slot.val) || self.best.is_some_and(|(_, diff, count)| {
+                slot.count < count && self.target.distance(slot.val) == diff
+            });
+        if better
+        {
+            let diff = self.target.distance(slot.val);
+            self.best = Some((slot.node, diff, slot.count));
+            self.best_nodes = self.nodes;
+            self.window = self.target.window(Some(diff));
+            if diff.is_zero()
+            {
+                self.done = !self.opts.subset || slot.count == 1 || self.any_exact;
+            }
+        }
+    }
+
+    /// Check if combining two values using `count` numbers in total may still
+    /// lead to a better expression.
+    fn count_allowed(&self, count: u8) -> bool
+    {
+        if self.opts.max_ops.is_some_and(|max_ops| count as usize - 1 > max_ops)
+        {
+            return false;
+        }
+        // After an exact expression is found, only expressions using fewer
+        // numbers are better
+        !self.best.is_some_and(|(_, diff, best_count)| diff.is_zero() && count >= best_count)
+    }
+
+    /// The operation computing the value in `slot`, or the index of the
+    /// number if it is an input number.
+    fn top_op(&self, slot: Slot) -> Op
+    {
+        let node = slot.node as usize;
+        if node < N { self.idxs[node] } else { self.nodes[node - N].0 }
+    }
+
+    /// Search all ways to combine the first `len` values in `slots`, where
+    /// the previous combination used numbers from position `last` onwards.
+    fn search(&mut self, slots: &mut [Slot; N], len: usize, last: u8)
+    {
+        let node = (2 * N - len) as u8;
+        for i in 0..len
+        {
+            for j in i+1..len
+            {
+                if len + 1 >= N && self.opts.cancelled()
+                {
+                    self.done = true;
+                }
+                if self.done
+                {
+                    return;
+                }
+
+                let (a, b) = (slots[i], slots[j]);
+                let (hi, lo) = if a.val >= b.val { (a, b) } else { (b, a) };
+                let count = a.count + b.count;
+                let first = a.first.min(b.first);
+                if len < N && a.node != node - 1 && b.node != node - 1 && first < last
+                {
+                    continue;
+                }
+                if !self.count_allowed(count)
+                {
+                    continue;
+                }
+
+                // Combinations that can be rewritten into a different order
+                // of the same operations are skipped, e.g. (a-b)+c is found
+                // as (a+c)-b, and a/(b*c) as a/b/c
+                let (hi_op, lo_op) = (self.top_op(hi), self.top_op(lo));
+                let mut combs = ArrayVec::<(Op, Slot, Slot), 5>::new();
+                if hi_op != SUB && lo_op != SUB && (hi_op != ADD || lo_op != ADD)
+                {
+                    combs.push((ADD, hi, lo));
+                }
+                if lo_op != ADD && lo_op != SUB
+                {
+                    combs.push((SUB, hi, lo));
+                }
+                if hi_op != DIV && lo_op != DIV && (hi_op != MUL || lo_op != MUL)
+                {
+                    combs.push((MUL, hi, lo));
+                }
+                // Dividing by one gives the same value as multiplying by one
+                if !lo.val.is_zero() && !lo.val.is_one() && lo_op != MUL && lo_op != DIV
+                {
+                    combs.push((DIV, hi, lo));
+                }
+                if !hi.val.is_zero() && hi.val != lo.val && hi_op != MUL && hi_op != DIV
+                {
+                    combs.push((DIV, lo, hi));
+                }
+
+                slots[j] = slots[len-1];
+                for (op, lhs, rhs) in combs
+                {
+                    let val = match apply_small(op, lhs.val, rhs.val, self.opts)
+                        {
+                            Some(val) => val,
+                            None      => continue
+                        };
+
+                    self.steps += 1;
+                    if self.steps > MAX_SMALL_STEPS && !self.any_exact
+                    {
+                        self.done = true;
+                        return;
+                    }
+                    self.nodes[node as usize - N] = (op, lhs.node, rhs.node);
+                    slots[i] = Slot { val, node, count, first };
+                    self.consider(slots[i]);
+                    if len > 2
+                    {
+                        self.search(slots, len - 1, first);
+                    }
+                    if self.done
+                    {
+                        return;
+                    }
+                }
+                slots[i] = a;
+                slots[j] = b;
+            }
+        }
+    }
+
+    /// Append the operations of the sub-expression at `node` in the best
+    /// expression to `ops`.
+    fn push_ops(&self, node: u8, ops: &mut Vec<Op>)
+    {
+        let node = node as usize;
+        if node < N
+        {
+            ops.push(self.idxs[node]);
+        }
+        else
+        {
+            let (op, lhs, rhs) = self.best_nodes[node - N];
+            self.push_ops(lhs, ops);
+            self.push_ops(rhs, ops);
+            ops.push(op);
+        }
+    }
+
+    /// The best expression found thus far, or the empty expression if none
+    /// has been found.
+    fn expression(&self) -> Expr
+    {
+        match self.best
+        {
+            Some((root, _, _)) => {
+                let mut ops = Vec::with_capacity(2 * N - 1);
+                self.push_ops(root, &mut ops);
+                Expr::from_ops(ops, self.nrs, self.opts).unwrap_or_else(Expr::empty)
+            },
+            None => Expr::empty()
+        }
+    }
+}
+
+/// Apply operation `op` to `a` and `b`.
+///
+/// As `apply()`, for the basic operations only, and for operands that are
+/// known to be valid for `op`. Since most values in a search are integers,
+/// the arithmetic on integers avoids the reduction of fractions.
+fn apply_small(op: Op, a: Rat, b: Rat, opts: &Options) -> Option<Rat>
+{
+    if !a.is_integer() || !b.is_integer()
+    {
+        return apply(op, a, b, opts);
+    }
+
+    let (a, b) = (*a.numer(), *b.numer());
+    match op
+    {
+        ADD => Some(Rat::from_integer(a + b)),
+        SUB => Some(Rat::from_integer(a - b)),
+        MUL => Some(Rat::from_integer(a * b)),
+        _ if a % b == 0 => Some(Rat::from_integer(a / b)),
+        _ if opts.integers_only => None,
+        _ => Some(Rat::new(a, b))
+    }
+}
+
+/// Find the expression nearest to target for exactly `N` numbers.
+///
+/// Find an arithmetic expression using the numbers `nrs[i]` for the indices `i`
+/// in `idxs`, that evaluates to a number as close as possible to `target`,
+/// using fixed-size arrays only. If `any_exact` is true, the first exact
+/// expression found is returned, even if `opts.subset` is set and a different
+/// expression uses fewer numbers. Returns `None` if the search gives up before
+/// it is complete.
+fn nearest<const N: usize>(nrs: &[u64], idxs: &[Idx; N], target: &Target, opts: &Options,
+    any_exact: bool) -> Option<Expr>
+{
+    let mut slots = [Slot { val: Rat::zero(), node: 0, count: 1, first: 0 }; N];
+    let mut search = SmallSearch {
+            nrs,
+            idxs,
+            target,
+            opts,
+            nodes: [(0, 0, 0); N],
+            best_nodes: [(0, 0, 0); N],
+            best: None,
+            window: target.window(None),
+            done: false,
+            steps: 0,
+            any_exact
+        };
+    for (i, slot) in slots.iter_mut().enumerate()
+    {
+        let val = Rat::from_integer(nrs[idxs[i] as usize]);
+        *slot = Slot { val, node: i as u8, count: 1, first: i as u8 };
+        search.consider(*slot);
+    }
+    search.search(&mut slots, N, 0);
+    if search.steps > MAX_SMALL_STEPS && !any_exact
+    {
+        None
+    }
+    else
+    {
+        Some(search.expression())
+    }
+}
+
+/// Check if the fixed-size search can be used for options `opts`, i.e. if
+/// only the basic operations are used, and the search is not constrained or
+/// observed in a way the fixed-size search does not support. The fixed-size
+/// search does not print intermediate results, so it is not used when these
+/// are asked for.
+fn supported(opts: &Options) -> bool
+{
+    let supported = opts.shape == Shape::Any
+        && opts.forbidden_values.is_empty()
+        && opts.shuffle_seed.is_none()
+        && opts.binary_ops.is_empty()
+        && opts.unary_ops.is_empty()
+        && opts.progress.is_none()
+        && opts.observer.is_none()
+        && !opts.print_intermediate;
+    #[cfg(feature = "std")]
+    let supported = supported && opts.cache_counters.is_none() && opts.trace.is_none();
+    supported
+}
+
+/// Find the expression nearest to target for a few numbers.
+///
+/// If the options in `opts` allow it, and `idxs` indexes at least two and at
+/// most six numbers, find an arithmetic expression using the
+/// numbers `nrs[i]` for the indices `i` in `idxs` that evaluates to a number
+/// as close as possible to `target`, using a search that works on fixed-size
+/// arrays only. Returns `None` if the fixed-size search cannot be used, or
+/// gives up because it takes too long, in which case the general search
+/// should be used.
+pub(crate) fn get_nearest_expression_small(nrs: &[u64], idxs: &[Idx], target: &Target,
+    opts: &Options) -> Option<Expr>
+{
+    nearest_small(nrs, idxs, target, opts, false)
+}
+
+/// Find an exact expression for a few numbers.
+///
+/// As `get_nearest_expression_small()`, but the first expression found that
+/// hits `target` exactly is returned, even if an expression using fewer
+/// numbers exists, and the search does not give up. This is meant for finding
+/// a witness for a target that is known to be reachable. Returns `None` if
+/// the fixed-size search cannot be used, or if no exact expression exists.
+#[cfg(feature = "std")]
+pub(crate) fn exact_expression_small(nrs: &[u64], idxs: &[Idx], target: &Target,
+    opts: &Options) -> Option<Expr>
+{
+    nearest_small(nrs, idxs, target, opts, true).filter(|expr| target.contains(expr.val))
+}
+
+/// Check if a target can be hit exactly with a few numbers.
+///
+/// As `exact_expression_small()`, but only whether an exact expression exists
+/// is returned. Returns `None` if the fixed-size search cannot be used.
+pub(crate) fn reachable_small(nrs: &[u64], idxs: &[Idx], target: &Target, opts: &Options)
+    -> Option<bool>
+{
+    nearest_small(nrs, idxs, target, opts, true).map(|expr| target.contains(expr.val))
+}
+
+/// Dispatch the fixed-size search for the numbers indexed by `idxs`.
+fn nearest_small(nrs: &[u64], idxs: &[Idx], target: &Target, opts: &Options, any_exact: bool)
+    -> Option<Expr>
+{
+    if !supported(opts)
+    {
+        return None;
+    }
+
+    match idxs.len()
+    {
+        2 => nearest::<2>(nrs, idxs.try_into().ok()?, target, opts, any_exact),
+        3 => nearest::<3>(nrs, idxs.try_into().ok()?, target, opts, any_exact),
+        4 => nearest::<4>(nrs, idxs.try_into().ok()?, target, opts, any_exact),
+        5 => nearest::<5>(nrs, idxs.try_into().ok()?, target, opts, any_exact),
+        6 => nearest::<6>(nrs, idxs.try_into().ok()?, target, opts, any_exact),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use alloc::vec;
+    use super::*;
+    use crate::{get_nearest_expression_cached, unique_indices, Cache};
+
+    const PUZZLES: [(&[u64], u64); 7] = [
+        (&[1, 3, 4, 6], 24),
+        (&[3, 3, 8, 8], 24),
+        (&[2, 2, 2, 2], 17),
+        (&[1, 1, 1, 1], 30),
+        (&[25, 50, 75, 100, 3], 695),
+        (&[4, 7, 7, 9, 10], 811),
+        (&[1, 2, 3, 4, 5, 6], 997)
+    ];
+
+    #[test]
+    fn small_matches_general_search()
+    {
+        let variants = [
+            Options::default(),
+            Options { subset: true, ..Options::default() },
+            Options { integers_only: true, ..Options::default() }
+        ];
+        for opts in variants.iter()
+        {
+            for &(nrs, target) in PUZZLES.iter()
+            {
+                let idxs = unique_indices(nrs);
+                for target in [Target::new(target), Target::range(target - 3, target + 3)].iter()
+                {
+                    let small = match get_nearest_expression_small(nrs, &idxs, target, opts)
+                        {
+                            Some(expr) => expr,
+                            None       => continue
+                        };
+                    let general = get_nearest_expression_cached(nrs, &idxs, target, opts,
+                        &mut Cache::default());
+                    assert_eq!(target.distance(small.val), target.distance(general.val),
+                        "{:?} for {:?}", nrs, target);
+                    let ops = small.ops.clone();
+                    assert_eq!(Expr::from_ops(ops, nrs, opts).map(|e| e.val), Some(small.val));
+                    assert_eq!(reachable_small(nrs, &idxs, target, opts),
+                        Some(target.contains(general.val)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_options()
+    {
+        let opts = Options { print_intermediate: true, ..Options::default() };
+        let idxs = vec![0, 1];
+        assert!(get_nearest_expression_small(&[2, 3], &idxs, &Target::new(6), &opts).is_none());
+        let opts = Options { shape: Shape::Chain, ..Options::default() };
+        assert!(get_nearest_expression_small(&[2, 3], &idxs, &Target::new(6), &opts).is_none());
+    }
+}
@@ -0,0 +1,526 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::{format, vec};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+use crate::{builtin_binary_op, builtin_unary_op, get_nearest_expression, BinaryOp,
+    CancellationToken, Expr, Observer, Options, Progress, Rat, Shape, Solution, Stats, Target,
+    UnaryOp, MAX_BINARY_OPS, MAX_NUMBERS, MAX_UNARY_OPS};
+
+/// Error in building a solver
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BuildError
+{
+    /// No input numbers were given
+    NoNumbers,
+    /// More input numbers were given than can be encoded, the number given
+    TooManyNumbers(usize),
+    /// No target was given
+    NoTarget,
+    /// The number of threads is zero
+    NoThreads
+}
+
+impl ::core::fmt::Display for BuildError
+{
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result
+    {
+        match self
+        {
+            BuildError::NoNumbers => write!(f, "no input numbers given"),
+            BuildError::TooManyNumbers(count) => {
+                write!(f, "too many input numbers: {}, at most {} are allowed", count, MAX_NUMBERS)
+            },
+            BuildError::NoTarget => write!(f, "no target given"),
+            BuildError::NoThreads => write!(f, "the number of threads should be at least one")
+        }
+    }
+}
+
+impl ::core::error::Error for BuildError {}
+
+/// Set of operations allowed in a search
+///
+/// Struct `OpSet` holds the custom operations that may be used in a search in
+/// addition to the four basic operations, which are always allowed. The
+/// default set contains only the basic operations.
+#[derive(Clone, Debug, Default)]
+pub struct OpSet
+{
+    /// Custom binary operations
+    binary_ops: Vec<Arc<dyn BinaryOp>>,
+    /// Custom unary operations
+    unary_ops: Vec<Arc<dyn UnaryOp>>,
+    /// The maximum number of unary operations applied in succession
+    max_unary_depth: usize
+}
+
+impl OpSet
+{
+    /// Create a set of operations from a comma separated list of names of
+    /// operations provided by this library, like `"gcd,pow,dec"`.
+    pub fn from_names(names: &str) -> Result<Self, String>
+    {
+        let mut set = OpSet::default();
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty())
+        {
+            if let Some(op) = builtin_binary_op(name)
+            {
+                if set.binary_ops.len() >= MAX_BINARY_OPS
+                {
+                    return Err(String::from("too many custom binary operations"));
+                }
+                set.binary_ops.push(op);
+            }
+            else if let Some(op) = builtin_unary_op(name)
+            {
+                if set.unary_ops.len() >= MAX_UNARY_OPS
+                {
+                    return Err(String::from("too many custom unary operations"));
+                }
+                set.unary_ops.push(op);
+                set.max_unary_depth = set.max_unary_depth.max(1);
+            }
+            else
+            {
+                return Err(format!("unknown operation {}", name));
+            }
+        }
+        Ok(set)
+    }
+
+    /// Add the custom binary operation `op` to this set.
+    ///
+    /// At most `MAX_BINARY_OPS` custom binary operations can be added.
+    pub fn binary_op<T: BinaryOp + 'static>(mut self, op: T) -> Self
+    {
+        assert!(self.binary_ops.len() < MAX_BINARY_OPS, "too many custom binary operations");
+        self.binary_ops.push(Arc::new(op));
+        self
+    }
+
+    /// Add the custom unary operation `op` to this set.
+    ///
+    /// At most `MAX_UNARY_OPS` custom unary operations can be added. If the
+    /// maximum depth of unary operations is zero, it is set to one.
+    pub fn unary_op<T: UnaryOp + 'static>(mut self, op: T) -> Self
+    {
+        assert!(self.unary_ops.len() < MAX_UNARY_OPS, "too many custom unary operations");
+        self.unary_ops.push(Arc::new(op));
+        self.max_unary_depth = self.max_unary_depth.max(1);
+        self
+    }
+
+    /// Set the maximum number of unary operations applied in succession to a
+    /// single sub-expression.
+    pub fn max_unary_depth(mut self, depth: usize) -> Self
+    {
+        self.max_unary_depth = depth;
+        self
+    }
+}
+
+/// Configured search for an expression
+///
+/// Struct `Solver` holds the input numbers, the target and the options of a
+/// search. It is created with a `SolverBuilder`, e.g.
+/// ```
+/// use makeexpr::{OpSet, Solver};
+///
+/// let solver = Solver::builder().numbers(&[1, 3, 4, 6]).target(24).ops(OpSet::default())
+///     .build().unwrap();
+/// let expr = solver.solve();
+/// assert_eq!(expr.to_string(solver.numbers()), "6/(1-3/4)");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Solver
+{
+    /// The input numbers
+    nrs: Vec<u64>,
+    /// The target
+    target: Target,
+    /// The search options
+    pub(crate) opts: Options,
+    /// The number of threads the search may use
+    threads: usize,
+    /// The maximum time the search may take, if any
+    #[cfg(feature = "std")]
+    timeout: Option<Duration>
+}
+
+impl Solver
+{
+    /// Create a builder for a new solver.
+    pub fn builder() -> SolverBuilder
+    {
+        SolverBuilder::default()
+    }
+
+    /// The input numbers.
+    pub fn numbers(&self) -> &[u64]
+    {
+        &self.nrs
+    }
+
+    /// The target.
+    pub fn target(&self) -> &Target
+    {
+        &self.target
+    }
+
+    /// The search options.
+    pub fn options(&self) -> &Options
+    {
+        &self.opts
+    }
+
+    /// The number of threads the search may use.
+    pub fn threads(&self) -> usize
+    {
+        self.threads
+    }
+
+    /// Find the expression nearest to the target.
+    ///
+    /// If a timeout was set, the search is cancelled when it expires, and the
+    /// best expression found thus far is returned. Timeouts require the `std`
+    /// feature.
+    pub fn solve(&self) -> Expr
+    {
+        #[cfg(feature = "std")]
+        if let Some(timeout) = self.timeout
+        {
+            let opts = Options { deadline: Some(Instant::now() + timeout), ..self.opts.clone() };
+            return get_nearest_expression(&self.nrs, &self.target, &opts);
+        }
+        get_nearest_expression(&self.nrs, &self.target, &self.opts)
+    }
+
+    /// Find the expression nearest to the target, until cancelled.
+    ///
+    /// As `solve()`, but the search is cancelled when `token` is, e.g. from
+    /// another thread, instead of through the flag set with
+    /// `SolverBuilder::cancel()`. Returns the summary of the search, with the
+    /// best expression found before it was cancelled, if any, and whether it
+    /// was cancelled before it was completed.
+    pub fn solve_cancellable(&self, token: &CancellationToken) -> Stats
+    {
+        let inner = self.opts.observer.clone();
+        let counter = Arc::new(StepCounter { steps: AtomicUsize::new(0), inner });
+        let solver = Solver {
+                opts: Options {
+                    cancel: Some(token.flag()),
+                    observer: Some(counter.clone()),
+                    ..self.opts.clone()
+                },
+                ..self.clone()
+            };
+        let expr = solver.solve();
+        let best = if expr.is_empty()
+            {
+                None
+            }
+            else
+            {
+                Some(Solution { distance: self.target.distance(expr.val), expr })
+            };
+        Stats {
+            steps: counter.steps.load(Ordering::Relaxed),
+            best,
+            cancelled: solver.opts.cancelled()
+        }
+    }
+}
+
+/// Observer counting the steps of a search
+///
+/// Struct `StepCounter` counts the completed steps of a search, and passes
+/// all events on to the observer registered in the search options, if any.
+#[derive(Debug)]
+struct StepCounter
+{
+    /// The number of steps completed
+    steps: AtomicUsize,
+    /// The observer registered in the search options, if any
+    inner: Option<Arc<dyn Observer>>
+}
+
+impl Observer for StepCounter
+{
+    fn on_new_best(&self, solution: &Solution)
+    {
+        if let Some(inner) = self.inner.as_ref()
+        {
+            inner.on_new_best(solution);
+        }
+    }
+
+    fn on_partition_done(&self, mask: &[bool])
+    {
+        self.steps.fetch_add(1, Ordering::Relaxed);
+        if let Some(inner) = self.inner.as_ref()
+        {
+            inner.on_partition_done(mask);
+        }
+    }
+
+    fn on_finished(&self, stats: &Stats)
+    {
+        if let Some(inner) = self.inner.as_ref()
+        {
+            inner.on_finished(stats);
+        }
+    }
+}
+
+/// Builder for a `Solver`
+///
+/// Struct `SolverBuilder` configures a search step by step. The input numbers
+/// and the target are required, all other settings are optional, and default
+/// to those of `Options::default()`.
+#[derive(Clone, Debug)]
+pub struct SolverBuilder
+{
+    /// The input numbers
+    nrs: Vec<u64>,
+    /// The target, if set
+    target: Option<Target>,
+    /// The search options
+    opts: Options,
+    /// The number of threads the search may use
+    threads: usize,
+    /// The maximum time the search may take, if any
+    #[cfg(feature = "std")]
+    timeout: Option<Duration>
+}
+
+impl Default for SolverBuilder
+{
+    fn default() -> Self
+    {
+        SolverBuilder {
+            nrs: vec![],
+            target: None,
+            opts: Options::default(),
+            threads: 1,
+            #[cfg(feature = "std")]
+            timeout: None
+        }
+    }
+}
+
+impl SolverBuilder
+{
+    /// Set the input numbers.
+    pub fn numbers(mut self, nrs: &[u64]) -> Self
+    {
+        self.nrs = nrs.to_vec();
+        self
+    }
+
+    /// Set the target, either a `Target` or a single number.
+    pub fn target<T: Into<Target>>(mut self, target: T) -> Self
+    {
+        self.target = Some(target.into());
+        self
+    }
+
+    /// Set the operations allowed besides the four basic operations.
+    pub fn ops(mut self, ops: OpSet) -> Self
+    {
+        self.opts.binary_ops = ops.binary_ops;
+        self.opts.unary_ops = ops.unary_ops;
+        self.opts.max_unary_depth = ops.max_unary_depth;
+        self
+    }
+
+    /// Set whether all intermediate results should be integers.
+    pub fn integers_only(mut self, integers_only: bool) -> Self
+    {
+        self.opts.integers_only = integers_only;
+        self
+    }
+
+    /// Set the maximum number of binary operations in an expression.
+    pub fn max_ops(mut self, max_ops: usize) -> Self
+    {
+        self.opts.max_ops = Some(max_ops);
+        self
+    }
+
+    /// Set whether an expression may use a subset of the input numbers.
+    pub fn subset(mut self, subset: bool) -> Self
+    {
+        self.opts.subset = subset;
+        self
+    }
+
+    /// Set whether sub-expressions that can be made with fewer numbers are
+    /// pruned, see `Options::prune_dominated`.
+    pub fn prune_dominated(mut self, prune_dominated: bool) -> Self
+    {
+        self.opts.prune_dominated = prune_dominated;
+        self
+    }
+
+    /// Set the shape of the expression tree.
+    pub fn shape(mut self, shape: Shape) -> Self
+    {
+        self.opts.shape = shape;
+        self
+    }
+
+    /// Forbid proper sub-expressions evaluating to `val`.
+    pub fn forbid_value(mut self, val: Rat) -> Self
+    {
+        self.opts.forbidden_values.push(val);
+        self
+    }
+
+    /// Shuffle the search order using `seed`.
+    pub fn shuffle_seed(mut self, seed: u64) -> Self
+    {
+        self.opts.shuffle_seed = Some(seed);
+        self
+    }
+
+    /// Set the number of threads the search may use.
+    ///
+    /// By default, the search runs on the calling thread only. Threads require
+    /// the `std` feature.
+    pub fn threads(mut self, threads: usize) -> Self
+    {
+        self.threads = threads;
+        self
+    }
+
+    /// Set the maximum time a single search may take.
+    #[cfg(feature = "std")]
+    pub fn timeout(mut self, timeout: Duration) -> Self
+    {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set a flag through which the search can be cancelled.
+    pub fn cancel(mut self, cancel: Arc<AtomicBool>) -> Self
+    {
+        self.opts.cancel = Some(cancel);
+        self
+    }
+
+    /// Set a token through which the search can be cancelled.
+    pub fn cancellation_token(self, token: &CancellationToken) -> Self
+    {
+        self.cancel(token.flag())
+    }
+
+    /// Set an observer for the progress of the search.
+    pub fn progress(mut self, progress: Arc<dyn Progress>) -> Self
+    {
+        self.opts.progress = Some(progress);
+        self
+    }
+
+    /// Set an observer for the events of the search.
+    pub fn observer(mut self, observer: Arc<dyn Observer>) -> Self
+    {
+        self.opts.observer = Some(observer);
+        self
+    }
+
+    /// Use the search options `opts`, replacing all options set thus far.
+    pub fn options(mut self, opts: Options) -> Self
+    {
+        self.opts = opts;
+        self
+    }
+
+    /// Create the solver.
+    ///
+    /// Returns an error if the input numbers or the target are missing, if
+    /// there are too many input numbers, or if the number of threads is zero.
+    pub fn build(self) -> Result<Solver, BuildError>
+    {
+        if self.nrs.is_empty()
+        {
+            return Err(BuildError::NoNumbers);
+        }
+        if self.nrs.len() > MAX_NUMBERS
+        {
+            return Err(BuildError::TooManyNumbers(self.nrs.len()));
+        }
+        if self.threads == 0
+        {
+            return Err(BuildError::NoThreads);
+        }
+        let target = self.target.ok_or(BuildError::NoTarget)?;
+        #[cfg(feature = "std")]
+        let opts = Options { threads: self.threads, ..self.opts };
+        #[cfg(not(feature = "std"))]
+        let opts = self.opts;
+
+        Ok(Solver {
+            nrs: self.nrs,
+            target,
+            opts,
+            threads: self.threads,
+            #[cfg(feature = "std")]
+            timeout: self.timeout
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn build_errors()
+    {
+        let err = Solver::builder().target(24).build().unwrap_err();
+        assert_eq!(err, BuildError::NoNumbers);
+        let err = Solver::builder().numbers(&[1, 2]).build().unwrap_err();
+        assert_eq!(err, BuildError::NoTarget);
+        let err = Solver::builder().numbers(&[1, 2]).target(3).threads(0).build().unwrap_err();
+        assert_eq!(err, BuildError::NoThreads);
+        let nrs = vec![1; MAX_NUMBERS + 1];
+        let err = Solver::builder().numbers(&nrs).target(3).build().unwrap_err();
+        assert_eq!(err, BuildError::TooManyNumbers(MAX_NUMBERS + 1));
+    }
+
+    #[test]
+    fn solve_puzzle()
+    {
+        let solver = Solver::builder().numbers(&[1, 3, 4, 6]).target(24).build().unwrap();
+        let expr = solver.solve();
+        assert_eq!(expr.to_string(solver.numbers()), "6/(1-3/4)");
+
+        let solver = Solver::builder()
+            .numbers(&[2, 3])
+            .target(8)
+            .ops(OpSet::from_names("pow").unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(solver.solve().val, 8.into());
+        assert!(OpSet::from_names("pow,frobnicate").is_err());
+    }
+}
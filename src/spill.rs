@@ -0,0 +1,330 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{Cache, Expr, Idx, Rat};
+
+/// Counter for creating unique names for spill files
+static SPILL_FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// The approximate number of bytes used by the expressions in a cache entry.
+pub(crate) fn entry_bytes(key: &str, exprs: &[Expr]) -> usize
+{
+    key.len() + exprs.iter()
+        .map(|expr| ::std::mem::size_of_val(expr) + expr.ops.capacity())
+        .sum::<usize>()
+}
+
+/// Temporary file holding the spilled entries
+struct SpillFile
+{
+    /// The path of the file
+    path: PathBuf,
+    /// The open file
+    file: File,
+    /// The current length of the file
+    len: u64
+}
+
+/// A cache entry spilled to disk
+struct SpilledEntry
+{
+    /// The offset of the entry in the spill file
+    offset: u64,
+    /// The length of the entry in bytes
+    len: usize,
+    /// The input numbers of the search that made the entry
+    nrs: Vec<u64>,
+    /// The indices in `nrs` of the numbers of the entry
+    idxs: Vec<Idx>
+}
+
+/// Overflow storage for a cache of sub-expressions
+///
+/// Struct `SpillCache` limits the memory used by a `Cache`. When the
+/// approximate size of the expressions in the cache exceeds the limit, the
+/// largest entries are written to a temporary file and removed from the cache,
+/// and they are read back from the file whenever they are needed. This is
+/// much slower than keeping the expressions in memory, but allows a search
+/// over many numbers to complete on a machine with less memory. If an entry
+/// cannot be read back, e.g. because the file was truncated, its expressions
+/// are computed again. A `SpillCache` should only be used with a single
+/// cache. The temporary file is removed when the `SpillCache` is dropped.
+pub struct SpillCache
+{
+    /// The maximum number of bytes of expressions to keep in memory
+    limit: usize,
+    /// The spill file, created when the first entry is spilled
+    file: Mutex<Option<SpillFile>>,
+    /// The spilled entries, by key
+    index: Mutex<HashMap<String, SpilledEntry>>
+}
+
+impl SpillCache
+{
+    /// Create a new spill cache, keeping at most `limit` bytes of expressions
+    /// in memory.
+    pub fn new(limit: usize) -> Self
+    {
+        SpillCache { limit, file: Mutex::new(None), index: Mutex::new(HashMap::new()) }
+    }
+
+    /// The number of cache entries spilled to disk.
+    pub fn spilled_entries(&self) -> usize
+    {
+        self.index.lock().unwrap().len()
+    }
+
+    /// Check if the entry with key `key` was spilled.
+    pub(crate) fn contains(&self, key: &str) -> bool
+    {
+        self.index.lock().unwrap().contains_key(key)
+    }
+
+    /// The input numbers, and the indices of the numbers in them, for which
+    /// the entry with key `key` was made, if it was spilled. This is used to
+    /// compute the expressions again when the entry cannot be read back.
+    pub(crate) fn origin(&self, key: &str) -> Option<(Vec<u64>, Vec<Idx>)>
+    {
+        self.index.lock().unwrap().get(key).map(|entry| (entry.nrs.clone(), entry.idxs.clone()))
+    }
+
+    /// Read the spilled entry with key `key` back from disk, if it exists.
+    /// Returns `None` if the entry was not spilled, or if it cannot be read.
+    pub(crate) fn load(&self, key: &str) -> Option<Vec<Expr>>
+    {
+        let (offset, len) = self.index.lock().unwrap().get(key)
+            .map(|entry| (entry.offset, entry.len))?;
+        let mut buf = vec![0; len];
+        {
+            let mut guard = self.file.lock().unwrap();
+            let spill = guard.as_mut()?;
+            spill.file.seek(SeekFrom::Start(offset)).ok()?;
+            spill.file.read_exact(&mut buf).ok()?;
+        }
+        decode(&buf)
+    }
+
+    /// Spill entries from `cache`, made in a search over the numbers `nrs`,
+    /// until it fits within the memory limit.
+    ///
+    /// The largest entries are spilled first. Entries for a single number are
+    /// never spilled. If writing to the spill file fails, the remaining
+    /// entries are kept in memory.
+    pub(crate) fn spill_excess(&self, nrs: &[u64], cache: &mut Cache)
+    {
+        let mut total: usize = cache.iter().map(|(key, exprs)| entry_bytes(key, exprs)).sum();
+        if total <= self.limit
+        {
+            return;
+        }
+
+        let mut entries = cache.iter()
+            .filter(|(key, _)| key.contains('_'))
+            .map(|(key, exprs)| (entry_bytes(key, exprs), key.clone()))
+            .collect::<Vec<_>>();
+        entries.sort();
+        while total > self.limit
+        {
+            let (bytes, key) = match entries.pop()
+                {
+                    Some(entry) => entry,
+                    None        => break
+                };
+            let idxs = match key_indices(nrs, &key)
+                {
+                    Some(idxs) => idxs,
+                    None       => continue
+                };
+            if self.write(&key, nrs, idxs, &cache[&key]).is_err()
+            {
+                break;
+            }
+            cache.remove(&key);
+            total -= bytes;
+        }
+    }
+
+    /// Append the expressions `exprs` for key `key`, over the numbers in `nrs`
+    /// indexed by `idxs`, to the spill file.
+    fn write(&self, key: &str, nrs: &[u64], idxs: Vec<Idx>, exprs: &[Expr])
+        -> ::std::io::Result<()>
+    {
+        let buf = encode(exprs);
+        let mut guard = self.file.lock().unwrap();
+        if guard.is_none()
+        {
+            let count = SPILL_FILE_COUNT.fetch_add(1, Ordering::Relaxed);
+            let path = ::std::env::temp_dir()
+                .join(format!("makeexpr-spill-{}-{}", ::std::process::id(), count));
+            let file = File::options().read(true).write(true).create_new(true).open(&path)?;
+            *guard = Some(SpillFile { path, file, len: 0 });
+        }
+
+        let spill = guard.as_mut().unwrap();
+        spill.file.seek(SeekFrom::Start(spill.len))?;
+        spill.file.write_all(&buf)?;
+        let entry = SpilledEntry { offset: spill.len, len: buf.len(), nrs: nrs.to_vec(), idxs };
+        self.index.lock().unwrap().insert(key.to_string(), entry);
+        spill.len += buf.len() as u64;
+        Ok(())
+    }
+}
+
+impl Drop for SpillCache
+{
+    fn drop(&mut self)
+    {
+        if let Some(spill) = self.file.get_mut().unwrap().take()
+        {
+            drop(spill.file);
+            let _ = ::std::fs::remove_file(&spill.path);
+        }
+    }
+}
+
+impl ::std::fmt::Debug for SpillCache
+{
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+    {
+        f.debug_struct("SpillCache")
+            .field("limit", &self.limit)
+            .field("spilled_entries", &self.spilled_entries())
+            .finish()
+    }
+}
+
+/// The indices in `nrs` of the numbers in cache key `key`, taking the first
+/// occurrence of every number, or `None` if a number does not occur in `nrs`.
+fn key_indices(nrs: &[u64], key: &str) -> Option<Vec<Idx>>
+{
+    key.split('_')
+        .map(|s| {
+            let nr = s.parse::<u64>().ok()?;
+            nrs.iter().position(|&n| n == nr).map(|idx| idx as Idx)
+        })
+        .collect()
+}
+
+/// Encode a list of expressions, as the number of operations of every
+/// expression, followed by the operations, and the numerators and
+/// denominators of its value and its largest sub-expression value.
+fn encode(exprs: &[Expr]) -> Vec<u8>
+{
+    let mut buf = vec![];
+    for expr in exprs.iter()
+    {
+        buf.extend_from_slice(&(expr.ops.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&expr.ops);
+        buf.extend_from_slice(&expr.val.numer().to_le_bytes());
+        buf.extend_from_slice(&expr.val.denom().to_le_bytes());
+        buf.extend_from_slice(&expr.max.numer().to_le_bytes());
+        buf.extend_from_slice(&expr.max.denom().to_le_bytes());
+    }
+    buf
+}
+
+/// Decode a list of expressions encoded with `encode()`.
+fn decode(mut buf: &[u8]) -> Option<Vec<Expr>>
+{
+    fn take<'a>(buf: &mut &'a [u8], n: usize) -> Option<&'a [u8]>
+    {
+        if buf.len() < n
+        {
+            return None;
+        }
+        let (head, tail) = buf.split_at(n);
+        *buf = tail;
+        Some(head)
+    }
+
+    let mut exprs = vec![];
+    while !buf.is_empty()
+    {
+        let len = u32::from_le_bytes(take(&mut buf, 4)?.try_into().ok()?) as usize;
+        let ops = take(&mut buf, len)?.to_vec();
+        let mut rat = || {
+                let numer = u64::from_le_bytes(take(&mut buf, 8)?.try_into().ok()?);
+                let denom = u64::from_le_bytes(take(&mut buf, 8)?.try_into().ok()?);
+                Some(Rat::new_raw(numer, denom))
+            };
+        let val = rat()?;
+        let max = rat()?;
+        exprs.push(Expr { ops, val, max });
+    }
+    Some(exprs)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::Arc;
+    use super::*;
+    use crate::{distinct_values, get_nearest_expression_cached, unique_indices, Options, Target};
+
+    #[test]
+    fn encode_round_trip()
+    {
+        let nrs = [1, 3, 4, 6];
+        let idxs = unique_indices(&nrs);
+        let exprs = distinct_values(&nrs, &idxs[..3], &Options::default(), &mut Cache::default());
+        let buf = encode(&exprs);
+        assert_eq!(decode(&buf), Some(exprs));
+        assert_eq!(decode(&buf[..buf.len()-1]), None);
+    }
+
+    #[test]
+    fn spilled_search_keeps_nearest()
+    {
+        let nrs = [25, 50, 75, 100, 3, 6];
+        let idxs = unique_indices(&nrs);
+        let target = Target::new(952);
+        let plain = get_nearest_expression_cached(&nrs, &idxs, &target, &Options::default(),
+            &mut Cache::default());
+        let spill = Arc::new(SpillCache::new(1 << 12));
+        let opts = Options { spill: Some(spill.clone()), ..Options::default() };
+        let expr = get_nearest_expression_cached(&nrs, &idxs, &target, &opts,
+            &mut Cache::default());
+        assert!(spill.spilled_entries() > 0);
+        assert_eq!(expr.to_string(&nrs), plain.to_string(&nrs));
+    }
+
+    #[test]
+    fn unreadable_entries_recomputed()
+    {
+        let nrs = [25, 50, 75, 100, 3, 6];
+        let idxs = unique_indices(&nrs);
+        let target = Target::new(952);
+        let plain = get_nearest_expression_cached(&nrs, &idxs, &target, &Options::default(),
+            &mut Cache::default());
+        let spill = Arc::new(SpillCache::new(1 << 12));
+        let opts = Options { spill: Some(spill.clone()), ..Options::default() };
+        let mut cache = Cache::default();
+        get_nearest_expression_cached(&nrs, &idxs, &target, &opts, &mut cache);
+
+        spill.file.lock().unwrap().as_ref().unwrap().file.set_len(0).unwrap();
+        let key = spill.index.lock().unwrap().keys().next().unwrap().clone();
+        assert_eq!(spill.load(&key), None);
+        let expr = get_nearest_expression_cached(&nrs, &idxs, &target, &opts, &mut cache);
+        assert_eq!(expr.to_string(&nrs), plain.to_string(&nrs));
+    }
+}
@@ -0,0 +1,245 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use serde::{Deserialize, Serialize};
+
+use crate::{builtin_binary_op, builtin_unary_op, get_nearest_expression, Cache, Expr, Options,
+    Rat, Shape, Target, MAX_BINARY_OPS, MAX_UNARY_OPS};
+use crate::partition::{sub_multisets, unique_indices};
+use crate::search::{get_nearest_expression_multiple, get_nearest_expression_subset, Checkpoint};
+
+/// Serializable search configuration
+///
+/// Struct `SolverConfig` holds the settings in `Options` that determine the
+/// result of a search. Custom operations are stored by their symbol, so only
+/// the operations provided by this library can be restored.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SolverConfig
+{
+    /// See `Options::integers_only`
+    pub integers_only: bool,
+    /// See `Options::max_ops`
+    pub max_ops: Option<usize>,
+    /// See `Options::subset`
+    pub subset: bool,
+    /// See `Options::prune_dominated`
+    #[serde(default)]
+    pub prune_dominated: bool,
+    /// See `Options::shape`
+    pub shape: Shape,
+    /// See `Options::forbidden_values`
+    pub forbidden_values: Vec<Rat>,
+    /// See `Options::shuffle_seed`
+    pub shuffle_seed: Option<u64>,
+    /// The symbols of the custom binary operations
+    pub binary_ops: Vec<String>,
+    /// The symbols of the custom unary operations
+    pub unary_ops: Vec<String>,
+    /// See `Options::max_unary_depth`
+    pub max_unary_depth: usize
+}
+
+impl SolverConfig
+{
+    /// Extract the configuration from the search options `opts`.
+    pub fn from_options(opts: &Options) -> Self
+    {
+        SolverConfig {
+            integers_only: opts.integers_only,
+            max_ops: opts.max_ops,
+            subset: opts.subset,
+            prune_dominated: opts.prune_dominated,
+            shape: opts.shape,
+            forbidden_values: opts.forbidden_values.clone(),
+            shuffle_seed: opts.shuffle_seed,
+            binary_ops: opts.binary_ops.iter().map(|op| op.symbol().to_string()).collect(),
+            unary_ops: opts.unary_ops.iter().map(|op| op.symbol().to_string()).collect(),
+            max_unary_depth: opts.max_unary_depth
+        }
+    }
+
+    /// Create search options with this configuration.
+    ///
+    /// Returns an error if one of the custom operations is not provided by
+    /// this library, or if there are too many of them.
+    pub fn to_options(&self) -> Result<Options, String>
+    {
+        if self.binary_ops.len() > MAX_BINARY_OPS || self.unary_ops.len() > MAX_UNARY_OPS
+        {
+            return Err(String::from("too many custom operations"));
+        }
+
+        let binary_ops = self.binary_ops.iter()
+            .map(|name| {
+                builtin_binary_op(name).ok_or_else(|| format!("unknown operation {}", name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let unary_ops = self.unary_ops.iter()
+            .map(|name| builtin_unary_op(name).ok_or_else(|| format!("unknown operation {}", name)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Options {
+            integers_only: self.integers_only,
+            max_ops: self.max_ops,
+            subset: self.subset,
+            prune_dominated: self.prune_dominated,
+            shape: self.shape,
+            forbidden_values: self.forbidden_values.clone(),
+            shuffle_seed: self.shuffle_seed,
+            binary_ops,
+            unary_ops,
+            max_unary_depth: self.max_unary_depth,
+            ..Options::default()
+        })
+    }
+}
+
+/// Serializable state of a search
+///
+/// Struct `SolverState` holds the puzzle being solved, the configuration of
+/// the search, and its progress, so that a search can be checkpointed, e.g.
+/// when it is cancelled, and resumed later, possibly on a different machine.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SolverState
+{
+    /// The input numbers
+    pub numbers: Vec<u64>,
+    /// The target
+    pub target: Target,
+    /// The configuration of the search
+    pub config: SolverConfig,
+    /// The number of partitions of the numbers at the top level of the
+    /// search that have been completed, or the number of subsets of the
+    /// numbers when searching over subsets
+    pub done: usize,
+    /// The best expression found thus far, if any
+    pub best: Option<Expr>,
+    /// Whether the search has finished
+    pub finished: bool
+}
+
+impl SolverState
+{
+    /// Create the state for a new search for an expression over the numbers
+    /// `nrs`, nearest to `target`, with the search options `opts`.
+    pub fn new(nrs: &[u64], target: &Target, opts: &Options) -> Self
+    {
+        SolverState {
+            numbers: nrs.to_vec(),
+            target: target.clone(),
+            config: SolverConfig::from_options(opts),
+            done: 0,
+            best: None,
+            finished: false
+        }
+    }
+
+    /// Serialize this state to JSON.
+    pub fn to_json(&self) -> String
+    {
+        serde_json::to_string(self).expect("failed to serialize solver state")
+    }
+
+    /// Deserialize a state from JSON.
+    pub fn from_json(s: &str) -> Result<Self, String>
+    {
+        serde_json::from_str(s).map_err(|err| err.to_string())
+    }
+
+    /// Run the search from this state.
+    ///
+    /// Continue the search from where it was left off, with the search
+    /// options `opts`, which should match the configuration in this state,
+    /// and return the best expression found. If the search is cancelled
+    /// through `opts.cancel`, the state records the progress thus far, so that
+    /// the search can be resumed by calling this function again. The cache of
+    /// sub-expressions is not part of the state, so it is rebuilt on resuming.
+    pub fn resume(&mut self, opts: &Options) -> Expr
+    {
+        if self.finished
+        {
+            return self.best.clone().unwrap_or_else(Expr::empty);
+        }
+
+        let nrs = &self.numbers;
+        let idxs = unique_indices(nrs);
+        let mut checkpoint = Checkpoint {
+                done: self.done,
+                best: self.best.clone().unwrap_or_else(Expr::empty)
+            };
+        let mut cache = Cache::default();
+        let expr = if opts.subset
+            {
+                get_nearest_expression_subset(nrs, sub_multisets(&idxs), &self.target, opts,
+                    &mut cache, &mut checkpoint)
+            }
+            else if idxs.len() > 1
+            {
+                get_nearest_expression_multiple(nrs, &idxs, &self.target, opts, &mut cache, true,
+                    &mut checkpoint)
+            }
+            else
+            {
+                get_nearest_expression(nrs, &self.target, opts)
+            };
+
+        self.done = checkpoint.done;
+        self.best = if expr.is_empty() { None } else { Some(expr.clone()) };
+        self.finished = !opts.cancelled();
+        expr
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use super::*;
+
+    #[test]
+    fn config_round_trip()
+    {
+        let mut opts = Options { integers_only: true, subset: true, ..Options::default() };
+        opts.binary_ops.push(builtin_binary_op("pow").unwrap());
+        let config = SolverConfig::from_options(&opts);
+        let restored = config.to_options().unwrap();
+        assert_eq!(SolverConfig::from_options(&restored), config);
+
+        let config = SolverConfig { unary_ops: vec![String::from("frobnicate")], ..config };
+        assert!(config.to_options().is_err());
+    }
+
+    #[test]
+    fn resume_cancelled_search()
+    {
+        let nrs = [25, 50, 75, 100, 3, 6];
+        let target = Target::new(952);
+        let cancel = Arc::new(AtomicBool::new(true));
+        let opts = Options { cancel: Some(cancel.clone()), ..Options::default() };
+        let mut state = SolverState::new(&nrs, &target, &opts);
+        state.resume(&opts);
+        assert!(!state.finished);
+
+        let mut state = SolverState::from_json(&state.to_json()).unwrap();
+        cancel.store(false, Ordering::Relaxed);
+        let expr = state.resume(&opts);
+        assert!(state.finished);
+        assert_eq!(state.best.as_ref(), Some(&expr));
+        let nearest = get_nearest_expression(&nrs, &target, &Options::default());
+        assert_eq!(target.distance(expr.val), target.distance(nearest.val));
+        // A finished search is not run again
+        assert_eq!(state.resume(&opts), expr);
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::sync::Mutex;
+
+use crate::Cache;
+
+/// Counters of cache lookups
+///
+/// Struct `CacheCounters` counts, for every subset size, how often the
+/// sub-expressions for a set of numbers were found in the cache, and how often
+/// they had to be computed. Register the counters in the search options as
+/// `Options::cache_counters` to have them updated during a search.
+#[derive(Debug, Default)]
+pub struct CacheCounters
+{
+    /// The number of hits and misses, indexed by subset size
+    counts: Mutex<Vec<(u64, u64)>>
+}
+
+impl CacheCounters
+{
+    /// Record a lookup of the sub-expressions for `size` numbers.
+    pub(crate) fn record(&self, size: usize, hit: bool)
+    {
+        let mut counts = self.counts.lock().unwrap();
+        if counts.len() <= size
+        {
+            counts.resize(size + 1, (0, 0));
+        }
+        if hit
+        {
+            counts[size].0 += 1;
+        }
+        else
+        {
+            counts[size].1 += 1;
+        }
+    }
+
+    /// The number of hits and misses for subsets of `size` numbers.
+    pub fn get(&self, size: usize) -> (u64, u64)
+    {
+        self.counts.lock().unwrap().get(size).cloned().unwrap_or((0, 0))
+    }
+}
+
+/// Statistics for the cache entries of a single subset size
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheSizeStats
+{
+    /// The number of input numbers in the subsets
+    pub size: usize,
+    /// The number of cache entries, i.e. distinct sets of numbers
+    pub entries: usize,
+    /// The total number of expressions in these entries
+    pub expressions: usize,
+    /// The approximate memory used by these expressions, in bytes
+    pub bytes: usize,
+    /// The number of lookups that found the entry in the cache
+    pub hits: u64,
+    /// The number of lookups for which the entry had to be computed
+    pub misses: u64
+}
+
+impl CacheSizeStats
+{
+    /// The average number of expressions per entry.
+    pub fn average_expressions(&self) -> f64
+    {
+        if self.entries == 0 { 0.0 } else { self.expressions as f64 / self.entries as f64 }
+    }
+
+    /// The fraction of lookups that found the entry in the cache.
+    pub fn hit_rate(&self) -> f64
+    {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 { 0.0 } else { self.hits as f64 / lookups as f64 }
+    }
+}
+
+/// Compute statistics for a cache.
+///
+/// Summarize the entries in `cache` per subset size, sorted by size. If
+/// `counters` is given, the hit rates recorded in it are included, and sizes
+/// that were looked up but no longer have entries in the cache are reported
+/// as well.
+pub fn cache_stats(cache: &Cache, counters: Option<&CacheCounters>) -> Vec<CacheSizeStats>
+{
+    let mut res: Vec<CacheSizeStats> = vec![];
+    for (key, exprs) in cache.iter()
+    {
+        let size = key.split('_').count();
+        if res.len() < size
+        {
+            res.resize(size, CacheSizeStats::default());
+        }
+        let stats = &mut res[size-1];
+        stats.entries += 1;
+        stats.expressions += exprs.len();
+        stats.bytes += exprs.iter()
+            .map(|expr| ::std::mem::size_of_val(expr) + expr.ops.capacity())
+            .sum::<usize>();
+    }
+
+    if let Some(counters) = counters
+    {
+        let max_size = counters.counts.lock().unwrap().len().saturating_sub(1);
+        if res.len() < max_size
+        {
+            res.resize(max_size, CacheSizeStats::default());
+        }
+        for (i, stats) in res.iter_mut().enumerate()
+        {
+            let (hits, misses) = counters.get(i + 1);
+            stats.hits = hits;
+            stats.misses = misses;
+        }
+    }
+
+    for (i, stats) in res.iter_mut().enumerate()
+    {
+        stats.size = i + 1;
+    }
+    res.retain(|stats| stats.entries > 0 || stats.hits + stats.misses > 0);
+    res
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{get_nearest_expression_cached, unique_indices, Options, Target};
+
+    #[test]
+    fn stats_of_search_cache()
+    {
+        let nrs = [1, 3, 4, 6];
+        let counters = Arc::new(CacheCounters::default());
+        let opts = Options { cache_counters: Some(counters.clone()), ..Options::default() };
+        let mut cache = Cache::default();
+        get_nearest_expression_cached(&nrs, &unique_indices(&nrs), &Target::new(24), &opts,
+            &mut cache);
+        let stats = cache_stats(&cache, Some(&counters));
+        assert_eq!(stats.iter().map(|stats| stats.size).collect::<Vec<_>>(), [1, 2, 3]);
+        assert_eq!(stats.iter().map(|stats| stats.entries).sum::<usize>(), cache.len());
+        assert_eq!(stats.iter().map(|stats| stats.expressions).sum::<usize>(),
+            cache.values().map(|exprs| exprs.len()).sum::<usize>());
+        for stats in stats.iter()
+        {
+            assert_eq!((stats.hits, stats.misses), counters.get(stats.size));
+            assert!(stats.bytes >= stats.expressions * ::std::mem::size_of::<crate::Expr>());
+            assert_eq!(stats.average_expressions(),
+                stats.expressions as f64 / stats.entries as f64);
+        }
+        assert!(stats.iter().any(|stats| stats.hits > 0 && stats.hit_rate() > 0.0));
+
+        let stats = cache_stats(&cache, None);
+        assert!(stats.iter().all(|stats| stats.hits + stats.misses == 0));
+        assert_eq!(CacheSizeStats::default().average_expressions(), 0.0);
+        assert_eq!(CacheSizeStats::default().hit_rate(), 0.0);
+    }
+}
@@ -0,0 +1,658 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use alloc::vec;
+use alloc::vec::Vec;
+use num_traits::Zero;
+
+use crate::{get_nearest_expression, Cache, Expr, FastHash, HashMap, HashSet, NormalizedRat, Options,
+    Rat, Shape, Target, ADD, DIV, MUL, SUB};
+use crate::partition::unique_indices;
+use crate::evolve::evolve;
+use crate::search::{allowed_partitions, cached, expressions};
+use crate::shuffle::SplitMix;
+
+/// The number of partial solutions kept in every step of a beam search
+pub const DEFAULT_BEAM_WIDTH: usize = 1000;
+/// The number of random expressions tried in a sampling search
+pub const DEFAULT_SAMPLE_ITERATIONS: usize = 100_000;
+/// The number of generations in an evolutionary search
+pub const DEFAULT_EVOLVE_GENERATIONS: usize = 1000;
+/// The largest size of the sets of numbers searched exhaustively in a hybrid
+/// search
+pub const DEFAULT_HYBRID_DEPTH: usize = 4;
+/// The number of groupings of the numbers tried in a hybrid search
+const HYBRID_ROUNDS: usize = 4;
+
+/// Strategy for searching an expression
+///
+/// Enum `Strategy` lists the ways in which an expression nearest to the
+/// target can be searched for, which differ in the time and memory they use,
+/// and in whether they are guaranteed to find the best expression.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy
+{
+    /// Enumerate all distinct values for every subset of the numbers. This
+    /// always finds the best expression.
+    Exhaustive,
+    /// For every split of the numbers in two, enumerate the values of both
+    /// halves, and look up the value completing each value of the first half
+    /// to the target among the values of the second half. If no exact
+    /// solution is found this way, or the target is a range, an exhaustive
+    /// search is done.
+    MeetInTheMiddle,
+    /// Combine two sub-expressions at a time, keeping only the given number
+    /// of partial solutions that come closest to the target in every step.
+    /// This is fast, but may miss the best expression.
+    Beam(usize),
+    /// Build the given number of expressions by combining two randomly
+    /// chosen sub-expressions at a time, using a random operation, and keep
+    /// the one that comes closest to the target. This gives a usable answer
+    /// for inputs far too large for an exhaustive search, but is unlikely to
+    /// find the best expression.
+    Sample(usize),
+    /// Improve a population of random expressions for the given number of
+    /// generations, by crossing the best ones, and mutating them by changing
+    /// an operation, swapping two numbers, or swapping two sub-expressions.
+    /// Like `Sample`, this is meant for inputs too large for an exhaustive
+    /// search, but usually comes closer to the target in the same time.
+    Evolve(usize),
+    /// Split the numbers into groups of at most the given size, whose values
+    /// are enumerated exhaustively, and combine the values of the groups two
+    /// at a time, keeping only a limited number of the combined values. This
+    /// often finds the best expression, at a fraction of the cost of an
+    /// exhaustive search. If the expression shape is constrained, an
+    /// exhaustive search is done.
+    Hybrid(usize)
+}
+
+impl Strategy
+{
+    /// Look up a strategy by its command line name.
+    pub fn from_name(name: &str) -> Option<Self>
+    {
+        match name
+        {
+            "exhaustive" => Some(Strategy::Exhaustive),
+            "mitm"       => Some(Strategy::MeetInTheMiddle),
+            "beam"       => Some(Strategy::Beam(DEFAULT_BEAM_WIDTH)),
+            "sample"     => Some(Strategy::Sample(DEFAULT_SAMPLE_ITERATIONS)),
+            "evolve"     => Some(Strategy::Evolve(DEFAULT_EVOLVE_GENERATIONS)),
+            "hybrid"     => Some(Strategy::Hybrid(DEFAULT_HYBRID_DEPTH)),
+            _            => None
+        }
+    }
+
+    /// The command line name of this strategy.
+    pub fn name(self) -> &'static str
+    {
+        match self
+        {
+            Strategy::Exhaustive      => "exhaustive",
+            Strategy::MeetInTheMiddle => "mitm",
+            Strategy::Beam(_)         => "beam",
+            Strategy::Sample(_)       => "sample",
+            Strategy::Evolve(_)       => "evolve",
+            Strategy::Hybrid(_)       => "hybrid"
+        }
+    }
+
+    /// Find the expression nearest to target.
+    ///
+    /// Using this strategy, find an arithmetic expression using the numbers
+    /// in `nrs` that evaluates to a number as close as possible to `target`,
+    /// subject to the search options in `opts`.
+    pub fn solve(self, nrs: &[u64], target: &Target, opts: &Options) -> Expr
+    {
+        match self
+        {
+            Strategy::Exhaustive      => get_nearest_expression(nrs, target, opts),
+            Strategy::MeetInTheMiddle => meet_in_the_middle(nrs, target, opts),
+            Strategy::Beam(width)     => beam_search(nrs, target, opts, width),
+            Strategy::Sample(count)   => sample(nrs, target, opts, count),
+            Strategy::Evolve(count)   => evolve(nrs, target, opts, count),
+            Strategy::Hybrid(depth)   => hybrid(nrs, target, opts, depth)
+        }
+    }
+}
+
+/// Find an exact solution by meeting in the middle.
+///
+/// See `Strategy::MeetInTheMiddle`.
+fn meet_in_the_middle(nrs: &[u64], target: &Target, opts: &Options) -> Expr
+{
+    let idxs = unique_indices(nrs);
+    let values = match target.values()
+        {
+            Some(values) if !opts.subset && idxs.len() > 1 => values,
+            _ => return get_nearest_expression(nrs, target, opts)
+        };
+
+    let mut cache = Cache::default();
+    for (idxs0, idxs1) in allowed_partitions(&idxs, opts)
+    {
+        if opts.cancelled()
+        {
+            break;
+        }
+
+        let key0 = expressions(nrs, &idxs0, opts, &mut cache);
+        let key1 = expressions(nrs, &idxs1, opts, &mut cache);
+        let exprs1 = cached(&cache, &key1, opts);
+        let mut lookup = HashMap::with_hasher(FastHash);
+        for (j, expr1) in exprs1.iter().enumerate()
+        {
+            lookup.insert(NormalizedRat(expr1.val), j);
+        }
+
+        for expr0 in cached(&cache, &key0, opts).iter()
+        {
+            if opts.max_ops.is_some_and(|max_ops| expr0.op_count() + idxs1.len() > max_ops)
+            {
+                continue;
+            }
+
+            for &val in values.iter()
+            {
+                for (b, op, swap) in complements(expr0.val, val, opts)
+                {
+                    let expr1 = match lookup.get(&NormalizedRat(b))
+                        {
+                            Some(&j) => &exprs1[j],
+                            None     => continue
+                        };
+                    let ops = if swap
+                        {
+                            [&expr1.ops[..], &expr0.ops[..], &[op]].concat()
+                        }
+                        else
+                        {
+                            [&expr0.ops[..], &expr1.ops[..], &[op]].concat()
+                        };
+                    if let Some(expr) = Expr::from_ops(ops, nrs, opts).filter(|e| e.val == val)
+                    {
+                        if opts.max_ops.is_none_or(|max_ops| expr.op_count() <= max_ops)
+                        {
+                            return expr;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    get_nearest_expression(nrs, target, opts)
+}
+
+/// The values `b` that combine with `a` into `val` using one of the four basic
+/// operations, together with that operation, and whether `b` is the left hand
+/// side operand.
+pub(crate) fn complements(a: Rat, val: Rat, opts: &Options) -> Vec<(Rat, u8, bool)>
+{
+    let mut res = vec![];
+    // In a chain, the second half is a single number on the right hand side
+    let swap_ok = opts.shape != Shape::Chain;
+
+    if val >= a
+    {
+        res.push((val - a, ADD, false));
+    }
+    if a >= val
+    {
+        res.push((a - val, SUB, false));
+    }
+    if swap_ok
+    {
+        res.push((val + a, SUB, true));
+    }
+    if !a.is_zero()
+    {
+        res.push((val / a, MUL, false));
+        if swap_ok
+        {
+            res.push((val * a, DIV, true));
+        }
+    }
+    if !val.is_zero()
+    {
+        res.push((a / val, DIV, false));
+    }
+    res
+}
+
+/// Find an expression by beam search.
+///
+/// See `Strategy::Beam`. A partial solution is a set of expressions over
+/// disjoint sets of numbers, which is scored by the nearest distance to the
+/// target of any of its expressions. Every step combines two of these
+/// expressions, until only a single expression is left.
+fn beam_search(nrs: &[u64], target: &Target, opts: &Options, width: usize) -> Expr
+{
+    let idxs = unique_indices(nrs);
+    let mut best: Option<(Rat, Expr)> = None;
+    let update = |best: &mut Option<(Rat, Expr)>, expr: &Expr| {
+            let diff = target.distance(expr.val);
+            if best.as_ref().is_none_or(|(best_diff, _)| diff < *best_diff)
+            {
+                *best = Some((diff, expr.clone()));
+            }
+        };
+
+    let start = idxs.iter().map(|&idx| Expr::new(nrs, idx)).collect::<Vec<_>>();
+    if opts.subset || start.len() == 1
+    {
+        start.iter().for_each(|expr| update(&mut best, expr));
+    }
+
+    let mut beam = vec![start];
+    while !beam.is_empty() && !opts.cancelled()
+    {
+        let mut next: Vec<(Rat, Vec<Expr>)> = vec![];
+        let mut seen = HashSet::new();
+        for state in beam.iter()
+        {
+            for i in 0..state.len()
+            {
+                for j in 0..state.len()
+                {
+                    let (expr0, expr1) = (&state[i], &state[j]);
+                    let (count0, count1) = (expr0.indices().count(), expr1.indices().count());
+                    if i == j || count0 < count1 || (count0 == count1 && i > j)
+                        || !opts.shape.allows_split(count0, count1)
+                    {
+                        continue;
+                    }
+
+                    for (op, val) in expr0.possible_combinations(expr1, opts)
+                    {
+                        if opts.forbidden_values.contains(&val) && state.len() > 2
+                        {
+                            continue;
+                        }
+
+                        let mut new_state = state.iter().enumerate()
+                            .filter(|&(k, _)| k != i && k != j)
+                            .map(|(_, expr)| expr.clone())
+                            .collect::<Vec<_>>();
+                        new_state.push(expr0.combine(expr1, op, val));
+
+                        let mut key = new_state.iter().map(|expr| expr.val).collect::<Vec<_>>();
+                        key.sort();
+                        if !seen.insert(key)
+                        {
+                            continue;
+                        }
+
+                        let score = new_state.iter().map(|expr| target.distance(expr.val))
+                            .min().unwrap();
+                        next.push((score, new_state));
+                    }
+                }
+            }
+        }
+
+        next.sort_by_key(|(score, _)| *score);
+        next.truncate(width);
+        beam = next.into_iter().map(|(_, state)| state).collect();
+
+        for state in beam.iter()
+        {
+            if opts.subset
+            {
+                state.iter().for_each(|expr| update(&mut best, expr));
+            }
+            else if state.len() == 1
+            {
+                update(&mut best, &state[0]);
+            }
+        }
+        if best.as_ref().is_some_and(|(diff, _)| diff.is_zero())
+        {
+            break;
+        }
+    }
+
+    best.map_or_else(Expr::empty, |(_, expr)| expr)
+}
+
+/// Limit a pool of combined values.
+///
+/// Remove the entries with duplicate values from `pool`, where `val` gives the
+/// value of an entry, and if more than `width` entries are left, keep half of
+/// them coming closest to `target`, and the other half evenly spread over the
+/// remaining values, so that values far from the target are still available
+/// for further combinations.
+fn prune_pool<T, F>(pool: Vec<T>, target: &Target, width: usize, val: F) -> Vec<T>
+where F: Fn(&T) -> Rat
+{
+    let mut seen = HashSet::new();
+    let mut pool = pool.into_iter().filter(|entry| seen.insert(NormalizedRat(val(entry))))
+        .collect::<Vec<_>>();
+    if pool.len() <= width
+    {
+        return pool;
+    }
+
+    pool.sort_by_key(|entry| target.distance(val(entry)));
+    let rest = pool.split_off(width / 2);
+    let mut rest = rest.into_iter().map(Some).collect::<Vec<_>>();
+    rest.sort_by_key(|entry| entry.as_ref().map(&val));
+    let count = width - pool.len();
+    let len = rest.len();
+    pool.extend((0..count).filter_map(|i| rest[i * len / count].take()));
+    pool
+}
+
+/// Find an expression by a hybrid search.
+///
+/// See `Strategy::Hybrid`. The numbers are split into groups of nearly equal
+/// size, of at most `depth` numbers, and all values of every group are
+/// enumerated. Pools of values are then merged two at a time, by combining
+/// every value of one pool with every value of the other, and keeping at most
+/// `DEFAULT_BEAM_WIDTH` of the combined values, as in `prune_pool()`. The
+/// final merge is not pruned, so that the best combination of the last two
+/// pools is found. Since the grouping limits the expressions that are
+/// considered, this is repeated for `HYBRID_ROUNDS` groupings, the first in
+/// the order of the numbers, and the others in a pseudo-random order
+/// determined by `opts.shuffle_seed` (or 0 if it is not set).
+fn hybrid(nrs: &[u64], target: &Target, opts: &Options, depth: usize) -> Expr
+{
+    let mut idxs = unique_indices(nrs);
+    if opts.shape != Shape::Any || idxs.len() <= depth.max(1)
+    {
+        return get_nearest_expression(nrs, target, opts);
+    }
+
+    let mut best: Option<(Rat, Expr)> = None;
+    let update = |best: &mut Option<(Rat, Expr)>, expr: &Expr| {
+            let diff = target.distance(expr.val);
+            if best.as_ref().is_none_or(|(best_diff, _)| diff < *best_diff)
+            {
+                *best = Some((diff, expr.clone()));
+            }
+        };
+
+    let group_count = idxs.len().div_ceil(depth.max(1));
+    let mut rng = SplitMix::new(opts.shuffle_seed.unwrap_or(0), &idxs);
+    let mut cache = Cache::default();
+    for round in 0..HYBRID_ROUNDS
+    {
+        if opts.cancelled() || best.as_ref().is_some_and(|(diff, _)| diff.is_zero())
+        {
+            break;
+        }
+        if round > 0
+        {
+            rng.shuffle(&mut idxs);
+        }
+
+        let mut pools = vec![];
+        for g in 0..group_count
+        {
+            let mut group = idxs[g * idxs.len() / group_count..(g + 1) * idxs.len() / group_count]
+                .to_vec();
+            group.sort();
+            let key = expressions(nrs, &group, opts, &mut cache);
+            let pool = cached(&cache, &key, opts).into_owned();
+            if opts.subset
+            {
+                pool.iter().for_each(|expr| update(&mut best, expr));
+            }
+            pools.push(prune_pool(pool, target, DEFAULT_BEAM_WIDTH, |expr| expr.val));
+        }
+
+        while pools.len() > 1 && !opts.cancelled()
+        {
+            let last = pools.len() == 2;
+            let mut merged = vec![];
+            for pair in pools.chunks(2)
+            {
+                if pair.len() == 1
+                {
+                    merged.push(pair[0].clone());
+                    continue;
+                }
+
+                // Only the combinations that are kept, or that improve on the
+                // best expression, are built.
+                let mut pool = vec![];
+                for (i, expr0) in pair[0].iter().enumerate()
+                {
+                    for (j, expr1) in pair[1].iter().enumerate()
+                    {
+                        for (op, val) in expr0.all_combinations(expr1, opts)
+                        {
+                            if last
+                            {
+                                let diff = target.distance(val);
+                                if best.as_ref().is_none_or(|(best_diff, _)| diff < *best_diff)
+                                {
+                                    best = Some((diff, expr0.combine(expr1, op, val)));
+                                }
+                            }
+                            else if !opts.forbidden_values.contains(&val)
+                            {
+                                pool.push((val, i, j, op));
+                            }
+                        }
+                    }
+                }
+
+                let pool = prune_pool(pool, target, DEFAULT_BEAM_WIDTH, |entry| entry.0)
+                    .into_iter()
+                    .map(|(val, i, j, op)| pair[0][i].combine(&pair[1][j], op, val))
+                    .collect::<Vec<_>>();
+                if opts.subset
+                {
+                    pool.iter().for_each(|expr| update(&mut best, expr));
+                }
+                merged.push(pool);
+            }
+            pools = merged;
+        }
+    }
+
+    best.map_or_else(Expr::empty, |(_, expr)| expr)
+}
+
+/// Find an expression by random sampling.
+///
+/// See `Strategy::Sample`. Every iteration builds a random expression using
+/// `random_expr()`, starting from the single numbers. The random choices are
+/// determined by `opts.shuffle_seed` (or 0 if it is not set), so the result is
+/// reproducible.
+fn sample(nrs: &[u64], target: &Target, opts: &Options, iterations: usize) -> Expr
+{
+    let idxs = unique_indices(nrs);
+    let mut rng = SplitMix::new(opts.shuffle_seed.unwrap_or(0), &idxs);
+    let mut best: Option<(Rat, Expr)> = None;
+    let update = |best: &mut Option<(Rat, Expr)>, expr: &Expr| {
+            let diff = target.distance(expr.val);
+            if best.as_ref().is_none_or(|(best_diff, _)| diff < *best_diff)
+            {
+                *best = Some((diff, expr.clone()));
+            }
+        };
+
+    let start = idxs.iter().map(|&idx| Expr::new(nrs, idx)).collect::<Vec<_>>();
+    if opts.subset || start.len() == 1
+    {
+        start.iter().for_each(|expr| update(&mut best, expr));
+    }
+
+    for _ in 0..iterations
+    {
+        if opts.cancelled() || best.as_ref().is_some_and(|(diff, _)| diff.is_zero())
+        {
+            break;
+        }
+
+        let mut visit = |expr: &Expr| {
+                if opts.subset
+                {
+                    update(&mut best, expr);
+                }
+            };
+        if let Some(expr) = random_expr(&start, target, opts, &mut rng, &mut visit)
+        {
+            update(&mut best, &expr);
+        }
+    }
+
+    best.map_or_else(Expr::empty, |(_, expr)| expr)
+}
+
+/// Build a random expression.
+///
+/// Starting from the expressions in `start`, repeatedly replace two randomly
+/// chosen expressions, whose sizes are allowed by the shape in `opts`, by a
+/// random combination of them, until a single expression is left, which is
+/// returned. Only the last operation is chosen to come as close to `target`
+/// as possible. Every combination made is passed to `visit`. Returns `None` if
+/// at some point no combination can be found.
+pub(crate) fn random_expr(start: &[Expr], target: &Target, opts: &Options, rng: &mut SplitMix,
+    visit: &mut dyn FnMut(&Expr)) -> Option<Expr>
+{
+    let mut state = start.to_vec();
+    while state.len() > 1
+    {
+        // Pick a random pair of expressions that may be combined, giving up
+        // if none is found after a number of tries.
+        let mut choice = None;
+        for _ in 0..4 * state.len() * state.len()
+        {
+            let i = (rng.next_u64() % state.len() as u64) as usize;
+            let j = (rng.next_u64() % state.len() as u64) as usize;
+            let (count0, count1) = (state[i].indices().count(), state[j].indices().count());
+            if i == j || count0 < count1 || !opts.shape.allows_split(count0, count1)
+            {
+                continue;
+            }
+
+            let combinations = state[i].possible_combinations(&state[j], opts).into_iter()
+                .filter(|(_, val)| state.len() == 2 || !opts.forbidden_values.contains(val))
+                .collect::<Vec<_>>();
+            if !combinations.is_empty()
+            {
+                // The final operation is the one coming closest to the
+                // target, rather than a random one.
+                let k = if state.len() == 2
+                    {
+                        (0..combinations.len())
+                            .min_by_key(|&k| target.distance(combinations[k].1)).unwrap()
+                    }
+                    else
+                    {
+                        (rng.next_u64() % combinations.len() as u64) as usize
+                    };
+                choice = Some((i, j, combinations[k]));
+                break;
+            }
+        }
+
+        let (i, j, (op, val)) = choice?;
+        let expr = state[i].combine(&state[j], op, val);
+        state.swap_remove(i.max(j));
+        state.swap_remove(i.min(j));
+        visit(&expr);
+        state.push(expr);
+    }
+
+    state.pop()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::MAX_NUMBERS;
+
+    const PUZZLES: [(&[u64], u64); 5] = [
+        (&[1, 3, 4, 6], 24),
+        (&[2, 2, 2, 2], 17),
+        (&[25, 50, 75, 100, 3], 695),
+        (&[4, 7, 7, 9, 10], 811),
+        (&[3, 6, 8, 9, 25, 50], 952)
+    ];
+
+    /// Solve all puzzles using `strategy`, and check that the expressions
+    /// found are valid, and come no closer to the target than an exhaustive
+    /// search. Returns whether every expression is as near as possible.
+    fn check_strategy(strategy: Strategy) -> bool
+    {
+        let opts = Options::default();
+        let mut nearest = true;
+        for &(nrs, target) in PUZZLES.iter()
+        {
+            let target = Target::new(target);
+            let expr = strategy.solve(nrs, &target, &opts);
+            let val = Expr::from_ops(expr.ops.clone(), nrs, &opts).map(|e| e.val);
+            assert_eq!(val, Some(expr.val), "{:?} for {:?}", strategy, nrs);
+            let best = Strategy::Exhaustive.solve(nrs, &target, &opts);
+            assert!(target.distance(expr.val) >= target.distance(best.val));
+            nearest &= target.distance(expr.val) == target.distance(best.val);
+        }
+        nearest
+    }
+
+    #[test]
+    fn strategy_names()
+    {
+        for &strategy in [Strategy::Exhaustive, Strategy::MeetInTheMiddle,
+            Strategy::Beam(DEFAULT_BEAM_WIDTH)].iter()
+        {
+            assert_eq!(Strategy::from_name(strategy.name()), Some(strategy));
+        }
+        assert_eq!(Strategy::from_name("fastest"), None);
+    }
+
+    #[test]
+    fn meet_in_the_middle_is_exact()
+    {
+        assert!(check_strategy(Strategy::MeetInTheMiddle));
+    }
+
+    #[test]
+    fn beam_search_is_valid()
+    {
+        check_strategy(Strategy::Beam(DEFAULT_BEAM_WIDTH));
+    }
+
+    #[test]
+    fn sample_is_valid()
+    {
+        let strategy = Strategy::Sample(DEFAULT_SAMPLE_ITERATIONS);
+        assert_eq!(Strategy::from_name(strategy.name()), Some(strategy));
+        check_strategy(Strategy::Sample(1000));
+    }
+
+    #[test]
+    fn evolve_is_valid()
+    {
+        let strategy = Strategy::Evolve(DEFAULT_EVOLVE_GENERATIONS);
+        assert_eq!(Strategy::from_name(strategy.name()), Some(strategy));
+        check_strategy(Strategy::Evolve(20));
+    }
+
+    #[test]
+    fn hybrid_is_valid()
+    {
+        let strategy = Strategy::Hybrid(DEFAULT_HYBRID_DEPTH);
+        assert_eq!(Strategy::from_name(strategy.name()), Some(strategy));
+        check_strategy(strategy);
+        // With a single group, all values are enumerated
+        assert!(check_strategy(Strategy::Hybrid(MAX_NUMBERS)));
+    }
+}
@@ -0,0 +1,316 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result, Write};
+
+use crate::{Cache, Expr, NormalizedRat, Options, Rat, Shape, Target};
+use crate::partition::{sub_multisets, unique_indices};
+use crate::search::{cached, expressions};
+
+/// Magic bytes at the start of a table file
+const MAGIC: &[u8; 8] = b"MXTABLE1";
+/// Flag for a table of expressions using any subset of the numbers
+const FLAG_SUBSET: u64 = 1;
+/// Flag for a table of expressions with only integer intermediate results
+const FLAG_INTEGERS_ONLY: u64 = 2;
+/// The size of the header, in bytes
+const HEADER_SIZE: usize = 40;
+/// The size of a single entry, in bytes
+const ENTRY_SIZE: usize = 24;
+
+/// Precomputed table of reachable values
+///
+/// Struct `ValueTable` is a memory-mapped table of all distinct values that
+/// can be made from a fixed pool of numbers, sorted by value, with a witness
+/// expression for every value. For a pool that is queried often, like the
+/// numbers of a game, the nearest expression to a target is then found by a
+/// binary search in the table, instead of a search over all expressions.
+///
+/// The file starts with a header of five 64-bit little endian integers: the
+/// magic bytes `MXTABLE1`, the number of numbers in the pool, a set of flags,
+/// the number of entries, and the size of the witness operations. It is
+/// followed by the numbers, the entries, and the operations. Every entry
+/// consists of the numerator and denominator of the value, and the offset and
+/// length of its witness expression in the operations, in 32 bits each.
+#[derive(Debug)]
+pub struct ValueTable
+{
+    /// The mapped file
+    map: memmap2::Mmap,
+    /// The numbers in the pool
+    nrs: Vec<u64>,
+    /// The flags the table was generated with
+    flags: u64,
+    /// The number of entries in the table
+    count: usize
+}
+
+/// Read the little endian 64-bit integer at `offset` in `bytes`.
+fn read_u64(bytes: &[u8], offset: usize) -> u64
+{
+    u64::from_le_bytes(bytes[offset..offset+8].try_into().unwrap())
+}
+
+/// The flags describing the search options `opts` in a table.
+fn table_flags(opts: &Options) -> u64
+{
+    let mut flags = 0;
+    if opts.subset
+    {
+        flags |= FLAG_SUBSET;
+    }
+    if opts.integers_only
+    {
+        flags |= FLAG_INTEGERS_ONLY;
+    }
+    flags
+}
+
+/// Check if the search options `opts` can be answered by a table. Only the
+/// four basic operations, and the options `subset` and `integers_only`, are
+/// supported.
+fn supported(opts: &Options) -> bool
+{
+    opts.max_ops.is_none() && opts.shape == Shape::Any && opts.forbidden_values.is_empty()
+        && opts.binary_ops.is_empty() && opts.unary_ops.is_empty() && opts.shuffle_seed.is_none()
+}
+
+impl ValueTable
+{
+    /// Generate a table.
+    ///
+    /// Compute all distinct values that can be made from the numbers `nrs`,
+    /// subject to the search options in `opts`, and write them with their
+    /// witness expressions to the file at `path`. If `opts.subset` is set, the
+    /// values made from any subset of the numbers are included, with the
+    /// witness using the fewest numbers. Returns an error if the options are
+    /// not supported in a table, or if the file cannot be written.
+    pub fn generate(nrs: &[u64], opts: &Options, path: &str) -> Result<()>
+    {
+        if nrs.is_empty() || !supported(opts)
+        {
+            return Err(Error::new(ErrorKind::InvalidInput, "unsupported options for a table"));
+        }
+
+        let idxs = unique_indices(nrs);
+        let subsets = if opts.subset { sub_multisets(&idxs) } else { vec![idxs] };
+        let mut cache = Cache::default();
+        let mut values = HashMap::new();
+        for sub in subsets
+        {
+            let key = expressions(nrs, &sub, opts, &mut cache);
+            for expr in cached(&cache, &key, opts).iter()
+            {
+                values.entry(NormalizedRat(expr.val)).or_insert_with(|| expr.clone());
+            }
+        }
+        let mut exprs = values.into_values().collect::<Vec<_>>();
+        exprs.sort_by_key(|expr| expr.val);
+
+        let ops_len = exprs.iter().map(|expr| expr.ops.len()).sum::<usize>();
+        let mut buf = Vec::with_capacity(HEADER_SIZE + 8 * nrs.len() + ENTRY_SIZE * exprs.len()
+            + ops_len);
+        buf.extend_from_slice(MAGIC);
+        for word in [nrs.len() as u64, table_flags(opts), exprs.len() as u64, ops_len as u64]
+        {
+            buf.extend_from_slice(&word.to_le_bytes());
+        }
+        for &nr in nrs.iter()
+        {
+            buf.extend_from_slice(&nr.to_le_bytes());
+        }
+        let mut offset = 0;
+        for expr in exprs.iter()
+        {
+            buf.extend_from_slice(&expr.val.numer().to_le_bytes());
+            buf.extend_from_slice(&expr.val.denom().to_le_bytes());
+            buf.extend_from_slice(&(offset as u32).to_le_bytes());
+            buf.extend_from_slice(&(expr.ops.len() as u32).to_le_bytes());
+            offset += expr.ops.len();
+        }
+        for expr in exprs.iter()
+        {
+            buf.extend_from_slice(&expr.ops);
+        }
+
+        File::create(path)?.write_all(&buf)
+    }
+
+    /// Open the table in the file at `path`.
+    pub fn open(path: &str) -> Result<Self>
+    {
+        let file = File::open(path)?;
+        // Safety: the table is only read, and should not be modified while
+        // it is mapped.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        let invalid = || Error::new(ErrorKind::InvalidData, "not a valid table");
+        if map.len() < HEADER_SIZE || &map[..8] != MAGIC
+        {
+            return Err(invalid());
+        }
+
+        let nr_count = read_u64(&map, 8) as usize;
+        let flags = read_u64(&map, 16);
+        let count = read_u64(&map, 24) as usize;
+        let ops_len = read_u64(&map, 32) as usize;
+        let size = nr_count.checked_mul(8)
+            .and_then(|n| count.checked_mul(ENTRY_SIZE).and_then(|e| n.checked_add(e)))
+            .and_then(|n| n.checked_add(HEADER_SIZE + ops_len));
+        if size != Some(map.len())
+        {
+            return Err(invalid());
+        }
+
+        let nrs = (0..nr_count).map(|i| read_u64(&map, HEADER_SIZE + 8 * i)).collect();
+        Ok(ValueTable { map, nrs, flags, count })
+    }
+
+    /// The numbers in the pool of this table.
+    pub fn numbers(&self) -> &[u64]
+    {
+        &self.nrs
+    }
+
+    /// The number of distinct values in this table.
+    pub fn len(&self) -> usize
+    {
+        self.count
+    }
+
+    /// Check if this table contains no values.
+    pub fn is_empty(&self) -> bool
+    {
+        self.count == 0
+    }
+
+    /// Check if this table can answer a search for an expression over the
+    /// numbers `nrs`, in any order, with the search options `opts`.
+    pub fn matches(&self, nrs: &[u64], opts: &Options) -> bool
+    {
+        let mut pool = self.nrs.clone();
+        let mut query = nrs.to_vec();
+        pool.sort();
+        query.sort();
+        pool == query && supported(opts) && table_flags(opts) == self.flags
+    }
+
+    /// The value of entry `i`.
+    fn value(&self, i: usize) -> Rat
+    {
+        let offset = HEADER_SIZE + 8 * self.nrs.len() + ENTRY_SIZE * i;
+        Rat::new_raw(read_u64(&self.map, offset), read_u64(&self.map, offset + 8))
+    }
+
+    /// The witness expression of entry `i`, over the numbers `nrs`.
+    fn expr(&self, i: usize, nrs: &[u64]) -> Expr
+    {
+        let entry = HEADER_SIZE + 8 * self.nrs.len() + ENTRY_SIZE * i;
+        let word = read_u64(&self.map, entry + 16);
+        let (offset, len) = ((word & 0xffff_ffff) as usize, (word >> 32) as usize);
+        let start = HEADER_SIZE + 8 * self.nrs.len() + ENTRY_SIZE * self.count + offset;
+
+        // Map the indices into the pool to the first occurrence of the number
+        // in `nrs`, as in `unique_indices()`.
+        let ops = self.map[start..start+len].iter().map(|&op| {
+                match self.nrs.get(op as usize)
+                {
+                    Some(nr) => nrs.iter().position(|x| x == nr).unwrap() as u8,
+                    None     => op
+                }
+            }).collect::<Vec<_>>();
+
+        // The largest sub-expression value is not stored in the table
+        let val = self.value(i);
+        let max = Expr::from_ops(ops.clone(), nrs, &Options::default()).map_or(val, |e| e.max);
+        Expr { ops, val, max }
+    }
+
+    /// Find the expression nearest to target.
+    ///
+    /// Look up the value in this table nearest to `target`, and return its
+    /// witness expression over the numbers `nrs`, which should match the
+    /// numbers of this table. Returns an empty expression if the table is
+    /// empty.
+    pub fn nearest(&self, nrs: &[u64], target: &Target) -> Expr
+    {
+        let mut best: Option<(Rat, usize)> = None;
+        for &(min, _) in target.intervals()
+        {
+            // Binary search for the first value not below the interval
+            let (mut lo, mut hi) = (0, self.count);
+            while lo < hi
+            {
+                let mid = (lo + hi) / 2;
+                if self.value(mid) < min { lo = mid + 1; } else { hi = mid; }
+            }
+            for i in [lo.wrapping_sub(1), lo]
+            {
+                if i < self.count
+                {
+                    let diff = target.distance(self.value(i));
+                    if best.is_none_or(|(best_diff, _)| diff < best_diff)
+                    {
+                        best = Some((diff, i));
+                    }
+                }
+            }
+        }
+
+        best.map_or_else(Expr::empty, |(_, i)| self.expr(i, nrs))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::get_nearest_expression;
+
+    #[test]
+    fn table_matches_search()
+    {
+        let path = ::std::env::temp_dir().join(format!("makeexpr-table-{}.bin",
+            ::std::process::id()));
+        let path = path.to_str().unwrap();
+        let pool = [25, 3, 4, 6, 3];
+        for opts in [Options::default(), Options { subset: true, ..Options::default() }].iter()
+        {
+            ValueTable::generate(&pool, opts, path).unwrap();
+            let table = ValueTable::open(path).unwrap();
+            assert_eq!(table.numbers(), pool);
+            // The same numbers, in a different order
+            let nrs = [3, 3, 4, 6, 25];
+            assert!(table.matches(&nrs, opts));
+            assert!(!table.matches(&nrs[1..], opts));
+            assert!(!table.matches(&nrs, &Options { integers_only: true, ..opts.clone() }));
+            for target in [Target::new(1), Target::new(317), Target::range(700, 710),
+                Target::new(100_000)].iter()
+            {
+                let expr = table.nearest(&nrs, target);
+                let val = Expr::from_ops(expr.ops.clone(), &nrs, opts).map(|e| e.val);
+                assert_eq!(val, Some(expr.val));
+                let nearest = get_nearest_expression(&nrs, target, opts);
+                assert_eq!(target.distance(expr.val), target.distance(nearest.val));
+            }
+        }
+        ::std::fs::remove_file(path).unwrap();
+
+        let opts = Options { shape: Shape::Chain, ..Options::default() };
+        assert!(ValueTable::generate(&pool, &opts, path).is_err());
+    }
+}
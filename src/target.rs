@@ -0,0 +1,218 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use num_traits::Zero;
+
+use crate::Rat;
+
+/// Structure describing the target of a search
+///
+/// Struct `Target` describes which values count as an exact hit. This can be
+/// a single number, an interval of numbers, in which case any value in the
+/// interval counts as exact, or a combination of several of these. For values
+/// outside the target, the distance to the target is the distance to the
+/// nearest value inside it.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Target
+{
+    /// The closed intervals of values that count as an exact hit
+    intervals: Vec<(Rat, Rat)>
+}
+
+impl Target
+{
+    /// Create a new target for the single number `target`.
+    pub fn new(target: u64) -> Self
+    {
+        let val = Rat::from_integer(target);
+        Target { intervals: vec![(val, val)] }
+    }
+
+    /// Create a new target for the single, possibly fractional, value `val`.
+    pub(crate) fn from_rat(val: Rat) -> Self
+    {
+        Target { intervals: vec![(val, val)] }
+    }
+
+    /// Create a new target for the interval `min..=max`.
+    pub fn range(min: u64, max: u64) -> Self
+    {
+        assert!(min <= max, "Empty target range {}..={}", min, max);
+        Target { intervals: vec![(Rat::from_integer(min), Rat::from_integer(max))] }
+    }
+
+    /// Create a new target that is hit when any of `targets` is hit.
+    pub fn any(targets: &[Target]) -> Self
+    {
+        assert!(!targets.is_empty(), "No targets given");
+        let intervals = targets.iter().flat_map(|t| t.intervals.iter().cloned()).collect();
+        Target { intervals }
+    }
+
+    /// The closed intervals of values that count as an exact hit.
+    pub(crate) fn intervals(&self) -> &[(Rat, Rat)]
+    {
+        &self.intervals
+    }
+
+    /// The lowest value that counts as an exact hit.
+    pub fn min(&self) -> Rat
+    {
+        self.intervals.iter().map(|&(min, _)| min).min().unwrap()
+    }
+
+    /// Check if `val` counts as an exact hit.
+    pub fn contains(&self, val: Rat) -> bool
+    {
+        self.intervals.iter().any(|&(min, max)| val >= min && val <= max)
+    }
+
+    /// The values in the target, if it consists of single values only, and
+    /// not of ranges.
+    pub(crate) fn values(&self) -> Option<Vec<Rat>>
+    {
+        if self.intervals.iter().all(|&(min, max)| min == max)
+        {
+            Some(self.intervals.iter().map(|&(min, _)| min).collect())
+        }
+        else
+        {
+            None
+        }
+    }
+
+    /// The distance between `val` and the nearest value in the target.
+    pub fn distance(&self, val: Rat) -> Rat
+    {
+        self.intervals.iter().map(|&(min, max)| {
+                if val < min
+                {
+                    min - val
+                }
+                else if val > max
+                {
+                    val - max
+                }
+                else
+                {
+                    Rat::zero()
+                }
+            }).min().unwrap()
+    }
+
+    /// Create a window of values closer to this target than `diff`.
+    ///
+    /// Create a window containing all values whose distance to this target is
+    /// strictly less than `diff`. If `diff` is `None`, the window contains all
+    /// values.
+    pub(crate) fn window(&self, diff: Option<Rat>) -> Window
+    {
+        match diff
+        {
+            None => Window { bounds: vec![(None, None)] },
+            Some(diff) => {
+                let bounds = self.intervals.iter().map(|&(min, max)| {
+                        let lo = if diff > min { None } else { Some(min - diff) };
+                        (lo, Some(max + diff))
+                    }).collect();
+                Window { bounds }
+            }
+        }
+    }
+}
+
+impl From<u64> for Target
+{
+    fn from(target: u64) -> Self
+    {
+        Target::new(target)
+    }
+}
+
+impl ::core::fmt::Display for Target
+{
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result
+    {
+        for (i, &(min, max)) in self.intervals.iter().enumerate()
+        {
+            if i > 0
+            {
+                write!(f, ",")?;
+            }
+            if min == max
+            {
+                write!(f, "{}", min)?;
+            }
+            else
+            {
+                write!(f, "{}..={}", min, max)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A union of open intervals of values
+///
+/// Struct `Window` is used in the search to quickly check if a value is closer
+/// to the target than the best value found thus far, without computing the
+/// distance to the target for every candidate value.
+pub(crate) struct Window
+{
+    /// The exclusive lower and upper bounds of the intervals, if any
+    bounds: Vec<(Option<Rat>, Option<Rat>)>
+}
+
+impl Window
+{
+    /// Check if `val` lies within this window.
+    pub(crate) fn contains(&self, val: Rat) -> bool
+    {
+        self.bounds.iter().any(|&(min, max)| {
+                min.is_none_or(|min| val > min) && max.is_none_or(|max| val < max)
+            })
+    }
+
+    /// Check if any value in the closed interval `lo..=hi` lies within this
+    /// window.
+    pub(crate) fn intersects(&self, lo: Rat, hi: Rat) -> bool
+    {
+        self.bounds.iter().any(|&(min, max)| {
+                min.is_none_or(|min| hi > min) && max.is_none_or(|max| lo < max)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn distance_to_intervals()
+    {
+        let target = Target::any(&[Target::new(10), Target::range(20, 30)]);
+        assert_eq!(target.min(), 10.into());
+        assert!(target.contains(25.into()) && target.contains(10.into()));
+        assert!(!target.contains(Rat::new(21, 2)));
+        assert_eq!(target.distance(Rat::new(21, 2)), Rat::new(1, 2));
+        assert_eq!(target.distance(16.into()), 4.into());
+        assert_eq!(target.distance(33.into()), 3.into());
+        assert_eq!(target.distance(Rat::zero()), 10.into());
+        assert_eq!(target.values(), None);
+        assert_eq!(Target::new(7).values(), Some(vec![7.into()]));
+    }
+}
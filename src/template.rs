@@ -0,0 +1,197 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec::Vec;
+use num_traits::Zero;
+
+use crate::{Expr, Idx, Options, Target, ADD, DIV, MUL, SUB};
+use crate::parse::{parse_infix, Item, ParseError};
+use crate::partition::unique_indices;
+
+/// The maximum number of operator blanks in a template. Every assignment of
+/// operations to the blanks is tried, for every order of the numbers.
+pub const MAX_OPERATOR_BLANKS: usize = 16;
+
+/// Structure describing an expression template
+///
+/// Struct `Template` describes the shape of an expression, with blanks for
+/// the numbers, and optionally for the operations. It is parsed from an infix
+/// string like
+/// ```text
+/// (_ + _) * _ ? _
+/// ```
+/// where `_` denotes a number blank, and `?` an operator blank. Operator
+/// blanks have the precedence of addition and subtraction, so use parentheses
+/// to make the intended shape explicit.
+#[derive(Clone, Debug)]
+pub struct Template
+{
+    /// The template in reverse polish notation
+    items: Vec<Item>
+}
+
+impl Template
+{
+    /// Parse a template.
+    ///
+    /// Parse the infix template string `s`, returning the error if it is not a
+    /// valid template, or has more than `MAX_OPERATOR_BLANKS` operator blanks.
+    pub fn parse(s: &str) -> Result<Self, ParseError>
+    {
+        let items = parse_infix(s)?;
+        if items.iter().any(|item| matches!(item, Item::Number(_)))
+        {
+            return Err(ParseError::NumberNotAllowed);
+        }
+        let template = Template { items };
+        if template.operator_blank_count() > MAX_OPERATOR_BLANKS
+        {
+            return Err(ParseError::TooManyOperatorBlanks(MAX_OPERATOR_BLANKS));
+        }
+        Ok(template)
+    }
+
+    /// The number of number blanks in this template.
+    pub fn number_count(&self) -> usize
+    {
+        self.items.iter().filter(|&&item| item == Item::Blank).count()
+    }
+
+    /// The number of operator blanks in this template.
+    pub fn operator_blank_count(&self) -> usize
+    {
+        self.items.iter().filter(|&&item| item == Item::AnyOp).count()
+    }
+}
+
+/// Rearrange `idxs` into the lexicographically next permutation.
+///
+/// Returns `false` if `idxs` was already the last permutation.
+fn next_permutation(idxs: &mut [Idx]) -> bool
+{
+    let count = idxs.len();
+    if count < 2
+    {
+        return false;
+    }
+
+    let mut i = count - 1;
+    while i > 0 && idxs[i-1] >= idxs[i]
+    {
+        i -= 1;
+    }
+    if i == 0
+    {
+        return false;
+    }
+
+    let mut j = count - 1;
+    while idxs[j] <= idxs[i-1]
+    {
+        j -= 1;
+    }
+    idxs.swap(i-1, j);
+    idxs[i..].reverse();
+    true
+}
+
+/// Fill a template with numbers.
+///
+/// Find the assignment of the numbers in `nrs` to the number blanks of
+/// `template`, and of operations to its operator blanks, such that the
+/// resulting expression is nearest to `target`. Every number is used exactly
+/// once, so the template should have as many number blanks as there are
+/// numbers. Returns an empty expression if no valid assignment exists.
+pub fn fill_template(template: &Template, nrs: &[u64], target: &Target, opts: &Options) -> Expr
+{
+    let mut best = Expr::empty();
+    if template.number_count() != nrs.len()
+    {
+        return best;
+    }
+
+    // Since the number of operator blanks is limited, the count of choices
+    // fits in 64 bits
+    let any_op_count = template.operator_blank_count();
+    let mut window = target.window(None);
+    let mut perm = unique_indices(nrs);
+    loop
+    {
+        for op_choice in 0..4u64.pow(any_op_count as u32)
+        {
+            let mut idx_iter = perm.iter();
+            let mut choice = op_choice;
+            let ops = template.items.iter().map(|item| {
+                    match *item
+                    {
+                        Item::Blank  => *idx_iter.next().unwrap(),
+                        Item::Op(op) => op,
+                        Item::Number(_) => unreachable!(),
+                        Item::AnyOp  => {
+                            let op = [ADD, SUB, MUL, DIV][(choice % 4) as usize];
+                            choice /= 4;
+                            op
+                        }
+                    }
+                }).collect();
+
+            if let Some(expr) = Expr::from_ops(ops, nrs, opts)
+            {
+                if window.contains(expr.val)
+                {
+                    let diff = target.distance(expr.val);
+                    best = expr;
+                    if diff.is_zero()
+                    {
+                        return best;
+                    }
+                    window = target.window(Some(diff));
+                }
+            }
+        }
+
+        if !next_permutation(&mut perm)
+        {
+            break;
+        }
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests
+{
+    use alloc::vec;
+    use super::*;
+
+    #[test]
+    fn fill_operator_blanks()
+    {
+        let template = Template::parse("(_ ? _) ? _ ? _").unwrap();
+        assert_eq!((template.number_count(), template.operator_blank_count()), (4, 3));
+        let nrs = [2, 3, 4, 5];
+        let expr = fill_template(&template, &nrs, &Target::new(24), &Options::default());
+        assert_eq!(expr.val, 24.into());
+    }
+
+    #[test]
+    fn too_many_operator_blanks()
+    {
+        let blanks = |count: usize| vec!["_"; count + 1].join("?");
+        assert!(Template::parse(&blanks(MAX_OPERATOR_BLANKS)).is_ok());
+        assert_eq!(Template::parse(&blanks(32)).unwrap_err(),
+            ParseError::TooManyOperatorBlanks(MAX_OPERATOR_BLANKS));
+    }
+}
@@ -0,0 +1,168 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+
+use std::io::Write;
+use std::sync::Mutex;
+
+use crate::{Expr, Idx, Rat};
+use crate::partition::used_positions;
+
+/// Log of a search
+///
+/// Struct `Trace` writes a line for every partition of the numbers considered
+/// in a search, and for every new best expression found, for post-mortem
+/// analysis of a search. Register it in the search options as
+/// `Options::trace`. Every line starts with the time since the trace was
+/// created in microseconds, followed by the event:
+/// * `p mask0 mask1`: a partition considered at the top level of the search,
+/// * `c mask0 mask1`: a partition considered while computing the
+///   sub-expressions for a set of numbers,
+/// * `b mask value distance`: a new best expression with value `value`, at
+///   distance `distance` from the target.
+///
+/// Here, a mask is a hexadecimal number whose bit `i` is set if the `i`'th
+/// input number is used. When a number occurs multiple times in the input,
+/// its occurrences are assigned to the positions in the input in order.
+pub struct Trace
+{
+    /// The time at which the trace was started
+    start: ::std::time::Instant,
+    /// The destination of the trace
+    out: Mutex<Box<dyn Write + Send>>
+}
+
+impl Trace
+{
+    /// Create a new trace, writing to `out`.
+    pub fn new<W: Write + Send + 'static>(out: W) -> Self
+    {
+        Trace { start: ::std::time::Instant::now(), out: Mutex::new(Box::new(out)) }
+    }
+
+    /// Write a single event to the trace.
+    fn event(&self, event: ::std::fmt::Arguments)
+    {
+        let micros = self.start.elapsed().as_micros();
+        // Failure to write the trace should not abort the search
+        let _ = writeln!(self.out.lock().unwrap(), "{} {}", micros, event);
+    }
+
+    /// Log a partition of the numbers `nrs` indexed by `idxs0` and `idxs1`,
+    /// at the top level of the search if `top` is true.
+    pub(crate) fn partition(&self, nrs: &[u64], idxs0: &[Idx], idxs1: &[Idx], top: bool)
+    {
+        self.event(format_args!("{} {} {}", if top { 'p' } else { 'c' }, mask(nrs, idxs0),
+            mask(nrs, idxs1)));
+    }
+
+    /// Log a new best expression `expr` over the numbers `nrs`, at distance
+    /// `distance` from the target.
+    pub(crate) fn best(&self, nrs: &[u64], expr: &Expr, distance: Rat)
+    {
+        let idxs = expr.indices().collect::<Vec<_>>();
+        self.event(format_args!("b {} {} {}", mask(nrs, &idxs), expr.val, distance));
+    }
+
+    /// Flush the trace to its destination.
+    pub fn flush(&self) -> ::std::io::Result<()>
+    {
+        self.out.lock().unwrap().flush()
+    }
+}
+
+impl ::std::fmt::Debug for Trace
+{
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result
+    {
+        f.debug_struct("Trace").field("start", &self.start).finish_non_exhaustive()
+    }
+}
+
+/// The hexadecimal mask of input positions of the numbers in `nrs` indexed by
+/// `idxs`.
+fn mask(nrs: &[u64], idxs: &[Idx]) -> String
+{
+    let digits = used_positions(nrs, idxs).chunks(4).rev()
+        .map(|bits| {
+            let digit = bits.iter().rev().fold(0, |acc, &bit| 2 * acc + bit as u32);
+            ::std::char::from_digit(digit, 16).unwrap()
+        })
+        .collect::<String>();
+    let digits = digits.trim_start_matches('0');
+    if digits.is_empty() { String::from("0") } else { digits.to_string() }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{get_nearest_expression, Options, Target};
+
+    /// A buffer that can be shared between a trace and the test reading it
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer
+    {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize>
+        {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()>
+        {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn masks_of_positions()
+    {
+        assert_eq!(mask(&[1, 3, 4, 6], &[0, 2]), "5");
+        assert_eq!(mask(&[2, 2, 5], &[0, 0]), "3");
+        assert_eq!(mask(&[1, 2, 3, 4, 5, 6], &[5, 4]), "30");
+        assert_eq!(mask(&[1, 2, 3], &[]), "0");
+    }
+
+    #[test]
+    fn trace_of_search()
+    {
+        let buffer = SharedBuffer::default();
+        let trace = Arc::new(Trace::new(buffer.clone()));
+        let opts = Options { trace: Some(trace.clone()), ..Options::default() };
+        let expr = get_nearest_expression(&[1, 3, 4, 6], &Target::new(24), &opts);
+        trace.flush().unwrap();
+
+        let contents = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let events = contents.lines()
+            .map(|line| line.split(' ').collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        for event in events.iter()
+        {
+            assert!(event[0].parse::<u128>().is_ok());
+            match event[1]
+            {
+                "p" | "c" => assert_eq!(event.len(), 4),
+                "b" => assert_eq!(event.len(), 5),
+                kind => panic!("unknown event {}", kind)
+            }
+        }
+        assert!(events.iter().any(|event| event[1] == "p"));
+        let best = events.iter().rev().find(|event| event[1] == "b").unwrap();
+        assert_eq!(best[2..], ["f", &expr.val.to_string(), "0"]);
+    }
+}
@@ -0,0 +1,364 @@
+// Copyright 2019 Gé Vissers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Interactive terminal interface for the `tui` command of the program.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::time::{Duration, Instant};
+
+use makeexpr::{elegance_score, exact_solutions, get_nearest_expression, Expr, Observer, Op,
+    Options, Progress, Rat, Solution, Stats, Target, DIV, MAX_NUMBERS};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, Gauge, List, ListItem, ListState, Paragraph};
+use ratatui::Frame;
+
+/// The time to wait for a key press before redrawing the screen
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Event in the search, sent from the search thread to the interface
+enum SearchEvent
+{
+    /// The search starts, with this many steps
+    Start(usize),
+    /// A step of the search was completed
+    Step,
+    /// A new best expression was found
+    NewBest(Solution),
+    /// The search finished, or was cancelled
+    Finished(Stats),
+    /// The exact solutions, enumerated after the search
+    Solutions(Vec<Expr>)
+}
+
+/// Observer forwarding the events of a search to the interface
+#[derive(Debug)]
+struct Forwarder
+{
+    /// The channel to the interface
+    events: Sender<SearchEvent>
+}
+
+impl Progress for Forwarder
+{
+    fn start(&self, total: usize)
+    {
+        let _ = self.events.send(SearchEvent::Start(total));
+    }
+
+    fn step(&self, _best_distance: Option<Rat>) {}
+
+    fn finish(&self) {}
+}
+
+impl Observer for Forwarder
+{
+    fn on_new_best(&self, solution: &Solution)
+    {
+        let _ = self.events.send(SearchEvent::NewBest(solution.clone()));
+    }
+
+    fn on_partition_done(&self, _mask: &[bool])
+    {
+        let _ = self.events.send(SearchEvent::Step);
+    }
+
+    fn on_finished(&self, stats: &Stats)
+    {
+        let _ = self.events.send(SearchEvent::Finished(stats.clone()));
+    }
+}
+
+/// State of the interface
+struct App
+{
+    /// The input numbers
+    nrs: Vec<u64>,
+    /// The target
+    target: Target,
+    /// The search options
+    opts: Options,
+    /// The time at which the search started
+    start: Instant,
+    /// The duration of the search, once it has finished
+    elapsed: Option<Duration>,
+    /// The number of steps of the search
+    total: usize,
+    /// The number of steps completed
+    done: usize,
+    /// The number of times a better expression was found
+    improvements: usize,
+    /// The best expression found thus far
+    best: Option<Solution>,
+    /// Whether the search was cancelled
+    cancelled: bool,
+    /// The exact solutions, once they are enumerated
+    solutions: Option<Vec<Expr>>,
+    /// The selected solution in the list
+    selected: ListState
+}
+
+impl App
+{
+    /// Update the state for search event `event`.
+    fn handle(&mut self, event: SearchEvent)
+    {
+        match event
+        {
+            SearchEvent::Start(total) => {
+                self.total = total;
+            },
+            SearchEvent::Step => {
+                self.done += 1;
+            },
+            SearchEvent::NewBest(solution) => {
+                self.improvements += 1;
+                self.best = Some(solution);
+            },
+            SearchEvent::Finished(stats) => {
+                self.elapsed = Some(self.start.elapsed());
+                self.cancelled = stats.cancelled;
+                if stats.best.is_some()
+                {
+                    self.best = stats.best;
+                }
+            },
+            SearchEvent::Solutions(exprs) => {
+                if !exprs.is_empty()
+                {
+                    self.selected.select(Some(0));
+                }
+                self.solutions = Some(exprs);
+            }
+        }
+    }
+
+    /// Move the selection in the list of solutions by `delta` entries.
+    fn move_selection(&mut self, delta: isize)
+    {
+        let count = self.solutions.as_ref().map_or(0, |exprs| exprs.len());
+        if count > 0
+        {
+            let current = self.selected.selected().unwrap_or(0) as isize;
+            self.selected.select(Some((current + delta).clamp(0, count as isize - 1) as usize));
+        }
+    }
+
+    /// The expression shown in the tree view: the selected solution, or the
+    /// best expression found thus far.
+    fn shown(&self) -> Option<&Expr>
+    {
+        let selected = self.solutions.as_ref()
+            .and_then(|exprs| self.selected.selected().and_then(|i| exprs.get(i)));
+        selected.or(self.best.as_ref().map(|solution| &solution.expr))
+    }
+
+    /// Draw the interface.
+    fn draw(&mut self, frame: &mut Frame)
+    {
+        let [top, body, help] = Layout::vertical([Constraint::Length(3), Constraint::Min(0),
+            Constraint::Length(1)]).areas(frame.area());
+        let [left, right] = Layout::horizontal([Constraint::Percentage(40),
+            Constraint::Percentage(60)]).areas(body);
+        let [tree, stats] = Layout::vertical([Constraint::Min(0), Constraint::Length(7)])
+            .areas(right);
+
+        let nrs = self.nrs.iter().map(|nr| nr.to_string()).collect::<Vec<_>>().join(" ");
+        let (ratio, state) = match (self.elapsed, self.cancelled)
+            {
+                (None, _) => {
+                    (if self.total == 0 { 0.0 } else { self.done as f64 / self.total as f64 },
+                        "searching")
+                },
+                (Some(_), false) => (1.0, "finished"),
+                (Some(_), true) => (self.done as f64 / self.total.max(1) as f64, "cancelled")
+            };
+        let gauge = Gauge::default()
+            .block(Block::default().borders(Borders::ALL)
+                .title(format!(" {} → {} ", nrs, self.target)))
+            .ratio(ratio.min(1.0))
+            .label(format!("{} {}/{}", state, self.done, self.total));
+        frame.render_widget(gauge, top);
+
+        let items = match &self.solutions
+            {
+                Some(exprs) if exprs.is_empty() => vec![ListItem::new("no exact solution")],
+                Some(exprs) => exprs.iter()
+                    .map(|expr| ListItem::new(expr.to_string_with(&self.nrs, &self.opts)))
+                    .collect(),
+                None if self.elapsed.is_some() && !self.cancelled => {
+                    vec![ListItem::new("enumerating solutions...")]
+                },
+                None => vec![]
+            };
+        let count = self.solutions.as_ref().map_or(0, |exprs| exprs.len());
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(format!(" Solutions ({}) ", count)))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, left, &mut self.selected);
+
+        let lines = self.shown().map_or(vec![], |expr| tree_lines(expr, &self.nrs, &self.opts));
+        let view = Paragraph::new(lines.join("\n"))
+            .block(Block::default().borders(Borders::ALL).title(" Expression "));
+        frame.render_widget(view, tree);
+
+        let elapsed = self.elapsed.unwrap_or_else(|| self.start.elapsed());
+        let best = match &self.best
+            {
+                Some(solution) if solution.distance == Rat::from_integer(0) => {
+                    format!("{} (exact)", solution.expr.val)
+                },
+                Some(solution) => format!("{} (off by {})", solution.expr.val, solution.distance),
+                None => String::from("none")
+            };
+        let text = [
+            format!("Elapsed:      {:.1} s", elapsed.as_secs_f64()),
+            format!("Steps:        {} of {}", self.done, self.total),
+            format!("Improvements: {}", self.improvements),
+            format!("Best value:   {}", best),
+            format!("Threads:      {}", self.opts.threads.max(1))
+        ];
+        let view = Paragraph::new(text.join("\n"))
+            .block(Block::default().borders(Borders::ALL).title(" Statistics "));
+        frame.render_widget(view, stats);
+
+        let keys = "q: quit  ↑/↓: select solution  Home/End: first/last";
+        frame.render_widget(Paragraph::new(keys), help);
+    }
+}
+
+/// The lines of the tree view of expression `expr` over the numbers `nrs`.
+///
+/// Every operation is shown with its value and the sub-expression it
+/// computes, with the operands indented below it.
+fn tree_lines(expr: &Expr, nrs: &[u64], opts: &Options) -> Vec<String>
+{
+    // For every sub-expression on the stack, its start in the operations, and
+    // the lines of its tree
+    let mut stack: Vec<(usize, Vec<String>)> = vec![];
+    for (i, &op) in expr.ops.iter().enumerate()
+    {
+        if (op as usize) < MAX_NUMBERS
+        {
+            stack.push((i, vec![nrs[op as usize].to_string()]));
+            continue;
+        }
+
+        let arity = if op >= DIV - opts.binary_ops.len() as Op { 2 } else { 1 };
+        let children = stack.split_off(stack.len() - arity);
+        let start = children[0].0;
+        let label = match Expr::from_ops(expr.ops[start..=i].to_vec(), nrs, opts)
+            {
+                Some(sub) => format!("{} = {}", sub.val, sub.to_string_with(nrs, opts)),
+                None => String::from("?")
+            };
+        let mut lines = vec![label];
+        for (j, (_, child)) in children.iter().enumerate()
+        {
+            let last = j + 1 == children.len();
+            for (k, line) in child.iter().enumerate()
+            {
+                let prefix = match (k, last)
+                    {
+                        (0, false) => "├─ ",
+                        (0, true)  => "└─ ",
+                        (_, false) => "│  ",
+                        (_, true)  => "   "
+                    };
+                lines.push(format!("{}{}", prefix, line));
+            }
+        }
+        stack.push((start, lines));
+    }
+    stack.pop().map_or(vec![], |(_, lines)| lines)
+}
+
+/// Run the interface.
+///
+/// Search for the expression over the numbers `nrs` nearest to `target` on a
+/// background thread, showing the progress of the search, and the best
+/// expression found thus far. When an exact solution is found, all exact
+/// solutions are listed afterwards, most elegant first, and can be browsed
+/// with the arrow keys.
+pub fn run(nrs: Vec<u64>, target: Target, mut opts: Options) -> ::std::io::Result<()>
+{
+    let (events, received) = channel();
+    let forwarder = Arc::new(Forwarder { events: events.clone() });
+    let cancel = Arc::new(AtomicBool::new(false));
+    opts.progress = Some(forwarder.clone());
+    opts.observer = Some(forwarder);
+    opts.cancel = Some(cancel.clone());
+
+    {
+        let (nrs, target, opts) = (nrs.clone(), target.clone(), opts.clone());
+        ::std::thread::spawn(move || {
+                let best = get_nearest_expression(&nrs, &target, &opts);
+                if !opts.cancelled()
+                {
+                    let mut exprs = if target.contains(best.val)
+                        {
+                            exact_solutions(&nrs, &target, !opts.subset, &opts)
+                        }
+                        else
+                        {
+                            vec![]
+                        };
+                    exprs.sort_by_key(|expr| elegance_score(expr, &nrs));
+                    let _ = events.send(SearchEvent::Solutions(exprs));
+                }
+            });
+    }
+
+    let opts = Options { progress: None, observer: None, ..opts };
+    let mut app = App { nrs, target, opts, start: Instant::now(), elapsed: None, total: 0,
+        done: 0, improvements: 0, best: None, cancelled: false, solutions: None,
+        selected: ListState::default() };
+    let mut terminal = ratatui::init();
+    let res = loop
+        {
+            while let Ok(event) = received.try_recv()
+            {
+                app.handle(event);
+            }
+            if let Err(err) = terminal.draw(|frame| app.draw(frame))
+            {
+                break Err(err);
+            }
+
+            match event::poll(POLL_INTERVAL).and_then(|ready| ready.then(event::read).transpose())
+            {
+                Ok(Some(Event::Key(key))) if key.kind == KeyEventKind::Press => match key.code
+                    {
+                        KeyCode::Char('q') | KeyCode::Esc => {
+                            cancel.store(true, Ordering::Relaxed);
+                            break Ok(());
+                        },
+                        KeyCode::Up | KeyCode::Char('k') => app.move_selection(-1),
+                        KeyCode::Down | KeyCode::Char('j') => app.move_selection(1),
+                        KeyCode::PageUp => app.move_selection(-10),
+                        KeyCode::PageDown => app.move_selection(10),
+                        KeyCode::Home => app.move_selection(isize::MIN / 2),
+                        KeyCode::End => app.move_selection(isize::MAX / 2),
+                        _ => {}
+                    },
+                Ok(_) => {},
+                Err(err) => break Err(err)
+            }
+        };
+    ratatui::restore();
+    res
+}